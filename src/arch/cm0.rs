@@ -17,9 +17,18 @@
 
 use volatile::Volatile;
 use task::args::Args;
-use alloc::boxed::Box;
+use power::{self, LowPowerMode};
 use syscall;
+use tick;
 
+#[path = "frame.rs"]
+mod frame;
+
+/// Request a context switch by pending the PendSV exception.
+///
+/// From thread mode the switch happens right away. From an interrupt or exception handler it is
+/// deferred until the handler (and any it preempted) return, PendSV has the lowest priority so it
+/// can't preempt them. The request isn't lost, it just doesn't take effect in the handler.
 pub fn yield_cpu() {
     const ICSR_ADDR: usize = 0xE000_ED04;
     const PEND_SV_SET: usize = 0b1 << 28;
@@ -30,16 +39,18 @@ pub fn yield_cpu() {
     }
 }
 
-pub fn initialize_stack(stack_ptr: Volatile<usize>, code: fn(&mut Args), args: &Box<Args>) -> usize {
-    const INITIAL_XPSR: usize = 0x0100_0000;
-    unsafe {
-        // Initial offset added to account for way MCU uses stack on entry/exit of interrupts
-        *stack_ptr.offset(-1) = INITIAL_XPSR; /* xPSR */
-        *stack_ptr.offset(-2) = code as usize; /* PC */
-        *stack_ptr.offset(-3) = exit_error as usize; /* LR */
-        *stack_ptr.offset(-8) = &**args as *const _ as usize; /* R0 */
-        stack_ptr.offset(-16).as_ptr() as usize
-    }
+/// The number of words `initialize_stack` writes to the top of a new task's stack.
+///
+/// This is the size of the initial frame, the registers the processor stacks on exception entry
+/// plus the registers (r4-r11) that are saved on a context switch.
+pub const MIN_STACK_WORDS: usize = frame::FRAME_WORDS;
+
+/// Write the initial frame for a new task to the top of `stack`, returning the task's initial
+/// stack pointer.
+///
+/// Returns `None` without writing anything if `stack` is shorter than `MIN_STACK_WORDS`.
+pub fn initialize_stack(stack: &mut [usize], code: fn(&mut Args), arg: usize) -> Option<usize> {
+    frame::write(stack, code as usize, exit_error as usize, arg)
 }
 
 #[inline(never)]
@@ -73,6 +84,50 @@ pub fn start_first_task() {
     }
 }
 
+pub fn enter_low_power(mode: LowPowerMode) {
+    const SCR_ADDR: usize = 0xE000_ED10;
+
+    unsafe {
+        let mut scr = Volatile::new(SCR_ADDR as *const usize);
+        *scr = power::scr_for_mode(*scr, mode);
+        #[cfg(target_arch="arm")]
+        asm!("wfi"
+            : /* no outputs */
+            : /* no inputs */
+            : /* no clobbers */
+            : "volatile"
+        );
+    }
+}
+
+const DEMCR_ADDR: usize = 0xE000_EDFC;
+const DWT_CTRL_ADDR: usize = 0xE000_1000;
+const DWT_CYCCNT_ADDR: usize = 0xE000_1004;
+
+pub fn enable_cycle_counter() -> bool {
+    unsafe {
+        let mut demcr = Volatile::new(DEMCR_ADDR as *const usize);
+        *demcr |= tick::DEMCR_TRCENA;
+
+        let mut dwt_ctrl = Volatile::new(DWT_CTRL_ADDR as *const usize);
+        if *dwt_ctrl & tick::DWT_CTRL_NOCYCCNT != 0 {
+            // The Cortex-M0 doesn't implement the cycle counter
+            return false;
+        }
+        let mut cyccnt = Volatile::new(DWT_CYCCNT_ADDR as *const usize);
+        *cyccnt = 0;
+        *dwt_ctrl |= tick::DWT_CTRL_CYCCNTENA;
+    }
+    true
+}
+
+pub fn read_cycle_counter() -> usize {
+    unsafe {
+        let cyccnt = Volatile::new(DWT_CYCCNT_ADDR as *const usize);
+        *cyccnt
+    }
+}
+
 pub fn in_kernel_mode() -> bool {
     const MAIN_STACK: usize = 0b00;
     const _PROGRAM_STACK: usize = 0b10;
@@ -93,7 +148,29 @@ pub fn in_kernel_mode() -> bool {
     }
 }
 
-pub fn begin_critical() -> usize {
+/// Check if the processor is currently handling an exception.
+///
+/// This reads the exception number from the IPSR register, which is zero in thread mode and
+/// nonzero while any exception or interrupt handler is running.
+pub fn in_interrupt() -> bool {
+    unsafe {
+        let ipsr: usize;
+        #[cfg(target_arch="arm")]
+        asm!("mrs $0, IPSR\n" /* get the current exception number */
+            : "=r"(ipsr)
+            : /* no inputs */
+            : /* no clobbers */
+            : "volatile"
+        );
+        #[cfg(not(target_arch="arm"))]
+        {
+            ipsr = 0;
+        }
+        ipsr & 0x3F != 0
+    }
+}
+
+pub(crate) fn begin_critical() -> usize {
     let primask: usize;
     unsafe {
         #[cfg(target_arch="arm")]
@@ -115,7 +192,7 @@ pub fn begin_critical() -> usize {
     primask
 }
 
-pub fn end_critical(primask: usize) {
+pub(crate) fn end_critical(primask: usize) {
     unsafe {
         #[cfg(target_arch="arm")]
         asm!("msr PRIMASK, $0"
@@ -127,6 +204,15 @@ pub fn end_critical(primask: usize) {
     }
 }
 
+// The Cortex-M0 doesn't have a BASEPRI register, so all interrupts have to be disabled
+pub(crate) fn begin_critical_bounded(_priority: u8) -> usize {
+    begin_critical()
+}
+
+pub(crate) fn end_critical_bounded(primask: usize) {
+    end_critical(primask);
+}
+
 #[naked]
 #[inline(never)]
 #[cfg(feature="syscall")]
@@ -153,7 +239,8 @@ pub fn syscall0(call: u32) -> usize {
     match call {
         syscall::SYS_EXIT => syscall::sys_exit(),
         syscall::SYS_SCHED_YIELD => syscall::sys_sched_yield(),
-        _ => panic!("Invalid syscall code for syscall0: {}", call),
+        syscall::SYS_WAKE_REASON => return syscall::sys_wake_reason() as usize,
+        _ => return syscall::sys_invalid_syscall(call),
     }
     return 0;
 }
@@ -185,6 +272,7 @@ pub fn syscall1(call: u32, arg1: usize) -> usize {
     match call {
         syscall::SYS_SLEEP => syscall::sys_sleep(arg1),
         syscall::SYS_WAKE => syscall::sys_wake(arg1),
+        syscall::SYS_NOTIFY_WAIT => return syscall::sys_notify_wait(arg1),
         syscall::SYS_MX_LOCK => {
             let lock = unsafe { &*(arg1 as *const RawMutex) };
             return syscall::sys_mutex_lock(lock) as usize;
@@ -201,11 +289,61 @@ pub fn syscall1(call: u32, arg1: usize) -> usize {
             let condvar = unsafe { &*(arg1 as *const CondVar) };
             syscall::sys_condvar_broadcast(condvar);
         },
-        _ => panic!("Invalid syscall code for syscall1: {}", call),
+        syscall::SYS_CV_SIGNAL => {
+            let condvar = unsafe { &*(arg1 as *const CondVar) };
+            syscall::sys_condvar_signal(condvar);
+        },
+        _ => return syscall::sys_invalid_syscall(call),
     }
     return 0;
 }
 
+// A 64 bit value is returned in R0:R1 under the AAPCS, so the system call handler's two words
+// are passed straight through by leaving them in place.
+#[naked]
+#[inline(never)]
+#[cfg(feature="syscall")]
+extern "aapcs" fn svc_wide(_call: u32, _arg1: usize) -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("push {lr}
+            sub sp, sp, #4
+            svc 0
+            add sp, sp, #4
+            pop {pc}"
+        : "={r0}"(low), "={r1}"(high)
+        );
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+#[cfg(feature="syscall")]
+pub fn syscall1_wide(call: u32, arg1: usize) -> (usize, usize) {
+    let res = svc_wide(call, arg1);
+    (res as u32 as usize, (res >> 32) as u32 as usize)
+}
+
+#[cfg(not(feature="syscall"))]
+pub fn syscall1_wide(call: u32, arg1: usize) -> (usize, usize) {
+    use sync::RawMutex;
+    use task::TaskId;
+
+    // Make sure any system call gets executed atomically
+    let _g = ::sync::CriticalSection::begin();
+    match call {
+        syscall::SYS_MX_TRY_LOCK_OWNER => {
+            let lock = unsafe { &*(arg1 as *const RawMutex) };
+            syscall::sys_mutex_try_lock_owner(lock)
+        },
+        syscall::SYS_JOIN => {
+            let id = unsafe { &*(arg1 as *const TaskId) };
+            syscall::sys_join(id)
+        },
+        _ => (syscall::sys_invalid_syscall(call), 0),
+    }
+}
+
 #[naked]
 #[inline(never)]
 #[cfg(feature="syscall")]
@@ -237,7 +375,8 @@ pub fn syscall2(call: u32, arg1: usize, arg2: usize) -> usize {
             let lock = unsafe { &*(arg2 as *const RawMutex) };
             syscall::sys_condvar_wait(condvar, lock);
         },
-        _ => panic!("Invalid syscall code for syscall2: {}", call),
+        _ if call >= syscall::SYS_USER_BASE => return syscall::sys_user_syscall(call, arg1, arg2),
+        _ => return syscall::sys_invalid_syscall(call),
     }
     return 0;
 }
@@ -0,0 +1,58 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! The initial stack frame of a Cortex-M task.
+//!
+//! On exception entry the processor stacks r0-r3, r12, lr, pc and xPSR, and a context switch saves
+//! r4-r11 below them. A new task's stack starts out holding both, as if it had been switched out
+//! right before its first instruction, so the first switch to it unstacks straight into its code.
+
+/// The number of registers the context switch saves, r4-r11.
+pub const SAVED_WORDS: usize = 8;
+
+/// The number of registers the processor stacks on exception entry.
+pub const STACKED_WORDS: usize = 8;
+
+/// The number of words in a new task's initial frame.
+pub const FRAME_WORDS: usize = SAVED_WORDS + STACKED_WORDS;
+
+// Where each register the kernel sets sits in the frame, counted up from its bottom
+pub const R0: usize = SAVED_WORDS;
+pub const LR: usize = SAVED_WORDS + 5;
+pub const PC: usize = SAVED_WORDS + 6;
+pub const XPSR: usize = SAVED_WORDS + 7;
+
+const INITIAL_XPSR: usize = 0x0100_0000;
+
+// Write the initial frame to the top of `stack`, returning the address of its bottom.
+//
+// Returns `None` without writing anything if `stack` is shorter than `FRAME_WORDS`.
+pub fn write(stack: &mut [usize], pc: usize, lr: usize, r0: usize) -> Option<usize> {
+    let base = match stack.len().checked_sub(FRAME_WORDS) {
+        Some(base) => base,
+        None => return None,
+    };
+    let frame = &mut stack[base..];
+    for word in frame.iter_mut() {
+        *word = 0;
+    }
+    frame[XPSR] = INITIAL_XPSR;
+    frame[PC] = pc;
+    frame[LR] = lr;
+    frame[R0] = r0;
+    Some(frame.as_ptr() as usize)
+}
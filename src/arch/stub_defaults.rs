@@ -0,0 +1,161 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Weak default implementations of the architecture stubs.
+//!
+//! When porting to a new architecture every `__`-prefixed function declared in `unknown.rs` has
+//! to be provided, or linking fails. With the `stub_defaults` feature enabled these weak
+//! definitions fill in for any the port doesn't provide yet, each one panics with the name of the
+//! missing function the first time it's called. A port's own definitions always take precedence.
+
+#![allow(missing_docs)]
+
+use power::LowPowerMode;
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __yield_cpu() {
+    kernel_panic!("arch function not implemented: __yield_cpu");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __initialize_stack(_stack_ptr: usize, _code_ptr: usize, _args_ptr: usize) -> usize {
+    kernel_panic!("arch function not implemented: __initialize_stack");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __start_first_task() {
+    kernel_panic!("arch function not implemented: __start_first_task");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __enter_low_power(_mode: LowPowerMode) {
+    kernel_panic!("arch function not implemented: __enter_low_power");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __enable_cycle_counter() -> bool {
+    kernel_panic!("arch function not implemented: __enable_cycle_counter");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __read_cycle_counter() -> usize {
+    kernel_panic!("arch function not implemented: __read_cycle_counter");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __in_kernel_mode() -> bool {
+    kernel_panic!("arch function not implemented: __in_kernel_mode");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __in_interrupt() -> bool {
+    kernel_panic!("arch function not implemented: __in_interrupt");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __begin_critical() -> usize {
+    kernel_panic!("arch function not implemented: __begin_critical");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __end_critical(_mask: usize) {
+    kernel_panic!("arch function not implemented: __end_critical");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __begin_critical_bounded(_priority: u8) -> usize {
+    kernel_panic!("arch function not implemented: __begin_critical_bounded");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __end_critical_bounded(_mask: usize) {
+    kernel_panic!("arch function not implemented: __end_critical_bounded");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __syscall0(_call: u32) -> usize {
+    kernel_panic!("arch function not implemented: __syscall0");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __syscall1(_call: u32, _arg1: usize) -> usize {
+    kernel_panic!("arch function not implemented: __syscall1");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __syscall1_wide(_call: u32, _arg1: usize) -> (usize, usize) {
+    kernel_panic!("arch function not implemented: __syscall1_wide");
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __syscall2(_call: u32, _arg1: usize, _arg2: usize) -> usize {
+    kernel_panic!("arch function not implemented: __syscall2");
+}
+
+#[cfg(feature="smp")]
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __current_core() -> usize {
+    kernel_panic!("arch function not implemented: __current_core");
+}
+
+#[cfg(feature="smp")]
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __start_core(_core: usize, _entry: usize) {
+    kernel_panic!("arch function not implemented: __start_core");
+}
+
+#[cfg(feature="smp")]
+#[no_mangle]
+#[linkage = "weak"]
+pub fn __interrupt_core(_core: usize) {
+    kernel_panic!("arch function not implemented: __interrupt_core");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "arch function not implemented: __yield_cpu")]
+    fn test_missing_stub_panics_with_name() {
+        __yield_cpu();
+    }
+
+    #[test]
+    #[should_panic(expected = "arch function not implemented: __syscall2")]
+    fn test_missing_stub_with_args_panics_with_name() {
+        __syscall2(0, 0, 0);
+    }
+}
@@ -17,46 +17,167 @@
 
 //! This module is used to provide stubs for the architecture layer for testing.
 
-use volatile::Volatile;
 use task::args::Args;
-use alloc::boxed::Box;
+use task::TaskId;
 use sync::{RawMutex, CondVar};
+use power::{self, LowPowerMode};
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, AtomicBool, ATOMIC_BOOL_INIT, Ordering};
 use sched;
 use syscall;
+use tick;
 
+// Built into the tests so the weak stub defaults are checked on the host
+#[cfg(test)]
+#[path = "stub_defaults.rs"]
+mod stub_defaults;
+
+// The mock lays out the same initial frame as the Cortex-M backend
+#[path = "frame.rs"]
+mod frame;
+
+// A mock of the System Control Register
+pub static SCR: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// A mock of the BASEPRI register
+pub static BASEPRI: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// A mock of the PRIMASK register, 1 means interrupts are disabled
+pub static PRIMASK: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Mocks of the debug registers used by the cycle counter
+pub static DEMCR: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static DWT_CTRL: AtomicUsize = ATOMIC_USIZE_INIT;
+pub static DWT_CYCCNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// The number of context switches requested through `yield_cpu`
+pub static YIELD_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// A mock of the PendSV set-pending bit in the Interrupt Control and State Register
+pub static PENDSV_PENDING: AtomicBool = ATOMIC_BOOL_INIT;
+
+static IN_INTERRUPT: AtomicBool = ATOMIC_BOOL_INIT;
+
+// The core the test is pretending to run on
+#[cfg(feature="smp")]
+pub static CORE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// A bit for each core that has been started with `start_core`
+#[cfg(feature="smp")]
+pub static STARTED_CORES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// A bit for each core with an inter-core interrupt raised that the test hasn't handled yet
+#[cfg(feature="smp")]
+pub static INTERRUPTED_CORES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Simulate entering or leaving an interrupt handler, leaving it runs a pending context switch the
+// same way returning from the last handler does on the hardware
+pub fn set_in_interrupt(in_interrupt: bool) {
+    IN_INTERRUPT.store(in_interrupt, Ordering::Relaxed);
+    if !in_interrupt && PENDSV_PENDING.swap(false, Ordering::Relaxed) {
+        sched::switch_context();
+    }
+}
+
+// Inside of an interrupt handler the switch is only pended, PendSV has the lowest priority so it
+// can't run until the handler returns
 pub fn yield_cpu() {
-    sched::switch_context();
+    YIELD_COUNT.fetch_add(1, Ordering::Relaxed);
+    if in_interrupt() {
+        PENDSV_PENDING.store(true, Ordering::Relaxed);
+    }
+    else {
+        sched::switch_context();
+    }
 }
 
-pub fn initialize_stack(stack_ptr: Volatile<usize>, _code: fn(&mut Args), _args: &Box<Args>)
-    -> usize {
+/// The number of words `initialize_stack` writes to the top of a new task's stack.
+///
+/// The mock lays out the Cortex-M frame so tests see the same stack usage as the hardware.
+pub const MIN_STACK_WORDS: usize = frame::FRAME_WORDS;
 
-    stack_ptr.as_ptr() as usize
+// There's no exit trampoline to return to in the mock, so the frame's LR is left at 0
+pub fn initialize_stack(stack: &mut [usize], code: fn(&mut Args), arg: usize) -> Option<usize> {
+    frame::write(stack, code as usize, 0, arg)
 }
 
 pub fn start_first_task() {
     // no-op
 }
 
+pub fn enter_low_power(mode: LowPowerMode) {
+    let scr = SCR.load(Ordering::Relaxed);
+    SCR.store(power::scr_for_mode(scr, mode), Ordering::Relaxed);
+}
+
+pub fn enable_cycle_counter() -> bool {
+    DEMCR.fetch_or(tick::DEMCR_TRCENA, Ordering::Relaxed);
+    if DWT_CTRL.load(Ordering::Relaxed) & tick::DWT_CTRL_NOCYCCNT != 0 {
+        return false;
+    }
+    DWT_CYCCNT.store(0, Ordering::Relaxed);
+    DWT_CTRL.fetch_or(tick::DWT_CTRL_CYCCNTENA, Ordering::Relaxed);
+    true
+}
+
+pub fn read_cycle_counter() -> usize {
+    DWT_CYCCNT.load(Ordering::Relaxed)
+}
+
 pub fn in_kernel_mode() -> bool {
     // no-op
     true
 }
 
-pub fn begin_critical() -> usize {
-    // no-op
-    0
+/// Check if the processor is currently handling an exception.
+///
+/// The test backend returns the value set by `set_in_interrupt`.
+pub fn in_interrupt() -> bool {
+    IN_INTERRUPT.load(Ordering::Relaxed)
 }
 
-pub fn end_critical(_mask: usize) {
-    // no-op
+#[cfg(feature="smp")]
+pub fn current_core() -> usize {
+    CORE.load(Ordering::Relaxed)
+}
+
+// The test runs the core's entry point itself, after switching `CORE` over to it
+#[cfg(feature="smp")]
+pub fn start_core(core: usize, _entry: fn()) {
+    STARTED_CORES.fetch_or(1 << core, Ordering::Relaxed);
+}
+
+#[cfg(feature="smp")]
+pub fn interrupt_core(core: usize) {
+    INTERRUPTED_CORES.fetch_or(1 << core, Ordering::Relaxed);
+}
+
+pub(crate) fn begin_critical() -> usize {
+    PRIMASK.swap(1, Ordering::Relaxed)
+}
+
+pub(crate) fn end_critical(primask: usize) {
+    PRIMASK.store(primask, Ordering::Relaxed);
+}
+
+// Like BASEPRI_MAX, the mask is only written if it raises the current one
+pub(crate) fn begin_critical_bounded(priority: u8) -> usize {
+    let basepri = BASEPRI.load(Ordering::Relaxed);
+    if basepri == 0 || (priority as usize) < basepri {
+        BASEPRI.store(priority as usize, Ordering::Relaxed);
+    }
+    basepri
+}
+
+pub(crate) fn end_critical_bounded(basepri: usize) {
+    BASEPRI.store(basepri, Ordering::Relaxed);
 }
 
 pub fn syscall0(call: u32) -> usize {
     match call {
         syscall::SYS_EXIT => syscall::sys_exit(),
         syscall::SYS_SCHED_YIELD => syscall::sys_sched_yield(),
-        _ => panic!("Invalid syscall code for syscall0: {}", call),
+        syscall::SYS_WAKE_REASON => return syscall::sys_wake_reason() as usize,
+        _ => return syscall::sys_invalid_syscall(call),
     }
     return 0;
 }
@@ -65,6 +186,7 @@ pub fn syscall1(call: u32, arg1: usize) -> usize {
     match call {
         syscall::SYS_SLEEP => syscall::sys_sleep(arg1),
         syscall::SYS_WAKE => syscall::sys_wake(arg1),
+        syscall::SYS_NOTIFY_WAIT => return syscall::sys_notify_wait(arg1),
         syscall::SYS_MX_LOCK => {
             let lock = unsafe { &*(arg1 as *const RawMutex) };
             return syscall::sys_mutex_lock(lock) as usize;
@@ -81,11 +203,29 @@ pub fn syscall1(call: u32, arg1: usize) -> usize {
             let condvar = unsafe { &*(arg1 as *const CondVar) };
             syscall::sys_condvar_broadcast(condvar);
         },
-        _ => panic!("Invalid syscall code for syscall1: {}", call),
+        syscall::SYS_CV_SIGNAL => {
+            let condvar = unsafe { &*(arg1 as *const CondVar) };
+            syscall::sys_condvar_signal(condvar);
+        },
+        _ => return syscall::sys_invalid_syscall(call),
     }
     return 0;
 }
 
+pub fn syscall1_wide(call: u32, arg1: usize) -> (usize, usize) {
+    match call {
+        syscall::SYS_MX_TRY_LOCK_OWNER => {
+            let lock = unsafe { &*(arg1 as *const RawMutex) };
+            syscall::sys_mutex_try_lock_owner(lock)
+        },
+        syscall::SYS_JOIN => {
+            let id = unsafe { &*(arg1 as *const TaskId) };
+            syscall::sys_join(id)
+        },
+        _ => (syscall::sys_invalid_syscall(call), 0),
+    }
+}
+
 pub fn syscall2(call: u32, arg1: usize, arg2: usize) -> usize {
     match call {
         syscall::SYS_SLEEP_FOR => syscall::sys_sleep_for(arg1, arg2),
@@ -94,7 +234,108 @@ pub fn syscall2(call: u32, arg1: usize, arg2: usize) -> usize {
             let lock = unsafe { &*(arg2 as *const RawMutex) };
             syscall::sys_condvar_wait(condvar, lock);
         },
-        _ => panic!("Invalid syscall code for syscall2: {}", call),
+        _ if call >= syscall::SYS_USER_BASE => return syscall::sys_user_syscall(call, arg1, arg2),
+        _ => return syscall::sys_invalid_syscall(call),
     }
     return 0;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use task::args::Args;
+    use alloc::boxed::Box;
+
+    fn test_task(_args: &mut Args) {}
+
+    #[test]
+    fn test_initialize_stack_writes_min_stack_words() {
+        const SENTINEL: usize = 0xDEAD_BEEF;
+        const BUFFER_WORDS: usize = MIN_STACK_WORDS + 4;
+
+        let mut buffer = [SENTINEL; BUFFER_WORDS];
+        let args = Box::new(Args::empty());
+        let arg = &*args as *const _ as usize;
+        let top = buffer.as_ptr() as usize + BUFFER_WORDS * ::core::mem::size_of::<usize>();
+
+        let sp = initialize_stack(&mut buffer, test_task, arg).unwrap();
+
+        // The stack pointer is left at the bottom of a frame with one word per stacked and saved
+        // register
+        let words = (top - sp) / ::core::mem::size_of::<usize>();
+        assert_eq!(words, MIN_STACK_WORDS);
+        assert_eq!(words, frame::SAVED_WORDS + frame::STACKED_WORDS);
+        // The first switch to the task unstacks into its code with the argument in R0
+        let frame = &buffer[BUFFER_WORDS - MIN_STACK_WORDS..];
+        assert_eq!(frame[frame::PC], test_task as usize);
+        assert_eq!(frame[frame::R0], arg);
+        // Nothing below the frame should have been touched
+        assert!(buffer[..BUFFER_WORDS - MIN_STACK_WORDS].iter().all(|&word| word == SENTINEL));
+    }
+
+    #[test]
+    fn test_initialize_stack_rejects_tiny_stack_without_writing() {
+        const SENTINEL: usize = 0xDEAD_BEEF;
+
+        let mut buffer = [SENTINEL; 4];
+        let args = Box::new(Args::empty());
+
+        assert_eq!(initialize_stack(&mut buffer, test_task, &*args as *const _ as usize), None);
+        assert!(buffer.iter().all(|&word| word == SENTINEL));
+        assert_eq!(initialize_stack(&mut [], test_task, 0), None);
+    }
+
+    #[test]
+    fn test_syscall1_wide_returns_both_words() {
+        use sched;
+        use sync::RawMutex;
+
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        let lock = RawMutex::new();
+        let lock_addr = &lock as *const _ as usize;
+        sched::start_scheduler();
+
+        let tid_1 = handle_1.tid().unwrap();
+        assert_eq!(syscall1_wide(syscall::SYS_MX_TRY_LOCK_OWNER, lock_addr), (1, tid_1));
+
+        yield_cpu();
+        assert_eq!(handle_2.tid(), Ok(::test::current_task().unwrap().tid()));
+        assert_eq!(syscall1_wide(syscall::SYS_MX_TRY_LOCK_OWNER, lock_addr), (0, tid_1));
+
+        assert_eq!(syscall1_wide(!0, 0).0, syscall::SYS_ERR_INVALID);
+    }
+
+    #[test]
+    fn test_yield_from_interrupt_deferred_until_handler_returns() {
+        use sched;
+
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        sched::start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(::test::current_task().unwrap().tid()));
+
+        set_in_interrupt(true);
+        yield_cpu();
+        // Still in the handler, the switch is waiting
+        assert!(PENDSV_PENDING.load(Ordering::Relaxed));
+        assert_eq!(handle_1.tid(), Ok(::test::current_task().unwrap().tid()));
+
+        set_in_interrupt(false);
+        assert_not!(PENDSV_PENDING.load(Ordering::Relaxed));
+        assert_eq!(handle_2.tid(), Ok(::test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_in_interrupt_reflects_handler_mode() {
+        let _g = test::set_up();
+        assert_not!(in_interrupt());
+
+        set_in_interrupt(true);
+        assert!(in_interrupt());
+
+        set_in_interrupt(false);
+        assert_not!(in_interrupt());
+    }
+}
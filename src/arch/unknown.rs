@@ -16,13 +16,35 @@
 */
 
 //! This module is used to provide stubs for the architecture layer.
+//!
+//! A port must define every `__`-prefixed function declared here. While bringing up a new port,
+//! enable the `stub_defaults` feature to link against weak defaults that panic when called.
 
-use volatile::Volatile;
 use task::args::Args;
-use alloc::boxed::Box;
+use power::LowPowerMode;
+
+// Weak defaults for any of the stubs below that a port doesn't provide
+#[cfg(feature="stub_defaults")]
+#[path = "stub_defaults.rs"]
+mod stub_defaults;
+
+// Only the size of the Cortex-M frame is used, to budget for the port's
+#[allow(dead_code)]
+#[path = "frame.rs"]
+mod frame;
+
+/// The most words a port's `__initialize_stack` may write to the top of a new task's stack.
+///
+/// A port's frame size isn't known until link time, so this is the budget every port has to fit
+/// its initial frame in rather than the size of any one frame. It's the size of the largest frame
+/// the kernel has a backend for, the Cortex-M one.
+pub const MIN_STACK_WORDS: usize = frame::FRAME_WORDS;
 
 extern "Rust" {
     // Give up remaining CPU time to the scheduler, usually done through some inerrupt call
+    //
+    // If called from an interrupt handler the switch must be deferred until the handler returns
+    // rather than dropped.
     fn __yield_cpu();
 
     // Initialize the stack with the given arguments, `stack_ptr` is the initial stack pointer,
@@ -34,14 +56,36 @@ extern "Rust" {
 
     // Start the first task, assuming that `CURRENT_TASK` has been selected by the scheduler and
     // now just needs its context loaded into the CPU
+    //
+    // With the `smp` feature `CURRENT_TASK` is an array with an entry for each core, this and the
+    // context switch handler must use the entry for the core they're running on.
     fn __start_first_task();
 
+    // Put the processor into the low power mode `mode` and wait for an interrupt to wake it up.
+    fn __enter_low_power(mode: LowPowerMode);
+
+    // Start the hardware cycle counter, return `true` if the target has one. Targets without a
+    // cycle counter should return `false`.
+    fn __enable_cycle_counter() -> bool;
+
+    // Read the hardware cycle counter, this is only called if `enable_cycle_counter` returned
+    // `true`.
+    fn __read_cycle_counter() -> usize;
+
     // Check if the code is running in kernel mode, return `true` if it is. This is generally just
     // a convenience method, and can be stubbed out to return only `true` if needed.
     fn __in_kernel_mode() -> bool;
 
+    // Check if the code is running inside of an interrupt or exception handler, return `true` if
+    // it is.
+    fn __in_interrupt() -> bool;
+
     // Begin a critical section, disabling interrupts.
     //
+    // With the `smp` feature this must also keep every other core out of the kernel, by taking a
+    // lock shared by all of the cores (like one of the RP2040's hardware spinlocks). Critical
+    // sections nest, so the lock must be taken again without spinning by the core holding it.
+    //
     // Return a value that will be used in a future `end_critical` call, which may be useful for
     // restoring some state. If it is unneccessary, returning `0` is allowed.
     fn __begin_critical() -> usize;
@@ -52,6 +96,34 @@ extern "Rust" {
     // state of the processor or ignore the value is unneeded.
     fn __end_critical(mask: usize);
 
+    // Begin a critical section that only masks interrupts at or below `priority`, on Cortex-M
+    // targets this is done with the BASEPRI_MAX register, which only ever raises the mask. A
+    // looser `priority` than the current mask must leave the mask alone. Targets that can't mask by
+    // priority should disable all interrupts instead.
+    //
+    // Return a value that will be passed to the matching `end_critical_bounded` call.
+    fn __begin_critical_bounded(priority: u8) -> usize;
+
+    // End a critical section started with `begin_critical_bounded`.
+    //
+    // `mask` is the value returned from the matching `begin_critical_bounded` call.
+    fn __end_critical_bounded(mask: usize);
+
+    // Return the index of the core this is called on, counting from 0. Only needed with the `smp`
+    // feature.
+    #[cfg(feature="smp")]
+    fn __current_core() -> usize;
+
+    // Start `core` running the function at `entry`, on its own main stack and with interrupts
+    // enabled. `entry` never returns. Only needed with the `smp` feature.
+    #[cfg(feature="smp")]
+    fn __start_core(core: usize, entry: usize);
+
+    // Raise an interrupt on `core` whose handler calls `smp::handle_core_interrupt`, it may be
+    // raised again before the handler has run. Only needed with the `smp` feature.
+    #[cfg(feature="smp")]
+    fn __interrupt_core(core: usize);
+
     // Initiate a system call with 0 arguments, return the result of that system call as a pointer
     // width integer.
     fn __syscall0(call: u32) -> usize;
@@ -60,6 +132,10 @@ extern "Rust" {
     // width integer.
     fn __syscall1(call: u32, arg1: usize) -> usize;
 
+    // Initiate a system call with 1 argument that returns two words, return both words of the
+    // result of that system call.
+    fn __syscall1_wide(call: u32, arg1: usize) -> (usize, usize);
+
     // Initiate a system call with 2 arguments, return the result of that system call as a pointer
     // width integer.
     fn __syscall2(call: u32, arg1: usize, arg2: usize) -> usize;
@@ -69,28 +145,83 @@ pub fn yield_cpu() {
     unsafe { __yield_cpu() };
 }
 
-pub fn initialize_stack(stack_ptr: Volatile<usize>, code: fn(&mut Args), args: &Box<Args>) -> usize {
-    unsafe {
-        __initialize_stack(stack_ptr.as_ptr() as usize, code as usize, &**args as *const _ as usize)
+/// Write the initial frame for a new task to the top of `stack`, returning the task's initial
+/// stack pointer.
+///
+/// Returns `None` without calling into the port if `stack` is shorter than `MIN_STACK_WORDS`.
+///
+/// # Panics
+///
+/// This panics if the port wrote a frame larger than `MIN_STACK_WORDS`.
+pub fn initialize_stack(stack: &mut [usize], code: fn(&mut Args), arg: usize) -> Option<usize> {
+    if stack.len() < MIN_STACK_WORDS {
+        return None;
     }
+    let word = ::core::mem::size_of::<usize>();
+    let top = stack.as_ptr() as usize + stack.len() * word;
+    let ptr = unsafe { __initialize_stack(top, code as usize, arg) };
+    if ptr > top || (top - ptr) / word > MIN_STACK_WORDS {
+        kernel_panic!("initialize_stack - the port's frame doesn't fit in MIN_STACK_WORDS!");
+    }
+    Some(ptr)
 }
 
 pub fn start_first_task() {
     unsafe { __start_first_task() };
 }
 
+pub fn enter_low_power(mode: LowPowerMode) {
+    unsafe { __enter_low_power(mode) };
+}
+
+pub fn enable_cycle_counter() -> bool {
+    unsafe { __enable_cycle_counter() }
+}
+
+pub fn read_cycle_counter() -> usize {
+    unsafe { __read_cycle_counter() }
+}
+
 pub fn in_kernel_mode() -> bool {
     unsafe { __in_kernel_mode() }
 }
 
-pub fn begin_critical() -> usize {
+/// Check if the processor is currently handling an exception.
+pub fn in_interrupt() -> bool {
+    unsafe { __in_interrupt() }
+}
+
+#[cfg(feature="smp")]
+pub fn current_core() -> usize {
+    unsafe { __current_core() }
+}
+
+#[cfg(feature="smp")]
+pub fn start_core(core: usize, entry: fn()) {
+    unsafe { __start_core(core, entry as usize) };
+}
+
+#[cfg(feature="smp")]
+pub fn interrupt_core(core: usize) {
+    unsafe { __interrupt_core(core) };
+}
+
+pub(crate) fn begin_critical() -> usize {
     unsafe { __begin_critical() }
 }
 
-pub fn end_critical(mask: usize) {
+pub(crate) fn end_critical(mask: usize) {
     unsafe { __end_critical(mask) };
 }
 
+pub(crate) fn begin_critical_bounded(priority: u8) -> usize {
+    unsafe { __begin_critical_bounded(priority) }
+}
+
+pub(crate) fn end_critical_bounded(mask: usize) {
+    unsafe { __end_critical_bounded(mask) };
+}
+
 pub fn syscall0(call: u32) -> usize {
     unsafe { __syscall0(call) }
 }
@@ -99,6 +230,10 @@ pub fn syscall1(call: u32, arg1: usize) -> usize {
     unsafe { __syscall1(call, arg1) }
 }
 
+pub fn syscall1_wide(call: u32, arg1: usize) -> (usize, usize) {
+    unsafe { __syscall1_wide(call, arg1) }
+}
+
 pub fn syscall2(call: u32, arg1: usize, arg2: usize) -> usize {
     unsafe { __syscall2(call, arg1, arg2) }
 }
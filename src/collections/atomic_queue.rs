@@ -16,8 +16,7 @@
 */
 
 //! A synchronized wrapper around the Queue struct.
-use collections::{Queue, Node};
-use alloc::boxed::Box;
+use collections::{Queue, NodeBox};
 use sync::{SpinMutex, SpinGuard};
 
 /// A queue that is wrapped in a mutex lock.
@@ -25,14 +24,14 @@ use sync::{SpinMutex, SpinGuard};
 /// This collection is meant for use in the kernel, so it is implemented with a spin lock rather
 /// than a mutex lock. This type should not be used outside of the kernel. The `SyncQueue` just
 /// acts as a wrapper around the `Queue` collection, with all of its methods behaving the same way.
-pub struct SyncQueue<T> {
+pub struct SyncQueue<T: 'static> {
     lock: SpinMutex<Queue<T>>,
 }
 
-unsafe impl<T: Send> Sync for SyncQueue<T> {}
-unsafe impl<T: Send> Send for SyncQueue<T> {}
+unsafe impl<T: Send + 'static> Sync for SyncQueue<T> {}
+unsafe impl<T: Send + 'static> Send for SyncQueue<T> {}
 
-impl<T> SyncQueue<T> {
+impl<T: 'static> SyncQueue<T> {
     /// Creates a new `SyncQueue` with an empty queue.
     pub const fn new() -> Self {
         SyncQueue { lock: SpinMutex::new(Queue::new()) }
@@ -44,17 +43,31 @@ impl<T> SyncQueue<T> {
     }
 
     /// Places an item onto the back of the queue.
-    pub fn enqueue(&self, elem: Box<Node<T>>) {
+    pub fn enqueue(&self, elem: NodeBox<T>) {
         let mut queue = self.lock();
         queue.enqueue(elem);
     }
 
     /// Takes an item off of the front of the queue.
-    pub fn dequeue(&self) -> Option<Box<Node<T>>> {
+    pub fn dequeue(&self) -> Option<NodeBox<T>> {
         let mut queue = self.lock();
         queue.dequeue()
     }
 
+    /// Runs `block` on the item at the front of the queue without removing it.
+    ///
+    /// A reference to the item can't outlive the lock on the queue, so instead of returning one
+    /// the item is inspected inside of `block`. Returns `None` if the queue is empty.
+    pub fn try_peek<R, F: FnOnce(&T) -> R>(&self, block: F) -> Option<R> {
+        let queue = self.lock();
+        queue.peek().map(block)
+    }
+
+    /// Returns a copy of the item at the front of the queue without removing it.
+    pub fn peek_copy(&self) -> Option<T> where T: Copy {
+        self.try_peek(|item| *item)
+    }
+
     /// Removes all items from the queue matching `predicate`.
     pub fn remove<F: Fn(&T) -> bool>(&self, predicate: F) -> Queue<T> {
         let mut queue = self.lock();
@@ -94,7 +107,7 @@ impl<T> SyncQueue<T> {
     }
 }
 
-impl<T> Default for SyncQueue<T> {
+impl<T: 'static> Default for SyncQueue<T> {
     /// Creates an empty `SyncQueue`.
     fn default() -> Self {
         SyncQueue::new()
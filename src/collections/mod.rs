@@ -16,20 +16,29 @@
 */
 
 //! Implementations for different collections used throughout the kernel.
+//!
+//! Elements are moved in and out of the collections in a `NodeBox` rather than a `Box<Node<T>>`,
+//! so the kernel can keep tasks whose memory is static in the same queues as allocated ones. This
+//! changed the signatures of `enqueue`, `dequeue`, `insert` and `pop` on every collection, code
+//! that built its nodes with `Box::new(Node::new(x))` can pass `NodeBox::from` that box, or use
+//! `NodeBox::new(x)`. The elements of every collection now also have to be `'static`.
 
 mod queue;
 mod atomic_queue;
 mod sorted_list;
 mod ringbuffer;
+mod timer_wheel;
 
 pub use self::queue::*;
 pub use self::atomic_queue::*;
 pub use self::sorted_list::*;
 pub use self::ringbuffer::*;
+pub use self::timer_wheel::*;
 pub use core_collections::*;
 
 use alloc::boxed::Box;
 use core::ops::{Deref, DerefMut};
+use core::ptr;
 
 /// A wrapper struct that is used in AltOS-Rust collections.
 ///
@@ -37,12 +46,13 @@ use core::ops::{Deref, DerefMut};
 /// without doing reallocations. This is because of tight memory constraints, so it's best to avoid
 /// reallocating an object if possible.
 #[repr(C)]
-pub struct Node<T> {
+pub struct Node<T: 'static> {
     data: T,
-    next: Option<Box<Node<T>>>,
+    next: Option<NodeBox<T>>,
+    in_static: bool,
 }
 
-impl<T> Node<T> {
+impl<T: 'static> Node<T> {
     /// Creates a new `Node<T>` wrapping an object of type `T`.
     ///
     /// # Examples
@@ -57,11 +67,12 @@ impl<T> Node<T> {
         Node {
             data: data,
             next: None,
+            in_static: false,
         }
     }
 }
 
-impl<T> Deref for Node<T> {
+impl<T: 'static> Deref for Node<T> {
     type Target = T;
 
     /// Gives a reference to the wrapped data.
@@ -70,9 +81,86 @@ impl<T> Deref for Node<T> {
     }
 }
 
-impl<T> DerefMut for Node<T> {
+impl<T: 'static> DerefMut for Node<T> {
     /// Gives a mutable reference to the wrapped data.
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.data
     }
 }
+
+/// An owning pointer to a `Node`, this is what the collections hand nodes around in.
+///
+/// A `NodeBox` works like a `Box<Node<T>>`, except that the node it owns may also live in static
+/// memory. Dropping a static node drops its data where it is, its memory is never handed to the
+/// heap. Like a `Box` it's a single non-null pointer, so an `Option<NodeBox<T>>` is one word.
+pub struct NodeBox<T: 'static> {
+    node: &'static mut Node<T>,
+}
+
+impl<T: 'static> NodeBox<T> {
+    /// Allocates a new node wrapping `data` on the heap.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::collections::NodeBox;
+    ///
+    /// let node = NodeBox::new(0usize);
+    /// assert_eq!(**node, 0);
+    /// ```
+    pub fn new(data: T) -> Self {
+        NodeBox::from(Box::new(Node::new(data)))
+    }
+
+    /// Takes ownership of a node in static memory.
+    ///
+    /// # Safety
+    ///
+    /// The node is dropped in place once the `NodeBox` is dropped, so the memory it's in must never
+    /// be read, written or dropped through anything else afterwards.
+    pub unsafe fn from_static(node: &'static mut Node<T>) -> Self {
+        node.in_static = true;
+        NodeBox { node: node }
+    }
+
+    /// Returns true if the node lives in static memory rather than on the heap.
+    pub fn is_static(&self) -> bool {
+        self.node.in_static
+    }
+}
+
+impl<T: 'static> From<Box<Node<T>>> for NodeBox<T> {
+    fn from(node: Box<Node<T>>) -> Self {
+        // UNSAFE: The box is leaked here and only ever freed again in `drop`
+        NodeBox { node: unsafe { &mut *Box::into_raw(node) } }
+    }
+}
+
+impl<T: 'static> Deref for NodeBox<T> {
+    type Target = Node<T>;
+
+    fn deref(&self) -> &Node<T> {
+        &*self.node
+    }
+}
+
+impl<T: 'static> DerefMut for NodeBox<T> {
+    fn deref_mut(&mut self) -> &mut Node<T> {
+        &mut *self.node
+    }
+}
+
+impl<T: 'static> Drop for NodeBox<T> {
+    fn drop(&mut self) {
+        let node: *mut Node<T> = &mut *self.node;
+        // UNSAFE: The node is owned by this and dropped once, only heap nodes are deallocated
+        unsafe {
+            if (*node).in_static {
+                ptr::drop_in_place(node);
+            }
+            else {
+                drop(Box::from_raw(node));
+            }
+        }
+    }
+}
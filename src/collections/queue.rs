@@ -17,16 +17,15 @@
 
 //! A collection of items that can be accessed through a FIFO interface.
 
-use alloc::boxed::Box;
-use super::Node;
+use super::{Node, NodeBox};
 
 /// A collection that provides FIFO queue functionality.
-pub struct Queue<T> {
-    head: Option<Box<Node<T>>>,
+pub struct Queue<T: 'static> {
+    head: Option<NodeBox<T>>,
     tail: *mut Node<T>,
 }
 
-impl<T> Queue<T> {
+impl<T: 'static> Queue<T> {
     /// Creates an empty `Queue`.
     ///
     /// # Examples
@@ -50,14 +49,13 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue = Queue::new();
     ///
-    /// queue.enqueue(Box::new(Node::new(0)));
+    /// queue.enqueue(NodeBox::new(0));
     /// ```
-    pub fn enqueue(&mut self, elem: Box<Node<T>>) {
+    pub fn enqueue(&mut self, elem: NodeBox<T>) {
         let mut new_tail = elem;
         // Probably not necessary...
         new_tail.next = None;
@@ -85,17 +83,16 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue = Queue::new();
     ///
-    /// queue.enqueue(Box::new(Node::new(0)));
+    /// queue.enqueue(NodeBox::new(0));
     ///
     /// assert!(queue.dequeue().is_some());
     /// assert!(queue.dequeue().is_none());
     /// ```
-    pub fn dequeue(&mut self) -> Option<Box<Node<T>>> {
+    pub fn dequeue(&mut self) -> Option<NodeBox<T>> {
         self.head.take().map(|mut head| {
             self.head = head.next.take();
             if self.head.is_none() {
@@ -105,6 +102,31 @@ impl<T> Queue<T> {
         })
     }
 
+    /// Returns a reference to the item at the front of the queue without removing it. If there
+    /// are no items in the queue, it returns None.
+    ///
+    /// The queue stays borrowed for as long as the reference is held, so the item can't be
+    /// dequeued out from under it.
+    ///
+    /// O(1) algorithmic time
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::collections::{NodeBox, Queue};
+    ///
+    /// let mut queue = Queue::new();
+    ///
+    /// queue.enqueue(NodeBox::new(0));
+    ///
+    /// assert_eq!(queue.peek(), Some(&0));
+    /// assert!(queue.dequeue().is_some());
+    /// assert!(queue.peek().is_none());
+    /// ```
+    pub fn peek(&self) -> Option<&T> {
+        self.head.as_ref().map(|head| &head.data)
+    }
+
     /// Removes all elements matching `predicate` and returns them in a new queue.
     ///
     /// O(n) algorithmic time
@@ -112,13 +134,12 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue = Queue::new();
     ///
-    /// queue.enqueue(Box::new(Node::new(0)));
-    /// queue.enqueue(Box::new(Node::new(1)));
+    /// queue.enqueue(NodeBox::new(0));
+    /// queue.enqueue(NodeBox::new(1));
     ///
     /// let removed = queue.remove(|n| *n == 0);
     ///
@@ -150,14 +171,13 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue1 = Queue::new();
     /// let mut queue2 = Queue::new();
     ///
-    /// queue1.enqueue(Box::new(Node::new(0)));
-    /// queue2.enqueue(Box::new(Node::new(1)));
+    /// queue1.enqueue(NodeBox::new(0));
+    /// queue2.enqueue(NodeBox::new(1));
     ///
     /// queue1.append(queue2);
     ///
@@ -186,13 +206,12 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue = Queue::new();
     ///
-    /// queue.enqueue(Box::new(Node::new(0)));
-    /// queue.enqueue(Box::new(Node::new(1)));
+    /// queue.enqueue(NodeBox::new(0));
+    /// queue.enqueue(NodeBox::new(1));
     ///
     /// queue.modify_all(|n| *n = *n + 1);
     /// ```
@@ -212,13 +231,12 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue = Queue::new();
     ///
-    /// queue.enqueue(Box::new(Node::new(0)));
-    /// queue.enqueue(Box::new(Node::new(1)));
+    /// queue.enqueue(NodeBox::new(0));
+    /// queue.enqueue(NodeBox::new(1));
     ///
     /// let removed = queue.remove_all();
     ///
@@ -251,13 +269,12 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue = Queue::new();
-    /// queue.enqueue(Box::new(Node::new(1)));
-    /// queue.enqueue(Box::new(Node::new(2)));
-    /// queue.enqueue(Box::new(Node::new(3)));
+    /// queue.enqueue(NodeBox::new(1));
+    /// queue.enqueue(NodeBox::new(2));
+    /// queue.enqueue(NodeBox::new(3));
     ///
     /// let mut iter = queue.iter();
     /// assert_eq!(iter.next(), Some(&1));
@@ -274,13 +291,12 @@ impl<T> Queue<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, Queue};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, Queue};
     ///
     /// let mut queue = Queue::new();
-    /// queue.enqueue(Box::new(Node::new(1)));
-    /// queue.enqueue(Box::new(Node::new(2)));
-    /// queue.enqueue(Box::new(Node::new(3)));
+    /// queue.enqueue(NodeBox::new(1));
+    /// queue.enqueue(NodeBox::new(2));
+    /// queue.enqueue(NodeBox::new(3));
     ///
     /// let mut iter = queue.iter_mut();
     /// assert_eq!(iter.next(), Some(&mut 1));
@@ -293,8 +309,8 @@ impl<T> Queue<T> {
     }
 }
 
-impl<T> IntoIterator for Queue<T> {
-    type Item = Box<Node<T>>;
+impl<T: 'static> IntoIterator for Queue<T> {
+    type Item = NodeBox<T>;
     type IntoIter = IntoIter<T>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -302,7 +318,7 @@ impl<T> IntoIterator for Queue<T> {
     }
 }
 
-impl<T> Drop for Queue<T> {
+impl<T: 'static> Drop for Queue<T> {
     fn drop(&mut self) {
         // Drop the queue in an iterative fashion to avoid recursive drop calls
         let mut current = self.head.take();
@@ -314,21 +330,21 @@ impl<T> Drop for Queue<T> {
 }
 
 /// An iterator over `Queue` that consumes the values in the collection.
-pub struct IntoIter<T>(Queue<T>);
+pub struct IntoIter<T: 'static>(Queue<T>);
 
-impl<T> Iterator for IntoIter<T> {
-    type Item = Box<Node<T>>;
+impl<T: 'static> Iterator for IntoIter<T> {
+    type Item = NodeBox<T>;
     fn next(&mut self) -> Option<Self::Item> {
         self.0.dequeue()
     }
 }
 
 /// An iterator over `Queue` that holds references to the values in the collection.
-pub struct Iter<'a, T: 'a> {
+pub struct Iter<'a, T: 'static> {
     next: Option<&'a Node<T>>,
 }
 
-impl<'a, T> Iterator for Iter<'a, T> {
+impl<'a, T: 'static> Iterator for Iter<'a, T> {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
         self.next.map(|node| {
@@ -339,11 +355,11 @@ impl<'a, T> Iterator for Iter<'a, T> {
 }
 
 /// An iterator over `Queue` that holds mutable references to the values in the collection.
-pub struct IterMut<'a, T: 'a> {
+pub struct IterMut<'a, T: 'static> {
     next: Option<&'a mut Node<T>>,
 }
 
-impl<'a, T> Iterator for IterMut<'a, T> {
+impl<'a, T: 'static> Iterator for IterMut<'a, T> {
     type Item = &'a mut T;
     fn next(&mut self) -> Option<Self::Item> {
         self.next.take().map(|node| {
@@ -356,8 +372,18 @@ impl<'a, T> Iterator for IterMut<'a, T> {
 #[cfg(test)]
 mod tests {
     use super::Queue;
-    use super::super::Node;
-    use alloc::boxed::Box;
+    use super::super::{Node, NodeBox};
+    use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    static DROPPED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    struct CountDrops;
+
+    impl Drop for CountDrops {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
 
     #[test]
     #[allow(non_snake_case)]
@@ -372,17 +398,17 @@ mod tests {
         let mut list = Queue::new();
 
         // Populate list
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
 
         // Check normal removal
         assert_eq!(list.dequeue().map(|n| n.data), Some(1));
         assert_eq!(list.dequeue().map(|n| n.data), Some(2));
 
         // Push some more just to make sure nothing's corrupted
-        list.enqueue(Box::new(Node::new(4)));
-        list.enqueue(Box::new(Node::new(5)));
+        list.enqueue(NodeBox::new(4));
+        list.enqueue(NodeBox::new(5));
 
         // Check normal removal
         assert_eq!(list.dequeue().map(|n| n.data), Some(3));
@@ -393,8 +419,8 @@ mod tests {
         assert!(list.dequeue().is_none());
 
         // Check the exhaustion case fixed the pointer right
-        list.enqueue(Box::new(Node::new(6)));
-        list.enqueue(Box::new(Node::new(7)));
+        list.enqueue(NodeBox::new(6));
+        list.enqueue(NodeBox::new(7));
 
         // Check normal removal
         assert_eq!(list.dequeue().map(|n| n.data), Some(6));
@@ -402,16 +428,35 @@ mod tests {
         assert!(list.dequeue().is_none());
     }
 
+    #[test]
+    fn test_peek_does_not_remove_item() {
+        let mut list = Queue::new();
+        assert!(list.peek().is_none());
+
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+
+        assert_eq!(list.peek(), Some(&1));
+        assert_eq!(list.peek(), Some(&1));
+        assert_eq!(list.iter().count(), 2);
+
+        // Peeking returns the same item that gets dequeued next
+        assert_eq!(list.dequeue().map(|n| n.data), Some(1));
+        assert_eq!(list.peek(), Some(&2));
+        assert_eq!(list.dequeue().map(|n| n.data), Some(2));
+        assert!(list.peek().is_none());
+    }
+
     #[test]
     fn test_remove_predicate_removes_matching_values() {
         let mut list = Queue::new();
 
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
 
         let predicate = |data: &usize| *data == 1;
 
@@ -432,10 +477,10 @@ mod tests {
         let mut list1 = Queue::new();
         let mut list2 = Queue::new();
 
-        list1.enqueue(Box::new(Node::new(1)));
-        list1.enqueue(Box::new(Node::new(2)));
-        list2.enqueue(Box::new(Node::new(3)));
-        list2.enqueue(Box::new(Node::new(4)));
+        list1.enqueue(NodeBox::new(1));
+        list1.enqueue(NodeBox::new(2));
+        list2.enqueue(NodeBox::new(3));
+        list2.enqueue(NodeBox::new(4));
 
         list1.append(list2);
 
@@ -452,8 +497,8 @@ mod tests {
         let mut list1 = Queue::new();
         let list2 = Queue::new();
 
-        list1.enqueue(Box::new(Node::new(1)));
-        list1.enqueue(Box::new(Node::new(2)));
+        list1.enqueue(NodeBox::new(1));
+        list1.enqueue(NodeBox::new(2));
 
         list1.append(list2);
 
@@ -471,8 +516,8 @@ mod tests {
         let list2 = Queue::new();
         let list3 = Queue::new();
 
-        list1.enqueue(Box::new(Node::new(1)));
-        list1.enqueue(Box::new(Node::new(2)));
+        list1.enqueue(NodeBox::new(1));
+        list1.enqueue(NodeBox::new(2));
 
         list1.append(list2);
         list1.append(list3);
@@ -488,9 +533,9 @@ mod tests {
     fn test_modify_all_elements_of_queue() {
         let mut list = Queue::new();
 
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
 
         list.modify_all(|data: &mut usize| *data *= 10);
 
@@ -505,9 +550,9 @@ mod tests {
     fn test_remove_all_removes_all_and_returns_contents_of_old_queue() {
         let mut list = Queue::new();
 
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
 
         let mut old = list.remove_all();
 
@@ -525,8 +570,8 @@ mod tests {
 
         assert!(list.is_empty());
 
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
 
         assert!(!list.is_empty());
 
@@ -541,9 +586,9 @@ mod tests {
     #[test]
     fn test_into_iter_takes_ownership_and_iterates_queue() {
         let mut list = Queue::new();
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
 
         let mut iter = list.into_iter();
         assert_eq!(iter.next().map(|n| n.data), Some(1));
@@ -555,9 +600,9 @@ mod tests {
     #[test]
     fn test_iter_iterates_over_queue() {
         let mut list = Queue::new();
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
 
         {
             let mut iter = list.iter();
@@ -576,9 +621,9 @@ mod tests {
     #[test]
     fn test_iter_mut_iterates_list_and_ensures_each_element_gives_a_mut_reference() {
         let mut list = Queue::new();
-        list.enqueue(Box::new(Node::new(1)));
-        list.enqueue(Box::new(Node::new(2)));
-        list.enqueue(Box::new(Node::new(3)));
+        list.enqueue(NodeBox::new(1));
+        list.enqueue(NodeBox::new(2));
+        list.enqueue(NodeBox::new(3));
 
         {
             let mut iter = list.iter_mut();
@@ -593,4 +638,24 @@ mod tests {
         assert_eq!(list.dequeue().map(|n| n.data), Some(3));
         assert!(list.dequeue().is_none());
     }
+
+    #[test]
+    fn test_dropping_queue_drops_static_nodes_in_place() {
+        static mut NODE: Option<Node<CountDrops>> = None;
+        let mut list = Queue::new();
+        list.enqueue(NodeBox::new(CountDrops));
+        // UNSAFE: This is the only test that uses the static node
+        unsafe {
+            NODE = Some(Node::new(CountDrops));
+            if let Some(ref mut node) = NODE {
+                let node = NodeBox::from_static(node);
+                assert!(node.is_static());
+                list.enqueue(node);
+            }
+        }
+
+        // The static node would crash the allocator if it were handed to it
+        drop(list);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+    }
 }
@@ -21,18 +21,17 @@
 //! of having the collection sorted is not worth the overhead, think of using the unsorted
 //! collections.
 
-use super::Node;
-use alloc::boxed::Box;
+use super::{Node, NodeBox};
 
 /// A list where every insertion is in sorted order.
 ///
 /// The list will ensure that every item inserted into it goes in its proper place. This requires
 /// that the generic type wrapped by the list is `PartialOrd` so the values can be compared.
-pub struct SortedList<T: PartialOrd> {
-    head: Option<Box<Node<T>>>,
+pub struct SortedList<T: PartialOrd + 'static> {
+    head: Option<NodeBox<T>>,
 }
 
-impl<T: PartialOrd> SortedList<T> {
+impl<T: PartialOrd + 'static> SortedList<T> {
     /// Creates an empty `SortedList`.
     ///
     /// # Examples
@@ -55,15 +54,14 @@ impl<T: PartialOrd> SortedList<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, SortedList};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, SortedList};
     ///
     /// let mut list = SortedList::new();
     ///
-    /// list.insert(Box::new(Node::new(1)));
-    /// list.insert(Box::new(Node::new(0)));
+    /// list.insert(NodeBox::new(1));
+    /// list.insert(NodeBox::new(0));
     /// ```
-    pub fn insert(&mut self, mut elem: Box<Node<T>>) {
+    pub fn insert(&mut self, mut elem: NodeBox<T>) {
         // self.head is a box, in a node, as a reference, so all layers need to be dereferenced.
         if self.head.is_none() || **elem <= ***self.head.as_ref().unwrap() {
             elem.next = self.head.take();
@@ -95,16 +93,15 @@ impl<T: PartialOrd> SortedList<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, SortedList};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, SortedList};
     ///
     /// let mut list = SortedList::new();
     ///
-    /// list.insert(Box::new(Node::new(0)));
+    /// list.insert(NodeBox::new(0));
     ///
     /// assert_eq!(list.pop().map(|n| **n), Some(0));
     /// ```
-    pub fn pop(&mut self) -> Option<Box<Node<T>>> {
+    pub fn pop(&mut self) -> Option<NodeBox<T>> {
         match self.head.take() {
             Some(mut head) => {
                 self.head = head.next.take();
@@ -121,13 +118,12 @@ impl<T: PartialOrd> SortedList<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, SortedList};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, SortedList};
     ///
     /// let mut list = SortedList::new();
     ///
-    /// list.insert(Box::new(Node::new(0)));
-    /// list.insert(Box::new(Node::new(1)));
+    /// list.insert(NodeBox::new(0));
+    /// list.insert(NodeBox::new(1));
     ///
     /// let removed = list.remove(|node| *node == 0);
     ///
@@ -159,14 +155,13 @@ impl<T: PartialOrd> SortedList<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, SortedList};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, SortedList};
     ///
     /// let mut list1 = SortedList::new();
     /// let mut list2 = SortedList::new();
     ///
-    /// list1.insert(Box::new(Node::new(0)));
-    /// list2.insert(Box::new(Node::new(1)));
+    /// list1.insert(NodeBox::new(0));
+    /// list2.insert(NodeBox::new(1));
     ///
     /// list1.merge(list2);
     /// ```
@@ -185,13 +180,12 @@ impl<T: PartialOrd> SortedList<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, SortedList};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, SortedList};
     ///
     /// let mut list = SortedList::new();
     ///
-    /// list.insert(Box::new(Node::new(0)));
-    /// list.insert(Box::new(Node::new(1)));
+    /// list.insert(NodeBox::new(0));
+    /// list.insert(NodeBox::new(1));
     ///
     /// let removed = list.remove_all();
     ///
@@ -225,13 +219,12 @@ impl<T: PartialOrd> SortedList<T> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// use altos_core::collections::{Node, SortedList};
-    /// use altos_core::alloc::boxed::Box;
+    /// use altos_core::collections::{NodeBox, SortedList};
     ///
     /// let mut list = SortedList::new();
-    /// list.insert(Box::new(Node::new(1)));
-    /// list.insert(Box::new(Node::new(2)));
-    /// list.insert(Box::new(Node::new(3)));
+    /// list.insert(NodeBox::new(1));
+    /// list.insert(NodeBox::new(2));
+    /// list.insert(NodeBox::new(3));
     ///
     /// let mut iter = list.iter();
     /// assert_eq!(iter.next(), Some(&1));
@@ -244,8 +237,8 @@ impl<T: PartialOrd> SortedList<T> {
     }
 }
 
-impl<T: PartialOrd> IntoIterator for SortedList<T> {
-    type Item = Box<Node<T>>;
+impl<T: PartialOrd + 'static> IntoIterator for SortedList<T> {
+    type Item = NodeBox<T>;
     type IntoIter = IntoIter<T>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -253,7 +246,7 @@ impl<T: PartialOrd> IntoIterator for SortedList<T> {
     }
 }
 
-impl<T: PartialOrd> Drop for SortedList<T> {
+impl<T: PartialOrd + 'static> Drop for SortedList<T> {
     fn drop(&mut self) {
         // Drop the queue in an iterative fashion to avoid recursive drop calls
         let mut current = self.head.take();
@@ -263,20 +256,20 @@ impl<T: PartialOrd> Drop for SortedList<T> {
     }
 }
 
-pub struct IntoIter<T: PartialOrd>(SortedList<T>);
+pub struct IntoIter<T: PartialOrd + 'static>(SortedList<T>);
 
-impl<T: PartialOrd> Iterator for IntoIter<T> {
-    type Item = Box<Node<T>>;
+impl<T: PartialOrd + 'static> Iterator for IntoIter<T> {
+    type Item = NodeBox<T>;
     fn next(&mut self) -> Option<Self::Item> {
         self.0.pop()
     }
 }
 
-pub struct Iter<'a, T: PartialOrd + 'a> {
+pub struct Iter<'a, T: PartialOrd + 'static> {
     next: Option<&'a Node<T>>,
 }
 
-impl<'a, T: PartialOrd> Iterator for Iter<'a, T> {
+impl<'a, T: PartialOrd + 'static> Iterator for Iter<'a, T> {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
         self.next.map(|node| {
@@ -289,17 +282,16 @@ impl<'a, T: PartialOrd> Iterator for Iter<'a, T> {
 #[cfg(test)]
 mod tests {
     use super::SortedList;
-    use super::super::Node;
-    use alloc::boxed::Box;
+    use super::super::NodeBox;
 
     #[test]
     fn test_list_properly_sorts_when_given_unsorted_data() {
         let mut list = SortedList::new();
 
-        list.insert(Box::new(Node::new(4)));
-        list.insert(Box::new(Node::new(1)));
-        list.insert(Box::new(Node::new(2)));
-        list.insert(Box::new(Node::new(3)));
+        list.insert(NodeBox::new(4));
+        list.insert(NodeBox::new(1));
+        list.insert(NodeBox::new(2));
+        list.insert(NodeBox::new(3));
 
         assert_eq!(list.pop().map(|n| n.data), Some(1));
         assert_eq!(list.pop().map(|n| n.data), Some(2));
@@ -312,10 +304,10 @@ mod tests {
     fn test_list_properly_sorts_when_given_unsorted_data_part2() {
         let mut list = SortedList::new();
 
-        list.insert(Box::new(Node::new(3)));
-        list.insert(Box::new(Node::new(4)));
-        list.insert(Box::new(Node::new(2)));
-        list.insert(Box::new(Node::new(1)));
+        list.insert(NodeBox::new(3));
+        list.insert(NodeBox::new(4));
+        list.insert(NodeBox::new(2));
+        list.insert(NodeBox::new(1));
 
         assert_eq!(list.pop().map(|n| n.data), Some(1));
         assert_eq!(list.pop().map(|n| n.data), Some(2));
@@ -328,10 +320,10 @@ mod tests {
     fn test_list_properly_sorts_when_given_reverse_sorted_data() {
         let mut list = SortedList::new();
 
-        list.insert(Box::new(Node::new(4)));
-        list.insert(Box::new(Node::new(3)));
-        list.insert(Box::new(Node::new(2)));
-        list.insert(Box::new(Node::new(1)));
+        list.insert(NodeBox::new(4));
+        list.insert(NodeBox::new(3));
+        list.insert(NodeBox::new(2));
+        list.insert(NodeBox::new(1));
 
         assert_eq!(list.pop().map(|n| n.data), Some(1));
         assert_eq!(list.pop().map(|n| n.data), Some(2));
@@ -344,10 +336,10 @@ mod tests {
     fn test_list_properly_sorts_when_given_already_sorted_data() {
         let mut list = SortedList::new();
 
-        list.insert(Box::new(Node::new(1)));
-        list.insert(Box::new(Node::new(2)));
-        list.insert(Box::new(Node::new(3)));
-        list.insert(Box::new(Node::new(4)));
+        list.insert(NodeBox::new(1));
+        list.insert(NodeBox::new(2));
+        list.insert(NodeBox::new(3));
+        list.insert(NodeBox::new(4));
 
         assert_eq!(list.pop().map(|n| n.data), Some(1));
         assert_eq!(list.pop().map(|n| n.data), Some(2));
@@ -361,10 +353,10 @@ mod tests {
         let mut list1 = SortedList::new();
         let mut list2 = SortedList::new();
 
-        list1.insert(Box::new(Node::new(4)));
-        list1.insert(Box::new(Node::new(3)));
-        list2.insert(Box::new(Node::new(2)));
-        list2.insert(Box::new(Node::new(1)));
+        list1.insert(NodeBox::new(4));
+        list1.insert(NodeBox::new(3));
+        list2.insert(NodeBox::new(2));
+        list2.insert(NodeBox::new(1));
 
         list1.merge(list2);
 
@@ -380,10 +372,10 @@ mod tests {
         let mut list1 = SortedList::new();
         let mut list2 = SortedList::new();
 
-        list1.insert(Box::new(Node::new(2)));
-        list1.insert(Box::new(Node::new(1)));
-        list2.insert(Box::new(Node::new(4)));
-        list2.insert(Box::new(Node::new(3)));
+        list1.insert(NodeBox::new(2));
+        list1.insert(NodeBox::new(1));
+        list2.insert(NodeBox::new(4));
+        list2.insert(NodeBox::new(3));
 
         list1.merge(list2);
 
@@ -397,9 +389,9 @@ mod tests {
     #[test]
     fn test_list_with_into_iter_takes_ownership_of_list_elements() {
         let mut list = SortedList::new();
-        list.insert(Box::new(Node::new(1)));
-        list.insert(Box::new(Node::new(2)));
-        list.insert(Box::new(Node::new(3)));
+        list.insert(NodeBox::new(1));
+        list.insert(NodeBox::new(2));
+        list.insert(NodeBox::new(3));
 
         let mut iter = list.into_iter();
         assert_eq!(iter.next().map(|n| n.data), Some(1));
@@ -411,9 +403,9 @@ mod tests {
     #[test]
     fn test_iter_correctly_iterates_through_list_elements() {
         let mut list = SortedList::new();
-        list.insert(Box::new(Node::new(1)));
-        list.insert(Box::new(Node::new(2)));
-        list.insert(Box::new(Node::new(3)));
+        list.insert(NodeBox::new(1));
+        list.insert(NodeBox::new(2));
+        list.insert(NodeBox::new(3));
 
         {
             let mut iter = list.iter();
@@ -0,0 +1,398 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A hierarchical timer wheel for items that expire at a given tick.
+//!
+//! The wheel keeps its items in slots by how far away they expire rather than in one list, so
+//! inserting an item and collecting the items that expire on a tick take the same time no matter
+//! how many items are waiting. The first level has a slot for each of the next `SLOTS` ticks, each
+//! level after it has a slot for each of the next `SLOTS` spans of the level below it. When a level
+//! comes back around to its first slot, the next slot up is emptied and its items are spread out
+//! over the levels below. Items that expire further away than the last level reaches wait in an
+//! overflow list that's spread out whenever the last level comes back around.
+
+use super::{NodeBox, Queue};
+use sync::{SpinMutex, SpinGuard};
+
+/// The number of slots on each level of a `TimerWheel`.
+pub const SLOTS: usize = 1 << SLOT_BITS;
+
+/// The number of levels in a `TimerWheel`.
+pub const LEVELS: usize = 4;
+
+const SLOT_BITS: usize = 4;
+const SLOT_MASK: usize = SLOTS - 1;
+
+// Jumps forward of at least this many ticks rebuild the wheel instead of stepping through them
+const REBUILD_AFTER: usize = 1 << (SLOT_BITS * 2);
+
+/// An item that can be kept in a `TimerWheel`.
+pub trait Expiring {
+    /// The tick the item expires on.
+    fn expires_at(&self) -> usize;
+}
+
+/// A collection of items that hands them back once they've expired.
+///
+/// The wheel keeps its own count of the current tick, which is moved forward with `advance`. Tick
+/// counts wrap around, an item expires when the current tick reaches its expiry tick no matter
+/// which side of the wrap either of them is on, so it can't wait more than `isize::MAX` ticks.
+pub struct TimerWheel<T: 'static> {
+    levels: [[Queue<T>; SLOTS]; LEVELS],
+    overflow: Queue<T>,
+    expired: Queue<T>,
+    now: usize,
+}
+
+// One empty queue for each of the `SLOTS` slots on a level
+macro_rules! empty_level {
+    () => {
+        [Queue::new(), Queue::new(), Queue::new(), Queue::new(),
+         Queue::new(), Queue::new(), Queue::new(), Queue::new(),
+         Queue::new(), Queue::new(), Queue::new(), Queue::new(),
+         Queue::new(), Queue::new(), Queue::new(), Queue::new()]
+    }
+}
+
+impl<T: 'static> TimerWheel<T> {
+    /// Creates an empty `TimerWheel` at tick 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::collections::{Expiring, TimerWheel};
+    ///
+    /// struct Timeout(usize);
+    ///
+    /// impl Expiring for Timeout {
+    ///     fn expires_at(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let wheel = TimerWheel::<Timeout>::new();
+    /// ```
+    pub const fn new() -> Self {
+        TimerWheel {
+            levels: [empty_level!(), empty_level!(), empty_level!(), empty_level!()],
+            overflow: Queue::new(),
+            expired: Queue::new(),
+            now: 0,
+        }
+    }
+}
+
+impl<T: Expiring + 'static> TimerWheel<T> {
+    /// The tick the wheel was last advanced to.
+    pub fn now(&self) -> usize {
+        self.now
+    }
+
+    /// Places an item into the wheel, an item that has already expired is handed back by the next
+    /// call to `advance`.
+    ///
+    /// O(1) algorithmic time
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::collections::{Expiring, NodeBox, TimerWheel};
+    ///
+    /// struct Timeout(usize);
+    ///
+    /// impl Expiring for Timeout {
+    ///     fn expires_at(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let mut wheel = TimerWheel::new();
+    ///
+    /// wheel.insert(NodeBox::new(Timeout(10)));
+    /// ```
+    pub fn insert(&mut self, elem: NodeBox<T>) {
+        let expires = elem.expires_at();
+        let distance = expires.wrapping_sub(self.now) as isize;
+        if distance <= 0 {
+            return self.expired.enqueue(elem);
+        }
+
+        let distance = distance as usize;
+        for level in 0..LEVELS {
+            if distance < 1 << (SLOT_BITS * (level + 1)) {
+                let slot = (expires >> (SLOT_BITS * level)) & SLOT_MASK;
+                return self.levels[level][slot].enqueue(elem);
+            }
+        }
+        self.overflow.enqueue(elem);
+    }
+
+    /// Moves the wheel forward to tick `now`, returning every item that has expired by then.
+    ///
+    /// Each tick moved through takes O(1) algorithmic time, a jump of many ticks at once (after the
+    /// tick has been stopped, for instance) is done by sorting every item in the wheel again.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::collections::{Expiring, NodeBox, TimerWheel};
+    ///
+    /// struct Timeout(usize);
+    ///
+    /// impl Expiring for Timeout {
+    ///     fn expires_at(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let mut wheel = TimerWheel::new();
+    ///
+    /// wheel.insert(NodeBox::new(Timeout(10)));
+    ///
+    /// assert!(wheel.advance(9).is_empty());
+    /// assert!(!wheel.advance(10).is_empty());
+    /// ```
+    pub fn advance(&mut self, now: usize) -> Queue<T> {
+        let elapsed = now.wrapping_sub(self.now);
+        if elapsed as isize >= REBUILD_AFTER as isize || (elapsed as isize) < 0 {
+            let all = self.remove_all();
+            self.now = now;
+            for elem in all {
+                self.insert(elem);
+            }
+        }
+        else {
+            for _ in 0..elapsed {
+                self.step();
+            }
+        }
+        self.expired.remove_all()
+    }
+
+    // Move the wheel forward by one tick.
+    fn step(&mut self) {
+        self.now = self.now.wrapping_add(1);
+        // Spread out the next slot of each level that's come back around, from the top down
+        let mut wrapped = 0;
+        while wrapped + 1 < LEVELS && (self.now >> (SLOT_BITS * wrapped)) & SLOT_MASK == 0 {
+            wrapped += 1;
+        }
+        if wrapped + 1 == LEVELS && (self.now >> (SLOT_BITS * wrapped)) & SLOT_MASK == 0 {
+            let overflow = self.overflow.remove_all();
+            self.reinsert(overflow);
+        }
+        for level in (1..wrapped + 1).rev() {
+            let slot = (self.now >> (SLOT_BITS * level)) & SLOT_MASK;
+            let cascaded = self.levels[level][slot].remove_all();
+            self.reinsert(cascaded);
+        }
+
+        let due = self.levels[0][self.now & SLOT_MASK].remove_all();
+        self.expired.append(due);
+    }
+
+    fn reinsert(&mut self, queue: Queue<T>) {
+        for elem in queue {
+            self.insert(elem);
+        }
+    }
+
+    /// Removes all items in the wheel matching `predicate`.
+    ///
+    /// O(n) algorithmic time
+    pub fn remove<F: Fn(&T) -> bool>(&mut self, predicate: F) -> Queue<T> {
+        let mut matching = self.expired.remove(&predicate);
+        for level in self.levels.iter_mut() {
+            for slot in level.iter_mut() {
+                matching.append(slot.remove(&predicate));
+            }
+        }
+        matching.append(self.overflow.remove(&predicate));
+        matching
+    }
+
+    /// Modifies all the items in the wheel with the block passed in.
+    ///
+    /// Nothing that changes when an item expires should be modified, the item isn't moved to the
+    /// slot for its new expiry.
+    ///
+    /// O(n) algorithmic time
+    pub fn modify_all<F: Fn(&mut T)>(&mut self, block: F) {
+        for item in self.expired.iter_mut() {
+            block(item);
+        }
+        for level in self.levels.iter_mut() {
+            for slot in level.iter_mut() {
+                for item in slot.iter_mut() {
+                    block(item);
+                }
+            }
+        }
+        for item in self.overflow.iter_mut() {
+            block(item);
+        }
+    }
+
+    /// Removes all the items from `self` and returns them in a `Queue`.
+    ///
+    /// O(1) algorithmic time for each slot
+    pub fn remove_all(&mut self) -> Queue<T> {
+        let mut all = self.expired.remove_all();
+        for level in self.levels.iter_mut() {
+            for slot in level.iter_mut() {
+                all.append(slot.remove_all());
+            }
+        }
+        all.append(self.overflow.remove_all());
+        all
+    }
+
+    /// Checks if the wheel is empty. Returns true if it is empty, false otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.expired.is_empty() && self.overflow.is_empty() &&
+            self.levels.iter().all(|level| level.iter().all(|slot| slot.is_empty()))
+    }
+}
+
+/// A `TimerWheel` that is wrapped in a spin lock, for use in the kernel the same way as `SyncQueue`.
+pub struct SyncTimerWheel<T: 'static> {
+    lock: SpinMutex<TimerWheel<T>>,
+}
+
+unsafe impl<T: Send + 'static> Sync for SyncTimerWheel<T> {}
+unsafe impl<T: Send + 'static> Send for SyncTimerWheel<T> {}
+
+impl<T: 'static> SyncTimerWheel<T> {
+    /// Creates a new `SyncTimerWheel` with an empty wheel.
+    pub const fn new() -> Self {
+        SyncTimerWheel { lock: SpinMutex::new(TimerWheel::new()) }
+    }
+}
+
+impl<T: Expiring + 'static> SyncTimerWheel<T> {
+    /// Places an item into the wheel.
+    pub fn insert(&self, elem: NodeBox<T>) {
+        self.lock().insert(elem);
+    }
+
+    /// Moves the wheel forward to tick `now`, returning every item that has expired by then.
+    pub fn advance(&self, now: usize) -> Queue<T> {
+        self.lock().advance(now)
+    }
+
+    /// Removes all items in the wheel matching `predicate`.
+    pub fn remove<F: Fn(&T) -> bool>(&self, predicate: F) -> Queue<T> {
+        self.lock().remove(predicate)
+    }
+
+    /// Modifies all items in the wheel with `block`.
+    pub fn modify_all<F: Fn(&mut T)>(&self, block: F) {
+        self.lock().modify_all(block);
+    }
+
+    /// Removes all items from the wheel and returns them in a `Queue`.
+    pub fn remove_all(&self) -> Queue<T> {
+        self.lock().remove_all()
+    }
+
+    /// Checks if the wheel is empty, returns true if it is, false otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> SpinGuard<TimerWheel<T>> {
+        self.lock.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collections::Vec;
+
+    struct Timeout(usize);
+
+    impl Expiring for Timeout {
+        fn expires_at(&self) -> usize { self.0 }
+    }
+
+    fn wheel_at(now: usize) -> TimerWheel<Timeout> {
+        let mut wheel = TimerWheel::new();
+        wheel.now = now;
+        wheel
+    }
+
+    fn expired(wheel: &mut TimerWheel<Timeout>, now: usize) -> Vec<usize> {
+        wheel.advance(now).into_iter().map(|node| node.0).collect()
+    }
+
+    #[test]
+    fn test_items_expire_on_their_tick() {
+        let mut wheel = wheel_at(0);
+        for &expires in [3, 17, 300, 5000, 70000].iter() {
+            wheel.insert(NodeBox::new(Timeout(expires)));
+        }
+
+        let mut seen = Vec::new();
+        for tick in 1..70001 {
+            for expires in expired(&mut wheel, tick) {
+                assert_eq!(expires, tick);
+                seen.push(expires);
+            }
+        }
+        assert_eq!(seen, vec![3, 17, 300, 5000, 70000]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn test_items_expire_across_wrap() {
+        let mut wheel = wheel_at(!0 - 5);
+        wheel.insert(NodeBox::new(Timeout(10)));
+        wheel.insert(NodeBox::new(Timeout(!0 - 2)));
+
+        assert_eq!(expired(&mut wheel, !0 - 2), vec![!0 - 2]);
+        assert!(expired(&mut wheel, 9).is_empty());
+        assert_eq!(expired(&mut wheel, 10), vec![10]);
+    }
+
+    #[test]
+    fn test_long_jump_expires_everything_due() {
+        let mut wheel = wheel_at(100);
+        wheel.insert(NodeBox::new(Timeout(150)));
+        wheel.insert(NodeBox::new(Timeout(100 + REBUILD_AFTER * 2)));
+        wheel.insert(NodeBox::new(Timeout(100 + REBUILD_AFTER * 4)));
+
+        let mut due = expired(&mut wheel, 100 + REBUILD_AFTER * 3);
+        due.sort();
+        assert_eq!(due, vec![150, 100 + REBUILD_AFTER * 2]);
+        assert_eq!(expired(&mut wheel, 100 + REBUILD_AFTER * 4), vec![100 + REBUILD_AFTER * 4]);
+    }
+
+    #[test]
+    fn test_expired_item_handed_back_on_next_advance() {
+        let mut wheel = wheel_at(50);
+        wheel.insert(NodeBox::new(Timeout(40)));
+
+        assert_eq!(expired(&mut wheel, 50), vec![40]);
+    }
+
+    #[test]
+    fn test_remove_matching() {
+        let mut wheel = wheel_at(0);
+        for &expires in [3, 17, 300, 70000].iter() {
+            wheel.insert(NodeBox::new(Timeout(expires)));
+        }
+
+        let removed = wheel.remove(|item| item.0 > 10);
+        assert_eq!(removed.into_iter().count(), 3);
+        assert_eq!(wheel.remove_all().into_iter().count(), 1);
+    }
+}
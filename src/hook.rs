@@ -0,0 +1,90 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Slots for the functions an application hooks into the kernel.
+//!
+//! Every function the kernel calls out to (on a tick, a panic, a missed deadline...) is kept in a
+//! `Hook`. A hook is a single atomic word holding the address of the function, so it can be set,
+//! cleared and called from anywhere, including interrupt handlers, without taking a lock.
+
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use core::marker::PhantomData;
+use core::mem;
+
+// A function pointer of type `F` that may or may not be set, `F` must be a function pointer type
+// like `fn(u64)`.
+pub struct Hook<F> {
+    // The address of the function, or 0 if there isn't one
+    addr: AtomicUsize,
+    hook: PhantomData<F>,
+}
+
+impl<F> Hook<F> {
+    pub const fn new() -> Self {
+        Hook {
+            addr: ATOMIC_USIZE_INIT,
+            hook: PhantomData,
+        }
+    }
+
+    pub fn clear(&self) {
+        self.addr.store(0, Ordering::Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.addr.load(Ordering::Relaxed) != 0
+    }
+}
+
+impl<F: Copy> Hook<F> {
+    // Set the function, replacing the old one.
+    pub fn set(&self, hook: F) {
+        debug_assert_eq!(mem::size_of::<F>(), mem::size_of::<usize>());
+        // UNSAFE: `F` is a function pointer, which is a single word
+        self.addr.store(unsafe { mem::transmute_copy(&hook) }, Ordering::Relaxed);
+    }
+
+    // Get the function, if one is set.
+    pub fn get(&self) -> Option<F> {
+        match self.addr.load(Ordering::Relaxed) {
+            0 => None,
+            // UNSAFE: The only non-zero values ever stored are `F` pointers
+            addr => Some(unsafe { mem::transmute_copy(&addr) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn double(x: usize) -> usize { x * 2 }
+
+    #[test]
+    fn test_hook_set_get_and_clear() {
+        let hook: Hook<fn(usize) -> usize> = Hook::new();
+        assert!(hook.get().is_none());
+        assert_not!(hook.is_set());
+
+        hook.set(double);
+        assert!(hook.is_set());
+        assert_eq!(hook.get().map(|hook| hook(4)), Some(8));
+
+        hook.clear();
+        assert!(hook.get().is_none());
+    }
+}
@@ -29,6 +29,8 @@
 #![feature(cfg_target_has_atomic)]
 #![feature(heap_api)]
 #![feature(oom)]
+#![feature(linkage)]
+#![feature(core_intrinsics)]
 #![warn(missing_docs)]
 #![deny(trivial_numeric_casts)]
 #![no_std]
@@ -58,6 +60,10 @@ pub extern crate volatile_ptr as volatile;
 #[macro_use]
 mod test;
 
+// This has to come before `arch`, the backends use `kernel_panic!`
+#[macro_use]
+pub mod panic;
+
 #[cfg(all(not(test), target_arch="arm", feature="cm0"))]
 #[path = "arch/cm0.rs"]
 mod arch;
@@ -70,16 +76,66 @@ mod arch;
 #[path = "arch/unknown.rs"]
 mod arch;
 
+mod hook;
 pub mod tick;
+#[cfg(feature="trace")]
+pub mod trace;
+#[cfg(feature="logger")]
+pub mod logger;
 pub mod syscall;
 mod task;
 mod sched;
 pub mod sync;
 pub mod collections;
 pub mod init;
+pub mod power;
+#[cfg(feature="stats")]
+pub mod stats;
 
 #[cfg(target_has_atomic="ptr")]
 pub use core::sync::atomic as atomic;
-pub use task::{TaskHandle, Priority};
+pub use task::{TaskHandle, Priority, State, SpawnError};
+pub use task::{TaskInfo, with_task_snapshot, find_task};
+pub use task::remaining_sleep_ticks;
+pub use task::{WaitReason, wait_reason};
+pub use task::{TaskStatus, RestartError};
+pub use task::cpu_usage;
+pub use task::{TaskBuilder, DEFAULT_STACK_DEPTH};
+pub use task::TaskGroup;
+pub use task::spawn_periodic;
+pub use task::{TaskName, TASK_NAME_CAPACITY};
+pub use task::{TaskId, MAX_TID};
+pub use task::{JoinError, at_exit};
+pub use task::{LocalKey, TASK_LOCAL_SLOTS};
+pub use task::{set_last_error, last_error};
+pub use task::{set_task_create_hook, set_task_delete_hook, clear_task_hooks};
+pub use task::init_stack_arena;
+pub use task::StaticTask;
+#[cfg(feature="signals")]
+pub use task::{NUM_SIGNALS, SignalHandler, InvalidSignal};
+#[cfg(feature="signals")]
+pub use task::{set_signal_handler, clear_signal_handler, deliver_signals};
 pub use sched::{CURRENT_TASK, switch_context, start_scheduler};
+pub use sched::{Policy, set_intra_priority_policy, intra_priority_policy};
+pub use sched::{set_time_slice, set_priority_time_slice, time_slice};
+pub use sched::{Scheduler, PriorityScheduler, install_scheduler};
+pub use sched::{pin, unpin};
+pub use sched::{ALL_CORES, NUM_CORES};
+pub use sched::{set_stack_overflow_handler, clear_stack_overflow_handler};
+pub use sched::{set_cooperative, is_cooperative};
+pub use sched::{SchedulerGuard, suspend_scheduler, is_scheduler_suspended};
+pub use sched::{cpu_load_percent, idle_ticks, reset_cpu_load, LOAD_WINDOW};
+pub use sched::edf;
+pub use sched::{add_idle_hook, set_idle_hook, clear_idle_hooks, TooManyIdleHooks, MAX_IDLE_HOOKS};
+pub use task::TaskControl;
 pub use task::args;
+pub use arch::in_interrupt;
+pub use arch::MIN_STACK_WORDS;
+#[cfg(feature="replay")]
+pub use sched::replay;
+#[cfg(feature="starvation")]
+pub use sched::starvation;
+#[cfg(feature="stack_sizing")]
+pub use task::stack_sizing;
+#[cfg(feature="smp")]
+pub use sched::smp;
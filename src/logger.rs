@@ -0,0 +1,212 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Structured kernel diagnostics.
+//!
+//! With the `logger` feature enabled the kernel reports the problems it detects to an installed
+//! `KernelLogger` as `KernelEvent` records instead of only as panic messages. The logger decides
+//! how the records get out of the device (RTT, a serial port, a log in flash...), so the kernel
+//! doesn't depend on any particular transport. Fatal problems still panic after they've been
+//! logged.
+//!
+//! Until a logger is installed the events are dropped by `NoopLogger`.
+
+use sync::CriticalSection;
+
+/// A problem detected by the kernel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KernelEvent {
+    /// A system call was made with a number that isn't handled.
+    InvalidSyscall(u32),
+
+    /// The task with this tid ran past the bottom of its stack.
+    StackOverflow {
+        /// The tid of the task that overflowed.
+        tid: usize,
+    },
+
+    /// The stack pointer saved for the task with this tid doesn't lie within its stack.
+    CorruptStackPointer {
+        /// The tid of the task.
+        tid: usize,
+        /// The saved stack pointer.
+        ptr: usize,
+    },
+
+    /// The task with this tid panicked and was killed, see `panic::isolate_task_panic`.
+    TaskPanicked {
+        /// The tid of the task that panicked.
+        tid: usize,
+    },
+
+    /// The task with this tid ended having used more of its stack than the stack sizing warning
+    /// threshold allows, see `stack_sizing::set_warning_threshold`.
+    StackUsageHigh {
+        /// The tid of the task.
+        tid: usize,
+        /// The most bytes of its stack the task had in use at once.
+        peak: usize,
+        /// The stack size in bytes the task was spawned with.
+        requested: usize,
+    },
+}
+
+/// A destination for kernel diagnostics.
+///
+/// The logger may be called from interrupt handlers and from inside the kernel with interrupts
+/// disabled, so it must not block or make system calls.
+pub trait KernelLogger: Sync {
+    /// Record an error detected by the kernel.
+    fn error(&self, event: KernelEvent);
+
+    /// Record something the kernel noticed that isn't an error but may need looking into.
+    ///
+    /// Warnings are dropped unless the logger overrides this.
+    fn warn(&self, _event: KernelEvent) {}
+}
+
+/// A logger that drops every event, this is used until another logger is installed.
+pub struct NoopLogger;
+
+impl KernelLogger for NoopLogger {
+    fn error(&self, _event: KernelEvent) {}
+}
+
+static NOOP_LOGGER: NoopLogger = NoopLogger;
+
+static mut LOGGER: &'static KernelLogger = &NOOP_LOGGER;
+
+/// Install the logger the kernel reports its diagnostics to.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::logger::{self, KernelLogger, KernelEvent};
+///
+/// struct RttLogger;
+///
+/// impl KernelLogger for RttLogger {
+///     fn error(&self, event: KernelEvent) {
+///         // Write `event` to the RTT up channel...
+///     }
+/// }
+///
+/// static LOGGER: RttLogger = RttLogger;
+///
+/// logger::set_logger(&LOGGER);
+/// ```
+pub fn set_logger(logger: &'static KernelLogger) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing can be using the logger while we swap it
+    unsafe { LOGGER = logger };
+}
+
+/// Go back to dropping kernel diagnostics.
+pub fn clear_logger() {
+    set_logger(&NOOP_LOGGER);
+}
+
+// Report `event` to the installed logger.
+#[doc(hidden)]
+pub fn error(event: KernelEvent) {
+    // UNSAFE: The logger is only changed inside of a critical section
+    let logger = unsafe { LOGGER };
+    logger.error(event);
+}
+
+// Report `event` to the installed logger as a warning.
+#[doc(hidden)]
+pub fn warn(event: KernelEvent) {
+    // UNSAFE: The logger is only changed inside of a critical section
+    let logger = unsafe { LOGGER };
+    logger.warn(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use arch;
+    use sched;
+    use sync::SpinMutex;
+    use std::panic;
+
+    const CAPACITY: usize = 4;
+
+    struct CapturingLogger {
+        events: SpinMutex<([Option<KernelEvent>; CAPACITY], usize)>,
+    }
+
+    impl CapturingLogger {
+        fn reset(&self) {
+            *self.events.lock() = ([None; CAPACITY], 0);
+        }
+
+        fn events(&self) -> [Option<KernelEvent>; CAPACITY] {
+            self.events.lock().0
+        }
+    }
+
+    impl KernelLogger for CapturingLogger {
+        fn error(&self, event: KernelEvent) {
+            let mut events = self.events.lock();
+            let len = events.1;
+            if len < CAPACITY {
+                events.0[len] = Some(event);
+                events.1 += 1;
+            }
+        }
+    }
+
+    static CAPTURE: CapturingLogger = CapturingLogger {
+        events: SpinMutex::new(([None; CAPACITY], 0)),
+    };
+
+    #[test]
+    fn test_invalid_syscall_is_logged() {
+        let _g = test::set_up();
+        CAPTURE.reset();
+        set_logger(&CAPTURE);
+
+        assert_eq!(arch::syscall0(31), ::syscall::SYS_ERR_INVALID);
+        assert_eq!(::syscall::sys_user_syscall(::syscall::SYS_USER_BASE + 5, 0, 0),
+                   ::syscall::SYS_ERR_INVALID);
+
+        assert_eq!(CAPTURE.events(), [Some(KernelEvent::InvalidSyscall(31)),
+                                      Some(KernelEvent::InvalidSyscall(::syscall::SYS_USER_BASE + 5)),
+                                      None, None]);
+    }
+
+    #[test]
+    fn test_stack_overflow_is_logged_before_panicking() {
+        let _g = test::set_up();
+        CAPTURE.reset();
+        set_logger(&CAPTURE);
+        test::create_two_tasks();
+        sched::start_scheduler();
+
+        // Simulate the running task's stack pointer having run off the bottom of its stack, the
+        // saved stack pointer is the first word of the control block.
+        let current = test::current_task().unwrap();
+        let tid = current.tid();
+        let (bottom, _) = current.stack_bounds();
+        unsafe { *(current as *mut _ as *mut usize) = bottom };
+
+        assert!(panic::catch_unwind(|| sched::switch_context()).is_err());
+        assert_eq!(CAPTURE.events()[0], Some(KernelEvent::StackOverflow { tid: tid }));
+    }
+}
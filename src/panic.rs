@@ -0,0 +1,267 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Kernel panic handling.
+//!
+//! Whenever the kernel finds one of its invariants broken it calls `kernel_panic`, which gives the
+//! application's panic hook a chance to report the failure before the regular panic machinery
+//! takes over.
+//!
+//! A panic in a task's own code doesn't have to bring the whole system down. With a task panic
+//! handler set (see `set_task_panic_handler`), the application's panic handler can call
+//! `isolate_task_panic` to kill only the task that panicked and keep the rest running.
+
+use core::fmt;
+use atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use hook::Hook;
+use task::Priority;
+use sync;
+use arch;
+
+/// The exit value left for a task joining a task that was killed for panicking.
+pub const PANIC_EXIT_VALUE: usize = !0;
+
+static PANIC_HOOK: Hook<fn(fmt::Arguments)> = Hook::new();
+// Panics are only isolated while this is set
+static TASK_PANIC_HANDLER: Hook<fn(usize, fmt::Arguments)> = Hook::new();
+// Set once the kernel itself has panicked, a broken kernel can't be recovered from
+static KERNEL_PANICKING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Panic with a formatted message, going through the kernel panic hook.
+macro_rules! kernel_panic {
+    ($($arg:tt)+) => {
+        $crate::panic::kernel_panic(format_args!($($arg)+))
+    };
+}
+
+/// Report a broken kernel invariant.
+///
+/// The panic hook, if one is set, is called with `msg` first. If the hook returns the kernel
+/// falls back to a regular `panic!` with the same message.
+pub fn kernel_panic(msg: fmt::Arguments) -> ! {
+    KERNEL_PANICKING.store(true, Ordering::Relaxed);
+    #[cfg(feature="trace")]
+    {
+        // UNSAFE: Only the tid is read
+        let tid = unsafe { ::sched::current_task() }.map(|task| task.tid());
+        ::trace::record(::trace::EventKind::Fault, tid);
+    }
+    if let Some(hook) = PANIC_HOOK.get() {
+        hook(msg);
+    }
+    panic!("{}", msg);
+}
+
+/// Set a function to be called whenever the kernel panics.
+///
+/// The hook is passed the panic message and may do whatever the application needs to make the
+/// failure visible in the field, like blinking an error code on an LED, logging it over a serial
+/// port or resetting the device. The kernel is in an unknown state when the hook runs, so it must
+/// not use any kernel services. Setting a new hook replaces the old one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::fmt;
+/// use altos_core::panic::set_panic_hook;
+///
+/// fn blink_and_reset(_msg: fmt::Arguments) {
+///     // Blink the error LED and reset...
+///     loop {}
+/// }
+///
+/// set_panic_hook(blink_and_reset);
+/// ```
+pub fn set_panic_hook(hook: fn(fmt::Arguments)) {
+    PANIC_HOOK.set(hook);
+}
+
+/// Remove the panic hook, if one was set.
+pub fn clear_panic_hook() {
+    PANIC_HOOK.clear();
+}
+
+/// Isolate panics to the task that panicked, reporting each one to `handler`.
+///
+/// Once a handler is set, `isolate_task_panic` kills a task that panics instead of letting the
+/// panic halt the system. The handler is passed the tid of the task and the panic message before
+/// the task is killed, so it can log the failure (or restart the task). It's called in the context
+/// of the task that panicked, so it must not block. Setting a new handler replaces the old one.
+pub fn set_task_panic_handler(handler: fn(usize, fmt::Arguments)) {
+    TASK_PANIC_HANDLER.set(handler);
+}
+
+/// Remove the task panic handler, every panic halts the system again.
+pub fn clear_task_panic_handler() {
+    TASK_PANIC_HANDLER.clear();
+}
+
+/// Try to recover from a panic by killing only the task that panicked.
+///
+/// This is meant to be called first thing in the application's panic handler. If a task panic
+/// handler is set and the panic can be pinned on a single task, the handler is called, the task is
+/// killed the same way as if it had called `exit_with(PANIC_EXIT_VALUE)` (so its exit handlers
+/// run) and the other tasks keep being scheduled, in that case this never returns.
+///
+/// A panic can't be isolated if the kernel itself panicked, if it happened in an interrupt handler
+/// or a critical section (kernel state may have been left half updated), before the scheduler was
+/// started, or in the idle task. Then this returns and the panic handler should halt the system as
+/// it normally would.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::fmt;
+/// use altos_core::panic::{set_task_panic_handler, isolate_task_panic};
+///
+/// fn log_task_panic(tid: usize, msg: fmt::Arguments) {
+///     // Write the tid and message to the serial port...
+/// }
+///
+/// set_task_panic_handler(log_task_panic);
+///
+/// // In the application's panic handler:
+/// # let msg = format_args!("");
+/// isolate_task_panic(msg);
+/// // The panic couldn't be isolated, halt...
+/// ```
+pub fn isolate_task_panic(msg: fmt::Arguments) {
+    if let (Some(handler), Some(tid)) = (TASK_PANIC_HANDLER.get(), isolatable_task()) {
+        handler(tid, msg);
+        #[cfg(feature="logger")]
+        ::logger::error(::logger::KernelEvent::TaskPanicked { tid: tid });
+        ::syscall::exit_with(PANIC_EXIT_VALUE);
+    }
+}
+
+// The tid of the task a panic can be isolated to, if it can be isolated.
+fn isolatable_task() -> Option<usize> {
+    if !TASK_PANIC_HANDLER.is_set() || KERNEL_PANICKING.load(Ordering::Relaxed) ||
+        arch::in_interrupt() || sync::in_critical_section() {
+        return None;
+    }
+    // UNSAFE: Only the priority and tid are read
+    match unsafe { ::sched::current_task() } {
+        Some(task) if task.priority() != Priority::__Idle => Some(task.tid()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub fn reset_task_panics() {
+    clear_task_panic_handler();
+    KERNEL_PANICKING.store(false, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+    use test;
+    use syscall;
+
+    fn reporting_hook(msg: fmt::Arguments) {
+        panic!("panic hook: {}", msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "panic hook: wake_reason - current task doesn't exist!")]
+    fn test_panic_hook_receives_message() {
+        let _g = test::set_up();
+        set_panic_hook(reporting_hook);
+
+        // The scheduler was never started, so there's no current task
+        syscall::sys_wake_reason();
+    }
+
+    fn ignore_task_panic(_tid: usize, _msg: fmt::Arguments) {}
+
+    #[test]
+    fn test_panic_isolated_to_running_task() {
+        let _g = test::set_up();
+        // The scheduler hasn't been started, so there's no task to pin the panic on
+        set_task_panic_handler(ignore_task_panic);
+        assert_eq!(isolatable_task(), None);
+
+        test::create_two_tasks();
+        ::sched::start_scheduler();
+        let tid = test::current_task().unwrap().tid();
+        assert_eq!(isolatable_task(), Some(tid));
+
+        let guard = ::sync::CriticalSection::begin();
+        assert_eq!(isolatable_task(), None);
+        drop(guard);
+
+        clear_task_panic_handler();
+        assert_eq!(isolatable_task(), None);
+    }
+
+    static PANICKED_TID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_task_panic(tid: usize, _msg: fmt::Arguments) {
+        PANICKED_TID.store(tid, Ordering::Relaxed);
+    }
+
+    fn panicking_task(_args: &mut ::task::args::Args) {}
+
+    #[test]
+    fn test_isolated_panic_kills_only_the_task() {
+        use task::TaskBuilder;
+        let _g = test::set_up();
+        PANICKED_TID.store(0, Ordering::Relaxed);
+        set_task_panic_handler(record_task_panic);
+        let joiner = test::create_and_schedule_test_task(512, Priority::Normal, "joiner");
+        let worker = TaskBuilder::new(panicking_task)
+            .name("worker")
+            .joinable(true)
+            .spawn()
+            .unwrap();
+        let id = worker.id().unwrap();
+        let tid = worker.tid().unwrap();
+        ::sched::start_scheduler();
+
+        assert_eq!(syscall::sys_join(&id), (syscall::JOIN_WAITING, 0));
+        assert_eq!(Ok(test::current_task().unwrap().tid()), worker.tid());
+
+        // Exiting never returns to the task, which the test environment can only show by panicking
+        let result = ::std::panic::catch_unwind(|| isolate_task_panic(format_args!("task panic")));
+        assert!(result.is_err());
+        assert_eq!(PANICKED_TID.load(Ordering::Relaxed), tid);
+        assert_not!(worker.is_valid());
+
+        assert_eq!(joiner.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(syscall::sys_join(&id), (syscall::JOIN_EXITED, PANIC_EXIT_VALUE));
+    }
+
+    #[test]
+    fn test_kernel_panic_is_never_isolated() {
+        let _g = test::set_up();
+        set_task_panic_handler(ignore_task_panic);
+        test::create_two_tasks();
+        ::sched::start_scheduler();
+
+        assert!(::std::panic::catch_unwind(|| kernel_panic!("broken invariant")).is_err());
+        assert_eq!(isolatable_task(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "kernel panic without a hook")]
+    fn test_kernel_panic_without_hook() {
+        let _g = test::set_up();
+        kernel_panic!("kernel panic without a hook");
+    }
+}
@@ -0,0 +1,151 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Low power mode handling.
+//!
+//! When there are no tasks ready to run the idle task is scheduled. Instead of spinning, the idle
+//! task can put the processor into a low power mode until the next interrupt arrives.
+//!
+//! # Tick Compatibility
+//!
+//! Not every low power mode can be used with every tick configuration:
+//!
+//! * `Sleep` only stops the processor clock, the SysTick keeps running so it is compatible with
+//!   the regular periodic system tick.
+//! * `Stop` stops the SysTick along with most other clocks. The system tick will not wake the
+//!   processor, so an external wakeup timer (like an RTC alarm) must be programmed to wake the
+//!   system, and the tick count must be fixed up afterwards. Only use this mode with a tickless
+//!   tick configuration.
+//! * `Standby` powers down most of the chip, and on most parts the contents of RAM are lost. Waking
+//!   from standby is effectively a reset, so it is only useful for shutting the system down until
+//!   an external event occurs.
+//!
+//! On Cortex-M processors the core itself only distinguishes between sleep and deep sleep (the
+//! `SLEEPDEEP` bit in the System Control Register). Selecting between `Stop` and `Standby` is done
+//! through vendor specific power control registers, which the application must configure.
+
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use arch;
+
+/// The `SLEEPDEEP` bit in the Cortex-M System Control Register.
+pub const SCR_SLEEPDEEP: usize = 0b1 << 2;
+
+static LOW_POWER_MODE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Low power modes the idle task can enter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LowPowerMode {
+    /// Don't enter a low power mode, the idle task will just spin. This is the default.
+    Disabled = 0,
+
+    /// Stop the processor clock until the next interrupt, all peripherals keep running.
+    Sleep = 1,
+
+    /// Deep sleep, most clocks are stopped, including the SysTick.
+    Stop = 2,
+
+    /// The deepest sleep, most of the chip is powered down.
+    Standby = 3,
+}
+
+/// Select the low power mode the idle task should enter.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::power::{set_low_power_mode, LowPowerMode};
+///
+/// set_low_power_mode(LowPowerMode::Sleep);
+/// ```
+pub fn set_low_power_mode(mode: LowPowerMode) {
+    LOW_POWER_MODE.store(mode as usize, Ordering::Relaxed);
+}
+
+/// Return the low power mode the idle task will enter.
+pub fn low_power_mode() -> LowPowerMode {
+    match LOW_POWER_MODE.load(Ordering::Relaxed) {
+        1 => LowPowerMode::Sleep,
+        2 => LowPowerMode::Stop,
+        3 => LowPowerMode::Standby,
+        _ => LowPowerMode::Disabled,
+    }
+}
+
+/// Compute the value of the System Control Register needed to enter `mode`.
+///
+/// `scr` is the current value of the register, only the `SLEEPDEEP` bit is modified.
+pub fn scr_for_mode(scr: usize, mode: LowPowerMode) -> usize {
+    match mode {
+        LowPowerMode::Disabled => scr,
+        LowPowerMode::Sleep => scr & !SCR_SLEEPDEEP,
+        LowPowerMode::Stop | LowPowerMode::Standby => scr | SCR_SLEEPDEEP,
+    }
+}
+
+/// Enter the selected low power mode, if there is one.
+///
+/// This is called from the idle task, and returns once an interrupt has woken the processor. If a
+/// tickless timer has been installed (see `tick::set_tickless_timer`) the periodic tick is stopped
+/// until the next sleeping task is due.
+#[doc(hidden)]
+pub fn idle() {
+    let mode = low_power_mode();
+    if mode != LowPowerMode::Disabled && !::tick::idle_tickless(mode) {
+        arch::enter_low_power(mode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use arch;
+    use atomic::Ordering;
+
+    #[test]
+    fn test_default_low_power_mode_is_disabled() {
+        let _g = test::set_up();
+        assert_eq!(low_power_mode(), LowPowerMode::Disabled);
+    }
+
+    #[test]
+    fn test_scr_for_each_mode() {
+        let scr = 0b10;
+        assert_eq!(scr_for_mode(scr, LowPowerMode::Disabled), 0b10);
+        assert_eq!(scr_for_mode(scr, LowPowerMode::Sleep), 0b10);
+        assert_eq!(scr_for_mode(scr, LowPowerMode::Stop), 0b110);
+        assert_eq!(scr_for_mode(scr, LowPowerMode::Standby), 0b110);
+        assert_eq!(scr_for_mode(0b110, LowPowerMode::Sleep), 0b10);
+    }
+
+    #[test]
+    fn test_idle_configures_scr() {
+        let _g = test::set_up();
+
+        set_low_power_mode(LowPowerMode::Stop);
+        idle();
+        assert_eq!(arch::SCR.load(Ordering::Relaxed) & SCR_SLEEPDEEP, SCR_SLEEPDEEP);
+
+        set_low_power_mode(LowPowerMode::Sleep);
+        idle();
+        assert_eq!(arch::SCR.load(Ordering::Relaxed) & SCR_SLEEPDEEP, 0);
+
+        set_low_power_mode(LowPowerMode::Standby);
+        idle();
+        assert_eq!(arch::SCR.load(Ordering::Relaxed) & SCR_SLEEPDEEP, SCR_SLEEPDEEP);
+    }
+}
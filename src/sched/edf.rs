@@ -0,0 +1,416 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Earliest deadline first scheduling.
+//!
+//! Under the `Edf` policy tasks of the same priority are run in order of their absolute deadlines
+//! instead of the order they became ready in. A task registers its timing with `set_deadline`,
+//! then calls `wait_for_next_period` at the end of every job to sleep until its next job is
+//! released. Tasks that never register a deadline are run after every task that has one.
+//!
+//! Priorities still come first, a higher priority task preempts a lower priority one no matter
+//! what their deadlines are. Putting all of the real-time tasks at the same priority gives plain
+//! EDF scheduling.
+//!
+//! Deadlines are watched under every policy, so a periodic task can register one just to have its
+//! overruns reported to the deadline miss hook. `spawn_periodic` sets up the common case of a task
+//! that runs a function once every period, which under fixed priorities gives rate monotonic
+//! scheduling if shorter periods are given higher priorities.
+
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use sync::CriticalSection;
+use hook::Hook;
+use task::{TaskControl, TaskHandle, Priority, DEFAULT_STACK_DEPTH};
+use task::args::{Args, ArgsBuilder};
+use syscall;
+use tick;
+
+static DEADLINE_MISS_HOOK: Hook<fn(TaskHandle)> = Hook::new();
+// The nearest deadline of any task that isn't running, the waiting tasks are only checked once it
+// has passed. Only meaningful while `DEADLINE_PENDING` is set.
+static NEAREST_DEADLINE: AtomicUsize = ATOMIC_USIZE_INIT;
+static DEADLINE_PENDING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Register the running task's timing.
+///
+/// The task's current job is released now and is due `deadline` ticks from now, a new job is
+/// released every `period` ticks after that. The deadline is usually the same as the period.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::edf::{set_deadline, wait_for_next_period};
+///
+/// // Run the control loop every 10 ticks, finishing each pass within 8
+/// set_deadline(10, 8);
+/// loop {
+///     // Read the sensors, update the outputs...
+///     wait_for_next_period();
+/// }
+/// ```
+pub fn set_deadline(period: usize, deadline: usize) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing else can be touching the current task.
+    let now = tick::get_tick();
+    match unsafe { super::current_task() } {
+        Some(task) => {
+            task.set_deadline(period, deadline, now);
+            watch_deadline(task.deadline(), now);
+        },
+        None => kernel_panic!("set_deadline - current task doesn't exist!"),
+    }
+}
+
+/// Finish the running task's current job and sleep until its next one is released.
+///
+/// If the next job's release has already passed, because the current job ran long, this returns
+/// right away so the task can try to catch up.
+pub fn wait_for_next_period() {
+    let until_release = {
+        let _g = CriticalSection::begin();
+        // UNSAFE: We're in a critical section, so nothing else can be touching the current task.
+        let now = tick::get_tick();
+        let release = match unsafe { super::current_task() } {
+            Some(task) => {
+                let release = task.advance_period();
+                watch_deadline(task.deadline(), now);
+                release
+            },
+            None => kernel_panic!("wait_for_next_period - current task doesn't exist!"),
+        };
+        release.wrapping_sub(now) as isize
+    };
+    if until_release > 0 {
+        // A sleep of `n` ticks wakes on the tick after `n` full ticks have passed
+        syscall::sleep_for(syscall::FOREVER_CHAN, until_release as usize - 1);
+    }
+}
+
+/// Create a task that calls `code` once every `period` ticks.
+///
+/// The task takes care of the sleeping between calls itself. Each call is released `period` ticks
+/// after the one before it, no matter how long `code` ran for, so the period doesn't drift. A call
+/// that hasn't finished by the time the next one is released is reported to the deadline miss hook.
+/// The task is given a stack of `DEFAULT_STACK_DEPTH` bytes.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{Priority, spawn_periodic};
+///
+/// fn sample_sensors() {
+///     // Read the ADC...
+/// }
+///
+/// spawn_periodic(10, Priority::Critical, "sensors", sample_sensors);
+/// ```
+pub fn spawn_periodic(period: usize, priority: Priority, name: &'static str, code: fn())
+    -> TaskHandle {
+
+    let mut args = ArgsBuilder::with_capacity(2);
+    args.add_num(period)
+        .add_num(code as usize);
+    syscall::new_task(run_periodic, args.finalize(), DEFAULT_STACK_DEPTH, priority, name)
+}
+
+fn run_periodic(args: &mut Args) {
+    let period = args.pop_num();
+    // UNSAFE: `spawn_periodic` only ever passes a `fn()` pointer here
+    let code: fn() = unsafe { ::core::mem::transmute(args.pop_num()) };
+    set_deadline(period, period);
+    loop {
+        code();
+        wait_for_next_period();
+    }
+}
+
+/// Set a function to be called with a task's handle when it misses a deadline.
+///
+/// A job misses its deadline if the task hasn't called `wait_for_next_period` by the time the
+/// deadline passes, whether it's still running, waiting to run, or blocked on something else. The
+/// hook is called at most once per job, from the system tick interrupt, so it must be short and
+/// must NOT block. Setting a new hook replaces the old one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::TaskHandle;
+/// use altos_core::edf::set_deadline_miss_hook;
+///
+/// fn overrun(task: TaskHandle) {
+///     // Log `task.tid()` and put the outputs in a safe state...
+/// }
+///
+/// set_deadline_miss_hook(overrun);
+/// ```
+pub fn set_deadline_miss_hook(hook: fn(TaskHandle)) {
+    DEADLINE_MISS_HOOK.set(hook);
+}
+
+/// Remove the deadline miss hook, if one was set.
+pub fn clear_deadline_miss_hook() {
+    DEADLINE_MISS_HOOK.clear();
+}
+
+// Report every task whose deadline has passed, called from the system tick handler.
+//
+// The running task is checked on every tick, the rest of the tasks only once the nearest of their
+// deadlines has passed.
+#[doc(hidden)]
+pub fn check_deadlines(current: &mut TaskControl) {
+    let hook = match DEADLINE_MISS_HOOK.get() {
+        Some(hook) => hook,
+        None => return,
+    };
+    let now = tick::get_tick();
+    let check = |task: &mut TaskControl| {
+        if task.check_deadline_missed(now) {
+            hook(TaskHandle::new(task));
+        }
+    };
+    check(current);
+    if !DEADLINE_PENDING.load(Ordering::Relaxed) ||
+        (now.wrapping_sub(NEAREST_DEADLINE.load(Ordering::Relaxed)) as isize) <= 0 {
+        return;
+    }
+
+    // Find the next deadline to wait for while checking
+    DEADLINE_PENDING.store(false, Ordering::Relaxed);
+    super::for_each_task(|task| {
+        check(task);
+        watch_deadline(task.deadline(), now);
+    });
+    watch_deadline(current.deadline(), now);
+    // The tasks running on the other cores aren't in any queue, but may be switched out before
+    // their deadlines come up
+    #[cfg(feature="smp")]
+    {
+        // UNSAFE: The tick is handled in a critical section, so the other cores can't switch tasks
+        for running in unsafe { super::CURRENT_TASK.iter() } {
+            if let Some(ref task) = *running {
+                watch_deadline(task.deadline(), now);
+            }
+        }
+    }
+}
+
+// Make sure the waiting tasks are checked once `deadline` passes, if it hasn't already.
+fn watch_deadline(deadline: Option<usize>, now: usize) {
+    let deadline = match deadline {
+        Some(deadline) if (now.wrapping_sub(deadline) as isize) <= 0 => deadline,
+        _ => return,
+    };
+    if !DEADLINE_PENDING.load(Ordering::Relaxed) ||
+        is_earlier(Some(deadline), Some(NEAREST_DEADLINE.load(Ordering::Relaxed)), now) {
+        NEAREST_DEADLINE.store(deadline, Ordering::Relaxed);
+        DEADLINE_PENDING.store(true, Ordering::Relaxed);
+    }
+}
+
+// Check if deadline `a` comes before deadline `b`, tasks without a deadline come last.
+#[doc(hidden)]
+pub fn is_earlier(a: Option<usize>, b: Option<usize>, now: usize) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => (a.wrapping_sub(now) as isize) < (b.wrapping_sub(now) as isize),
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{start_scheduler, switch_context, set_intra_priority_policy, Policy};
+    use test;
+    use task::Priority;
+    use sync::SpinMutex;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn test_edf_runs_earliest_deadline_first() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::Edf);
+        let chan = 0x1234;
+        let (handle_1, handle_2) = test::create_two_tasks();
+        let handle_3 = test::create_and_schedule_test_task(512, Priority::Normal, "test task 3");
+        start_scheduler();
+
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        set_deadline(100, 100);
+        ::syscall::sleep(chan);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        set_deadline(50, 50);
+        ::syscall::sleep(chan);
+        assert_eq!(handle_3.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // Both woken tasks have deadlines, so they're run before task 3, earliest deadline first
+        ::syscall::wake(chan);
+        switch_context();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        ::syscall::sleep(chan);
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        ::syscall::sleep(chan);
+        assert_eq!(handle_3.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_next_period_released_without_drift() {
+        let _g = test::set_up();
+        let handle = test::create_and_schedule_test_task(512, Priority::Critical, "periodic");
+        start_scheduler();
+        let start = tick::get_tick();
+        set_deadline(10, 10);
+
+        // The job takes 3 ticks, then the task waits out the rest of the period
+        for _ in 0..3 {
+            ::syscall::system_tick();
+        }
+        wait_for_next_period();
+        assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+        while tick::get_tick() != start + 9 {
+            ::syscall::system_tick();
+        }
+        assert_eq!(handle.state(), Ok(::task::State::Blocked));
+        ::syscall::system_tick();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_spawn_periodic_creates_task() {
+        let _g = test::set_up();
+        fn work() {}
+
+        let handle = spawn_periodic(10, Priority::Critical, "periodic", work);
+        assert_eq!(handle.priority(), Ok(Priority::Critical));
+        assert_eq!(handle.name(), Ok("periodic"));
+    }
+
+    static MISSED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_miss(task: TaskHandle) {
+        MISSED.store(task.tid().unwrap() + 1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_deadline_miss_hook_fired_once_per_job() {
+        let _g = test::set_up();
+        MISSED.store(0, Ordering::Relaxed);
+        set_deadline_miss_hook(record_miss);
+        set_intra_priority_policy(Policy::Edf);
+        let (handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+        set_deadline(5, 2);
+
+        for _ in 0..2 {
+            ::syscall::system_tick();
+        }
+        assert_eq!(MISSED.load(Ordering::Relaxed), 0);
+        ::syscall::system_tick();
+        assert_eq!(MISSED.load(Ordering::Relaxed), handle_1.tid().unwrap() + 1);
+
+        MISSED.store(0, Ordering::Relaxed);
+        ::syscall::system_tick();
+        assert_eq!(MISSED.load(Ordering::Relaxed), 0);
+        clear_deadline_miss_hook();
+    }
+
+    #[test]
+    fn test_blocked_task_missing_deadline_reported() {
+        let _g = test::set_up();
+        MISSED.store(0, Ordering::Relaxed);
+        set_deadline_miss_hook(record_miss);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+        set_deadline(10, 1);
+        // Stuck waiting for something that never comes
+        ::syscall::sleep(0x1234);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        ::syscall::system_tick();
+        ::syscall::system_tick();
+        assert_eq!(MISSED.load(Ordering::Relaxed), handle_1.tid().unwrap() + 1);
+        clear_deadline_miss_hook();
+    }
+
+    static PERIODIC_TID: AtomicUsize = ATOMIC_USIZE_INIT;
+    static JOBS_RUN: AtomicUsize = ATOMIC_USIZE_INIT;
+    static RELEASES: SpinMutex<[usize; 3]> = SpinMutex::new([0; 3]);
+
+    fn periodic_job() {
+        // Tasks don't really block in the test environment, so let time pass here until the
+        // periodic task would have been switched back in
+        while test::current_task().unwrap().tid() != PERIODIC_TID.load(Ordering::Relaxed) {
+            ::syscall::system_tick();
+        }
+        let job = JOBS_RUN.fetch_add(1, Ordering::Relaxed);
+        if job == 3 {
+            panic!("ran enough periods");
+        }
+        RELEASES.lock()[job] = tick::get_tick();
+        // Each job runs longer than the one before it
+        for _ in 0..job {
+            ::syscall::system_tick();
+        }
+    }
+
+    #[test]
+    fn test_periodic_task_released_every_period() {
+        let _g = test::set_up();
+        JOBS_RUN.store(0, Ordering::Relaxed);
+        test::create_two_tasks();
+        let handle = spawn_periodic(10, Priority::Critical, "periodic", periodic_job);
+        start_scheduler();
+        PERIODIC_TID.store(handle.tid().unwrap(), Ordering::Relaxed);
+        let start = tick::get_tick();
+
+        // Task code isn't run by the test environment, so run the periodic task's code here
+        let mut args = ArgsBuilder::with_capacity(2);
+        args.add_num(10)
+            .add_num(periodic_job as usize);
+        let mut args = args.finalize();
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| run_periodic(&mut args))).is_err());
+
+        // The time the jobs ran for doesn't push the releases back
+        assert_eq!(*RELEASES.lock(), [start, start + 10, start + 20]);
+    }
+
+    #[test]
+    fn test_waiting_tasks_reported_at_their_own_deadlines() {
+        let _g = test::set_up();
+        MISSED.store(0, Ordering::Relaxed);
+        set_deadline_miss_hook(record_miss);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        test::create_and_schedule_test_task(512, Priority::Normal, "test task 3");
+        start_scheduler();
+
+        set_deadline(10, 3);
+        ::syscall::sleep(0x1234);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        set_deadline(10, 1);
+        ::syscall::sleep(0x1234);
+
+        ::syscall::system_tick();
+        ::syscall::system_tick();
+        assert_eq!(MISSED.load(Ordering::Relaxed), handle_2.tid().unwrap() + 1);
+
+        // The later deadline is still watched after the earlier one was reported
+        ::syscall::system_tick();
+        ::syscall::system_tick();
+        assert_eq!(MISSED.load(Ordering::Relaxed), handle_1.tid().unwrap() + 1);
+        clear_deadline_miss_hook();
+    }
+}
@@ -0,0 +1,149 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Idle task hooks.
+//!
+//! Applications can register functions for the idle task to call every time it runs, before it
+//! puts the processor into the low power mode. This is a good place for background housekeeping,
+//! like feeding a watchdog, that should only happen when nothing else needs the CPU.
+
+use sync::CriticalSection;
+use hook::Hook;
+
+/// The maximum number of idle hooks that can be registered at once.
+pub const MAX_IDLE_HOOKS: usize = 4;
+
+static IDLE_HOOKS: [Hook<fn()>; MAX_IDLE_HOOKS] = [
+    Hook::new(),
+    Hook::new(),
+    Hook::new(),
+    Hook::new(),
+];
+
+/// The error returned when every idle hook slot is already taken.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TooManyIdleHooks;
+
+/// Register a function for the idle task to call every time it runs.
+///
+/// Hooks are called in the order they were added. They're called from the idle task, outside of
+/// any critical section, so they can be interrupted and preempted like any other task code. Since
+/// the idle task must always be ready to run they must NOT block or sleep. Adding a hook that's
+/// already registered does nothing.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::add_idle_hook;
+///
+/// fn feed_watchdog() {
+///     // Reload the watchdog counter...
+/// }
+///
+/// add_idle_hook(feed_watchdog).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Returns `TooManyIdleHooks` if `MAX_IDLE_HOOKS` hooks are already registered.
+pub fn add_idle_hook(hook: fn()) -> Result<(), TooManyIdleHooks> {
+    let _g = CriticalSection::begin();
+    let registered = |slot: &Hook<fn()>| {
+        slot.get().map_or(false, |added| added as usize == hook as usize)
+    };
+    if IDLE_HOOKS.iter().any(registered) {
+        return Ok(());
+    }
+    match IDLE_HOOKS.iter().find(|slot| !slot.is_set()) {
+        Some(slot) => {
+            slot.set(hook);
+            Ok(())
+        },
+        None => Err(TooManyIdleHooks),
+    }
+}
+
+/// Make `hook` the only function the idle task calls, replacing any registered hooks.
+pub fn set_idle_hook(hook: fn()) {
+    let _g = CriticalSection::begin();
+    clear_idle_hooks();
+    IDLE_HOOKS[0].set(hook);
+}
+
+/// Remove every registered idle hook.
+pub fn clear_idle_hooks() {
+    let _g = CriticalSection::begin();
+    for slot in IDLE_HOOKS.iter() {
+        slot.clear();
+    }
+}
+
+// Call every registered hook, called from the idle task.
+#[doc(hidden)]
+pub fn run_idle_hooks() {
+    for hook in IDLE_HOOKS.iter().filter_map(|slot| slot.get()) {
+        hook();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    static CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    // Each hook shifts in its own digit, so the order they ran in can be read back
+    fn hook_1() { CALLS.store(CALLS.load(Ordering::SeqCst) * 10 + 1, Ordering::SeqCst); }
+    fn hook_2() { CALLS.store(CALLS.load(Ordering::SeqCst) * 10 + 2, Ordering::SeqCst); }
+    fn hook_3() { CALLS.store(CALLS.load(Ordering::SeqCst) * 10 + 3, Ordering::SeqCst); }
+
+    #[test]
+    fn test_idle_hooks_run_in_order_added() {
+        let _g = test::set_up();
+        CALLS.store(0, Ordering::SeqCst);
+        assert_eq!(add_idle_hook(hook_2), Ok(()));
+        assert_eq!(add_idle_hook(hook_1), Ok(()));
+        assert_eq!(add_idle_hook(hook_2), Ok(()));
+
+        run_idle_hooks();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 21);
+
+        CALLS.store(0, Ordering::SeqCst);
+        set_idle_hook(hook_3);
+        run_idle_hooks();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+    }
+
+    fn noop() {}
+    fn noop_2() {}
+    fn noop_3() {}
+    fn noop_4() {}
+
+    #[test]
+    fn test_idle_hook_slots_run_out() {
+        let _g = test::set_up();
+        for &hook in [noop as fn(), noop_2, noop_3, noop_4].iter() {
+            assert_eq!(add_idle_hook(hook), Ok(()));
+        }
+        assert_eq!(add_idle_hook(hook_1), Err(TooManyIdleHooks));
+
+        clear_idle_hooks();
+        assert_eq!(add_idle_hook(hook_1), Ok(()));
+    }
+}
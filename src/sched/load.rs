@@ -0,0 +1,175 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! CPU load tracking.
+//!
+//! On every system tick the kernel notes whether the idle task was the one running. The load is
+//! the share of ticks in the most recent window that some other task was running.
+
+use sync::{SpinMutex, CriticalSection};
+
+/// The number of most recent ticks `cpu_load_percent` is computed over.
+pub const LOAD_WINDOW: usize = 100;
+
+const WINDOW_WORDS: usize = (LOAD_WINDOW + 31) / 32;
+
+static LOAD: SpinMutex<Load> = SpinMutex::new(Load::new());
+
+struct Load {
+    // One bit per tick in the window, set if the idle task was running
+    window: [u32; WINDOW_WORDS],
+    // Where the next tick goes in the window
+    next: usize,
+    // The number of ticks in the window that have been filled in
+    samples: usize,
+    idle_ticks: usize,
+    total_ticks: usize,
+}
+
+impl Load {
+    const fn new() -> Self {
+        Load {
+            window: [0; WINDOW_WORDS],
+            next: 0,
+            samples: 0,
+            idle_ticks: 0,
+            total_ticks: 0,
+        }
+    }
+
+    fn record(&mut self, idle: bool) {
+        let (word, bit) = (self.next / 32, self.next % 32);
+        if idle {
+            self.window[word] |= 1 << bit;
+            self.idle_ticks = self.idle_ticks.wrapping_add(1);
+        }
+        else {
+            self.window[word] &= !(1 << bit);
+        }
+        self.total_ticks = self.total_ticks.wrapping_add(1);
+        self.next = (self.next + 1) % LOAD_WINDOW;
+        if self.samples < LOAD_WINDOW {
+            self.samples += 1;
+        }
+    }
+
+    fn percent(&self) -> u8 {
+        if self.samples == 0 {
+            return 0;
+        }
+        let idle = self.window.iter().map(|word| word.count_ones() as usize).sum::<usize>();
+        let busy = self.samples - idle;
+        // Round to the nearest percent
+        ((busy * 100 + self.samples / 2) / self.samples) as u8
+    }
+}
+
+// Note whether the idle task was running for the tick that just happened, called from the system
+// tick handler.
+#[doc(hidden)]
+pub fn record_tick(idle: bool) {
+    let _g = CriticalSection::begin();
+    LOAD.lock().record(idle);
+}
+
+/// Return how busy the CPU has been over the last `LOAD_WINDOW` ticks, from 0 to 100.
+///
+/// The load is the percentage of ticks that a task other than the idle task was running when the
+/// tick happened. It's only a sample, a task that runs for a short time between two ticks isn't
+/// counted, but it's cheap and always available. Until `LOAD_WINDOW` ticks have passed since the
+/// start (or since `reset_cpu_load`) the load is computed over the ticks there have been so far.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::cpu_load_percent;
+///
+/// if cpu_load_percent() > 90 {
+///     // Shed some work...
+/// }
+/// ```
+pub fn cpu_load_percent() -> u8 {
+    let _g = CriticalSection::begin();
+    LOAD.lock().percent()
+}
+
+/// Return the number of ticks the idle task was running for, and the total number of ticks counted.
+///
+/// Both counts start from 0 when the system starts and whenever `reset_cpu_load` is called, so the
+/// load over a longer period than `LOAD_WINDOW` can be sampled by reading the counts and resetting
+/// them every period.
+pub fn idle_ticks() -> (usize, usize) {
+    let _g = CriticalSection::begin();
+    let load = LOAD.lock();
+    (load.idle_ticks, load.total_ticks)
+}
+
+/// Start counting the load over again, clearing the window and the tick counts.
+pub fn reset_cpu_load() {
+    let _g = CriticalSection::begin();
+    *LOAD.lock() = Load::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use syscall;
+    use sched::start_scheduler;
+    use task::Priority;
+
+    #[test]
+    fn test_load_counts_busy_and_idle_ticks() {
+        let _g = test::set_up();
+        test::create_and_schedule_test_task(512, Priority::Normal, "busy");
+        start_scheduler();
+        reset_cpu_load();
+
+        for _ in 0..30 {
+            syscall::system_tick();
+        }
+        // With the only other task asleep, the idle task runs
+        syscall::sleep(0x1234);
+        assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+        for _ in 0..70 {
+            syscall::system_tick();
+        }
+
+        assert_eq!(cpu_load_percent(), 30);
+        assert_eq!(idle_ticks(), (70, 100));
+    }
+
+    #[test]
+    fn test_load_window_slides() {
+        let _g = test::set_up();
+        reset_cpu_load();
+
+        for _ in 0..LOAD_WINDOW {
+            record_tick(false);
+        }
+        assert_eq!(cpu_load_percent(), 100);
+        // Half of the window has been pushed out by idle ticks
+        for _ in 0..(LOAD_WINDOW / 2) {
+            record_tick(true);
+        }
+        assert_eq!(cpu_load_percent(), 50);
+        assert_eq!(idle_ticks(), (LOAD_WINDOW / 2, LOAD_WINDOW + LOAD_WINDOW / 2));
+
+        reset_cpu_load();
+        assert_eq!(cpu_load_percent(), 0);
+    }
+}
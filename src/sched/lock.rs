@@ -0,0 +1,142 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Scheduler suspension.
+//!
+//! A `CriticalSection` keeps the running task from being switched out by disabling interrupts,
+//! which delays every interrupt for as long as it's held. Suspending the scheduler only holds
+//! back context switches, interrupts keep being serviced and tasks they wake up are made ready as
+//! usual, but the running task keeps the CPU until the scheduler is resumed. Any switch that was
+//! asked for in the meantime happens then. With the `smp` feature each core's scheduler is
+//! suspended separately, suspending it only keeps the task on the calling core running.
+
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use arch;
+use super::{NUM_CORES, current_core};
+
+// How many suspensions are held on each core, and whether a switch was held back by them
+#[cfg(not(feature="smp"))]
+static SUSPEND_DEPTH: [AtomicUsize; NUM_CORES] = [ATOMIC_USIZE_INIT];
+#[cfg(feature="smp")]
+static SUSPEND_DEPTH: [AtomicUsize; NUM_CORES] = [ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT];
+#[cfg(not(feature="smp"))]
+static SWITCH_DEFERRED: [AtomicBool; NUM_CORES] = [ATOMIC_BOOL_INIT];
+#[cfg(feature="smp")]
+static SWITCH_DEFERRED: [AtomicBool; NUM_CORES] = [ATOMIC_BOOL_INIT, ATOMIC_BOOL_INIT];
+
+/// Stop the running task from being switched out until the returned guard is dropped.
+///
+/// Suspensions nest, the scheduler resumes once every guard has been dropped. The running task
+/// must NOT block while the scheduler is suspended, there would be nothing to switch to it from,
+/// so blocking calls panic. Only the calling core's scheduler is suspended, so the guard must be
+/// dropped on the same core.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::suspend_scheduler;
+///
+/// let guard = suspend_scheduler();
+/// // Update state shared with other tasks, interrupts are still serviced...
+/// drop(guard);
+/// ```
+pub fn suspend_scheduler() -> SchedulerGuard {
+    SUSPEND_DEPTH[current_core()].fetch_add(1, Ordering::SeqCst);
+    SchedulerGuard(())
+}
+
+/// Returns `true` if the scheduler is currently suspended on the calling core.
+pub fn is_scheduler_suspended() -> bool {
+    SUSPEND_DEPTH[current_core()].load(Ordering::SeqCst) != 0
+}
+
+/// Tracks a suspension of the scheduler.
+///
+/// Can only be created by `suspend_scheduler`. When this falls out of scope the suspension ends,
+/// and if it was the last one any context switch that was held back happens right away.
+#[must_use]
+pub struct SchedulerGuard(());
+
+impl Drop for SchedulerGuard {
+    fn drop(&mut self) {
+        let core = current_core();
+        if SUSPEND_DEPTH[core].fetch_sub(1, Ordering::SeqCst) == 1 &&
+            SWITCH_DEFERRED[core].swap(false, Ordering::SeqCst) {
+            arch::yield_cpu();
+        }
+    }
+}
+
+// Switch tasks, or hold the switch back until the scheduler is resumed if it's suspended.
+#[doc(hidden)]
+pub fn request_switch() {
+    if is_scheduler_suspended() {
+        SWITCH_DEFERRED[current_core()].store(true, Ordering::SeqCst);
+    }
+    else {
+        arch::yield_cpu();
+    }
+}
+
+#[cfg(test)]
+pub fn reset_scheduler_suspension() {
+    for core in 0..NUM_CORES {
+        SUSPEND_DEPTH[core].store(0, Ordering::SeqCst);
+        SWITCH_DEFERRED[core].store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{start_scheduler, set_intra_priority_policy, Policy};
+    use test;
+    use syscall;
+
+    #[test]
+    fn test_switches_deferred_until_last_guard_dropped() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        let outer = suspend_scheduler();
+        let inner = suspend_scheduler();
+        syscall::system_tick();
+        syscall::sched_yield();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        drop(inner);
+        assert!(is_scheduler_suspended());
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        drop(outer);
+        assert_not!(is_scheduler_suspended());
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    #[should_panic(expected = "scheduler is suspended")]
+    fn test_blocking_while_suspended_panics() {
+        let _g = test::set_up();
+        test::create_two_tasks();
+        start_scheduler();
+
+        let _suspended = suspend_scheduler();
+        syscall::sleep(0x1234);
+    }
+}
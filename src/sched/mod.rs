@@ -20,20 +20,60 @@
 //! This module contains functionality for scheduling tasks to run and scheduler initialization.
 
 use task::{self, TaskControl, Delay, Priority, State};
-use collections::{SyncQueue, Node};
-use alloc::boxed::Box;
+use collections::{SyncQueue, SyncTimerWheel, NodeBox};
 use core::ops::Index;
 use task::NUM_PRIORITIES;
-use atomic::{AtomicUsize, Ordering,ATOMIC_USIZE_INIT};
+use atomic::{AtomicUsize, Ordering,ATOMIC_USIZE_INIT, AtomicBool, ATOMIC_BOOL_INIT};
 use arch;
+use hook::Hook;
+use sync::CriticalSection;
+
+mod scheduler;
+pub mod load;
+pub mod edf;
+mod idle;
+mod lock;
+#[cfg(feature="replay")]
+pub mod replay;
+#[cfg(feature="starvation")]
+pub mod starvation;
+#[cfg(feature="smp")]
+pub mod smp;
+
+pub use self::scheduler::{Scheduler, PriorityScheduler, install_scheduler, scheduler};
+pub use self::scheduler::DEFAULT_SCHEDULER;
+pub use self::load::{cpu_load_percent, idle_ticks, reset_cpu_load, LOAD_WINDOW};
+pub use self::idle::{add_idle_hook, set_idle_hook, clear_idle_hooks, run_idle_hooks};
+pub use self::idle::{TooManyIdleHooks, MAX_IDLE_HOOKS};
+pub use self::lock::{SchedulerGuard, suspend_scheduler, is_scheduler_suspended, request_switch};
+#[cfg(test)]
+pub use self::lock::reset_scheduler_suspension;
+
+/// The number of cores tasks are scheduled on.
+#[cfg(not(feature="smp"))]
+pub const NUM_CORES: usize = 1;
+
+/// The number of cores tasks are scheduled on.
+#[cfg(feature="smp")]
+pub const NUM_CORES: usize = 2;
 
 /// The current task.
 ///
 /// This keeps track of the currently running task, this should always be `Some` unless the task is
 /// actively being switched out or the scheduler has not been started.
+#[cfg(not(feature="smp"))]
+#[no_mangle]
+#[doc(hidden)]
+pub static mut CURRENT_TASK: Option<NodeBox<TaskControl>> = None;
+
+/// The current task on each core.
+///
+/// The task running on core `n` is at index `n`, each entry should always be `Some` unless that
+/// core's task is actively being switched out or the core hasn't been started.
+#[cfg(feature="smp")]
 #[no_mangle]
 #[doc(hidden)]
-pub static mut CURRENT_TASK: Option<Box<Node<TaskControl>>> = None;
+pub static mut CURRENT_TASK: [Option<NodeBox<TaskControl>>; NUM_CORES] = [None, None];
 pub static PRIORITY_QUEUES: [SyncQueue<TaskControl>; NUM_PRIORITIES] = [
     SyncQueue::new(),
     SyncQueue::new(),
@@ -41,12 +81,242 @@ pub static PRIORITY_QUEUES: [SyncQueue<TaskControl>; NUM_PRIORITIES] = [
     SyncQueue::new()
 ];
 pub static SLEEP_QUEUE: SyncQueue<TaskControl> = SyncQueue::new();
-pub static DELAY_QUEUE: SyncQueue<TaskControl> = SyncQueue::new();
-pub static OVERFLOW_DELAY_QUEUE: SyncQueue<TaskControl> = SyncQueue::new();
+pub static DELAY_WHEEL: SyncTimerWheel<TaskControl> = SyncTimerWheel::new();
+pub static SUSPENDED_QUEUE: SyncQueue<TaskControl> = SyncQueue::new();
 pub static NORMAL_TASK_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
 
+/// The affinity mask that lets a task run on every core.
+pub const ALL_CORES: usize = !0;
+
+/// Returns the index of the core this code is running on.
+///
+/// Without the `smp` feature there's only one core, so this is always `0`.
+#[cfg(not(feature="smp"))]
+pub fn current_core() -> usize {
+    0
+}
+
+/// Returns the index of the core this code is running on.
+#[cfg(feature="smp")]
+pub fn current_core() -> usize {
+    arch::current_core()
+}
+
+/// Returns the task running on the current core.
+///
+/// Kernel code should go through this rather than reading `CURRENT_TASK` directly, so the current
+/// task can become per core without touching every call site.
+///
+/// # Safety
+///
+/// The caller must make sure the current task can't be switched out while the returned reference
+/// is alive, usually by being in a critical section.
+#[doc(hidden)]
+pub unsafe fn current_task() -> Option<&'static mut TaskControl> {
+    current_slot().as_mut().map(|task| &mut ***task)
+}
+
+// Where the task running on this core is kept.
+#[cfg(not(feature="smp"))]
+unsafe fn current_slot() -> &'static mut Option<NodeBox<TaskControl>> {
+    &mut CURRENT_TASK
+}
+
+#[cfg(feature="smp")]
+unsafe fn current_slot() -> &'static mut Option<NodeBox<TaskControl>> {
+    &mut CURRENT_TASK[current_core()]
+}
+
+static INTRA_PRIORITY_POLICY: AtomicUsize = ATOMIC_USIZE_INIT;
+// The round robin time slice for each priority in ticks, 0 is treated as a single tick
+static TIME_SLICES: [AtomicUsize; NUM_PRIORITIES] = [
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT
+];
+// The ticks left in the running task's time slice, for each core
+#[cfg(not(feature="smp"))]
+static QUANTUM_LEFT: [AtomicUsize; NUM_CORES] = [ATOMIC_USIZE_INIT];
+#[cfg(feature="smp")]
+static QUANTUM_LEFT: [AtomicUsize; NUM_CORES] = [ATOMIC_USIZE_INIT, ATOMIC_USIZE_INIT];
+// Set when the pending context switch was forced on the running task rather than requested by it,
+// for each core
+#[cfg(not(feature="smp"))]
+static PREEMPTING: [AtomicBool; NUM_CORES] = [ATOMIC_BOOL_INIT];
+#[cfg(feature="smp")]
+static PREEMPTING: [AtomicBool; NUM_CORES] = [ATOMIC_BOOL_INIT, ATOMIC_BOOL_INIT];
+static COOPERATIVE: AtomicBool = ATOMIC_BOOL_INIT;
+
 const NORMAL_TASK_MAX: usize = 10;
 
+/// Policies for scheduling tasks that share the same priority.
+///
+/// The policy only decides how tasks of equal priority share the CPU, a higher priority task that
+/// becomes ready will always preempt a lower priority one regardless of the policy selected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Policy {
+    /// Run-to-block scheduling.
+    ///
+    /// A running task keeps the CPU until it blocks or yields, the system tick will not rotate it
+    /// out in favor of another task with the same priority. This is the default policy.
+    Fifo = 0,
+
+    /// Time sliced scheduling.
+    ///
+    /// Once the running task has used up its time slice (see `set_time_slice`) it's moved to the
+    /// back of its priority queue if there is another task with the same priority that is ready to
+    /// run.
+    RoundRobin = 1,
+
+    /// Earliest deadline first scheduling.
+    ///
+    /// The ready task with the nearest absolute deadline runs, and a task that becomes ready with
+    /// an earlier deadline than the running task preempts it on the next system tick. Tasks
+    /// register their deadlines with the functions in the `edf` module, tasks without one are run
+    /// after every task that has one, in FIFO order.
+    Edf = 2,
+}
+
+/// Select how tasks of the same priority share the CPU.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{set_intra_priority_policy, Policy};
+///
+/// // Rotate between tasks of the same priority every tick
+/// set_intra_priority_policy(Policy::RoundRobin);
+/// ```
+pub fn set_intra_priority_policy(policy: Policy) {
+    INTRA_PRIORITY_POLICY.store(policy as usize, Ordering::Relaxed);
+}
+
+/// Return the policy currently used for tasks of the same priority.
+pub fn intra_priority_policy() -> Policy {
+    match INTRA_PRIORITY_POLICY.load(Ordering::Relaxed) {
+        0 => Policy::Fifo,
+        1 => Policy::RoundRobin,
+        _ => Policy::Edf,
+    }
+}
+
+/// Turn preemption off, or back on.
+///
+/// In cooperative mode the running task keeps the CPU until it yields, blocks or exits. Neither the
+/// system tick nor a higher priority task being woken up will switch it out, which makes the order
+/// tasks run in completely predictable, at the cost of every task having to yield often enough for
+/// the others to keep up. The scheduler still decides which task runs next whenever the CPU is
+/// given up. Tasks that run past their runtime limit are still terminated.
+///
+/// This can be switched at any time, including before the scheduler is started. Preemption is on
+/// by default.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::set_cooperative;
+///
+/// set_cooperative(true);
+/// ```
+pub fn set_cooperative(cooperative: bool) {
+    COOPERATIVE.store(cooperative, Ordering::Relaxed);
+}
+
+/// Returns `true` if preemption has been turned off with `set_cooperative`.
+pub fn is_cooperative() -> bool {
+    COOPERATIVE.load(Ordering::Relaxed)
+}
+
+/// Set how many system ticks a task runs for before being rotated out under the `RoundRobin`
+/// policy, for every priority.
+///
+/// A slice of 0 is treated as a single tick, which is the default.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{set_intra_priority_policy, set_time_slice, Policy};
+///
+/// // Rotate between tasks of the same priority every 10 ticks
+/// set_intra_priority_policy(Policy::RoundRobin);
+/// set_time_slice(10);
+/// ```
+pub fn set_time_slice(ticks: usize) {
+    for slice in TIME_SLICES.iter() {
+        slice.store(ticks, Ordering::Relaxed);
+    }
+}
+
+/// Set the round robin time slice for tasks of a single priority.
+///
+/// This overrides the slice set by `set_time_slice` for `priority` only, so for instance low
+/// priority background work can be given longer, less frequent turns.
+pub fn set_priority_time_slice(priority: Priority, ticks: usize) {
+    TIME_SLICES[priority as usize].store(ticks, Ordering::Relaxed);
+}
+
+/// Return the round robin time slice in ticks for tasks with `priority`.
+pub fn time_slice(priority: Priority) -> usize {
+    match TIME_SLICES[priority as usize].load(Ordering::Relaxed) {
+        0 => 1,
+        ticks => ticks,
+    }
+}
+
+// Count a tick against the running task's time slice, called from the system tick handler.
+#[doc(hidden)]
+pub fn consume_quantum() {
+    let quantum = &QUANTUM_LEFT[current_core()];
+    let left = quantum.load(Ordering::Relaxed);
+    quantum.store(left.saturating_sub(1), Ordering::Relaxed);
+}
+
+/// Returns `true` once the running task has used up its time slice.
+#[doc(hidden)]
+pub fn quantum_expired() -> bool {
+    QUANTUM_LEFT[current_core()].load(Ordering::Relaxed) == 0
+}
+
+// Give the task being switched in a full time slice.
+fn start_quantum(task: &TaskControl) {
+    QUANTUM_LEFT[current_core()].store(time_slice(task.priority()), Ordering::Relaxed);
+}
+
+/// Stop the running task from being rotated out in favor of tasks with the same priority.
+///
+/// While pinned, the task keeps the CPU under the `RoundRobin` policy the same way it would under
+/// `Fifo`, until it calls `unpin`, blocks or yields. Higher priority tasks still preempt it and
+/// interrupts are still serviced, so this is a much lighter way to run a short sequence without
+/// interference from the task's peers than a critical section.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{pin, unpin};
+///
+/// pin();
+/// // Update state shared with tasks of the same priority...
+/// unpin();
+/// ```
+pub fn pin() {
+    set_current_pinned(true);
+}
+
+/// Let the running task be rotated out in favor of tasks with the same priority again.
+pub fn unpin() {
+    set_current_pinned(false);
+}
+
+fn set_current_pinned(pinned: bool) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing else can be touching the current task.
+    match unsafe { current_task() } {
+        Some(task) => task.set_pinned(pinned),
+        None => kernel_panic!("pin - current task doesn't exist!"),
+    }
+}
+
 impl Index<Priority> for [SyncQueue<TaskControl>] {
     type Output = SyncQueue<TaskControl>;
     fn index(&self, idx: Priority) -> &Self::Output {
@@ -54,81 +324,194 @@ impl Index<Priority> for [SyncQueue<TaskControl>] {
     }
 }
 
+/// Run `block` on every task that isn't currently running, regardless of which queue it's in.
+pub fn for_each_task<F: Fn(&mut TaskControl)>(block: F) {
+    scheduler().modify_all(&block);
+    SLEEP_QUEUE.modify_all(&block);
+    DELAY_WHEEL.modify_all(&block);
+    SUSPENDED_QUEUE.modify_all(&block);
+}
+
+/// Force the running task off of the CPU.
+///
+/// This is the same as yielding, except the context switch is recorded as preemptive instead of
+/// voluntary. It should be used by the kernel whenever it decides to switch away from a task that
+/// didn't ask for it, like when its time slice runs out.
+#[doc(hidden)]
+pub fn preempt() {
+    PREEMPTING[current_core()].store(true, Ordering::Relaxed);
+    request_switch();
+}
+
 /// Select a new task to run and switch its context, this function MUST only be called from the
 /// PendSV handler, calling it from elsewhere could lead to undefined behavior. It must be exposed
 /// publicly so that the compiler doesn't optimize it away when compiling for release.
 #[no_mangle]
 #[doc(hidden)]
 pub fn switch_context() {
+    // The ready queues are shared with the other cores, the critical section keeps them out
+    #[cfg(feature="smp")]
+    let _g = CriticalSection::begin();
+    let _preempted = PREEMPTING[current_core()].swap(false, Ordering::Relaxed);
+    #[cfg(feature="stats")]
+    ::stats::record_switch(_preempted);
+
     // UNSAFE: Accessing CURRENT_TASK
-    match unsafe { CURRENT_TASK.take() } {
+    match unsafe { current_slot().take() } {
         Some(mut running) => {
             if running.is_destroyed() {
                 drop(running);
             } else {
-                let queue_index = running.priority();
                 if running.is_stack_overflowed() {
-                    panic!("switch_context - The current task's stack overflowed!");
+                    #[cfg(feature="logger")]
+                    ::logger::error(::logger::KernelEvent::StackOverflow { tid: running.tid() });
+                    call_stack_overflow_handler(&running);
+                    kernel_panic!("switch_context - The current task's stack overflowed!");
                 }
+                #[cfg(feature="trace")]
+                ::trace::switch_out(&running);
+                #[cfg(feature="starvation")]
+                starvation::switched_out(&mut running);
                 if running.state() == State::Blocked {
+                    scheduler().on_block(&running);
+                    #[cfg(feature="trace")]
+                    ::trace::record(::trace::EventKind::Block, Some(running.tid()));
                     match running.delay_type() {
-                        Delay::Timeout => DELAY_QUEUE.enqueue(running),
-                        Delay::Overflowed => OVERFLOW_DELAY_QUEUE.enqueue(running),
+                        Delay::Timeout | Delay::Overflowed => DELAY_WHEEL.insert(running),
                         Delay::Sleep => SLEEP_QUEUE.enqueue(running),
-                        Delay::Invalid => panic!(
+                        Delay::Invalid => kernel_panic!(
                             "switch_context - Running task delay type was not set when switched to Blocked!"
                         ),
                     }
+                } else if running.is_suspended() {
+                    running.set_ready();
+                    SUSPENDED_QUEUE.enqueue(running);
                 } else {
                     running.set_ready();
-                    PRIORITY_QUEUES[queue_index].enqueue(running);
+                    scheduler().add_task(running);
                 }
             }
 
-            // If more than NORMAL_TASK_MAX Normal tasks have run, don't try and schedule
-            // a normal priorty task, instead giving a low priority task a shot at running.
-            let selected = if NORMAL_TASK_COUNTER.load(Ordering::Relaxed) >= NORMAL_TASK_MAX {
-                NORMAL_TASK_COUNTER.store(0, Ordering::Relaxed);
-                select_task(Priority::all_except(Priority::Normal))
+            let selected = select_task(|scheduler| scheduler.pick_next());
+            check_saved_psp(&selected);
+            start_quantum(&selected);
+            #[cfg(feature="trace")]
+            {
+                ::trace::record(::trace::EventKind::Switch, Some(selected.tid()));
+                ::trace::switch_in(&selected);
             }
-            else {
-                select_task(Priority::all())
-            };
-            if let Priority::Normal = selected.priority() {
-                NORMAL_TASK_COUNTER.fetch_add(1, Ordering::Relaxed);
-            }
-            unsafe { CURRENT_TASK = Some(selected) };
+            unsafe { *current_slot() = Some(selected) };
         },
-        None => panic!("switch_context - current task doesn't exist!"),
+        None => kernel_panic!("switch_context - current task doesn't exist!"),
     }
 }
 
-/// Select the next task to run from PRIORITY_QUEUES using a provided Priority Iterator.
+/// Select the next task to run from the installed scheduler, using `pick` to choose it.
 ///
-/// Will select the first available task from the priorities provided by the Iterator.
-/// If no task is found, the function panics, but this should not happen due to the idle task.
-fn select_task<I: Iterator<Item=Priority>>(priorities: I) -> Box<Node<TaskControl>> {
-    for priority in priorities {
-        while let Some(mut new_task) = PRIORITY_QUEUES[priority].dequeue() {
-            if new_task.is_destroyed() {
-                drop(new_task);
-            } else {
-                new_task.set_running();
-                return new_task;
-            }
+/// Tasks that have been destroyed are dropped instead of being run.
+fn select_task<F: Fn(&Scheduler) -> NodeBox<TaskControl>>(pick: F) -> NodeBox<TaskControl> {
+    #[cfg(feature="replay")]
+    {
+        if let Some(mut forced) = replay::forced_choice() {
+            forced.set_running();
+            return forced;
+        }
+    }
+
+    loop {
+        let mut new_task = pick(scheduler());
+        if new_task.is_destroyed() {
+            drop(new_task);
+        } else if new_task.is_suspended() {
+            // The task was woken up after being suspended, park it until it's resumed
+            SUSPENDED_QUEUE.enqueue(new_task);
+        } else {
+            new_task.set_running();
+            #[cfg(feature="replay")]
+            replay::record_choice(new_task.tid());
+            return new_task;
         }
     }
-    panic!("select_task - task not selected!");
 }
 
 /// Start running the first task in the queue.
+///
+/// With the `smp` feature this also starts the other cores, see the `smp` module.
 pub fn start_scheduler() {
     task::init_idle_task();
-    // UNSAFE: Accessing CURRENT_TASK
-    unsafe { CURRENT_TASK = Some(select_task(Priority::all())) };
+    load_first_task();
+    #[cfg(feature="smp")]
+    smp::start_other_cores();
     arch::start_first_task();
 }
 
+// Select the first task to run on this core and make it the current task.
+fn load_first_task() {
+    let selected = select_task(|scheduler| scheduler.pick_first());
+    check_saved_psp(&selected);
+    start_quantum(&selected);
+    #[cfg(feature="trace")]
+    ::trace::switch_in(&selected);
+    // UNSAFE: Accessing CURRENT_TASK
+    unsafe { *current_slot() = Some(selected) };
+}
+
+static STACK_OVERFLOW_HANDLER: Hook<fn(&TaskControl)> = Hook::new();
+
+/// Set a handler to be called when a task is found to have overflowed its stack.
+///
+/// A task's stack is checked every time it's switched out, both its stack pointer and a canary
+/// written to the bottom of the stack when it was created. When either shows the task has run off
+/// the end of its stack the handler is called with the task, then the kernel panics. The handler
+/// can report which task it was (its name, tid and stack bounds) or reset the device, but the
+/// memory below the stack may have been corrupted so it should do as little as it can and must not
+/// use any kernel services. Setting a new handler replaces the old one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{TaskControl, set_stack_overflow_handler};
+///
+/// fn report_overflow(task: &TaskControl) {
+///     let (bottom, top) = task.stack_bounds();
+///     // Write the task's name and stack bounds to the debug port...
+/// }
+///
+/// set_stack_overflow_handler(report_overflow);
+/// ```
+pub fn set_stack_overflow_handler(handler: fn(&TaskControl)) {
+    STACK_OVERFLOW_HANDLER.set(handler);
+}
+
+/// Remove the stack overflow handler, if one was set.
+pub fn clear_stack_overflow_handler() {
+    STACK_OVERFLOW_HANDLER.clear();
+}
+
+// Call the stack overflow handler with the task that overflowed, if there is one.
+fn call_stack_overflow_handler(task: &TaskControl) {
+    if let Some(handler) = STACK_OVERFLOW_HANDLER.get() {
+        handler(task);
+    }
+}
+
+/// Make sure the stack pointer about to be loaded into the PSP lies within the task's stack.
+///
+/// A corrupted stack pointer would otherwise be restored blindly and fault somewhere unrelated to
+/// the actual problem, so panic here with the name of the task instead.
+fn check_saved_psp(task: &TaskControl) {
+    if !task.is_stack_ptr_valid() {
+        #[cfg(feature="logger")]
+        ::logger::error(::logger::KernelEvent::CorruptStackPointer {
+            tid: task.tid(),
+            ptr: task.stack_ptr(),
+        });
+        let (bottom, top) = task.stack_bounds();
+        kernel_panic!("switch_context - corrupt PSP {:#x} for task {} '{}' (stack {:#x}..{:#x})",
+               task.stack_ptr(), task.tid(), task.name(), bottom, top);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -306,4 +689,213 @@ mod tests {
         switch_context();
         assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
     }
+
+    #[test]
+    fn test_default_policy_is_fifo() {
+        let _g = test::set_up();
+        assert_eq!(intra_priority_policy(), Policy::Fifo);
+    }
+
+    #[test]
+    fn test_fifo_policy_keeps_task_running_until_it_blocks() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::Fifo);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        for _ in 0..10 {
+            ::syscall::system_tick();
+            assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        }
+
+        test::block_current_task(Delay::Sleep);
+        switch_context();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        for _ in 0..10 {
+            ::syscall::system_tick();
+            assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        }
+    }
+
+    #[test]
+    fn test_fifo_policy_still_preempts_for_higher_priority() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::Fifo);
+        test::create_and_schedule_test_task(512, Priority::Normal, "normal task");
+        start_scheduler();
+
+        let handle = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        ::syscall::system_tick();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_round_robin_policy_rotates_equal_priority_tasks() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        for _ in 0..5 {
+            assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+            ::syscall::system_tick();
+            assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+            ::syscall::system_tick();
+        }
+    }
+
+    #[test]
+    fn test_round_robin_rotates_after_time_slice() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        set_time_slice(3);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        for _ in 0..2 {
+            for _ in 0..2 {
+                ::syscall::system_tick();
+                assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+            }
+            ::syscall::system_tick();
+            assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+            for _ in 0..2 {
+                ::syscall::system_tick();
+                assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+            }
+            ::syscall::system_tick();
+        }
+    }
+
+    #[test]
+    fn test_priority_time_slice_overrides_default() {
+        let _g = test::set_up();
+        set_time_slice(5);
+        set_priority_time_slice(Priority::Low, 20);
+        assert_eq!(time_slice(Priority::Normal), 5);
+        assert_eq!(time_slice(Priority::Low), 20);
+
+        set_time_slice(0);
+        assert_eq!(time_slice(Priority::Normal), 1);
+    }
+
+    #[test]
+    fn test_cooperative_mode_only_switches_on_yield() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        set_cooperative(true);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        for _ in 0..5 {
+            ::syscall::system_tick();
+            assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        }
+
+        ::syscall::sched_yield();
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+        ::syscall::sleep(0x1234);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        // Waking the critical task doesn't take the CPU away either
+        ::syscall::wake(0x1234);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_pinned_task_not_rotated_by_equal_priority() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        pin();
+        for _ in 0..5 {
+            ::syscall::system_tick();
+            assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        }
+
+        unpin();
+        ::syscall::system_tick();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_task_exempt_from_time_slicing_not_rotated() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        fn test_task(_args: &mut ::task::args::Args) {}
+        let exempt = ::task::TaskBuilder::new(test_task).time_sliced(false).spawn().unwrap();
+        let sliced_1 = test::create_and_schedule_test_task(512, Priority::Normal, "sliced 1");
+        let sliced_2 = test::create_and_schedule_test_task(512, Priority::Normal, "sliced 2");
+        start_scheduler();
+
+        for _ in 0..5 {
+            ::syscall::system_tick();
+            assert_eq!(exempt.tid(), Ok(test::current_task().unwrap().tid()));
+        }
+
+        // Once it yields the other tasks go back to taking turns
+        ::syscall::sched_yield();
+        assert_eq!(sliced_1.tid(), Ok(test::current_task().unwrap().tid()));
+        ::syscall::system_tick();
+        assert_eq!(sliced_2.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_pinned_task_still_preempted_by_higher_priority() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        let (handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        pin();
+        ::syscall::system_tick();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        let handle = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        ::syscall::system_tick();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt PSP")]
+    fn test_switch_context_traps_corrupt_psp() {
+        let _g = test::set_up();
+        let (_handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        // Simulate the PendSV handler having saved a garbage PSP for the task that's switched in
+        // next, the saved stack pointer is the first word of the control block.
+        let task_2 = test::convert_handle_to_task_control(handle_2);
+        unsafe { *(task_2 as *const TaskControl as *mut usize) = 0x4 };
+
+        switch_context();
+    }
+
+    static OVERFLOWED_TID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_overflow(task: &TaskControl) {
+        OVERFLOWED_TID.store(task.tid(), Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_overwritten_canary_calls_overflow_handler() {
+        use std::panic;
+        let _g = test::set_up();
+        OVERFLOWED_TID.store(!0, Ordering::SeqCst);
+        set_stack_overflow_handler(record_overflow);
+        test::create_two_tasks();
+        start_scheduler();
+
+        // The stack pointer is back within the stack, but the canary at the bottom was written over
+        let current = test::current_task().unwrap();
+        let tid = current.tid();
+        let (bottom, _) = current.stack_bounds();
+        unsafe { *(bottom as *mut usize) = 0 };
+
+        assert!(panic::catch_unwind(|| switch_context()).is_err());
+        assert_eq!(OVERFLOWED_TID.load(Ordering::SeqCst), tid);
+    }
 }
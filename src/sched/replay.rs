@@ -0,0 +1,237 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Deterministic replay of scheduling decisions.
+//!
+//! Concurrency bugs often depend on the exact order tasks were scheduled in, which makes them hard
+//! to reproduce. In record mode the scheduler saves the tid of every task it picks to run, in
+//! replay mode it is forced to pick the same tasks again in the same order.
+//!
+//! Task ids are handed out in creation order, so as long as the application creates its tasks in
+//! the same order a schedule recorded on one run refers to the same tasks on the next. Only tasks
+//! that are ready to run can be chosen, if a recorded task isn't ready when its turn comes the
+//! replay is abandoned, the scheduler goes back to making its own decisions and `has_diverged`
+//! will return `true`.
+
+use super::scheduler;
+use task::TaskControl;
+use collections::NodeBox;
+use sync::{SpinMutex, CriticalSection};
+
+/// The maximum number of scheduling decisions that can be recorded.
+pub const SCHEDULE_CAPACITY: usize = 256;
+
+static REPLAY: SpinMutex<Replay> = SpinMutex::new(Replay::new());
+
+/// A recorded sequence of scheduling decisions.
+#[derive(Copy, Clone)]
+pub struct Schedule {
+    choices: [usize; SCHEDULE_CAPACITY],
+    len: usize,
+}
+
+impl Schedule {
+    const fn new() -> Self {
+        Schedule {
+            choices: [0; SCHEDULE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// The tids of the tasks that were chosen to run, in the order they were chosen.
+    pub fn choices(&self) -> &[usize] {
+        &self.choices[..self.len]
+    }
+
+    /// Returns `true` if no more decisions can be recorded into this schedule.
+    pub fn is_full(&self) -> bool {
+        self.len == SCHEDULE_CAPACITY
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Off,
+    Record,
+    Replay,
+}
+
+struct Replay {
+    mode: Mode,
+    schedule: Schedule,
+    cursor: usize,
+    diverged: bool,
+}
+
+impl Replay {
+    const fn new() -> Self {
+        Replay {
+            mode: Mode::Off,
+            schedule: Schedule::new(),
+            cursor: 0,
+            diverged: false,
+        }
+    }
+}
+
+/// Start recording the scheduler's decisions, discarding any previous recording.
+///
+/// Once `SCHEDULE_CAPACITY` decisions have been recorded any further decisions are dropped.
+pub fn start_recording() {
+    let _g = CriticalSection::begin();
+    let mut replay = REPLAY.lock();
+    replay.mode = Mode::Record;
+    replay.schedule = Schedule::new();
+    replay.diverged = false;
+}
+
+/// Stop recording or replaying, returning the schedule that was recorded.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::replay;
+///
+/// replay::start_recording();
+/// // Run until the bug shows up...
+/// let schedule = replay::stop();
+///
+/// // After a restart, make the scheduler take the same decisions
+/// replay::start_replay(&schedule);
+/// ```
+pub fn stop() -> Schedule {
+    let _g = CriticalSection::begin();
+    let mut replay = REPLAY.lock();
+    replay.mode = Mode::Off;
+    replay.schedule
+}
+
+/// Force the scheduler to make the decisions in `schedule`.
+///
+/// Once every decision in the schedule has been made the scheduler goes back to normal.
+pub fn start_replay(schedule: &Schedule) {
+    let _g = CriticalSection::begin();
+    let mut replay = REPLAY.lock();
+    replay.mode = Mode::Replay;
+    replay.schedule = *schedule;
+    replay.cursor = 0;
+    replay.diverged = false;
+}
+
+/// Returns `true` if the last replay had to be abandoned because a recorded task wasn't ready.
+pub fn has_diverged() -> bool {
+    let _g = CriticalSection::begin();
+    REPLAY.lock().diverged
+}
+
+// Record that the task with id `tid` was chosen to run.
+#[doc(hidden)]
+pub fn record_choice(tid: usize) {
+    let mut replay = REPLAY.lock();
+    if replay.mode == Mode::Record && !replay.schedule.is_full() {
+        let len = replay.schedule.len;
+        replay.schedule.choices[len] = tid;
+        replay.schedule.len += 1;
+    }
+}
+
+// Take the next task the schedule says should run off of its ready queue, if we're replaying.
+#[doc(hidden)]
+pub fn forced_choice() -> Option<NodeBox<TaskControl>> {
+    let mut replay = REPLAY.lock();
+    if replay.mode != Mode::Replay {
+        return None;
+    }
+    if replay.cursor == replay.schedule.len {
+        replay.mode = Mode::Off;
+        return None;
+    }
+
+    let tid = replay.schedule.choices[replay.cursor];
+    replay.cursor += 1;
+    match scheduler().remove_task(tid) {
+        Some(ref task) if task.is_destroyed() => {},
+        Some(task) => return Some(task),
+        None => {},
+    }
+
+    replay.mode = Mode::Off;
+    replay.diverged = true;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{start_scheduler, switch_context, set_intra_priority_policy, Policy};
+    use task::Priority;
+    use std::vec::Vec;
+    use test;
+
+    fn run_order(switches: usize) -> Vec<usize> {
+        let mut order = Vec::new();
+        for _ in 0..switches {
+            switch_context();
+            order.push(test::current_task().unwrap().tid());
+        }
+        order
+    }
+
+    #[test]
+    fn test_replayed_schedule_runs_tasks_in_recorded_order() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        test::create_and_schedule_test_task(512, Priority::Normal, "task 1");
+        test::create_and_schedule_test_task(512, Priority::Normal, "task 2");
+        test::create_and_schedule_test_task(512, Priority::Normal, "task 3");
+        start_scheduler();
+
+        start_recording();
+        let recorded = run_order(6);
+        let schedule = stop();
+        assert_eq!(schedule.choices(), &recorded[..]);
+
+        // Knock the round robin rotation out of step, left alone it wouldn't repeat the order
+        switch_context();
+
+        start_replay(&schedule);
+        let replayed = run_order(6);
+        assert_eq!(replayed, recorded);
+        assert_not!(has_diverged());
+    }
+
+    #[test]
+    fn test_replay_diverges_when_recorded_task_is_not_ready() {
+        let _g = test::set_up();
+        let mut handle = test::create_and_schedule_test_task(512, Priority::Normal, "task 1");
+        test::create_and_schedule_test_task(512, Priority::Normal, "task 2");
+        start_scheduler();
+
+        start_recording();
+        switch_context();
+        switch_context();
+        let schedule = stop();
+        assert_eq!(schedule.choices()[1], handle.tid().unwrap());
+
+        handle.destroy();
+        start_replay(&schedule);
+        switch_context();
+        switch_context();
+        assert!(has_diverged());
+        stop();
+    }
+}
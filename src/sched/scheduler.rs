@@ -0,0 +1,517 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Pluggable scheduling policies.
+//!
+//! The kernel keeps track of blocked and sleeping tasks itself, but every task that is ready to
+//! run is handed off to the installed `Scheduler`, which decides which one runs next. By default
+//! the `PriorityScheduler` is used, an application can install its own scheduler at init to try
+//! out a different scheduling policy without rebuilding the kernel.
+
+use super::{PRIORITY_QUEUES, NORMAL_TASK_COUNTER, NORMAL_TASK_MAX, Policy, intra_priority_policy};
+use super::{quantum_expired, current_core, edf};
+use task::{TaskControl, Priority, NUM_PRIORITIES};
+use collections::{NodeBox, SyncQueue};
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use sync::CriticalSection;
+use core::cell::Cell;
+use tick;
+
+#[doc(hidden)]
+pub static DEFAULT_SCHEDULER: PriorityScheduler = PriorityScheduler;
+
+static mut SCHEDULER: &'static Scheduler = &DEFAULT_SCHEDULER;
+
+// One bit per priority, set when a task is added to that priority's ready queue. Bits are cleared
+// lazily when the queue is found empty, so a set bit only means the queue MAY have a task in it.
+// Critical is bit 0, so the lowest set bit is the highest priority with a task ready to run.
+static READY_PRIORITIES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+const ALL_PRIORITIES: usize = (1 << NUM_PRIORITIES) - 1;
+
+/// A policy for choosing which ready task should run next.
+///
+/// A scheduler owns every task that is ready to run. The kernel hands tasks to it with `add_task`
+/// when they are created, woken up or switched out, and takes them back with `pick_next` when it
+/// needs something to run. It's told when the running task blocks with `on_block` and on every
+/// system tick with `on_tick`, everything else about how tasks are chosen is up to the scheduler.
+/// All methods are called from within the kernel with interrupts disabled, so they should be kept
+/// short, `pick_next` in particular is called on every context switch.
+///
+/// The idle task is added to the scheduler like any other task when the system starts, so
+/// `pick_next` always has at least one task to choose from.
+///
+/// Tasks are handed over in a `NodeBox`, which may hold a task that lives in static memory.
+/// Dropping one is always safe, a static task is never handed to the heap.
+pub trait Scheduler: Sync {
+    /// Add a task that is ready to run.
+    fn add_task(&self, task: NodeBox<TaskControl>);
+
+    /// Remove the ready task with the id `tid`, returning it if the scheduler was holding it.
+    fn remove_task(&self, tid: usize) -> Option<NodeBox<TaskControl>>;
+
+    /// Remove and return the task that should run next.
+    fn pick_next(&self) -> NodeBox<TaskControl>;
+
+    /// Remove and return the first task to run when the system starts.
+    ///
+    /// This defaults to `pick_next`, schedulers that keep statistics about their choices can use it
+    /// to keep the initial choice out of them.
+    fn pick_first(&self) -> NodeBox<TaskControl> {
+        self.pick_next()
+    }
+
+    /// Called on every system tick with the running task, return `true` if it should be preempted.
+    ///
+    /// A pinned task (see `pin`) must not be preempted in favor of a task with the same or a lower
+    /// priority.
+    fn on_tick(&self, current: &TaskControl) -> bool;
+
+    /// Called after tasks have been woken up, return `true` if one of them should preempt the
+    /// running task right away instead of waiting for the next system tick.
+    ///
+    /// This defaults to `false`, so the woken tasks are only considered on the next tick.
+    fn should_preempt(&self, _current: &TaskControl) -> bool {
+        false
+    }
+
+    /// Called when the running task blocks, before it's put on the kernel's sleep or delay queue.
+    ///
+    /// The task isn't handed to the scheduler while it's blocked, it comes back through `add_task`
+    /// once it's woken up. This defaults to doing nothing, schedulers that track how long tasks run
+    /// for can use it to close out the task's run.
+    fn on_block(&self, _task: &TaskControl) {}
+
+    /// Called when the running task yields, return `false` if `pick_next` would just pick it again.
+    ///
+    /// The kernel skips the context switch when this returns `false`. This defaults to `true`, so
+    /// every yield switches unless a scheduler knows better.
+    fn should_yield(&self, _current: &TaskControl) -> bool {
+        true
+    }
+
+    /// Run `block` on every task the scheduler is holding.
+    fn modify_all(&self, block: &Fn(&mut TaskControl));
+}
+
+/// Install a new scheduler.
+///
+/// This must be done before any tasks are created, tasks already held by the old scheduler are not
+/// moved over to the new one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{install_scheduler, PriorityScheduler};
+///
+/// static SCHEDULER: PriorityScheduler = PriorityScheduler;
+///
+/// install_scheduler(&SCHEDULER);
+/// ```
+pub fn install_scheduler(scheduler: &'static Scheduler) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing can be using the scheduler while we swap it
+    unsafe { SCHEDULER = scheduler };
+}
+
+/// Get the installed scheduler.
+#[doc(hidden)]
+pub fn scheduler() -> &'static Scheduler {
+    // UNSAFE: The scheduler is only changed inside of a critical section
+    unsafe { SCHEDULER }
+}
+
+/// The default priority based scheduler.
+///
+/// Tasks are run in order of their `Priority`, tasks with the same priority share the CPU according
+/// to the policy selected with `set_intra_priority_policy`. To keep low priority tasks from
+/// starving, every so often a low priority task is given a chance to run over normal priority
+/// tasks.
+///
+/// Each priority has its own ready queue, and a bitmap of the priorities with ready tasks lets the
+/// highest priority task be found in constant time no matter how many tasks there are. A task that
+/// is woken up with a higher priority than the running task preempts it immediately.
+pub struct PriorityScheduler;
+
+impl PriorityScheduler {
+    // Find the highest priority out of the `allowed` mask that has a task ready to run, clearing
+    // the bits of any queues that turn out to be empty along the way.
+    fn highest_ready(&self, allowed: usize) -> Option<usize> {
+        loop {
+            let ready = READY_PRIORITIES.load(Ordering::Relaxed) & allowed;
+            if ready == 0 {
+                return None;
+            }
+            let priority = ready.trailing_zeros() as usize;
+            if !PRIORITY_QUEUES[priority].is_empty() {
+                return Some(priority);
+            }
+            READY_PRIORITIES.fetch_and(!(1 << priority), Ordering::SeqCst);
+            // A task may have been added between the check and the clear, its bit has to be put
+            // back or it won't be found until another task is added at its priority
+            if !PRIORITY_QUEUES[priority].is_empty() {
+                READY_PRIORITIES.fetch_or(1 << priority, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Take the next task to run on this core out of the ready queue for `priority`, under EDF this
+    // is the task with the earliest deadline rather than the first one in the queue. Returns `None`
+    // if none of the tasks at `priority` are allowed to run on this core.
+    fn dequeue_from(&self, priority: usize) -> Option<NodeBox<TaskControl>> {
+        let queue = &PRIORITY_QUEUES[priority];
+        let core = current_core();
+        let by_deadline = intra_priority_policy() == Policy::Edf;
+        // Usually the task at the front of the queue is free to run, so skip searching for one
+        if !by_deadline && queue.try_peek(|task| task.can_run_on(core)).unwrap_or(true) {
+            return queue.dequeue();
+        }
+
+        let now = tick::get_tick();
+        let mut tasks = queue.remove_all();
+        let chosen = tasks.iter()
+            .filter(|task| task.can_run_on(core))
+            .fold(None, |best: Option<&TaskControl>, task| match best {
+                Some(best) if !by_deadline || !edf::is_earlier(task.deadline(), best.deadline(), now) => {
+                    Some(best)
+                },
+                _ => Some(task),
+            })
+            .map(|task| task.tid());
+        let chosen = chosen.and_then(|tid| tasks.remove(|task| task.tid() == tid).dequeue());
+        queue.append(tasks);
+        chosen
+    }
+
+    // Check if a task with an earlier deadline than `current` is waiting at its priority.
+    fn has_earlier_deadline(&self, current: &TaskControl) -> bool {
+        let now = tick::get_tick();
+        let core = current_core();
+        let found = Cell::new(false);
+        PRIORITY_QUEUES[current.priority()].modify_all(|task| {
+            if task.can_run_on(core) && edf::is_earlier(task.deadline(), current.deadline(), now) {
+                found.set(true);
+            }
+        });
+        found.get()
+    }
+
+    // Select the first available task from the priorities in the `allowed` mask.
+    //
+    // If no task is found, the function panics, but this should not happen due to the idle task.
+    fn pick_from(&self, mut allowed: usize) -> NodeBox<TaskControl> {
+        while let Some(priority) = self.highest_ready(allowed) {
+            while let Some(new_task) = self.dequeue_from(priority) {
+                if new_task.is_destroyed() {
+                    drop(new_task);
+                } else {
+                    return new_task;
+                }
+            }
+            // Whatever is left at this priority has to run on another core
+            allowed &= !(1 << priority);
+        }
+        kernel_panic!("select_task - task not selected!");
+    }
+}
+
+// The bit for `priority` in the ready bitmap.
+fn priority_bit(priority: Priority) -> usize {
+    1 << (priority as usize)
+}
+
+// Check if any of the tasks in `queue` is allowed to run on this core.
+fn has_task_for_core(queue: &SyncQueue<TaskControl>) -> bool {
+    let core = current_core();
+    // Usually the task at the front of the queue is free to run, so skip searching for one
+    match queue.try_peek(|task| task.can_run_on(core)) {
+        Some(false) => {
+            let found = Cell::new(false);
+            queue.modify_all(|task| {
+                if task.can_run_on(core) {
+                    found.set(true);
+                }
+            });
+            found.get()
+        },
+        Some(true) => true,
+        None => false,
+    }
+}
+
+impl Scheduler for PriorityScheduler {
+    fn add_task(&self, task: NodeBox<TaskControl>) {
+        let priority = task.priority();
+        PRIORITY_QUEUES[priority].enqueue(task);
+        READY_PRIORITIES.fetch_or(priority_bit(priority), Ordering::Relaxed);
+    }
+
+    fn remove_task(&self, tid: usize) -> Option<NodeBox<TaskControl>> {
+        for priority in Priority::all() {
+            let mut found = PRIORITY_QUEUES[priority].remove(|task| task.tid() == tid);
+            if let Some(task) = found.dequeue() {
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    fn pick_next(&self) -> NodeBox<TaskControl> {
+        // If more than NORMAL_TASK_MAX Normal tasks have run, don't try and schedule
+        // a normal priorty task, instead giving a low priority task a shot at running.
+        let selected = if NORMAL_TASK_COUNTER.load(Ordering::Relaxed) >= NORMAL_TASK_MAX {
+            NORMAL_TASK_COUNTER.store(0, Ordering::Relaxed);
+            self.pick_from(ALL_PRIORITIES & !priority_bit(Priority::Normal))
+        }
+        else {
+            self.pick_from(ALL_PRIORITIES)
+        };
+        if let Priority::Normal = selected.priority() {
+            NORMAL_TASK_COUNTER.fetch_add(1, Ordering::Relaxed);
+        }
+        selected
+    }
+
+    fn pick_first(&self) -> NodeBox<TaskControl> {
+        self.pick_from(ALL_PRIORITIES)
+    }
+
+    fn on_tick(&self, current: &TaskControl) -> bool {
+        let current_priority = current.priority();
+        let policy = intra_priority_policy();
+        for i in Priority::higher(current_priority) {
+            // Under EDF a task of the same priority only preempts if it's due sooner
+            if i == current_priority && policy == Policy::Edf && !current.is_pinned() {
+                return self.has_earlier_deadline(current);
+            }
+            // Under FIFO, or while the task is pinned or exempt from time slicing, tasks of the
+            // same priority keep running until they block or yield. Under round robin they keep
+            // running until their time slice is up.
+            if i == current_priority &&
+                (policy == Policy::Fifo || current.is_pinned() || !current.is_time_sliced() ||
+                 !quantum_expired()) {
+                break;
+            }
+            if has_task_for_core(&PRIORITY_QUEUES[i]) {
+                // Only context switch if there's another task at a priority level allowed to preempt
+                return true;
+            }
+        }
+        false
+    }
+
+    fn should_preempt(&self, current: &TaskControl) -> bool {
+        // Only strictly higher priorities, the bits below the current priority's
+        let mut higher = priority_bit(current.priority()) - 1;
+        while let Some(priority) = self.highest_ready(higher) {
+            if has_task_for_core(&PRIORITY_QUEUES[priority]) {
+                return true;
+            }
+            // The tasks at this priority have to run on another core
+            higher &= !(1 << priority);
+        }
+        false
+    }
+
+    fn should_yield(&self, current: &TaskControl) -> bool {
+        let current_priority = current.priority();
+        // Every so often a low priority task gets its turn over a normal priority one
+        if current_priority == Priority::Normal &&
+            NORMAL_TASK_COUNTER.load(Ordering::Relaxed) >= NORMAL_TASK_MAX &&
+            has_task_for_core(&PRIORITY_QUEUES[Priority::Low]) {
+            return true;
+        }
+        Priority::higher(current_priority)
+            .any(|priority| has_task_for_core(&PRIORITY_QUEUES[priority]))
+    }
+
+    fn modify_all(&self, block: &Fn(&mut TaskControl)) {
+        for queue in PRIORITY_QUEUES.iter() {
+            queue.modify_all(block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{start_scheduler, switch_context};
+    use collections::SyncQueue;
+    use syscall;
+    use test;
+
+    // A scheduler that ignores priorities and runs every task in turn
+    struct RoundRobinScheduler {
+        queue: SyncQueue<TaskControl>,
+    }
+
+    impl Scheduler for RoundRobinScheduler {
+        fn add_task(&self, task: NodeBox<TaskControl>) {
+            self.queue.enqueue(task);
+        }
+
+        fn remove_task(&self, tid: usize) -> Option<NodeBox<TaskControl>> {
+            self.queue.remove(|task| task.tid() == tid).dequeue()
+        }
+
+        fn pick_next(&self) -> NodeBox<TaskControl> {
+            self.queue.dequeue().expect("RoundRobinScheduler - no tasks to run!")
+        }
+
+        fn on_tick(&self, _current: &TaskControl) -> bool {
+            !self.queue.is_empty()
+        }
+
+        fn modify_all(&self, block: &Fn(&mut TaskControl)) {
+            self.queue.modify_all(block);
+        }
+    }
+
+    static ROUND_ROBIN: RoundRobinScheduler = RoundRobinScheduler { queue: SyncQueue::new() };
+
+    // Wraps the default scheduler, counting the tasks that block
+    struct CountingScheduler {
+        blocked: AtomicUsize,
+    }
+
+    impl Scheduler for CountingScheduler {
+        fn add_task(&self, task: NodeBox<TaskControl>) {
+            DEFAULT_SCHEDULER.add_task(task);
+        }
+
+        fn remove_task(&self, tid: usize) -> Option<NodeBox<TaskControl>> {
+            DEFAULT_SCHEDULER.remove_task(tid)
+        }
+
+        fn pick_next(&self) -> NodeBox<TaskControl> {
+            DEFAULT_SCHEDULER.pick_next()
+        }
+
+        fn on_tick(&self, current: &TaskControl) -> bool {
+            DEFAULT_SCHEDULER.on_tick(current)
+        }
+
+        fn on_block(&self, _task: &TaskControl) {
+            self.blocked.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn modify_all(&self, block: &Fn(&mut TaskControl)) {
+            DEFAULT_SCHEDULER.modify_all(block);
+        }
+    }
+
+    static COUNTING: CountingScheduler = CountingScheduler { blocked: ATOMIC_USIZE_INIT };
+
+    #[test]
+    fn test_installed_scheduler_picks_tasks() {
+        let _g = test::set_up();
+        ROUND_ROBIN.queue.remove_all();
+        install_scheduler(&ROUND_ROBIN);
+
+        let low = test::create_and_schedule_test_task(512, Priority::Low, "low task");
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        let normal = test::create_and_schedule_test_task(512, Priority::Normal, "normal task");
+        start_scheduler();
+
+        // Priorities are ignored, tasks run in the order they were added, followed by idle
+        for _ in 0..3 {
+            assert_eq!(low.tid(), Ok(test::current_task().unwrap().tid()));
+            syscall::system_tick();
+            assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+            syscall::system_tick();
+            assert_eq!(normal.tid(), Ok(test::current_task().unwrap().tid()));
+            switch_context();
+            assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+            switch_context();
+        }
+        ROUND_ROBIN.queue.remove_all();
+    }
+
+    #[test]
+    fn test_scheduler_told_when_task_blocks() {
+        let _g = test::set_up();
+        COUNTING.blocked.store(0, Ordering::Relaxed);
+        install_scheduler(&COUNTING);
+        test::create_two_tasks();
+        start_scheduler();
+
+        syscall::sched_yield();
+        assert_eq!(COUNTING.blocked.load(Ordering::Relaxed), 0);
+        syscall::sleep(0x1234);
+        assert_eq!(COUNTING.blocked.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_ready_bitmap_follows_queues() {
+        let _g = test::set_up();
+        let low = test::create_and_schedule_test_task(512, Priority::Low, "low task");
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        assert_eq!(DEFAULT_SCHEDULER.highest_ready(ALL_PRIORITIES), Some(Priority::Critical as usize));
+
+        let task = DEFAULT_SCHEDULER.pick_next();
+        assert_eq!(critical.tid(), Ok(task.tid()));
+        // The critical queue is empty now, so its bit is cleared the next time it's looked at
+        assert_eq!(DEFAULT_SCHEDULER.highest_ready(ALL_PRIORITIES), Some(Priority::Low as usize));
+        assert_eq!(READY_PRIORITIES.load(Ordering::Relaxed) & priority_bit(Priority::Critical), 0);
+
+        // A higher priority task being ready is enough to preempt, an equal one isn't
+        assert_not!(DEFAULT_SCHEDULER.should_preempt(&task));
+        let low_task = DEFAULT_SCHEDULER.pick_next();
+        assert_eq!(low.tid(), Ok(low_task.tid()));
+        DEFAULT_SCHEDULER.add_task(task);
+        assert!(DEFAULT_SCHEDULER.should_preempt(&low_task));
+    }
+
+    #[test]
+    fn test_tasks_only_picked_for_allowed_cores() {
+        let _g = test::set_up();
+        let mut elsewhere = test::create_and_schedule_test_task(512, Priority::Critical, "elsewhere");
+        let here = test::create_and_schedule_test_task(512, Priority::Critical, "here");
+        let low = test::create_and_schedule_test_task(512, Priority::Low, "low task");
+        elsewhere.set_affinity(1 << (current_core() + 1)).unwrap();
+
+        // The task kept off this core is passed over, even once it's the only one at its priority
+        let task = DEFAULT_SCHEDULER.pick_next();
+        assert_eq!(here.tid(), Ok(task.tid()));
+        let task = DEFAULT_SCHEDULER.pick_next();
+        assert_eq!(low.tid(), Ok(task.tid()));
+
+        elsewhere.set_affinity(::sched::ALL_CORES).unwrap();
+        DEFAULT_SCHEDULER.add_task(task);
+        let task = DEFAULT_SCHEDULER.pick_next();
+        assert_eq!(elsewhere.tid(), Ok(task.tid()));
+    }
+
+    #[test]
+    fn test_task_for_another_core_doesnt_preempt() {
+        let _g = test::set_up();
+        let mut elsewhere = test::create_and_schedule_test_task(512, Priority::Critical, "elsewhere");
+        let normal = test::create_and_schedule_test_task(512, Priority::Normal, "normal");
+        elsewhere.set_affinity(1 << (current_core() + 1)).unwrap();
+
+        let task = DEFAULT_SCHEDULER.pick_next();
+        assert_eq!(normal.tid(), Ok(task.tid()));
+        assert_not!(DEFAULT_SCHEDULER.should_preempt(&task));
+        assert_not!(DEFAULT_SCHEDULER.should_yield(&task));
+        assert_not!(DEFAULT_SCHEDULER.on_tick(&task));
+
+        elsewhere.set_affinity(::sched::ALL_CORES).unwrap();
+        assert!(DEFAULT_SCHEDULER.should_preempt(&task));
+        assert!(DEFAULT_SCHEDULER.should_yield(&task));
+        assert!(DEFAULT_SCHEDULER.on_tick(&task));
+    }
+}
@@ -0,0 +1,282 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Scheduling on more than one core.
+//!
+//! With the `smp` feature the kernel runs tasks on `NUM_CORES` cores at once, like both cores of
+//! an RP2040. Every core has its own running task (its entry in `CURRENT_TASK`) and its own idle
+//! task, and they all take tasks from the same ready queues, so a task runs on whichever core gets
+//! to it first unless its affinity keeps it to some of them (see `TaskHandle::set_affinity`).
+//!
+//! The cores are kept out of each other's way by the critical section: on top of disabling
+//! interrupts, the port's `__begin_critical` must take a lock shared by every core, such as one of
+//! the RP2040's hardware spinlocks. The port also provides `__current_core`, `__start_core` to get
+//! the other cores going, and `__interrupt_core` to raise an interrupt on another core, whose
+//! handler must call `handle_core_interrupt`. Its context switch handler and `__start_first_task`
+//! load the running task from the `CURRENT_TASK` entry of the core they run on, and its system
+//! calls must each run inside of a critical section.
+//!
+//! `start_scheduler` is called on core 0, which starts the others once it has picked its own first
+//! task. Only core 0 takes the system tick, it passes every tick on to the other cores so they can
+//! account it to their own running task, and when a task is woken up that should preempt the task
+//! running on another core that core is interrupted to switch to it.
+
+use atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use sync::CriticalSection;
+use task::State;
+use arch;
+use super::{NUM_CORES, CURRENT_TASK, current_core, current_task, scheduler, preempt};
+use super::is_cooperative;
+
+// Set for a core when core 0 has passed it a system tick it hasn't handled yet
+static TICK_PENDING: [AtomicBool; NUM_CORES] = [ATOMIC_BOOL_INIT, ATOMIC_BOOL_INIT];
+
+// Start every core other than this one, each picks its first task in `run_core`.
+pub(super) fn start_other_cores() {
+    let this_core = current_core();
+    for core in (0..NUM_CORES).filter(|&core| core != this_core) {
+        arch::start_core(core, run_core);
+    }
+}
+
+// Where every core other than the first starts running.
+fn run_core() {
+    let g = CriticalSection::begin();
+    super::load_first_task();
+    drop(g);
+    arch::start_first_task();
+}
+
+// Pass the system tick on to the other cores, called from the system tick handler on core 0.
+pub(crate) fn tick_other_cores() {
+    let this_core = current_core();
+    for core in (0..NUM_CORES).filter(|&core| core != this_core) {
+        TICK_PENDING[core].store(true, Ordering::Relaxed);
+        arch::interrupt_core(core);
+    }
+}
+
+// Interrupt every other core that's running a task one of the tasks that was just woken up should
+// preempt.
+pub(crate) fn preempt_other_cores() {
+    let _g = CriticalSection::begin();
+    let this_core = current_core();
+    for core in (0..NUM_CORES).filter(|&core| core != this_core) {
+        // UNSAFE: We're in a critical section, so the other core can't be switching its task out
+        let running = unsafe { CURRENT_TASK[core].as_ref() };
+        if let Some(running) = running {
+            if running.state() != State::Blocked && scheduler().should_preempt(running) {
+                arch::interrupt_core(core);
+            }
+        }
+    }
+}
+
+/// Handle an interrupt raised on this core by another one.
+///
+/// The port's handler for the interrupt raised by `__interrupt_core` must call this. It accounts
+/// any system tick passed on from core 0 to the task running on this core, and switches the task
+/// out if a task woken up on another core should preempt it.
+pub fn handle_core_interrupt() {
+    let _g = CriticalSection::begin();
+    let core = current_core();
+    if TICK_PENDING[core].load(Ordering::Relaxed) {
+        TICK_PENDING[core].store(false, Ordering::Relaxed);
+        ::syscall::core_tick();
+    }
+
+    if is_cooperative() {
+        return;
+    }
+    // UNSAFE: Accessing the current task
+    if let Some(current) = unsafe { current_task() } {
+        if current.state() != State::Blocked && scheduler().should_preempt(current) {
+            preempt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{start_scheduler, set_intra_priority_policy, Policy};
+    use task::Priority;
+    use syscall;
+    use test;
+
+    // Carry on with the test as if it were running on `core`
+    fn on_core(core: usize) {
+        arch::CORE.store(core, Ordering::Relaxed);
+    }
+
+    fn running_tid() -> usize {
+        test::current_task().unwrap().tid()
+    }
+
+    #[test]
+    fn test_each_core_runs_its_own_task() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        assert_eq!(arch::STARTED_CORES.load(Ordering::Relaxed), 1 << 1);
+        assert_eq!(handle_1.tid(), Ok(running_tid()));
+
+        // Core 1 picks its first task once it's started
+        on_core(1);
+        run_core();
+        assert_eq!(handle_2.tid(), Ok(running_tid()));
+
+        on_core(0);
+        assert_eq!(handle_1.tid(), Ok(running_tid()));
+    }
+
+    #[test]
+    fn test_core_only_runs_tasks_its_allowed_to() {
+        let _g = test::set_up();
+        let mut pinned = test::create_and_schedule_test_task(512, Priority::Normal, "core 1 only");
+        pinned.set_affinity(1 << 1).unwrap();
+
+        start_scheduler();
+        assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+
+        on_core(1);
+        run_core();
+        assert_eq!(pinned.tid(), Ok(running_tid()));
+    }
+
+    #[test]
+    fn test_woken_task_preempts_task_on_other_core() {
+        let _g = test::set_up();
+        let high = test::create_and_schedule_test_task(512, Priority::Critical, "high");
+        let waiter = test::create_and_schedule_test_task(512, Priority::Critical, "waiter");
+        let normal = test::create_and_schedule_test_task(512, Priority::Normal, "normal");
+        let wchan = 0x1234;
+
+        start_scheduler();
+        on_core(1);
+        run_core();
+        assert_eq!(waiter.tid(), Ok(running_tid()));
+        syscall::sleep(wchan);
+        assert_eq!(normal.tid(), Ok(running_tid()));
+
+        // Core 0's task has the same priority as the woken one, so only core 1 is interrupted
+        on_core(0);
+        syscall::wake(wchan);
+        assert_eq!(high.tid(), Ok(running_tid()));
+        assert_eq!(arch::INTERRUPTED_CORES.load(Ordering::Relaxed), 1 << 1);
+
+        on_core(1);
+        handle_core_interrupt();
+        assert_eq!(waiter.tid(), Ok(running_tid()));
+    }
+
+    #[test]
+    fn test_tick_is_passed_on_to_other_cores() {
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        let handle_3 = test::create_and_schedule_test_task(512, Priority::Normal, "test task 3");
+
+        start_scheduler();
+        on_core(1);
+        run_core();
+
+        // Both cores use up their time slice, core 0 moves on first
+        on_core(0);
+        syscall::system_tick();
+        assert_eq!(handle_3.tid(), Ok(running_tid()));
+        assert_eq!(arch::INTERRUPTED_CORES.load(Ordering::Relaxed), 1 << 1);
+
+        on_core(1);
+        assert_eq!(handle_2.tid(), Ok(running_tid()));
+        handle_core_interrupt();
+        assert_eq!(handle_1.tid(), Ok(running_tid()));
+    }
+
+    #[test]
+    fn test_task_running_on_other_core_cant_be_restarted() {
+        use task::RestartError;
+        let _g = test::set_up();
+        let (handle_1, mut handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        on_core(1);
+        run_core();
+        assert_eq!(handle_2.tid(), Ok(running_tid()));
+
+        on_core(0);
+        assert_eq!(handle_1.tid(), Ok(running_tid()));
+        assert_eq!(handle_2.restart(), Err(RestartError::Running));
+        assert_eq!(handle_2.state(), Ok(State::Running));
+    }
+
+    #[test]
+    fn test_task_running_on_other_core_can_be_joined() {
+        use task::TaskBuilder;
+        use task::args::Args;
+        use syscall::{JOIN_WAITING, sys_join};
+        fn worker_task(_args: &mut Args) {}
+
+        let _g = test::set_up();
+        let joiner = test::create_and_schedule_test_task(512, Priority::Normal, "joiner");
+        let worker = TaskBuilder::new(worker_task)
+            .name("worker")
+            .joinable(true)
+            .spawn()
+            .unwrap();
+        let other = test::create_and_schedule_test_task(512, Priority::Normal, "other");
+
+        start_scheduler();
+        on_core(1);
+        run_core();
+        assert_eq!(worker.tid(), Ok(running_tid()));
+
+        // The worker isn't in any queue, but it's still running so the joiner has to wait for it
+        on_core(0);
+        assert_eq!(joiner.tid(), Ok(running_tid()));
+        assert_eq!(sys_join(&worker.id().unwrap()), (JOIN_WAITING, 0));
+        assert_eq!(other.tid(), Ok(running_tid()));
+    }
+
+    #[test]
+    fn test_suspending_the_scheduler_only_holds_back_this_core() {
+        use super::super::{suspend_scheduler, is_scheduler_suspended};
+        let _g = test::set_up();
+        set_intra_priority_policy(Policy::RoundRobin);
+        let (handle_1, handle_2) = test::create_two_tasks();
+        let handle_3 = test::create_and_schedule_test_task(512, Priority::Normal, "test task 3");
+
+        start_scheduler();
+        on_core(1);
+        run_core();
+        let suspended = suspend_scheduler();
+
+        // Core 0 still switches tasks while core 1's scheduler is suspended
+        on_core(0);
+        assert_not!(is_scheduler_suspended());
+        syscall::sched_yield();
+        assert_eq!(handle_3.tid(), Ok(running_tid()));
+
+        on_core(1);
+        assert!(is_scheduler_suspended());
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(running_tid()));
+        drop(suspended);
+        assert_eq!(handle_1.tid(), Ok(running_tid()));
+    }
+}
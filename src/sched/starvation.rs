@@ -0,0 +1,176 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Starvation detection for bring-up.
+//!
+//! With the `starvation` feature enabled the kernel counts how many ticks each ready task has spent
+//! waiting without getting to run. Once a task has waited for the starvation limit it's reported
+//! to the starvation hook, and if priority aging is turned on it's moved up a priority level until
+//! it next gets to run. A task that keeps starving after it's been aged is reported and aged again
+//! every time it waits out the limit.
+//!
+//! This is meant for catching misconfigured priorities during bring-up, counting the waiting ticks
+//! walks every ready task on every tick.
+
+use atomic::{AtomicBool, ATOMIC_BOOL_INIT, AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use core::cell::{Cell, RefCell};
+use task::{TaskControl, TaskHandle, Priority};
+use hook::Hook;
+use super::scheduler;
+
+// The number of ticks a ready task may wait before it's starving, 0 if starvation isn't watched
+static STARVATION_LIMIT: AtomicUsize = ATOMIC_USIZE_INIT;
+static STARVATION_HOOK: Hook<fn(TaskHandle)> = Hook::new();
+static PRIORITY_AGING: AtomicBool = ATOMIC_BOOL_INIT;
+
+// The most starving tasks aged on one tick, any more are left starving until the ticks after. The
+// tick handler can't allocate, so the tasks to age are collected in a fixed array.
+const MAX_AGED_PER_TICK: usize = 4;
+
+/// Start watching for ready tasks that go `ticks` ticks without running, 0 stops watching.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::TaskHandle;
+/// use altos_core::starvation;
+///
+/// fn report_starving(task: TaskHandle) {
+///     // Log the starving task's name...
+/// }
+///
+/// starvation::set_starvation_hook(report_starving);
+/// starvation::set_starvation_limit(500);
+/// ```
+pub fn set_starvation_limit(ticks: usize) {
+    STARVATION_LIMIT.store(ticks, Ordering::Relaxed);
+}
+
+/// The number of ticks a ready task may wait before it's starving, 0 if starvation isn't watched.
+pub fn starvation_limit() -> usize {
+    STARVATION_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Set a function to be called with a task's handle when it's starving.
+///
+/// The hook is run from the system tick handler, so it must be short and must not block.
+pub fn set_starvation_hook(hook: fn(TaskHandle)) {
+    STARVATION_HOOK.set(hook);
+}
+
+/// Stop reporting starving tasks.
+pub fn clear_starvation_hook() {
+    STARVATION_HOOK.clear();
+}
+
+/// Set whether starving tasks are moved up a priority level until they next get to run.
+///
+/// A task that holds a mutex keeps its aged priority until it releases the last one it holds.
+pub fn set_priority_aging(enabled: bool) {
+    PRIORITY_AGING.store(enabled, Ordering::Relaxed);
+}
+
+// Count a tick against every ready task, reporting and aging the ones that are starving. Called
+// from the system tick handler.
+#[doc(hidden)]
+pub fn check_starvation() {
+    let limit = STARVATION_LIMIT.load(Ordering::Relaxed);
+    if limit == 0 {
+        return;
+    }
+    let hook = STARVATION_HOOK.get();
+    let aging = PRIORITY_AGING.load(Ordering::Relaxed);
+
+    let starving = RefCell::new([0; MAX_AGED_PER_TICK]);
+    let count = Cell::new(0);
+    scheduler().modify_all(&|task| {
+        if task.priority() != Priority::__Idle && task.count_starved_tick() >= limit {
+            if aging && count.get() == MAX_AGED_PER_TICK {
+                return;
+            }
+            if let Some(hook) = hook {
+                hook(TaskHandle::new(task));
+            }
+            task.reset_starved_ticks();
+            if aging {
+                starving.borrow_mut()[count.get()] = task.tid();
+                count.set(count.get() + 1);
+            }
+        }
+    });
+
+    // The ready queues are picked by priority, so the task has to be taken out to be aged
+    for &tid in &starving.borrow()[..count.get()] {
+        if let Some(mut task) = scheduler().remove_task(tid) {
+            task.age_priority();
+            scheduler().add_task(task);
+        }
+    }
+}
+
+// Start the count over for a task that's just been switched out, dropping any priority it was
+// aged to.
+#[doc(hidden)]
+pub fn switched_out(task: &mut TaskControl) {
+    task.reset_starved_ticks();
+    task.drop_aged_priority();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{start_scheduler, set_intra_priority_policy, Policy};
+    use test;
+    use syscall;
+
+    static STARVED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_starved(task: TaskHandle) {
+        STARVED.store(task.tid().unwrap(), Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_starving_task_reported_and_aged() {
+        let _g = test::set_up();
+        STARVED.store(!0, Ordering::Relaxed);
+        set_starvation_hook(record_starved);
+        set_starvation_limit(5);
+        set_priority_aging(true);
+        set_intra_priority_policy(Policy::RoundRobin);
+        let busy = test::create_and_schedule_test_task(512, Priority::Normal, "busy");
+        let starved = test::create_and_schedule_test_task(512, Priority::Low, "starved");
+        start_scheduler();
+
+        for _ in 0..4 {
+            syscall::system_tick();
+        }
+        assert_eq!(STARVED.load(Ordering::Relaxed), !0);
+        assert_eq!(busy.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // On the fifth tick the low priority task is reported and moved up level with the busy one,
+        // so it gets a turn
+        syscall::system_tick();
+        assert_eq!(STARVED.load(Ordering::Relaxed), starved.tid().unwrap());
+        assert_eq!(starved.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(starved.priority(), Ok(Priority::Normal));
+
+        // Once it's had its turn it goes back to its own priority
+        syscall::sched_yield();
+        assert_eq!(busy.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(starved.priority(), Ok(Priority::Low));
+    }
+}
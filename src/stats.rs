@@ -0,0 +1,316 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Kernel statistics.
+//!
+//! This module keeps track of counters that describe how the kernel is using the system's
+//! resources. It is only available when the `stats` feature is enabled, as keeping the counters up
+//! to date adds a small amount of overhead to the paths that update them.
+
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+pub use task::task_switch_count;
+
+static HEAP_COUNTER: HeapCounter = HeapCounter::new();
+static SWITCH_COUNTER: SwitchCounter = SwitchCounter::new();
+
+/// A snapshot of the heap memory used by the kernel.
+///
+/// Only memory allocated by the kernel on behalf of tasks (their stacks and argument boxes) is
+/// accounted for, allocations made by task code itself are not tracked.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeapStats {
+    /// The number of bytes currently allocated.
+    pub current: usize,
+
+    /// The highest number of bytes that have been allocated at any one time.
+    pub peak: usize,
+
+    /// The total number of allocations that have been made.
+    pub allocations: usize,
+}
+
+/// Return a snapshot of the kernel's heap usage.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::stats::heap_stats;
+///
+/// let stats = heap_stats();
+/// assert!(stats.current <= stats.peak);
+/// ```
+pub fn heap_stats() -> HeapStats {
+    HEAP_COUNTER.stats()
+}
+
+#[doc(hidden)]
+pub fn record_alloc(size: usize) {
+    HEAP_COUNTER.alloc(size);
+}
+
+#[doc(hidden)]
+pub fn record_free(size: usize) {
+    HEAP_COUNTER.free(size);
+}
+
+/// A snapshot of how often the scheduler has switched between tasks.
+///
+/// Voluntary switches happen when a task yields, blocks or exits, and are a normal part of running.
+/// Preemptive switches happen when the kernel takes the CPU away from a task, like when a higher
+/// priority task becomes ready or a time slice runs out, a high rate of these points at tasks
+/// contending for the CPU.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SchedulerStats {
+    /// The number of context switches the running task asked for.
+    pub voluntary_switches: usize,
+
+    /// The number of context switches forced on the running task.
+    pub preemptive_switches: usize,
+
+    /// The number of system ticks the kernel has handled, ticks that happen while the tick is
+    /// suspended aren't counted.
+    pub ticks: usize,
+}
+
+impl SchedulerStats {
+    /// The total number of context switches, voluntary and preemptive.
+    pub fn context_switches(&self) -> usize {
+        self.voluntary_switches.wrapping_add(self.preemptive_switches)
+    }
+}
+
+/// Return a snapshot of the scheduler's context switch counters.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::stats::scheduler_stats;
+///
+/// let stats = scheduler_stats();
+/// // The average number of context switches per tick, no ticks have been handled right after boot
+/// if stats.ticks != 0 {
+///     let rate = stats.context_switches() / stats.ticks;
+/// }
+/// ```
+pub fn scheduler_stats() -> SchedulerStats {
+    SWITCH_COUNTER.stats()
+}
+
+#[doc(hidden)]
+pub fn record_switch(preemptive: bool) {
+    SWITCH_COUNTER.switch(preemptive);
+}
+
+#[doc(hidden)]
+pub fn record_tick() {
+    SWITCH_COUNTER.ticks.fetch_add(1, Ordering::Relaxed);
+}
+
+struct HeapCounter {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+}
+
+impl HeapCounter {
+    const fn new() -> Self {
+        HeapCounter {
+            current: ATOMIC_USIZE_INIT,
+            peak: ATOMIC_USIZE_INIT,
+            allocations: ATOMIC_USIZE_INIT,
+        }
+    }
+
+    fn alloc(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        let current = self.current.fetch_add(size, Ordering::Relaxed) + size;
+
+        // Only raise the peak, someone else may have raised it higher in the meantime
+        let mut peak = self.peak.load(Ordering::Relaxed);
+        while current > peak {
+            let old = self.peak.compare_and_swap(peak, current, Ordering::Relaxed);
+            if old == peak {
+                break;
+            }
+            peak = old;
+        }
+    }
+
+    fn free(&self, size: usize) {
+        self.current.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            current: self.current.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct SwitchCounter {
+    voluntary: AtomicUsize,
+    preemptive: AtomicUsize,
+    ticks: AtomicUsize,
+}
+
+impl SwitchCounter {
+    const fn new() -> Self {
+        SwitchCounter {
+            voluntary: ATOMIC_USIZE_INIT,
+            preemptive: ATOMIC_USIZE_INIT,
+            ticks: ATOMIC_USIZE_INIT,
+        }
+    }
+
+    fn switch(&self, preemptive: bool) {
+        if preemptive {
+            self.preemptive.fetch_add(1, Ordering::Relaxed);
+        }
+        else {
+            self.voluntary.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self) -> SchedulerStats {
+        SchedulerStats {
+            voluntary_switches: self.voluntary.load(Ordering::Relaxed),
+            preemptive_switches: self.preemptive.load(Ordering::Relaxed),
+            ticks: self.ticks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use sched;
+    use syscall;
+    use task::Priority;
+
+    #[test]
+    fn test_heap_counter_tracks_current_and_peak() {
+        let counter = HeapCounter::new();
+
+        // Spawn two tasks...
+        counter.alloc(512);
+        counter.alloc(256);
+        assert_eq!(counter.stats(), HeapStats { current: 768, peak: 768, allocations: 2 });
+
+        // ...then kill them
+        counter.free(512);
+        counter.free(256);
+        assert_eq!(counter.stats(), HeapStats { current: 0, peak: 768, allocations: 2 });
+
+        counter.alloc(128);
+        assert_eq!(counter.stats(), HeapStats { current: 128, peak: 768, allocations: 3 });
+    }
+
+    #[test]
+    fn test_spawning_a_task_records_its_allocations() {
+        let _g = test::set_up();
+        sched::start_scheduler();
+
+        let before = heap_stats();
+        test::create_and_schedule_test_task(512, Priority::Normal, "stats task");
+        let after = heap_stats();
+
+        // The task's stack and its arguments
+        assert_eq!(after.allocations, before.allocations + 2);
+        assert!(after.current >= before.current + 512);
+        assert!(after.peak >= after.current);
+    }
+
+    #[test]
+    fn test_destroying_a_task_frees_its_allocations() {
+        let _g = test::set_up();
+        sched::start_scheduler();
+
+        let before = heap_stats();
+        let mut handle = test::create_and_schedule_test_task(512, Priority::Normal, "stats task");
+        let spawned = heap_stats();
+        assert!(spawned.current >= before.current + 512);
+        assert!(spawned.peak >= spawned.current);
+
+        // A ready task is freed right away
+        assert!(handle.destroy());
+        let after = heap_stats();
+        assert_eq!(after.current, before.current);
+        assert_eq!(after.peak, spawned.peak);
+    }
+
+    #[test]
+    fn test_switches_attributed_to_their_cause() {
+        let _g = test::set_up();
+        sched::set_intra_priority_policy(sched::Policy::RoundRobin);
+        let (_task_1, task_2) = test::create_two_tasks();
+        let task_3 = test::create_and_schedule_test_task(512, Priority::Normal, "test task 3");
+        sched::start_scheduler();
+
+        // Blocking is a voluntary switch
+        let before = scheduler_stats();
+        syscall::sleep(1);
+        let after = scheduler_stats();
+        assert_eq!(task_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(after.voluntary_switches, before.voluntary_switches + 1);
+        assert_eq!(after.preemptive_switches, before.preemptive_switches);
+
+        // The time slice running out is a preemptive one
+        syscall::system_tick();
+        let last = scheduler_stats();
+        assert_eq!(task_3.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(last.voluntary_switches, after.voluntary_switches);
+        assert_eq!(last.preemptive_switches, after.preemptive_switches + 1);
+        assert_eq!(last.context_switches(), after.context_switches() + 1);
+    }
+
+    #[test]
+    fn test_only_handled_ticks_counted() {
+        use tick;
+
+        let _g = test::set_up();
+        test::create_two_tasks();
+        sched::start_scheduler();
+
+        let before = scheduler_stats();
+        syscall::system_tick();
+        syscall::system_tick();
+        tick::suspend_tick();
+        syscall::system_tick();
+        tick::resume_tick(0);
+        assert_eq!(scheduler_stats().ticks, before.ticks + 2);
+    }
+
+    #[test]
+    fn test_task_switch_count_only_counts_switches_to_the_task() {
+        let _g = test::set_up();
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        let normal = test::create_and_schedule_test_task(512, Priority::Normal, "normal task");
+        sched::start_scheduler();
+        assert_eq!(task_switch_count(&critical), Some(1));
+
+        // The critical task is picked again every time
+        for _ in 0..5 {
+            sched::switch_context();
+        }
+        assert_eq!(task_switch_count(&critical), Some(6));
+        assert_eq!(task_switch_count(&normal), Some(0));
+    }
+}
@@ -30,8 +30,17 @@ use sync::mutex::{RawMutex, MutexGuard};
 ///
 /// Each condition variable can be used with only one mutex at runtime. Any attempt to use multiple
 /// mutexes on the same condition variable will result in a panic.
+///
+/// # Deferred Signals
+///
+/// Notifying a condition variable while holding its mutex usually wakes the waiters only for them
+/// to block again trying to reacquire the mutex, which the notifier still holds. A condition
+/// variable created with `new_deferred()` avoids this by moving the waiters over to wait on the
+/// mutex instead (sometimes called "wait morphing"). They are woken when the notifier releases the
+/// mutex, at which point they can acquire it right away.
 pub struct CondVar {
     mutex: AtomicUsize,
+    deferred: bool,
 }
 
 unsafe impl Send for CondVar {}
@@ -42,6 +51,18 @@ impl CondVar {
     pub const fn new() -> Self {
         CondVar {
             mutex: ATOMIC_USIZE_INIT,
+            deferred: false,
+        }
+    }
+
+    /// Create a new `CondVar` that defers notifications made while its mutex is held.
+    ///
+    /// Waiters notified while the mutex is locked aren't woken until the mutex is released, see
+    /// the type level documentation for details.
+    pub const fn new_deferred() -> Self {
+        CondVar {
+            mutex: ATOMIC_USIZE_INIT,
+            deferred: true,
         }
     }
 
@@ -73,10 +94,44 @@ impl CondVar {
     /// This method will wake up any waiters on this condition variable. The calls to
     /// `notify_all()` are not buffered in any way. Calling `wait()` on another thread after
     /// calling `notify_all()` will still block the thread.
+    ///
+    /// If this condition variable defers notifications and its mutex is locked, the waiters are
+    /// only woken once the mutex is released.
     pub fn notify_all(&self) {
         ::syscall::condvar_broadcast(self);
     }
 
+    /// Wake up a single task that is blocked on this condition variable.
+    ///
+    /// The highest priority waiter is woken first, waiters with the same priority are woken in the
+    /// order they started waiting. Like `notify_all()`, the notification is not buffered, if no
+    /// task is waiting it is lost.
+    ///
+    /// Like `notify_all()`, a deferred notification only wakes the task once the mutex is released.
+    pub fn notify_one(&self) {
+        ::syscall::condvar_signal(self);
+    }
+
+    // Get the mutex that notifications should be handed off to, if this condition variable defers
+    // them and its mutex is currently locked.
+    //
+    // The mutex is the one bound by `wait`, which must still be alive since tasks can only be
+    // waiting on this condition variable while they're using it.
+    #[doc(hidden)]
+    pub fn deferred_mutex(&self) -> Option<&RawMutex> {
+        if !self.deferred {
+            return None;
+        }
+        match self.mutex.load(Ordering::SeqCst) {
+            0 => None,
+            addr => {
+                // UNSAFE: The address was stored from a live `RawMutex` in `verify`
+                let mutex = unsafe { &*(addr as *const RawMutex) };
+                if mutex.holder().is_some() { Some(mutex) } else { None }
+            },
+        }
+    }
+
     // Verify that only one mutex is being used on this condition variable at a time
     fn verify(&self, mutex: &RawMutex) {
         let addr = mutex.address();
@@ -88,7 +143,7 @@ impl CondVar {
             n if n == addr => {},
 
             // We're using more than one mutex on this CondVar
-            _ => panic!("Attempted to use a condition variable with two mutexes!"),
+            _ => kernel_panic!("Attempted to use a condition variable with two mutexes!"),
         }
     }
 }
@@ -97,7 +152,7 @@ impl CondVar {
 mod tests {
     use super::*;
     use sync::Mutex;
-    use task::State;
+    use task::{State, Priority};
     use sched;
     use syscall;
     use test;
@@ -105,6 +160,7 @@ mod tests {
     #[test]
     fn test_condvar_smoke() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(sched::Policy::RoundRobin);
         let condvar = CondVar::new();
         let mutex = Mutex::new(());
 
@@ -169,6 +225,7 @@ mod tests {
     #[test]
     fn test_condvar_notify_wakes_all_tasks() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(sched::Policy::RoundRobin);
         let condvar = CondVar::new();
         let mutex = Mutex::new(());
 
@@ -229,4 +286,120 @@ mod tests {
         // initially acquired the lock, which seems irrelavant to this test.
         ::core::mem::forget(guard);
     }
+
+    #[test]
+    fn test_condvar_notify_one_wakes_by_priority_then_fifo() {
+        let _g = test::set_up();
+        let condvar = CondVar::new();
+        let chan = &condvar as *const _ as usize;
+        let (normal_1, normal_2) = test::create_two_tasks();
+        sched::start_scheduler();
+
+        // Normal task 1 starts waiting first...
+        assert_eq!(normal_1.tid(), Ok(test::current_task().unwrap().tid()));
+        syscall::sleep(chan);
+
+        // ...then a critical task...
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        syscall::sched_yield();
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+        syscall::sleep(chan);
+
+        // ...and finally normal task 2
+        assert_eq!(normal_2.tid(), Ok(test::current_task().unwrap().tid()));
+        syscall::sleep(chan);
+        assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+
+        // The critical task outranks the idle task, so it starts running right away
+        condvar.notify_one();
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(normal_1.state(), Ok(State::Blocked));
+        assert_eq!(normal_2.state(), Ok(State::Blocked));
+
+        condvar.notify_one();
+        assert_eq!(normal_1.state(), Ok(State::Ready));
+        assert_eq!(normal_2.state(), Ok(State::Blocked));
+
+        condvar.notify_one();
+        assert_eq!(normal_2.state(), Ok(State::Ready));
+    }
+
+    // Simulate a consumer waiting on `condvar` and a producer that notifies it while holding the
+    // mutex, returning how many context switches it took for the consumer to reacquire the mutex.
+    fn switches_until_consumer_locks(condvar: &CondVar) -> usize {
+        let mutex = Mutex::new(());
+        let (consumer, producer) = test::create_two_tasks();
+        sched::start_scheduler();
+        assert_eq!(consumer.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // The consumer waits, and the producer (reacquiring the lock in the same call, see the
+        // smoke test) is left holding the mutex
+        let guard = mutex.lock();
+        let raw_mutex = unsafe { ::sync::mutex_from_guard(&guard) };
+        condvar.wait(&guard);
+        ::core::mem::forget(guard);
+        assert_eq!(producer.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(raw_mutex.holder(), producer.tid().ok());
+
+        condvar.notify_one();
+
+        // The producer gets preempted before it releases the mutex
+        let mut switches = 0;
+        syscall::sched_yield();
+        if consumer.tid() == Ok(test::current_task().unwrap().tid()) {
+            switches += 1;
+        }
+        loop {
+            let running = test::current_task().unwrap().tid();
+            if consumer.tid() == Ok(running) {
+                if syscall::sys_mutex_lock(raw_mutex) {
+                    return switches;
+                }
+                // The consumer went back to sleep on the mutex, switching back to the producer
+                switches += 1;
+            }
+            else {
+                if raw_mutex.holder() == Some(running) {
+                    syscall::mutex_unlock(raw_mutex);
+                }
+                syscall::sched_yield();
+                if test::current_task().unwrap().tid() != running {
+                    switches += 1;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_condvar_deferred_notify_saves_switches() {
+        let immediate = {
+            let _g = test::set_up();
+            switches_until_consumer_locks(&CondVar::new())
+        };
+        let deferred = {
+            let _g = test::set_up();
+            switches_until_consumer_locks(&CondVar::new_deferred())
+        };
+
+        assert_eq!(immediate, 3);
+        assert_eq!(deferred, 1);
+    }
+
+    #[test]
+    fn test_condvar_deferred_notify_without_lock_wakes_immediately() {
+        let _g = test::set_up();
+        let condvar = CondVar::new_deferred();
+        let mutex = Mutex::new(());
+        let (consumer, _producer) = test::create_two_tasks();
+        sched::start_scheduler();
+
+        let guard = mutex.lock();
+        let raw_mutex = unsafe { ::sync::mutex_from_guard(&guard) };
+        condvar.wait(&guard);
+        ::core::mem::forget(guard);
+        syscall::mutex_unlock(raw_mutex);
+
+        condvar.notify_all();
+        assert_eq!(consumer.state(), Ok(State::Ready));
+    }
 }
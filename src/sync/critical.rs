@@ -18,6 +18,10 @@
 use core::ops::Drop;
 use arch;
 
+// The raw `arch` functions for disabling and enabling interrupts are crate private, all critical
+// sections go through the guards in this module so the interrupt state can't be left disabled by
+// a missing `end_critical` call.
+
 /// A marker for a critical region of code.
 ///
 /// This struct marks the beginning of a critical section, returning a `CriticalSectionGuard` that
@@ -44,6 +48,59 @@ impl CriticalSection {
     pub fn begin() -> CriticalSectionGuard {
         CriticalSectionGuard(arch::begin_critical())
     }
+
+    /// Marks the beginning of a critical section that only masks interrupts at or below
+    /// `priority`, returning a `BoundedCriticalSectionGuard` that will end the critical section when
+    /// it falls out of scope.
+    ///
+    /// On targets with a `BASEPRI` register (Cortex-M3 and up) this sets `BASEPRI` to `priority`
+    /// instead of disabling interrupts entirely, so interrupts with a higher priority (numerically
+    /// lower) than the threshold keep firing. This is useful for deadline sensitive ISRs, like a
+    /// motor commutation timer, that can't wait for the kernel to finish its work. `priority` is
+    /// written to the register as is, so it must already be shifted into the priority bits the
+    /// target implements.
+    ///
+    /// The mask is only ever raised. Starting a bounded section inside one with a tighter threshold
+    /// (or inside a section from `begin()`) leaves the outer mask in place, like writing
+    /// `BASEPRI_MAX`.
+    ///
+    /// Any interrupt that calls into the kernel (the SysTick, PendSV and any ISR using kernel
+    /// primitives) MUST be configured at or below the threshold, otherwise it could interrupt the
+    /// kernel in the middle of a critical section.
+    ///
+    /// On targets without `BASEPRI`, like the Cortex-M0, this falls back to disabling all
+    /// interrupts the same way `begin()` does.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::CriticalSection;
+    ///
+    /// // Leave interrupts with priority 0x00 - 0x3F running
+    /// let critical_guard = CriticalSection::begin_bounded(0x40);
+    ///
+    /// // Do some critical work here...
+    ///
+    /// drop(critical_guard);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `priority` is 0, writing 0 to `BASEPRI` turns masking off rather than
+    /// masking every interrupt. Use `begin()` to mask all of them.
+    pub fn begin_bounded(priority: u8) -> BoundedCriticalSectionGuard {
+        if priority == 0 {
+            kernel_panic!("begin_bounded - a threshold of 0 wouldn't mask any interrupts!");
+        }
+        BoundedCriticalSectionGuard(arch::begin_critical_bounded(priority))
+    }
+}
+
+// Returns true if the code calling this is inside of a critical section.
+pub(crate) fn in_critical_section() -> bool {
+    let primask = arch::begin_critical();
+    arch::end_critical(primask);
+    primask != 0
 }
 
 /// Tracks the lifetime of a critical section.
@@ -58,3 +115,100 @@ impl Drop for CriticalSectionGuard {
         arch::end_critical(self.0);
     }
 }
+
+/// Tracks the lifetime of a priority bounded critical section.
+///
+/// Can only be generated by the `begin_bounded()` function on `CriticalSection`. When this falls
+/// out of scope, the previous interrupt mask is restored.
+#[must_use]
+pub struct BoundedCriticalSectionGuard(usize);
+
+impl Drop for BoundedCriticalSectionGuard {
+    fn drop(&mut self) {
+        arch::end_critical_bounded(self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arch;
+    use atomic::Ordering;
+    use test;
+
+    #[test]
+    fn test_critical_section_disables_and_restores_interrupts() {
+        let _g = test::set_up();
+        assert_eq!(arch::PRIMASK.load(Ordering::Relaxed), 0);
+
+        let outer = CriticalSection::begin();
+        assert_eq!(arch::PRIMASK.load(Ordering::Relaxed), 1);
+
+        // Ending a nested section must not re-enable interrupts for the outer one
+        let inner = CriticalSection::begin();
+        drop(inner);
+        assert_eq!(arch::PRIMASK.load(Ordering::Relaxed), 1);
+
+        drop(outer);
+        assert_eq!(arch::PRIMASK.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_kernel_calls_leave_interrupts_enabled() {
+        use sched;
+        use syscall;
+        use sync::Mutex;
+
+        let _g = test::set_up();
+        let mutex = Mutex::new(());
+        test::create_two_tasks();
+        sched::start_scheduler();
+
+        drop(mutex.lock());
+        syscall::sched_yield();
+        syscall::sleep_for(1, 1);
+        syscall::system_tick();
+        syscall::system_tick();
+        assert_eq!(arch::PRIMASK.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_bounded_critical_section_sets_and_restores_basepri() {
+        let _g = test::set_up();
+        assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0);
+
+        let guard = CriticalSection::begin_bounded(0x40);
+        assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0x40);
+
+        {
+            let _inner = CriticalSection::begin_bounded(0x20);
+            assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0x20);
+        }
+        assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0x40);
+
+        drop(guard);
+        assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_nested_looser_bound_keeps_outer_mask() {
+        let _g = test::set_up();
+        let guard = CriticalSection::begin_bounded(0x20);
+
+        {
+            let _inner = CriticalSection::begin_bounded(0x40);
+            assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0x20);
+        }
+        assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0x20);
+
+        drop(guard);
+        assert_eq!(arch::BASEPRI.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "begin_bounded - a threshold of 0 wouldn't mask any interrupts!")]
+    fn test_bounded_critical_section_rejects_zero() {
+        let _g = test::set_up();
+        let _guard = CriticalSection::begin_bounded(0);
+    }
+}
@@ -25,10 +25,17 @@ mod mutex;
 mod spin;
 mod critical;
 mod condvar;
+mod semaphore;
+mod pool;
+mod rwlock;
 
 pub use self::mutex::{RawMutex, Mutex, MutexGuard};
 pub use self::mutex::{LockResult, LockError, UnlockError};
 pub use self::mutex::mutex_from_guard;
 pub use self::spin::{SpinMutex, SpinGuard};
 pub use self::critical::CriticalSection;
+pub(crate) use self::critical::in_critical_section;
 pub use self::condvar::CondVar;
+pub use self::semaphore::Semaphore;
+pub use self::pool::{ResourcePool, PoolGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
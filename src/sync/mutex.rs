@@ -30,6 +30,8 @@ use atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
 use core::ops::{Drop, Deref, DerefMut};
 use core::cell::UnsafeCell;
 use syscall;
+use sync::CriticalSection;
+use task::TaskId;
 
 const LOCK_MASK: usize = ::core::isize::MIN as usize;
 const UNLOCKED: usize = 0;
@@ -110,6 +112,10 @@ unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 
 impl RawMutex {
     /// Create a new, unlocked, mutex
+    ///
+    /// The mutex is fully usable as soon as it's created, waiters sleep on the mutex's address
+    /// rather than a list stored inside of it, so there's nothing to set up on first use. This
+    /// makes it safe to put in a `static` and lock before any other kernel initialization.
     pub const fn new() -> Self {
         RawMutex {
             lock: ATOMIC_USIZE_INIT,
@@ -196,6 +202,19 @@ impl RawMutex {
 
 impl<T> Mutex<T> {
     /// Creates a new `Mutex` wrapping the supplied data
+    ///
+    /// This is a `const` function, so it can be used to declare a mutex protected global without
+    /// any runtime initialization.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::Mutex;
+    ///
+    /// static PACKETS_RECEIVED: Mutex<usize> = Mutex::new(0);
+    ///
+    /// *PACKETS_RECEIVED.lock() += 1;
+    /// ```
     pub const fn new(data: T) -> Self {
         Mutex {
             lock: RawMutex::new(),
@@ -263,6 +282,59 @@ impl<T: ?Sized> Mutex<T> {
         }
     }
 
+    /// Try to obtain the lock in a non-blocking fashion, reporting who holds it on failure.
+    ///
+    /// This works like `try_lock`, but if the lock can't be obtained the id of the task holding
+    /// it is returned in the `Err`, or `None` if that task has been destroyed. This is handy for
+    /// finding out which task is sitting on a lock without a debugger, or for building reports of
+    /// lock contention.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::Mutex;
+    ///
+    /// let lock = Mutex::new(0);
+    ///
+    /// match lock.try_lock_owner() {
+    ///   Ok(guard) => { /* Do work with the shared resource... */ },
+    ///   Err(Some(holder)) => { /* Task `holder` has the lock */ },
+    ///   Err(None) => { /* The task holding the lock is gone */ },
+    /// }
+    /// ```
+    pub fn try_lock_owner(&self) -> Result<MutexGuard<T>, Option<TaskId>> {
+        let (acquired, holder) = syscall::mutex_try_lock_owner(&self.lock);
+        if acquired {
+            // UNSAFE: We are guaranteed to have acquired exclusive access over the lock if we've
+            // gotten to this case
+            Ok(unsafe { self.build_guard() })
+        }
+        else {
+            let _g = CriticalSection::begin();
+            Err(::task::live_id(holder))
+        }
+    }
+
+    /// Get a mutable reference to the shared data without locking.
+    ///
+    /// Having `&mut self` already proves no other thread can be using the mutex, so no lock is
+    /// taken and no system call is made. This is handy for setting up the data before the mutex is
+    /// shared, even before the scheduler has started.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::Mutex;
+    ///
+    /// let mut lock = Mutex::new(0);
+    /// *lock.get_mut() = 10;
+    /// assert_eq!(*lock.lock(), 10);
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        // UNSAFE: We hold the only reference to the mutex, so nothing else can access the data
+        unsafe { &mut *self.data.get() }
+    }
+
     // Build a `MutexGuard` from this Mutex
     //
     // This is a helper function to generate a `MutexGuard` referencing the mutex, and should only
@@ -316,6 +388,9 @@ mod tests {
     // A temporary task id for testing
     const TASK_ID: usize = 0;
 
+    static STATIC_MUTEX: Mutex<usize> = Mutex::new(0);
+    static STATIC_RAW_MUTEX: RawMutex = RawMutex::new();
+
     #[test]
     fn test_raw_mutex_try_lock() {
         let raw_mutex = RawMutex::new();
@@ -428,6 +503,7 @@ mod tests {
     #[test]
     fn test_mutex_wakes_on_release() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(sched::Policy::RoundRobin);
         let mutex = Mutex::new(());
         let (handle_1, handle_2) = test::create_two_tasks();
 
@@ -475,6 +551,7 @@ mod tests {
     #[test]
     fn test_mutex_wakes_all_on_release() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(sched::Policy::RoundRobin);
         let mutex = Mutex::new(());
         let (handle_1, handle_2) = test::create_two_tasks();
         let (handle_3, handle_4) = test::create_two_tasks();
@@ -547,4 +624,141 @@ mod tests {
         *guard = 100;
         assert_eq!(*guard, unsafe { *mutex.data.get() });
     }
+
+    #[test]
+    fn test_mutex_get_mut_needs_no_lock() {
+        let _g = test::set_up();
+        let mut mutex = Mutex::new(0);
+
+        // There's no current task to hold the lock, so locking would panic
+        *mutex.get_mut() = 100;
+        assert_eq!(mutex.lock.holder(), None);
+        assert_eq!(unsafe { *mutex.data.get() }, 100);
+    }
+
+    #[test]
+    fn test_static_mutex_shared_between_tasks() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        sched::start_scheduler();
+        *STATIC_MUTEX.lock() = 0;
+
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        let guard = STATIC_MUTEX.lock();
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert!(STATIC_MUTEX.try_lock().is_none());
+
+        syscall::sched_yield();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        drop(guard);
+        *STATIC_MUTEX.lock() += 1;
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        *STATIC_MUTEX.try_lock().unwrap() += 1;
+        assert_eq!(*STATIC_MUTEX.lock(), 2);
+    }
+
+    #[test]
+    fn test_try_lock_owner_reports_holder() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        sched::start_scheduler();
+        let mutex = Mutex::new(0);
+
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        let guard = mutex.try_lock_owner().ok().unwrap();
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(mutex.try_lock_owner().err(), Some(handle_1.id().ok()));
+
+        syscall::sched_yield();
+        drop(guard);
+
+        syscall::sched_yield();
+        assert!(mutex.try_lock_owner().is_ok());
+    }
+
+    #[test]
+    fn test_blocked_task_reports_mutex_as_wait_reason() {
+        use task::{WaitReason, wait_reason};
+
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        sched::start_scheduler();
+        let lock = RawMutex::new();
+
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert!(syscall::mutex_try_lock(&lock));
+        assert_eq!(wait_reason(&handle_2), WaitReason::NotBlocked);
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_not!(syscall::sys_mutex_lock(&lock));
+
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(wait_reason(&handle_2), WaitReason::Mutex(lock.address()));
+        assert_eq!(wait_reason(&handle_1), WaitReason::NotBlocked);
+
+        // Releasing the lock wakes the waiter back up
+        syscall::mutex_unlock(&lock);
+        assert_eq!(wait_reason(&handle_2), WaitReason::NotBlocked);
+    }
+
+    #[test]
+    fn test_static_raw_mutex_locked_from_two_tasks() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        sched::start_scheduler();
+
+        // No initialization needed, task 1 can lock it straight away...
+        let tid_1 = handle_1.tid().unwrap();
+        assert_eq!(STATIC_RAW_MUTEX.holder(), None);
+        assert!(syscall::mutex_try_lock(&STATIC_RAW_MUTEX));
+        assert_eq!(STATIC_RAW_MUTEX.holder(), Some(tid_1));
+
+        // ...which keeps task 2 out until it's unlocked
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_not!(syscall::mutex_try_lock(&STATIC_RAW_MUTEX));
+
+        syscall::sched_yield();
+        syscall::mutex_unlock(&STATIC_RAW_MUTEX);
+        assert_eq!(STATIC_RAW_MUTEX.holder(), None);
+
+        syscall::sched_yield();
+        assert!(syscall::mutex_try_lock(&STATIC_RAW_MUTEX));
+        assert_eq!(STATIC_RAW_MUTEX.holder(), handle_2.tid().ok());
+        syscall::mutex_unlock(&STATIC_RAW_MUTEX);
+        assert_eq!(STATIC_RAW_MUTEX.holder(), None);
+    }
+
+    #[test]
+    fn test_holder_inherits_priority_of_waiter() {
+        use task::Priority;
+
+        let _g = test::set_up();
+        let low = test::create_and_schedule_test_task(512, Priority::Low, "low task");
+        sched::start_scheduler();
+        let lock = RawMutex::new();
+        assert!(syscall::mutex_try_lock(&lock));
+
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        let normal = test::create_and_schedule_test_task(512, Priority::Normal, "normal task");
+        syscall::sched_yield();
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_not!(syscall::sys_mutex_lock(&lock));
+
+        // The low priority task runs ahead of the normal one until it lets go of the lock
+        assert_eq!(low.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(low.priority(), Ok(Priority::Critical));
+        syscall::mutex_unlock(&lock);
+        assert_eq!(low.priority(), Ok(Priority::Low));
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(normal.state(), Ok(State::Ready));
+    }
+
 }
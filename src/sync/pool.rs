@@ -0,0 +1,233 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A pool of reusable resources.
+
+use core::ops::{Drop, Deref, DerefMut};
+use collections::Vec;
+use sync::{Mutex, Semaphore};
+
+/// A fixed set of resources that can be shared between tasks.
+///
+/// Each resource in the pool can only be held by one task at a time. When a task acquires a
+/// resource it is given a `PoolGuard`, when the guard goes out of scope the resource is returned to
+/// the pool and any task waiting on a resource is woken up.
+pub struct ResourcePool<T> {
+    semaphore: Semaphore,
+    free: Mutex<Vec<T>>,
+}
+
+/// A guard that gives access to a resource taken from a `ResourcePool`.
+///
+/// When the guard goes out of scope the resource will automatically be returned to the pool it
+/// came from.
+pub struct PoolGuard<'p, T: 'p> {
+    pool: &'p ResourcePool<T>,
+    resource: Option<T>,
+}
+
+impl<T> ResourcePool<T> {
+    /// Create a new `ResourcePool` managing the resources passed in.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::ResourcePool;
+    /// use altos_core::collections::Vec;
+    ///
+    /// struct DmaChannel(usize);
+    ///
+    /// let mut channels = Vec::new();
+    /// channels.push(DmaChannel(1));
+    /// channels.push(DmaChannel(2));
+    ///
+    /// let pool = ResourcePool::new(channels);
+    /// ```
+    pub fn new(resources: Vec<T>) -> Self {
+        ResourcePool {
+            semaphore: Semaphore::new(resources.len()),
+            free: Mutex::new(resources),
+        }
+    }
+
+    /// Take a resource from the pool, blocking until one is available.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::ResourcePool;
+    /// use altos_core::collections::Vec;
+    ///
+    /// let mut buffers = Vec::new();
+    /// buffers.push([0u8; 16]);
+    ///
+    /// let pool = ResourcePool::new(buffers);
+    ///
+    /// let mut buffer = pool.acquire();
+    /// buffer[0] = 0xFF;
+    /// drop(buffer); // The buffer is back in the pool
+    /// ```
+    pub fn acquire(&self) -> PoolGuard<T> {
+        self.semaphore.acquire();
+        self.take()
+    }
+
+    /// Take a resource from the pool if one is available, returning `None` otherwise.
+    pub fn try_acquire(&self) -> Option<PoolGuard<T>> {
+        if self.semaphore.try_acquire() {
+            Some(self.take())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Get the number of resources that are currently available.
+    pub fn available(&self) -> usize {
+        self.semaphore.available()
+    }
+
+    // Take a resource off of the free list, this must only be called after acquiring a permit from
+    // the semaphore.
+    fn take(&self) -> PoolGuard<T> {
+        let resource = self.free.lock().pop()
+            .expect("ResourcePool::take - acquired a permit with no free resources!");
+        PoolGuard {
+            pool: self,
+            resource: Some(resource),
+        }
+    }
+}
+
+impl<'p, T> Deref for PoolGuard<'p, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.resource.as_ref().unwrap()
+    }
+}
+
+impl<'p, T> DerefMut for PoolGuard<'p, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.resource.as_mut().unwrap()
+    }
+}
+
+impl<'p, T> Drop for PoolGuard<'p, T> {
+    /// Dropping the guard will return the resource to the pool and wake any tasks waiting on it.
+    fn drop(&mut self) {
+        if let Some(resource) = self.resource.take() {
+            self.pool.free.lock().push(resource);
+            self.pool.semaphore.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::State;
+    use sched;
+    use syscall;
+    use test;
+
+    fn create_pool() -> ResourcePool<usize> {
+        let mut resources = Vec::new();
+        resources.push(1);
+        resources.push(2);
+        ResourcePool::new(resources)
+    }
+
+    #[test]
+    fn test_pool_exhaustion() {
+        let _g = test::set_up();
+        let pool = create_pool();
+        sched::start_scheduler();
+
+        let first = pool.try_acquire();
+        let second = pool.try_acquire();
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(pool.try_acquire().is_none());
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[test]
+    fn test_pool_never_issues_a_resource_twice() {
+        let _g = test::set_up();
+        let pool = create_pool();
+        sched::start_scheduler();
+
+        let first = pool.acquire();
+        let second = pool.acquire();
+        assert_ne!(*first, *second);
+
+        drop(first);
+        let third = pool.acquire();
+        assert_ne!(*second, *third);
+    }
+
+    #[test]
+    fn test_pool_guard_returns_resource_on_early_return() {
+        fn use_resource(pool: &ResourcePool<usize>, bail: bool) -> Option<usize> {
+            let resource = pool.acquire();
+            if bail {
+                return None;
+            }
+            Some(*resource)
+        }
+
+        let _g = test::set_up();
+        let pool = create_pool();
+        sched::start_scheduler();
+
+        assert_eq!(use_resource(&pool, true), None);
+        assert_eq!(pool.available(), 2);
+        assert!(use_resource(&pool, false).is_some());
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn test_pool_dropping_guard_wakes_waiting_task() {
+        let _g = test::set_up();
+        let pool = create_pool();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        sched::start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        let first = pool.acquire();
+        let _second = pool.acquire();
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert!(pool.try_acquire().is_none());
+
+        // Tasks don't actually block in the test environment, so simulate task 2 blocking in
+        // `acquire()` by putting it to sleep on the pool's semaphore
+        syscall::sleep(pool.semaphore.address());
+        assert_eq!(handle_2.state(), Ok(State::Blocked));
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        drop(first);
+        assert_eq!(handle_2.state(), Ok(State::Ready));
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert!(pool.try_acquire().is_some());
+    }
+}
@@ -0,0 +1,310 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Reader-writer lock.
+
+use core::ops::{Drop, Deref, DerefMut};
+use core::cell::UnsafeCell;
+use sync::{Mutex, CondVar};
+
+/// A reader-writer lock.
+///
+/// Any number of tasks can hold the lock for reading at once, or a single task can hold it for
+/// writing. A task that can't get the lock is put to sleep until the lock is released.
+///
+/// By default a task asking to read is let in whenever no task is writing, even if a writer is
+/// already waiting, so a steady stream of readers can keep a writer out forever. A lock created
+/// with `new_writer_preferring` instead makes new readers wait behind any waiting writer.
+// We need this to be `repr(C)` because the address of the condition variable is used as the wait
+// channel for the lock, so it must be the first field in memory.
+#[repr(C)]
+pub struct RwLock<T: ?Sized> {
+    available: CondVar,
+    state: Mutex<RwState>,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that gives shared access to the data in a `RwLock`.
+///
+/// When the guard goes out of scope the read lock is released.
+pub struct RwLockReadGuard<'rw, T: ?Sized + 'rw> {
+    lock: &'rw RwLock<T>,
+    data: &'rw T,
+}
+
+/// A guard that gives exclusive access to the data in a `RwLock`.
+///
+/// When the guard goes out of scope the write lock is released.
+pub struct RwLockWriteGuard<'rw, T: ?Sized + 'rw> {
+    lock: &'rw RwLock<T>,
+    data: &'rw mut T,
+}
+
+struct RwState {
+    readers: usize,
+    writing: bool,
+    waiting_writers: usize,
+    prefer_writers: bool,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+
+impl RwState {
+    fn can_read(&self) -> bool {
+        !self.writing && !(self.prefer_writers && self.waiting_writers > 0)
+    }
+
+    fn can_write(&self) -> bool {
+        !self.writing && self.readers == 0
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new `RwLock` wrapping the supplied data.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::RwLock;
+    ///
+    /// static CONFIG: RwLock<[u8; 4]> = RwLock::new([0; 4]);
+    ///
+    /// let baud = CONFIG.read()[0];
+    /// CONFIG.write()[0] = 9;
+    /// ```
+    pub const fn new(data: T) -> Self {
+        Self::build(data, false)
+    }
+
+    /// Creates a new `RwLock` that makes new readers wait while a writer is waiting for the lock.
+    ///
+    /// This keeps writers from being starved by readers, at the cost of readers waiting longer.
+    pub const fn new_writer_preferring(data: T) -> Self {
+        Self::build(data, true)
+    }
+
+    const fn build(data: T, prefer_writers: bool) -> Self {
+        RwLock {
+            available: CondVar::new(),
+            state: Mutex::new(RwState {
+                readers: 0,
+                writing: false,
+                waiting_writers: 0,
+                prefer_writers: prefer_writers,
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Obtain the lock for reading, blocking until no task is writing.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let mut state = self.state.lock();
+        while !state.can_read() {
+            self.available.wait(&state);
+        }
+        state.readers += 1;
+        // UNSAFE: No task holds the lock for writing, so only shared references to the data exist
+        unsafe { self.build_read_guard() }
+    }
+
+    /// Try to obtain the lock for reading without blocking, returns `None` if it can't be.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let mut state = self.state.lock();
+        if state.can_read() {
+            state.readers += 1;
+            // UNSAFE: Same as in `read`
+            Some(unsafe { self.build_read_guard() })
+        }
+        else {
+            None
+        }
+    }
+
+    /// Obtain the lock for writing, blocking until no other task holds it.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let mut state = self.state.lock();
+        state.waiting_writers += 1;
+        while !state.can_write() {
+            self.available.wait(&state);
+        }
+        state.waiting_writers -= 1;
+        state.writing = true;
+        // UNSAFE: No other task holds the lock at all, so this is the only reference to the data
+        unsafe { self.build_write_guard() }
+    }
+
+    /// Try to obtain the lock for writing without blocking, returns `None` if it can't be.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let mut state = self.state.lock();
+        if state.can_write() {
+            state.writing = true;
+            // UNSAFE: Same as in `write`
+            Some(unsafe { self.build_write_guard() })
+        }
+        else {
+            None
+        }
+    }
+
+    /// Get a mutable reference to the data without locking.
+    ///
+    /// Having `&mut self` already proves no other task can be using the lock.
+    pub fn get_mut(&mut self) -> &mut T {
+        // UNSAFE: We hold the only reference to the lock, so nothing else can access the data
+        unsafe { &mut *self.data.get() }
+    }
+
+    /// Get the address of this lock in memory
+    ///
+    /// This is the channel that tasks waiting on the lock are sleeping on.
+    pub fn address(&self) -> usize {
+        self as *const _ as usize
+    }
+
+    // These should only be called once the lock has been obtained in the matching mode.
+    unsafe fn build_read_guard(&self) -> RwLockReadGuard<T> {
+        RwLockReadGuard {
+            lock: self,
+            data: &*self.data.get(),
+        }
+    }
+
+    unsafe fn build_write_guard(&self) -> RwLockWriteGuard<T> {
+        RwLockWriteGuard {
+            lock: self,
+            data: &mut *self.data.get(),
+        }
+    }
+}
+
+impl<'rw, T: ?Sized> Deref for RwLockReadGuard<'rw, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'rw, T: ?Sized> Drop for RwLockReadGuard<'rw, T> {
+    /// Dropping the last read guard wakes any tasks waiting to write.
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.lock.available.notify_all();
+        }
+    }
+}
+
+impl<'rw, T: ?Sized> Deref for RwLockWriteGuard<'rw, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.data
+    }
+}
+
+impl<'rw, T: ?Sized> DerefMut for RwLockWriteGuard<'rw, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.data
+    }
+}
+
+impl<'rw, T: ?Sized> Drop for RwLockWriteGuard<'rw, T> {
+    /// Dropping the guard wakes every task waiting to read or write.
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+        state.writing = false;
+        self.lock.available.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::State;
+    use sched;
+    use syscall;
+    use test;
+
+    #[test]
+    fn test_rwlock_many_readers_or_one_writer() {
+        let _g = test::set_up();
+        let lock = RwLock::new(0);
+        sched::start_scheduler();
+
+        let first = lock.try_read().unwrap();
+        let second = lock.try_read().unwrap();
+        assert_eq!(*first + *second, 0);
+        assert!(lock.try_write().is_none());
+
+        drop(first);
+        drop(second);
+        let mut writer = lock.try_write().unwrap();
+        *writer = 5;
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+
+        drop(writer);
+        assert_eq!(*lock.read(), 5);
+    }
+
+    #[test]
+    fn test_rwlock_writer_preference_holds_off_new_readers() {
+        let _g = test::set_up();
+        let lock = RwLock::new(0);
+        let preferring = RwLock::new_writer_preferring(0);
+        sched::start_scheduler();
+
+        // Simulate a writer that's waiting for the lock
+        lock.state.lock().waiting_writers = 1;
+        preferring.state.lock().waiting_writers = 1;
+
+        assert!(lock.try_read().is_some());
+        assert!(preferring.try_read().is_none());
+    }
+
+    #[test]
+    fn test_rwlock_release_wakes_waiting_tasks() {
+        let _g = test::set_up();
+        let lock = RwLock::new(0);
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        sched::start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        let reader = lock.read();
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // Tasks don't actually block in the test environment, so simulate task 2 failing to
+        // obtain the write lock by putting it to sleep on the lock
+        syscall::sleep(lock.address());
+        assert_eq!(handle_2.state(), Ok(State::Blocked));
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        drop(reader);
+        assert_eq!(handle_2.state(), Ok(State::Ready));
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert!(lock.try_write().is_some());
+    }
+}
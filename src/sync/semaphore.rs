@@ -0,0 +1,160 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Counting semaphore.
+
+use sync::{Mutex, CondVar};
+
+/// A counting semaphore.
+///
+/// A semaphore keeps track of a number of available permits. Acquiring a permit when none are
+/// available will block the current task until another task releases one.
+// We need this to be `repr(C)` because the address of the condition variable is used as the wait
+// channel for the semaphore, so it must be the first field in memory.
+#[repr(C)]
+pub struct Semaphore {
+    available: CondVar,
+    count: Mutex<usize>,
+}
+
+impl Semaphore {
+    /// Create a new `Semaphore` with `count` permits available.
+    pub const fn new(count: usize) -> Self {
+        Semaphore {
+            available: CondVar::new(),
+            count: Mutex::new(count),
+        }
+    }
+
+    /// Acquire a permit, blocking until one is available.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::sync::Semaphore;
+    ///
+    /// static SEMAPHORE: Semaphore = Semaphore::new(1);
+    ///
+    /// SEMAPHORE.acquire();
+    /// // Do some work with the resource...
+    /// SEMAPHORE.release();
+    /// ```
+    pub fn acquire(&self) {
+        let mut count = self.count.lock();
+        while *count == 0 {
+            self.available.wait(&count);
+        }
+        *count -= 1;
+    }
+
+    /// Try to acquire a permit without blocking.
+    ///
+    /// Returns `true` if a permit was acquired, `false` if none were available.
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count > 0 {
+            *count -= 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Release a permit, waking any tasks waiting to acquire one.
+    pub fn release(&self) {
+        let mut count = self.count.lock();
+        *count += 1;
+        self.available.notify_all();
+    }
+
+    /// Get the number of permits currently available.
+    ///
+    /// This is not an atomic operation, so the value may have changed by the time it's used.
+    pub fn available(&self) -> usize {
+        *self.count.lock()
+    }
+
+    /// Get the address of this semaphore in memory
+    ///
+    /// This is the channel that tasks waiting on a permit are sleeping on.
+    pub fn address(&self) -> usize {
+        self as *const _ as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::State;
+    use sched;
+    use syscall;
+    use test;
+
+    #[test]
+    fn test_semaphore_try_acquire_until_exhausted() {
+        let _g = test::set_up();
+        let semaphore = Semaphore::new(2);
+        sched::start_scheduler();
+
+        assert!(semaphore.try_acquire());
+        assert!(semaphore.try_acquire());
+        assert_not!(semaphore.try_acquire());
+        assert_eq!(semaphore.available(), 0);
+    }
+
+    #[test]
+    fn test_semaphore_release_makes_permit_available() {
+        let _g = test::set_up();
+        let semaphore = Semaphore::new(1);
+        sched::start_scheduler();
+
+        semaphore.acquire();
+        assert_not!(semaphore.try_acquire());
+
+        semaphore.release();
+        assert_eq!(semaphore.available(), 1);
+        assert!(semaphore.try_acquire());
+    }
+
+    #[test]
+    fn test_semaphore_release_wakes_waiting_tasks() {
+        let _g = test::set_up();
+        let semaphore = Semaphore::new(1);
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        sched::start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        semaphore.acquire();
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // Tasks don't actually block in the test environment, so simulate task 2 failing to
+        // acquire a permit by putting it to sleep on the semaphore
+        syscall::sleep(semaphore.address());
+        assert_eq!(handle_2.state(), Ok(State::Blocked));
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        semaphore.release();
+        assert_eq!(handle_2.state(), Ok(State::Ready));
+
+        syscall::sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert!(semaphore.try_acquire());
+    }
+}
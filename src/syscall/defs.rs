@@ -44,3 +44,25 @@ pub const SYS_CV_WAIT: u32 = 8;
 
 /// System call number for `condvar_broadcast(lock)`
 pub const SYS_CV_BROADCAST: u32 = 9;
+
+/// System call number for `wake_reason(void)`
+pub const SYS_WAKE_REASON: u32 = 10;
+
+/// System call number for `condvar_signal(condvar)`
+pub const SYS_CV_SIGNAL: u32 = 11;
+
+/// System call number for `mutex_try_lock_owner(lock)`, this returns two words
+pub const SYS_MX_TRY_LOCK_OWNER: u32 = 12;
+
+/// System call number for `join(task_id)`, this returns two words
+pub const SYS_JOIN: u32 = 13;
+
+/// System call number for `wait_notification(clear_mask, timeout)`
+pub const SYS_NOTIFY_WAIT: u32 = 14;
+
+/// The first system call number available for user defined system calls, every number below this
+/// is reserved for the kernel.
+pub const SYS_USER_BASE: u32 = 32;
+
+/// Returned from the system call dispatcher when it's given a system call number it doesn't know.
+pub const SYS_ERR_INVALID: usize = !0;
@@ -0,0 +1,146 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Polling fallback for blocking system calls made before the scheduler is running.
+//!
+//! Until the scheduler has been started there's no current task to put to sleep and nothing to
+//! switch to, so normally `sleep` and `sleep_for` panic. With the `blocking_fallback` feature
+//! enabled they instead wait in place, idling the processor and re-checking the condition after
+//! every interrupt, until they're woken up or their timeout runs out. This is ONLY meant to let
+//! single-threaded bring-up code (driver initialization that waits on a peripheral, for instance)
+//! make progress, it burns CPU time that tasks could be using and only one caller can wait at a
+//! time.
+
+use atomic::{AtomicBool, ATOMIC_BOOL_INIT, AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use super::imp::FOREVER_CHAN;
+use power;
+use tick;
+use arch;
+
+static WAITING: AtomicBool = ATOMIC_BOOL_INIT;
+static WAIT_CHAN: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Wait until `wchan` is woken, or until `delay` ticks have passed if there's a timeout. The
+// timeout is counted the same way as a task's `sleep_for`.
+pub fn poll_sleep(wchan: usize, delay: Option<usize>) {
+    let start = tick::get_tick();
+    WAIT_CHAN.store(wchan, Ordering::Relaxed);
+    WAITING.store(true, Ordering::Release);
+
+    loop {
+        if !WAITING.load(Ordering::Acquire) {
+            return;
+        }
+        if let Some(delay) = delay {
+            if tick::get_tick().wrapping_sub(start) > delay {
+                WAITING.store(false, Ordering::Relaxed);
+                return;
+            }
+        }
+        // Some targets run system calls with interrupts masked, and then neither the tick nor
+        // whatever is meant to wake us could ever fire. Nothing is half done at this point, so
+        // interrupts are let in while idling and masked again before checking.
+        let mask = arch::begin_critical();
+        arch::end_critical(0);
+        // Sleep until the next interrupt, if low power modes are enabled, then check again
+        power::idle();
+        arch::end_critical(mask);
+    }
+}
+
+// Wake the poller if it's waiting on `wchan`.
+pub fn wake(wchan: usize) {
+    if wchan != FOREVER_CHAN && WAIT_CHAN.load(Ordering::Relaxed) == wchan {
+        WAITING.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use syscall;
+    use sync::CriticalSection;
+    use std::thread;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Stand in for the system tick interrupt until `done` is set
+    fn spawn_ticker(done: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                // Like the real interrupt, the tick is held off while interrupts are masked
+                if arch::PRIMASK.load(Ordering::Relaxed) == 0 {
+                    tick::tick();
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    }
+
+    #[test]
+    fn test_sleep_for_before_scheduler_started_polls_until_timeout() {
+        let _g = test::set_up();
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker = spawn_ticker(done.clone());
+
+        let start = tick::get_tick();
+        syscall::sys_sleep_for(FOREVER_CHAN, 5);
+        let elapsed = tick::get_tick().wrapping_sub(start);
+
+        done.store(true, Ordering::Relaxed);
+        ticker.join().unwrap();
+        assert!(elapsed >= 6);
+    }
+
+    #[test]
+    fn test_poll_lets_tick_in_when_called_with_interrupts_masked() {
+        let _g = test::set_up();
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker = spawn_ticker(done.clone());
+
+        let start = tick::get_tick();
+        {
+            // Make the system call the way targets without an SVC handler do
+            let _cs = CriticalSection::begin();
+            arch::syscall2(syscall::SYS_SLEEP_FOR, FOREVER_CHAN, 5);
+            assert_eq!(arch::PRIMASK.load(Ordering::Relaxed), 1);
+        }
+        let elapsed = tick::get_tick().wrapping_sub(start);
+
+        done.store(true, Ordering::Relaxed);
+        ticker.join().unwrap();
+        assert!(elapsed >= 6);
+    }
+
+    #[test]
+    fn test_wake_ends_poll_early() {
+        let _g = test::set_up();
+        let wchan = 0x1234;
+        let waker = thread::spawn(move || {
+            while !WAITING.load(Ordering::Acquire) {
+                thread::yield_now();
+            }
+            syscall::sys_wake(wchan);
+        });
+
+        // Nothing is ticking, so only the wakeup can end the sleep
+        syscall::sys_sleep(wchan);
+        waker.join().unwrap();
+        assert_not!(WAITING.load(Ordering::Relaxed));
+    }
+}
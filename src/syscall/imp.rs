@@ -15,19 +15,30 @@
 * along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
-use sched::{CURRENT_TASK, SLEEP_QUEUE, DELAY_QUEUE, OVERFLOW_DELAY_QUEUE, PRIORITY_QUEUES};
-use task::{TaskHandle, TaskControl, Priority};
-use task::args::Args;
-use collections::Node;
+use sched::{SLEEP_QUEUE, DELAY_WHEEL, SUSPENDED_QUEUE};
+use sched::{self, scheduler};
+use task::{TaskHandle, TaskControl, TaskId, Priority, State, WakeReason, WaitObject, SpawnError};
+use task::{StaticTask, RestartError};
+use task::args::{Args, ArgsBuilder};
+use collections::{NodeBox, Queue, Vec};
 use alloc::boxed::Box;
 use tick;
 use sync::{RawMutex, CondVar, CriticalSection};
+use core::cell::{Cell, RefCell};
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, AtomicBool, ATOMIC_BOOL_INIT, Ordering};
 use arch;
+#[cfg(feature="blocking_fallback")]
+use super::fallback;
 
 /// An alias for the channel to sleep on that will never be awoken by a wakeup signal. It will
 /// still be woken after a timeout.
 pub const FOREVER_CHAN: usize = 0;
 
+// The nearest tick a task held back for using up its budget is replenished on, the suspended tasks
+// are only checked once it comes. Only meaningful while `REPLENISH_PENDING` is set.
+static NEAREST_REPLENISH: AtomicUsize = ATOMIC_USIZE_INIT;
+static REPLENISH_PENDING: AtomicBool = ATOMIC_BOOL_INIT;
+
 #[doc(hidden)]
 pub fn sys_new_task(code: fn(&mut Args), args: Args, stack_depth: usize, priority: Priority, name: &'static str)
     -> TaskHandle {
@@ -38,16 +49,106 @@ pub fn sys_new_task(code: fn(&mut Args), args: Args, stack_depth: usize, priorit
 pub fn new_task(code: fn(&mut Args), args: Args, stack_depth: usize, priority: Priority, name: &'static str)
     -> TaskHandle {
 
+    match try_new_task(code, args, stack_depth, priority, name) {
+        Ok(handle) => handle,
+        Err(err) => kernel_panic!("new_task - failed to create task: {:?}", err),
+    }
+}
+
+pub fn try_new_task(code: fn(&mut Args), args: Args, stack_depth: usize, priority: Priority, name: &'static str)
+    -> Result<TaskHandle, SpawnError> {
+
+    // Make sure the task is allocated in one fell swoop
+    let g = CriticalSection::begin();
+    let task = TaskControl::try_new(code, args, stack_depth, priority, name);
+    drop(g);
+
+    Ok(spawn_task(task?))
+}
+
+pub fn spawn_borrowed<T, F, R>(code: fn(&T), data: &T, stack_depth: usize, priority: Priority,
+                               name: &'static str, scope: F) -> Result<R, SpawnError>
+    where T: Sync, F: FnOnce(TaskHandle) -> R {
+
     // Make sure the task is allocated in one fell swoop
     let g = CriticalSection::begin();
-    let task = Box::new(Node::new(TaskControl::new(code, args, stack_depth, priority, name)));
+    // UNSAFE: `fn(&T)` and `fn(&mut Args)` both take a single pointer, so the task is handed
+    // `data` where it would otherwise get its arguments. The task is destroyed below, before the
+    // borrow of `data` ends.
+    let task = unsafe {
+        let code: fn(&mut Args) = ::core::mem::transmute(code);
+        TaskControl::try_new_borrowed(code, data as *const T as usize, stack_depth, priority, name)
+    };
+    drop(g);
+
+    let mut handle = spawn_task(task?);
+    let g = CriticalSection::begin();
+    // UNSAFE: Accessing the current task
+    if let Some(parent) = unsafe { sched::current_task() } {
+        parent.add_borrower(handle);
+    }
+    drop(g);
+
+    let result = scope(handle);
+    // The task may already have exited, in which case there's nothing left to do
+    handle.destroy();
+    let _g = CriticalSection::begin();
+    // UNSAFE: Accessing the current task
+    if let Some(parent) = unsafe { sched::current_task() } {
+        parent.remove_borrower(handle);
+    }
+    Ok(result)
+}
+
+#[doc(hidden)]
+pub fn sys_spawn_task(task: TaskControl) -> TaskHandle {
+    spawn_task(task)
+}
+
+pub fn new_static_task(code: fn(&mut Args), args: Args, tcb: &'static mut StaticTask,
+                           stack: &'static mut [u8], priority: Priority, name: &'static str)
+    -> Result<TaskHandle, SpawnError> {
+
+    let g = CriticalSection::begin();
+    let arg = tcb.store_args(args);
+    // UNSAFE: The arguments live in `tcb`, which belongs to the task for good, and the task is
+    // moved into `tcb` before it's scheduled
+    let task = unsafe { TaskControl::try_new_static(code, arg, stack, priority, name) }?;
+    let task = tcb.store(task);
     drop(g);
 
+    Ok(schedule_task(task))
+}
+
+// Hand a newly created task over to the scheduler
+fn spawn_task(task: TaskControl) -> TaskHandle {
+    schedule_task(NodeBox::new(task))
+}
+
+fn schedule_task(mut task: NodeBox<TaskControl>) -> TaskHandle {
+    // UNSAFE: Accessing the current task
+    task.set_parent(unsafe { sched::current_task() }.map(|parent| parent.tid()));
+
     let handle = TaskHandle::new(&**task);
-    PRIORITY_QUEUES[task.priority()].enqueue(task);
+    ::task::task_created(&task);
+    scheduler().add_task(task);
     handle
 }
 
+pub fn spawn<F: FnOnce() + Send + 'static>(code: F, stack_depth: usize, priority: Priority,
+                                            name: &'static str) -> TaskHandle {
+    let mut args = ArgsBuilder::with_capacity(1);
+    args.add_box(Box::new(code));
+    new_task(run_closure::<F>, args.finalize(), stack_depth, priority, name)
+}
+
+// Run the closure handed to a task by `spawn`.
+fn run_closure<F: FnOnce() + 'static>(args: &mut Args) {
+    // UNSAFE: `spawn` is the only place this is used as a task's code, and it packs a `Box<F>`
+    let code = unsafe { args.pop_box::<F>() };
+    (*code)();
+}
+
 #[no_mangle]
 #[doc(hidden)]
 pub extern "C" fn sys_exit() {
@@ -59,12 +160,315 @@ fn exit() {
     // one with a reference to the task. The destroy method is atomic so we don't have to worry
     // about any threading issues.
     unsafe {
-        debug_assert!(CURRENT_TASK.is_some());
-        CURRENT_TASK.as_mut().unwrap().destroy();
+        let current = sched::current_task();
+        debug_assert!(current.is_some());
+        terminate(current.unwrap());
     }
     sched_yield();
 }
 
+// Kill `task` for another task, freeing it right away if it isn't the one running.
+#[doc(hidden)]
+pub fn kill_task(task: &mut TaskControl) {
+    let _g = CriticalSection::begin();
+    let tid = task.tid();
+    terminate(task);
+
+    // UNSAFE: Accessing the current task
+    let running = unsafe { sched::current_task() }.map_or(false, |current| current.tid() == tid);
+    if !running {
+        // The task must not be touched after this, its memory is gone
+        drop(scheduler().remove_task(tid));
+    }
+}
+
+// Keep `task` from being scheduled until it's resumed.
+#[doc(hidden)]
+pub fn suspend_task(task: &mut TaskControl) {
+    if let Priority::__Idle = task.priority() {
+        kernel_panic!("Tried to suspend the Idle task!");
+    }
+
+    let _g = CriticalSection::begin();
+    let tid = task.tid();
+    task.set_suspended(true);
+
+    // UNSAFE: Accessing the current task
+    let running = unsafe { sched::current_task() }.map_or(false, |current| current.tid() == tid);
+    if running {
+        sched_yield();
+    }
+    // A ready task is parked right away, a blocked one is parked once it's woken up
+    else if let Some(ready) = scheduler().remove_task(tid) {
+        SUSPENDED_QUEUE.enqueue(ready);
+    }
+}
+
+// Start `task` over from the beginning of its code.
+#[doc(hidden)]
+pub fn restart_task(task: &mut TaskControl) -> Result<(), RestartError> {
+    if let Priority::__Idle = task.priority() {
+        kernel_panic!("Tried to restart the Idle task!");
+    }
+
+    let _g = CriticalSection::begin();
+    let tid = task.tid();
+    // UNSAFE: Accessing the current task
+    if unsafe { sched::current_task() }.map_or(false, |current| current.tid() == tid) {
+        return Err(RestartError::RestartSelf);
+    }
+    // Its stack can't be reset while another core is running on it
+    if task.state() == State::Running {
+        return Err(RestartError::Running);
+    }
+    task.restart()?;
+
+    // Whatever the task was waiting on, it starts over ready to run
+    let mut waiting = SLEEP_QUEUE.remove(|task| task.tid() == tid);
+    waiting.append(DELAY_WHEEL.remove(|task| task.tid() == tid));
+    waiting.append(SUSPENDED_QUEUE.remove(|task| task.tid() == tid));
+    for task in waiting {
+        scheduler().add_task(task);
+    }
+    preempt_for_woken();
+    Ok(())
+}
+
+// Let `task` be scheduled again.
+#[doc(hidden)]
+pub fn resume_task(task: &mut TaskControl) {
+    let _g = CriticalSection::begin();
+    let tid = task.tid();
+    task.set_suspended(false);
+    // A task that's used up its budget stays parked until the budget is replenished
+    if task.is_throttled() {
+        return;
+    }
+    // The task isn't touched after this, it's moved over to the ready queues
+    if let Some(parked) = SUSPENDED_QUEUE.remove(|task| task.tid() == tid).dequeue() {
+        scheduler().add_task(parked);
+    }
+}
+
+// Change the priority of `task`, moving it to its new ready queue if it's ready to run.
+#[doc(hidden)]
+pub fn set_task_priority(task: &mut TaskControl, priority: Priority) {
+    if task.priority() == Priority::__Idle || priority == Priority::__Idle {
+        kernel_panic!("Tried to change the priority of a task to or from Idle!");
+    }
+
+    let _g = CriticalSection::begin();
+    // The ready queues are picked by priority, so a ready task has to be taken out to be changed
+    match scheduler().remove_task(task.tid()) {
+        Some(mut ready) => {
+            ready.set_base_priority(priority);
+            scheduler().add_task(ready);
+        },
+        None => task.set_base_priority(priority),
+    }
+    preempt_for_woken();
+}
+
+// Destroy `task` and either kill or reparent its children, depending on what it asked for.
+fn terminate(task: &mut TaskControl) {
+    let tid = task.tid();
+    task.destroy();
+    for handler in task.take_exit_handlers().into_iter().rev() {
+        handler.run(tid);
+    }
+    if task.kills_children_on_exit() {
+        kill_children(tid);
+    }
+    else {
+        reparent_children(tid, task.parent());
+    }
+    free_waiting_destroyed();
+    wake(::task::join_channel());
+}
+
+// Free every destroyed task that's waiting, these would never be woken up to be cleaned up.
+fn free_waiting_destroyed() {
+    drop(SLEEP_QUEUE.remove(|task| task.is_destroyed()));
+    drop(DELAY_WHEEL.remove(|task| task.is_destroyed()));
+    drop(SUSPENDED_QUEUE.remove(|task| task.is_destroyed()));
+}
+
+// Set the value the running task exits with.
+#[doc(hidden)]
+pub fn set_exit_value(value: usize) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing else can be touching the current task.
+    match unsafe { sched::current_task() } {
+        Some(current) => current.set_exit_value(value),
+        None => kernel_panic!("exit_with - current task doesn't exist!"),
+    }
+}
+
+/// Returned from `sys_join` once the task has exited, along with its exit value.
+#[doc(hidden)]
+pub const JOIN_EXITED: usize = 0;
+/// Returned from `sys_join` when the calling task had to block, the call should be made again.
+#[doc(hidden)]
+pub const JOIN_WAITING: usize = 1;
+/// Returned from `sys_join` when the task can't be joined.
+#[doc(hidden)]
+pub const JOIN_NOT_JOINABLE: usize = 2;
+/// Returned from `sys_join` when a task tries to join itself.
+#[doc(hidden)]
+pub const JOIN_SELF: usize = 3;
+
+// Like `sys_mutex_try_lock_owner` this returns two words, so it's only called through the
+// architecture's `syscall1_wide`, which hands the first word back in R0 and the second in R1.
+#[doc(hidden)]
+pub fn sys_join(id: &TaskId) -> (usize, usize) {
+    join(*id)
+}
+
+// Collect the exit value of the task with id `id`, or block until it exits.
+fn join(id: TaskId) -> (usize, usize) {
+    // The task can't exit on another core between being looked for and the joiner going to sleep
+    let _g = CriticalSection::begin();
+    if let Some(value) = ::task::take_exit_value(id) {
+        return (JOIN_EXITED, value);
+    }
+
+    // UNSAFE: We're in a critical section, so the current task can't change under us
+    let current = match unsafe { sched::current_task() } {
+        Some(task) => task,
+        None => kernel_panic!("join - current task doesn't exist!"),
+    };
+    if current.id() == id {
+        return (JOIN_SELF, 0);
+    }
+    let joinable = |task: &TaskControl| {
+        task.id() == id && task.is_joinable() && !task.is_destroyed()
+    };
+    let running = Cell::new(false);
+    sched::for_each_task(|task| {
+        if joinable(task) {
+            running.set(true);
+        }
+    });
+    // The tasks running on the other cores aren't in any queue
+    #[cfg(feature="smp")]
+    {
+        // UNSAFE: We're in a critical section, so the other cores can't switch tasks
+        for other in unsafe { sched::CURRENT_TASK.iter() } {
+            if let Some(ref task) = *other {
+                if joinable(task) {
+                    running.set(true);
+                }
+            }
+        }
+    }
+    if !running.get() {
+        return (JOIN_NOT_JOINABLE, 0);
+    }
+    // Every exiting task wakes the joiners, which then check for their task's record again
+    sleep_on(::task::join_channel(), WaitObject::Channel);
+    (JOIN_WAITING, 0)
+}
+
+/// Returned from `sys_notify_wait` when a notification is already pending.
+#[doc(hidden)]
+pub const NOTIFY_PENDING: usize = 0;
+/// Returned from `sys_notify_wait` when the calling task had to block.
+#[doc(hidden)]
+pub const NOTIFY_WAITING: usize = 1;
+/// Passed to `sys_notify_wait` to wait without a timeout.
+#[doc(hidden)]
+pub const NOTIFY_FOREVER: usize = !0;
+
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn sys_notify_wait(delay: usize) -> usize {
+    notify_wait(delay)
+}
+
+// Block the current task until it's notified or `delay` ticks pass, unless a notification is
+// already pending.
+fn notify_wait(delay: usize) -> usize {
+    // A notification from an interrupt between the check and the task going to sleep would find
+    // nothing to wake and be missed until the next one, so keep interrupts out until it's asleep
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing else can be touching the current task.
+    let current = match unsafe { sched::current_task() } {
+        Some(task) => task,
+        None => kernel_panic!("wait_notification - current task doesn't exist!"),
+    };
+    if current.is_notify_pending() {
+        return NOTIFY_PENDING;
+    }
+    let wchan = notify_channel(current);
+    if delay == NOTIFY_FOREVER {
+        sleep_on(wchan, WaitObject::Channel);
+    }
+    else {
+        sleep_for(wchan, delay);
+    }
+    NOTIFY_WAITING
+}
+
+// Send `task` a notification, waking it if it's waiting for one.
+#[doc(hidden)]
+pub fn notify_task(task: &mut TaskControl, bits: u32) {
+    let _g = CriticalSection::begin();
+    task.notify(bits);
+    sys_wake(notify_channel(task));
+}
+
+// Take the current task's pending notification, if it has one.
+#[doc(hidden)]
+pub fn take_notification(clear_mask: u32) -> Option<u32> {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing else can be touching the current task.
+    match unsafe { sched::current_task() } {
+        Some(current) => current.take_notification(clear_mask),
+        None => kernel_panic!("wait_notification - current task doesn't exist!"),
+    }
+}
+
+// The channel a task waiting for a notification sleeps on, the address of the task is unique to it.
+fn notify_channel(task: &TaskControl) -> usize {
+    task as *const TaskControl as usize
+}
+
+// Destroy every descendant of the task with id `tid`.
+//
+// The whole subtree is killed, even if a child in the middle didn't ask for its own children to
+// be killed, so that no task is left behind with a parent that no longer exists.
+fn kill_children(tid: usize) {
+    let mut parents = Vec::new();
+    parents.push(tid);
+    while let Some(parent) = parents.pop() {
+        let killed = RefCell::new(Vec::new());
+        let handlers = RefCell::new(Vec::new());
+        sched::for_each_task(|task| {
+            if task.parent() == Some(parent) && !task.is_destroyed() {
+                task.destroy();
+                killed.borrow_mut().push(task.tid());
+                handlers.borrow_mut().push((task.tid(), task.take_exit_handlers()));
+            }
+        });
+        // The handlers are run once the tasks have been let go of, so they can make system calls
+        for (tid, handlers) in handlers.into_inner() {
+            for handler in handlers.into_iter().rev() {
+                handler.run(tid);
+            }
+        }
+        parents.append(&mut killed.into_inner());
+    }
+}
+
+// Hand the children of the task with id `tid` off to `new_parent`.
+fn reparent_children(tid: usize, new_parent: Option<usize>) {
+    sched::for_each_task(|task| {
+        if task.parent() == Some(tid) {
+            task.set_parent(new_parent);
+        }
+    });
+}
+
 #[no_mangle]
 #[doc(hidden)]
 pub extern "C" fn sys_sched_yield() {
@@ -72,7 +476,18 @@ pub extern "C" fn sys_sched_yield() {
 }
 
 fn sched_yield() {
-    arch::yield_cpu();
+    // Don't bother switching if the running task would just be picked again
+    // UNSAFE: Accessing the current task
+    if let Some(current) = unsafe { sched::current_task() } {
+        if current.state() == State::Running && !current.is_destroyed() &&
+            !current.is_suspended() && !scheduler().should_yield(current) {
+            return;
+        }
+        if current.state() == State::Blocked && sched::is_scheduler_suspended() {
+            kernel_panic!("sched_yield - can't block while the scheduler is suspended!");
+        }
+    }
+    sched::request_switch();
 }
 
 #[no_mangle]
@@ -82,11 +497,22 @@ pub extern "C" fn sys_sleep(wchan: usize) {
 }
 
 fn sleep(wchan: usize) {
+    sleep_on(wchan, WaitObject::Channel);
+}
+
+// Put the current task to sleep on `wchan`, recording that it belongs to `object`.
+fn sleep_on(wchan: usize, object: WaitObject) {
     debug_assert_ne!(wchan, FOREVER_CHAN);
-    // UNSAFE: Accessing CURRENT_TASK
-    match unsafe { CURRENT_TASK.as_mut() } {
-        Some(current) => current.sleep(wchan),
-        None => panic!("sleep - current task doesn't exist!"),
+    // UNSAFE: Accessing the current task
+    match unsafe { sched::current_task() } {
+        Some(current) => {
+            current.sleep(wchan);
+            current.set_wait_object(object);
+        },
+        #[cfg(feature="blocking_fallback")]
+        None => return fallback::poll_sleep(wchan, None),
+        #[cfg(not(feature="blocking_fallback"))]
+        None => kernel_panic!("sleep - current task doesn't exist!"),
     }
     sched_yield();
 }
@@ -98,10 +524,13 @@ pub extern "C" fn sys_sleep_for(wchan: usize, delay: usize) {
 }
 
 fn sleep_for(wchan: usize, delay: usize) {
-    // UNSAFE: Accessing CURRENT_TASK
-    match unsafe { CURRENT_TASK.as_mut() } {
+    // UNSAFE: Accessing the current task
+    match unsafe { sched::current_task() } {
         Some(current) => current.sleep_for(wchan, delay),
-        None => panic!("sleep_for - current task doesn't exist!"),
+        #[cfg(feature="blocking_fallback")]
+        None => return fallback::poll_sleep(wchan, Some(delay)),
+        #[cfg(not(feature="blocking_fallback"))]
+        None => kernel_panic!("sleep_for - current task doesn't exist!"),
     }
     sched_yield();
 }
@@ -110,60 +539,268 @@ fn sleep_for(wchan: usize, delay: usize) {
 #[doc(hidden)]
 pub extern "C" fn sys_wake(wchan: usize) {
     wake(wchan);
+    preempt_for_woken();
+}
+
+// Preempt the running task if a task that was just woken up outranks it. When called from an
+// interrupt handler the switch happens once the handler returns.
+fn preempt_for_woken() {
+    if sched::is_cooperative() {
+        return;
+    }
+    #[cfg(feature="smp")]
+    sched::smp::preempt_other_cores();
+    // UNSAFE: Accessing the current task
+    if let Some(current) = unsafe { sched::current_task() } {
+        if current.state() != State::Blocked && scheduler().should_preempt(current) {
+            sched::preempt();
+        }
+    }
 }
 
 fn wake(wchan: usize) {
+    #[cfg(feature="blocking_fallback")]
+    fallback::wake(wchan);
+
+    // The current task may have just gone to sleep on this channel without having been switched
+    // out yet, in which case it isn't in any of the sleep queues. Wake it in place so the wakeup
+    // isn't lost, the scheduler will put it back on its ready queue when it gets switched out.
+    // UNSAFE: Accessing the current task
+    if let Some(current) = unsafe { sched::current_task() } {
+        if current.state() == State::Blocked && current.wchan() == wchan {
+            current.wake(WakeReason::Event);
+        }
+    }
+
     let mut to_wake = SLEEP_QUEUE.remove(|task| task.wchan() == wchan);
-    to_wake.append(DELAY_QUEUE.remove(|task| task.wchan() == wchan));
-    to_wake.append(OVERFLOW_DELAY_QUEUE.remove(|task| task.wchan() == wchan));
+    to_wake.append(DELAY_WHEEL.remove(|task| task.wchan() == wchan));
     for mut task in to_wake {
-        task.wake();
-        PRIORITY_QUEUES[task.priority()].enqueue(task);
+        task.wake(WakeReason::Event);
+        scheduler().add_task(task);
+    }
+}
+
+// Report a system call number with no handler, returning the error the caller should be given.
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn sys_invalid_syscall(_call: u32) -> usize {
+    #[cfg(feature="logger")]
+    ::logger::error(::logger::KernelEvent::InvalidSyscall(_call));
+    super::SYS_ERR_INVALID
+}
+
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn sys_wake_reason() -> WakeReason {
+    wake_reason()
+}
+
+fn wake_reason() -> WakeReason {
+    // UNSAFE: Accessing the current task
+    match unsafe { sched::current_task() } {
+        Some(current) => current.wake_reason(),
+        None => kernel_panic!("wake_reason - current task doesn't exist!"),
     }
 }
 
 #[doc(hidden)]
 pub fn sys_system_tick() {
+    // The other cores may be in the kernel while the tick is being handled
+    #[cfg(feature="smp")]
+    let _g = CriticalSection::begin();
     system_tick();
 }
 
 fn system_tick() {
     debug_assert!(arch::in_kernel_mode());
 
+    // Timekeeping is paused, the ticks are made up for in `resume_tick`
+    if tick::is_tick_suspended() {
+        return;
+    }
+
     tick::tick();
+    #[cfg(feature="stats")]
+    ::stats::record_tick();
 
     // wake up all tasks sleeping until the current tick
     let ticks = tick::get_tick();
+    if !hold_for_coalescing(ticks) {
+        wake_expired(ticks);
+    }
+    release_replenished(ticks);
+
+    // UNSAFE: Accessing the current task
+    let current = match unsafe { sched::current_task() } {
+        Some(task) => task,
+        // Ticking before the scheduler starts only keeps time for the polling fallback
+        #[cfg(feature="blocking_fallback")]
+        None => return,
+        #[cfg(not(feature="blocking_fallback"))]
+        None => kernel_panic!("system_tick - current task doesn't exist!"),
+    };
 
-    let to_wake = DELAY_QUEUE.remove(|task| task.tick_to_wake() <= ticks);
-    for mut task in to_wake {
-        task.wake();
-        PRIORITY_QUEUES[task.priority()].enqueue(task);
+    sched::load::record_tick(current.priority() == Priority::__Idle);
+    #[cfg(feature="starvation")]
+    sched::starvation::check_starvation();
+
+    #[cfg(feature="smp")]
+    sched::smp::tick_other_cores();
+    tick_running_task(current, ticks);
+}
+
+// Account the tick to the task running on another core, which core 0 passed on to it.
+#[cfg(feature="smp")]
+#[doc(hidden)]
+pub fn core_tick() {
+    // UNSAFE: Accessing the current task
+    if let Some(current) = unsafe { sched::current_task() } {
+        tick_running_task(current, tick::get_tick());
+    }
+}
+
+// Account a system tick to the task running on this core, switching it out if it's used up its
+// runtime, budget or time slice.
+fn tick_running_task(current: &mut TaskControl, ticks: usize) {
+    sched::edf::check_deadlines(current);
+
+    // Kill the task if it's used up its runtime limit
+    if current.account_tick() {
+        let tid = current.tid();
+        terminate(current);
+        if let Some(callback) = current.runtime_exceeded_callback() {
+            callback(tid);
+        }
+        sched::preempt();
+        return;
+    }
+
+    // Suspend the task until its budget is replenished if it's used it all up
+    if let Some(replenish) = current.charge_budget(ticks) {
+        current.set_throttled(true);
+        watch_replenish(replenish);
+        sched::preempt();
+        return;
     }
 
-    // If ticks == all 1's then it's about to overflow.
-    if ticks == !0 {
-        let overflowed = OVERFLOW_DELAY_QUEUE.remove_all();
-        DELAY_QUEUE.append(overflowed);
+    sched::consume_quantum();
+    if !sched::is_cooperative() && scheduler().on_tick(current) {
+        sched::preempt();
     }
+}
 
-    // UNSAFE: Accessing CURRENT_TASK
-    let current_priority = unsafe {
-        match CURRENT_TASK.as_ref() {
-            Some(task) => task.priority(),
-            None => panic!("system_tick - current task doesn't exist!"),
+// Return how many ticks from now the next task sleeping with a timeout or held back for its budget
+// is due, `None` if there isn't one.
+#[doc(hidden)]
+pub fn ticks_until_wake() -> Option<usize> {
+    let now = tick::get_tick();
+    let next = Cell::new(None);
+    let earliest = |ticks: usize| {
+        if next.get().map_or(true, |next| ticks < next) {
+            next.set(Some(ticks));
         }
     };
+    DELAY_WHEEL.modify_all(|task| {
+        let due_in = task.tick_to_wake().wrapping_sub(now) as isize;
+        // Tasks held back by timer coalescing may already be past their deadline
+        earliest(if due_in > 0 { due_in as usize } else { 0 });
+    });
+    if REPLENISH_PENDING.load(Ordering::Relaxed) {
+        let due_in = NEAREST_REPLENISH.load(Ordering::Relaxed).wrapping_sub(now) as isize;
+        earliest(if due_in > 0 { due_in as usize } else { 0 });
+    }
+    next.get()
+}
 
-    for i in Priority::higher(current_priority) {
-        if !PRIORITY_QUEUES[i].is_empty() {
-            // Only context switch if there's another task at the same or higher priority level
-            sched_yield();
-            break;
+// Wake every delayed task whose deadline is at or before `ticks`.
+fn wake_expired(ticks: usize) {
+    let to_wake = DELAY_WHEEL.advance(ticks);
+    for mut task in to_wake {
+        task.wake(WakeReason::Timeout);
+        scheduler().add_task(task);
+    }
+}
+
+// Make sure the tasks held back for using up their budget are checked by tick `replenish`.
+fn watch_replenish(replenish: usize) {
+    let now = tick::get_tick();
+    let nearest = NEAREST_REPLENISH.load(Ordering::Relaxed);
+    if !REPLENISH_PENDING.load(Ordering::Relaxed) ||
+        (replenish.wrapping_sub(now) as isize) < (nearest.wrapping_sub(now) as isize) {
+        NEAREST_REPLENISH.store(replenish, Ordering::Relaxed);
+        REPLENISH_PENDING.store(true, Ordering::Relaxed);
+    }
+}
+
+// Let every task whose budget is replenished by `ticks` back into scheduling.
+fn release_replenished(ticks: usize) {
+    if !REPLENISH_PENDING.load(Ordering::Relaxed) ||
+        (ticks.wrapping_sub(NEAREST_REPLENISH.load(Ordering::Relaxed)) as isize) < 0 {
+        return;
+    }
+    REPLENISH_PENDING.store(false, Ordering::Relaxed);
+
+    let due = SUSPENDED_QUEUE.remove(|task| {
+        task.is_throttled() && (ticks.wrapping_sub(task.replenish_tick()) as isize) >= 0
+    });
+    for mut task in due {
+        task.set_throttled(false);
+        // A task that was also suspended stays parked until it's resumed
+        if task.is_suspended() {
+            SUSPENDED_QUEUE.enqueue(task);
+        }
+        else {
+            scheduler().add_task(task);
+        }
+    }
+    SUSPENDED_QUEUE.modify_all(|task| {
+        if task.is_throttled() {
+            watch_replenish(task.replenish_tick());
+        }
+    });
+}
+
+// Check if the tasks that have come due should wait for a task due soon after them, so they can
+// all be woken together.
+//
+// The window is measured from the earliest deadline that has passed, so no task is held back for
+// more than the coalescing window.
+fn hold_for_coalescing(ticks: usize) -> bool {
+    let window = tick::timer_coalescing();
+    if window == 0 {
+        return false;
+    }
+
+    let earliest_due = Cell::new(None);
+    let next_due = Cell::new(None);
+    DELAY_WHEEL.modify_all(|task| {
+        let due_in = task.tick_to_wake().wrapping_sub(ticks) as isize;
+        let slot = if due_in <= 0 { &earliest_due } else { &next_due };
+        if slot.get().map_or(true, |earliest| due_in < earliest) {
+            slot.set(Some(due_in));
         }
+    });
+
+    match (earliest_due.get(), next_due.get()) {
+        (Some(earliest), Some(next)) => next <= earliest.saturating_add(window as isize),
+        _ => false,
     }
 }
 
+#[doc(hidden)]
+pub fn sys_resume_tick(elapsed: usize) {
+    resume_tick(elapsed);
+}
+
+fn resume_tick(elapsed: usize) {
+    let _g = CriticalSection::begin();
+    // The delay wheel measures deadlines across the count wrapping around, so every task due by
+    // the new count is woken whether or not it wrapped while the tick was stopped
+    let ticks = tick::advance(elapsed);
+    wake_expired(ticks);
+    release_replenished(ticks);
+}
+
 #[no_mangle]
 #[doc(hidden)]
 pub extern "C" fn sys_mutex_lock(lock: &RawMutex) -> bool {
@@ -172,22 +809,49 @@ pub extern "C" fn sys_mutex_lock(lock: &RawMutex) -> bool {
 
 fn mutex_lock(lock: &RawMutex) -> bool {
     use sync::LockError;
-    // UNSAFE: Accessing CURRENT_TASK
-    let current_tid = match unsafe { CURRENT_TASK.as_ref() } {
-        Some(task) => task.tid(),
-        None => panic!("mutex_lock - current task doesn't exist!"),
+    // UNSAFE: Accessing the current task
+    let current = match unsafe { sched::current_task() } {
+        Some(task) => task,
+        None => kernel_panic!("mutex_lock - current task doesn't exist!"),
     };
-    match lock.try_lock(current_tid) {
+    match lock.try_lock(current.tid()) {
         Err(LockError::AlreadyOwned) => {
-            panic!("mutex_lock - attempted to acquire a lock that was already owned");
+            kernel_panic!("mutex_lock - attempted to acquire a lock that was already owned");
         },
         Err(LockError::Locked) => {
+            inherit_priority(lock, current.priority());
             let wchan = lock.address();
-            sleep(wchan);
+            sleep_on(wchan, WaitObject::Mutex);
             false
         },
-        Ok(_) => true,
+        Ok(_) => {
+            current.lock_acquired();
+            true
+        },
+    }
+}
+
+// Raise the priority of the task holding `lock` to `priority` if it's lower, so tasks with a
+// priority in between can't keep it from running and releasing the lock.
+//
+// Only the direct holder is boosted, if it's blocked on another mutex in turn that mutex's holder
+// keeps its own priority.
+fn inherit_priority(lock: &RawMutex, priority: Priority) {
+    let holder = match lock.holder() {
+        Some(tid) => tid,
+        None => return,
+    };
+    // A ready task has to be moved over to the queue for its new priority
+    if let Some(mut task) = scheduler().remove_task(holder) {
+        task.inherit_priority(priority);
+        scheduler().add_task(task);
+        return;
     }
+    sched::for_each_task(|task| {
+        if task.tid() == holder {
+            task.inherit_priority(priority);
+        }
+    });
 }
 
 #[no_mangle]
@@ -198,42 +862,61 @@ pub extern "C" fn sys_mutex_try_lock(lock: &RawMutex) -> bool {
 
 fn mutex_try_lock(lock: &RawMutex) -> bool {
     use sync::LockError;
-    // UNSAFE: Accessing CURRENT_TASK
-    let current_tid = match unsafe { CURRENT_TASK.as_ref() } {
-        Some(task) => task.tid(),
-        None => panic!("mutex_lock - current task doesn't exist!"),
+    // UNSAFE: Accessing the current task
+    let current = match unsafe { sched::current_task() } {
+        Some(task) => task,
+        None => kernel_panic!("mutex_lock - current task doesn't exist!"),
     };
-    match lock.try_lock(current_tid) {
+    match lock.try_lock(current.tid()) {
+        Ok(_) => {
+            current.lock_acquired();
+            true
+        },
         // We don't really care if we try to reacquire the lock since we're non-blocking
-        Ok(_) | Err(LockError::AlreadyOwned) => true,
+        Err(LockError::AlreadyOwned) => true,
         Err(LockError::Locked) => false,
     }
 }
 
+// This returns two words, so unlike the other system calls it isn't `extern "C"` and the tuple has
+// no fixed register layout. It's only called through the architecture's `syscall1_wide`, and that
+// is what hands the first word back in R0 and the second in R1.
+#[doc(hidden)]
+pub fn sys_mutex_try_lock_owner(lock: &RawMutex) -> (usize, usize) {
+    let acquired = mutex_try_lock(lock);
+    let holder = match lock.holder() {
+        Some(holder) => holder,
+        None => kernel_panic!("mutex_try_lock_owner - the lock isn't held by anyone!"),
+    };
+    (acquired as usize, holder)
+}
+
 #[no_mangle]
 #[doc(hidden)]
 pub extern "C" fn sys_mutex_unlock(lock: &RawMutex) {
     mutex_unlock(lock);
+    preempt_for_woken();
 }
 
 fn mutex_unlock(lock: &RawMutex) {
     use sync::UnlockError;
-    // UNSAFE: Accessing CURRENT_TASK
-    let current_tid = match unsafe { CURRENT_TASK.as_ref() } {
-        Some(task) => task.tid(),
-        None => panic!("mutex_unlock - current task doesn't exist!"),
+    // UNSAFE: Accessing the current task
+    let current = match unsafe { sched::current_task() } {
+        Some(task) => task,
+        None => kernel_panic!("mutex_unlock - current task doesn't exist!"),
     };
-    match lock.try_unlock(current_tid) {
+    match lock.try_unlock(current.tid()) {
         // No-op if we try to unlock a lock that's not locked
         Err(UnlockError::NotLocked) => {},
 
         // We tried to unlock a lock that we didn't acquire
         Err(UnlockError::NotOwned) => {
-            panic!("mutex_unlock - tried to unlock a lock that was not owned");
+            kernel_panic!("mutex_unlock - tried to unlock a lock that was not owned");
         },
 
-        // We successfully unlocked the lock, so we don't have to do any more
+        // We successfully unlocked the lock, give up any priority it lent us and wake the waiters
         Ok(_) => {
+            current.lock_released();
             let wchan = lock.address();
             wake(wchan);
         },
@@ -249,17 +932,109 @@ pub extern "C" fn sys_condvar_wait(condvar: &CondVar, lock: &RawMutex) {
 fn condvar_wait(condvar: &CondVar, lock: &RawMutex) {
     mutex_unlock(lock);
 
-    sleep(condvar as *const _ as usize);
+    sleep_on(condvar as *const _ as usize, WaitObject::CondVar);
 }
 
 #[no_mangle]
 #[doc(hidden)]
 pub extern "C" fn sys_condvar_broadcast(condvar: &CondVar) {
     condvar_broadcast(condvar);
+    preempt_for_woken();
 }
 
 fn condvar_broadcast(condvar: &CondVar) {
-    wake(condvar as *const _ as usize);
+    let wchan = condvar as *const _ as usize;
+    if let Some(mutex) = condvar.deferred_mutex() {
+        // Move the waiters over to the mutex so they're woken when it's released. Waiters with a
+        // timeout are left alone so they don't lose it, the `wake` below takes care of them.
+        let mutex_wchan = mutex.address();
+        // UNSAFE: Accessing the current task
+        if let Some(current) = unsafe { sched::current_task() } {
+            if current.state() == State::Blocked && current.wchan() == wchan {
+                current.move_to_wchan(mutex_wchan);
+                current.set_wait_object(WaitObject::Mutex);
+            }
+        }
+        let mut waiters = SLEEP_QUEUE.remove(|task| task.wchan() == wchan);
+        for task in waiters.iter_mut() {
+            task.move_to_wchan(mutex_wchan);
+            task.set_wait_object(WaitObject::Mutex);
+        }
+        SLEEP_QUEUE.append(waiters);
+    }
+    wake(wchan);
+}
+
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn sys_condvar_signal(condvar: &CondVar) {
+    condvar_signal(condvar);
+    preempt_for_woken();
+}
+
+fn condvar_signal(condvar: &CondVar) {
+    let wchan = condvar as *const _ as usize;
+    wake_one(wchan, condvar.deferred_mutex().map(|mutex| mutex.address()));
+}
+
+// Wake the highest priority task sleeping on `wchan`, if more than one task has that priority the
+// one that started waiting first is woken.
+//
+// Only tasks sleeping without a timeout are considered, the sleep queue keeps them in the order
+// they went to sleep in. If `morph_to` is given the chosen task is moved to sleep on that channel
+// instead of being woken.
+fn wake_one(wchan: usize, morph_to: Option<usize>) {
+    let mut waiters = SLEEP_QUEUE.remove(|task| task.wchan() == wchan);
+
+    // UNSAFE: Accessing the current task
+    // The current task may be waiting without having been switched out yet, if it is then it's the
+    // newest waiter and only wins if it has a strictly higher priority than everyone else.
+    let current = match unsafe { sched::current_task() } {
+        Some(task) if task.state() == State::Blocked && task.wchan() == wchan => Some(task),
+        _ => None,
+    };
+
+    let best = waiters.iter().map(|task| task.priority() as usize).min();
+    if let Some(current) = current {
+        if best.map_or(true, |best| (current.priority() as usize) < best) {
+            match morph_to {
+                Some(morph_wchan) => current.move_to_wchan(morph_wchan),
+                None => current.wake(WakeReason::Event),
+            }
+            SLEEP_QUEUE.append(waiters);
+            return;
+        }
+    }
+
+    let mut chosen = None;
+    let mut rest = Queue::new();
+    while let Some(task) = waiters.dequeue() {
+        if chosen.is_none() && Some(task.priority() as usize) == best {
+            chosen = Some(task);
+        }
+        else {
+            rest.enqueue(task);
+        }
+    }
+    SLEEP_QUEUE.append(rest);
+
+    if let Some(mut task) = chosen {
+        match morph_to {
+            Some(morph_wchan) => {
+                task.move_to_wchan(morph_wchan);
+                SLEEP_QUEUE.enqueue(task);
+            },
+            None => {
+                task.wake(WakeReason::Event);
+                scheduler().add_task(task);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn clear_replenish() {
+    REPLENISH_PENDING.store(false, Ordering::Relaxed);
 }
 
 #[cfg(test)]
@@ -268,7 +1043,8 @@ mod tests {
     use super::*;
     use task::{State, Priority};
     use task::args::Args;
-    use sched::start_scheduler;
+    use sched::{start_scheduler, Policy, PRIORITY_QUEUES};
+    use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 
     #[test]
     fn test_new_task() {
@@ -282,25 +1058,155 @@ mod tests {
         assert_not!(PRIORITY_QUEUES[Priority::Normal].remove_all().is_empty());
     }
 
+    static RUNTIME_EXCEEDED_TID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_runtime_exceeded(tid: usize) {
+        RUNTIME_EXCEEDED_TID.store(tid, Ordering::SeqCst);
+    }
+
     #[test]
-    fn test_sched_yield() {
-        // This isn't the greatest test, as the functionality of this method is really just
-        // dependent on the platform implementation, but at least we can make sure it's working
-        // properly for the test suite
+    fn test_task_over_max_runtime_is_killed() {
+        use task::TaskBuilder;
         let _g = test::set_up();
-        let (handle_1, handle_2) = test::create_two_tasks();
-
+        RUNTIME_EXCEEDED_TID.store(!0, Ordering::SeqCst);
+        let handle = TaskBuilder::new(test_task)
+            .max_runtime(2)
+            .on_runtime_exceeded(record_runtime_exceeded)
+            .spawn()
+            .unwrap();
+        let tid = handle.tid().unwrap();
+        let other = test::create_and_schedule_test_task(512, Priority::Low, "other task");
         start_scheduler();
-        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
 
-        sched_yield();
-        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        // The task is allowed to run for its full budget...
+        system_tick();
+        system_tick();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(RUNTIME_EXCEEDED_TID.load(Ordering::SeqCst), !0);
+
+        // ...but not a tick more, the task gets killed and freed
+        system_tick();
+        assert_eq!(other.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(RUNTIME_EXCEEDED_TID.load(Ordering::SeqCst), tid);
+
+        // Tasks without a limit can run forever
+        for _ in 0..10 {
+            system_tick();
+        }
+        assert_eq!(other.tid(), Ok(test::current_task().unwrap().tid()));
     }
 
     #[test]
-    fn test_sleep() {
+    fn test_task_over_budget_suspended_until_replenished() {
+        use task::TaskBuilder;
         let _g = test::set_up();
-        let (handle_1, handle_2) = test::create_two_tasks();
+        let start = tick::get_tick();
+        let handle = TaskBuilder::new(test_task)
+            .budget(2, 10)
+            .spawn()
+            .unwrap();
+        let other = test::create_and_schedule_test_task(512, Priority::Low, "other task");
+        start_scheduler();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // The task runs for its budget, then is held back for the rest of the period
+        system_tick();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+        system_tick();
+        assert_eq!(other.tid(), Ok(test::current_task().unwrap().tid()));
+        while tick::get_tick() != start + 9 {
+            system_tick();
+            assert_eq!(other.tid(), Ok(test::current_task().unwrap().tid()));
+        }
+
+        // The budget is topped up at the start of the next period
+        system_tick();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+        system_tick();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+        system_tick();
+        assert_eq!(other.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_task_over_budget_held_back_even_if_resumed() {
+        use task::{TaskBuilder, TaskStatus, WaitReason};
+        let _g = test::set_up();
+        let start = tick::get_tick();
+        let mut handle = TaskBuilder::new(test_task)
+            .budget(1, 10)
+            .spawn()
+            .unwrap();
+        let other = test::create_and_schedule_test_task(512, Priority::Low, "other task");
+        start_scheduler();
+
+        // Using up the budget with the scheduler locked doesn't count as blocking
+        let guard = sched::suspend_scheduler();
+        system_tick();
+        assert_eq!(handle.status(), Ok(TaskStatus::Suspended(WaitReason::Budget(start + 10))));
+        assert_eq!(handle.is_suspended(), Ok(false));
+        sched_yield();
+        drop(guard);
+        assert_eq!(other.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // Resuming the task doesn't let it run again before its budget is replenished
+        handle.resume().unwrap();
+        system_tick();
+        assert_eq!(other.tid(), Ok(test::current_task().unwrap().tid()));
+        while tick::get_tick() != start + 10 {
+            system_tick();
+        }
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(handle.status(), Ok(TaskStatus::Running));
+    }
+
+    #[test]
+    fn test_ticks_until_wake_includes_budget_replenish() {
+        use task::TaskBuilder;
+        let _g = test::set_up();
+        TaskBuilder::new(test_task)
+            .budget(1, 10)
+            .spawn()
+            .unwrap();
+        test::create_and_schedule_test_task(512, Priority::Low, "other task");
+        start_scheduler();
+        assert_eq!(ticks_until_wake(), None);
+
+        // Tickless idle mustn't sleep past the tick the task gets its budget back on
+        system_tick();
+        assert_eq!(ticks_until_wake(), Some(9));
+    }
+
+    #[test]
+    fn test_try_new_task_stack_too_small() {
+        let _g = test::set_up();
+        let depth = (arch::MIN_STACK_WORDS - 1) * ::core::mem::size_of::<usize>();
+        let result = try_new_task(test_task, Args::empty(), depth, Priority::Normal, "tiny task");
+
+        assert_eq!(result.err(), Some(SpawnError::StackTooSmall));
+        assert!(PRIORITY_QUEUES[Priority::Normal].remove_all().is_empty());
+    }
+
+    #[test]
+    fn test_sched_yield() {
+        // This isn't the greatest test, as the functionality of this method is really just
+        // dependent on the platform implementation, but at least we can make sure it's working
+        // properly for the test suite
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_sleep() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
 
         start_scheduler();
         assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
@@ -348,6 +1254,7 @@ mod tests {
     #[test]
     fn test_system_tick() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(Policy::RoundRobin);
         let (handle_1, handle_2) = test::create_two_tasks();
 
         start_scheduler();
@@ -359,9 +1266,74 @@ mod tests {
         assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
     }
 
+    #[test]
+    fn test_yield_as_sole_high_priority_task_doesnt_switch() {
+        use arch::YIELD_COUNT;
+
+        let _g = test::set_up();
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        let (handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+
+        let yields = YIELD_COUNT.load(Ordering::SeqCst);
+        sched_yield();
+        assert_eq!(YIELD_COUNT.load(Ordering::SeqCst), yields);
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // Once the critical task blocks, yielding to another task of the same priority switches
+        sleep(!FOREVER_CHAN);
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        let yields = YIELD_COUNT.load(Ordering::SeqCst);
+        sched_yield();
+        assert_eq!(YIELD_COUNT.load(Ordering::SeqCst), yields + 1);
+    }
+
+    #[test]
+    fn test_waking_higher_priority_task_preempts() {
+        let _g = test::set_up();
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        let (handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+        sleep(0x1234);
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        sys_wake(0x1234);
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_waking_higher_priority_task_from_interrupt_preempts_on_return() {
+        let _g = test::set_up();
+        let critical = test::create_and_schedule_test_task(512, Priority::Critical, "critical task");
+        let (handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+        sleep(0x1234);
+
+        arch::set_in_interrupt(true);
+        sys_wake(0x1234);
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        arch::set_in_interrupt(false);
+        assert_eq!(critical.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_waking_equal_priority_task_doesnt_preempt() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+        sleep(0x1234);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        sys_wake(0x1234);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+    }
+
     #[test]
     fn test_sleep_for_forever() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(Policy::RoundRobin);
         let (handle_1, handle_2) = test::create_two_tasks();
 
         start_scheduler();
@@ -384,7 +1356,11 @@ mod tests {
         assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
 
         system_tick();
-        // 4 Ticks have passed, task 1 should be woken up now
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        system_tick();
+        // 4 full ticks have passed, task 1 should be woken up now
         assert_ne!(handle_1.state(), Ok(State::Blocked));
         assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
 
@@ -395,6 +1371,7 @@ mod tests {
     #[test]
     fn test_sleep_for_timeout() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(Policy::RoundRobin);
         let (handle_1, handle_2) = test::create_two_tasks();
 
         start_scheduler();
@@ -417,7 +1394,11 @@ mod tests {
         assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
 
         system_tick();
-        // 4 Ticks have passed, task 1 should be woken up now
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        system_tick();
+        // 4 full ticks have passed, task 1 should be woken up now
         assert_ne!(handle_1.state(), Ok(State::Blocked));
         assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
 
@@ -428,6 +1409,7 @@ mod tests {
     #[test]
     fn test_sleep_for_early_wake() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(Policy::RoundRobin);
         let (handle_1, handle_2) = test::create_two_tasks();
 
         start_scheduler();
@@ -449,9 +1431,109 @@ mod tests {
         assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
     }
 
+    #[test]
+    fn test_sleep_for_one_tick_waits_a_full_tick() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // The mock tick can't tell how far into the current tick we are, so this could be right
+        // before the next tick edge. That edge must not count as the whole tick.
+        sleep_for(!FOREVER_CHAN, 1);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        system_tick();
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+
+        system_tick();
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+    }
+
+    #[test]
+    fn test_timer_coalescing_batches_nearby_timeouts() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        let handle_3 = test::create_and_schedule_test_task(512, Priority::Normal, "test task 3");
+        tick::set_timer_coalescing(3);
+
+        start_scheduler();
+        // Due after 5 ticks...
+        sleep_for(!FOREVER_CHAN, 4);
+        // ...within the window of task 1, after 7 ticks...
+        sleep_for(!FOREVER_CHAN, 6);
+        // ...and well outside of it, after 21 ticks
+        sleep_for(!FOREVER_CHAN, 20);
+
+        for _ in 0..6 {
+            system_tick();
+        }
+        // Task 1 is due, but is held back for task 2
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+        assert_eq!(handle_2.state(), Ok(State::Blocked));
+
+        system_tick();
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+        assert_eq!(handle_2.state(), Ok(State::Ready));
+        assert_eq!(handle_3.state(), Ok(State::Blocked));
+
+        for _ in 0..13 {
+            system_tick();
+        }
+        assert_eq!(handle_3.state(), Ok(State::Blocked));
+        system_tick();
+        assert_eq!(handle_3.state(), Ok(State::Ready));
+    }
+
+    #[test]
+    fn test_resume_tick_wakes_all_overdue_sleepers() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        let handle_3 = test::create_and_schedule_test_task(512, Priority::Normal, "test task 3");
+
+        start_scheduler();
+        sleep_for(!FOREVER_CHAN, 5);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        sleep_for(!FOREVER_CHAN, 50);
+        assert_eq!(handle_3.tid(), Ok(test::current_task().unwrap().tid()));
+
+        tick::suspend_tick();
+        let before = tick::get_tick();
+        // Ticks that land while suspended are ignored
+        system_tick();
+        assert_eq!(tick::get_tick(), before);
+
+        tick::resume_tick(1000);
+        assert_eq!(tick::get_tick(), before + 1000);
+        assert_not!(tick::is_tick_suspended());
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+        assert_eq!(handle_2.state(), Ok(State::Ready));
+        assert_eq!(task_wake_reason(handle_1), WakeReason::Timeout);
+        assert_eq!(task_wake_reason(handle_2), WakeReason::Timeout);
+    }
+
+    #[test]
+    fn test_resume_tick_keeps_future_deadlines() {
+        let _g = test::set_up();
+        let (handle_1, _handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        sleep_for(!FOREVER_CHAN, 50);
+
+        tick::suspend_tick();
+        tick::resume_tick(10);
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+    }
+
+    fn task_wake_reason(handle: TaskHandle) -> WakeReason {
+        test::convert_handle_to_task_control(handle).wake_reason()
+    }
+
     #[test]
     fn test_sleep_for_no_timeout_forever() {
         let _g = test::set_up();
+        sched::set_intra_priority_policy(Policy::RoundRobin);
         let (handle_1, handle_2) = test::create_two_tasks();
 
         start_scheduler();
@@ -727,6 +1809,628 @@ mod tests {
         assert_eq!(handle.state(), Ok(State::Ready));
     }
 
+    #[test]
+    fn test_double_wake_enqueues_task_once() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        sleep(!FOREVER_CHAN);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        wake(!FOREVER_CHAN);
+        wake(!FOREVER_CHAN);
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+
+        let tid = handle_1.tid().unwrap();
+        let queued = PRIORITY_QUEUES[Priority::Normal].remove(|task| task.tid() == tid);
+        assert_eq!(queued.into_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_wake_task_that_has_not_been_switched_out_yet() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // Block the task without switching it out, as if the wakeup came in right before the
+        // context switch could happen
+        test::current_task().unwrap().sleep(!FOREVER_CHAN);
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+
+        wake(!FOREVER_CHAN);
+        wake(!FOREVER_CHAN);
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+
+        sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        sched_yield();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_wait_with_timeout_woken_by_event() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        sleep_for(!FOREVER_CHAN, 4);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        wake(!FOREVER_CHAN);
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+
+        // The timeout has been cancelled, so it shouldn't change the reason the task woke up
+        for _ in 0..5 {
+            system_tick();
+        }
+        let task_1 = test::convert_handle_to_task_control(handle_1);
+        assert_eq!(task_1.wake_reason(), WakeReason::Event);
+
+        sched_yield();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(wake_reason(), WakeReason::Event);
+    }
+
+    #[test]
+    fn test_wait_with_timeout_woken_by_timeout() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        start_scheduler();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+
+        sleep_for(!FOREVER_CHAN, 2);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        system_tick();
+        system_tick();
+        system_tick();
+        assert_eq!(handle_1.state(), Ok(State::Ready));
+
+        // The task is no longer waiting on the channel, a late event should be ignored
+        wake(!FOREVER_CHAN);
+        let task_1 = test::convert_handle_to_task_control(handle_1);
+        assert_eq!(task_1.wake_reason(), WakeReason::Timeout);
+
+        sched_yield();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(wake_reason(), WakeReason::Timeout);
+        assert!(PRIORITY_QUEUES[Priority::Normal].remove(|task| task.tid() == handle_1.tid().unwrap()).is_empty());
+    }
+
+    fn borrowing_task(_data: &AtomicUsize) {}
+
+    #[test]
+    fn test_spawn_borrowed_passes_data_directly() {
+        let _g = test::set_up();
+        let data = AtomicUsize::new(7);
+
+        let (handle, result) = spawn_borrowed(borrowing_task, &data, 512, Priority::Normal,
+                                              "borrower", |handle| {
+            assert_eq!(handle.state(), Ok(State::Ready));
+            let task = test::convert_handle_to_task_control(handle);
+            assert!(task.is_borrowed());
+            // The initial frame holds R0 eight words above the saved stack pointer
+            let r0 = unsafe { *(task.stack_ptr() as *const usize).offset(8) };
+            assert_eq!(r0, &data as *const _ as usize);
+            (handle, 42)
+        }).unwrap();
+
+        assert_eq!(result, 42);
+        assert_not!(handle.is_valid());
+    }
+
+    #[test]
+    fn test_idle_task_is_static() {
+        let _g = test::set_up();
+        start_scheduler();
+
+        let idle = test::current_task().unwrap();
+        assert_eq!(idle.priority(), Priority::__Idle);
+        assert!(idle.is_static());
+    }
+
+    #[test]
+    fn test_static_task_lives_in_given_memory() {
+        static mut TCB: StaticTask = StaticTask::new();
+        static mut STACK: [u8; 512] = [0; 512];
+        let _g = test::set_up();
+
+        // UNSAFE: This is the only test that uses the static memory
+        let (mut handle, tcb, stack) = unsafe {
+            let tcb = (&TCB as *const StaticTask as usize, ::core::mem::size_of::<StaticTask>());
+            let stack = STACK.as_ptr() as usize;
+            let handle = new_static_task(test_task, Args::empty(), &mut TCB, &mut STACK,
+                                             Priority::Normal, "static").unwrap();
+            (handle, tcb, stack)
+        };
+
+        assert_eq!(handle.state(), Ok(State::Ready));
+        let task = test::convert_handle_to_task_control(handle);
+        assert!(task.is_static());
+        assert_eq!(task.stack_bounds(), (stack, stack + 512));
+        let addr = task as *const TaskControl as usize;
+        assert!(addr >= tcb.0 && addr < tcb.0 + tcb.1);
+
+        // The task is dropped where it is rather than being handed to the heap
+        assert!(handle.destroy());
+        assert_not!(handle.is_valid());
+        assert!(PRIORITY_QUEUES[Priority::Normal].remove_all().is_empty());
+    }
+
+    #[test]
+    fn test_spawn_runs_closure_with_captured_state() {
+        let _g = test::set_up();
+        static RAN_WITH: AtomicUsize = ATOMIC_USIZE_INIT;
+        RAN_WITH.store(0, Ordering::SeqCst);
+        let captured = 0x1234;
+
+        let handle = spawn(move || RAN_WITH.store(captured, Ordering::SeqCst), 512, Priority::Normal,
+                           "closure");
+
+        run_task_code(handle);
+        assert_eq!(RAN_WITH.load(Ordering::SeqCst), 0x1234);
+    }
+
+    // Start a task the way the hardware would, calling the code in its initial frame with the
+    // argument pointer in R0
+    fn run_task_code(handle: TaskHandle) {
+        let task = test::convert_handle_to_task_control(handle);
+        let (entry, args) = unsafe {
+            let frame = task.stack_ptr() as *const usize;
+            (*frame.offset(14), *frame.offset(8))
+        };
+        let entry: fn(&mut Args) = unsafe { ::core::mem::transmute(entry) };
+        entry(unsafe { &mut *(args as *mut Args) });
+    }
+
+    static RESTARTED_WITH: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_arg(args: &mut Args) {
+        RESTARTED_WITH.store(args.pop_num(), Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_restart_runs_task_again_with_original_args() {
+        let _g = test::set_up();
+        let mut args = ArgsBuilder::with_capacity(1);
+        args.add_num(42);
+        let mut handle = new_task(record_arg, args.finalize(), 512, Priority::Normal, "restart");
+        let mut other = test::create_and_schedule_test_task(512, Priority::Normal, "other");
+
+        run_task_code(handle);
+        assert_eq!(RESTARTED_WITH.swap(0, Ordering::SeqCst), 42);
+
+        start_scheduler();
+        assert_eq!(handle.tid(), Ok(test::current_task().unwrap().tid()));
+        sleep(0x1234);
+        assert_eq!(other.restart(), Err(RestartError::RestartSelf));
+
+        assert_eq!(handle.restart(), Ok(()));
+        assert_eq!(handle.state(), Ok(State::Ready));
+        assert!(SLEEP_QUEUE.remove(|task| task.tid() == handle.tid().unwrap()).is_empty());
+
+        // The argument that was popped on the first run is there again
+        run_task_code(handle);
+        assert_eq!(RESTARTED_WITH.load(Ordering::SeqCst), 42);
+    }
+
+    #[test]
+    fn test_restart_task_with_boxed_args_fails() {
+        let _g = test::set_up();
+        let mut handle = spawn(|| {}, 512, Priority::Normal, "closure");
+        assert_eq!(handle.restart(), Err(RestartError::BoxedArgs));
+        assert_eq!(handle.state(), Ok(State::Ready));
+    }
+
+    static SUMMED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    struct Pair {
+        a: usize,
+        b: usize,
+    }
+
+    fn sum_pair(pair: Pair) {
+        SUMMED.store(pair.a + pair.b, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_spawn_with_passes_typed_argument() {
+        let _g = test::set_up();
+        SUMMED.store(0, Ordering::SeqCst);
+
+        let pair = Pair { a: 3, b: 4 };
+        let handle = ::syscall::spawn_with(sum_pair, pair, 512, Priority::Normal, "sum");
+
+        run_task_code(handle);
+        assert_eq!(SUMMED.load(Ordering::SeqCst), 7);
+    }
+
+    #[test]
+    fn test_spawn_borrowed_stack_too_small() {
+        let _g = test::set_up();
+        let data = AtomicUsize::new(0);
+
+        let result = spawn_borrowed(borrowing_task, &data, 8, Priority::Normal, "borrower", |_| {
+            panic!("scope shouldn't be called when the task can't be created");
+        });
+        assert_eq!(result, Err(SpawnError::StackTooSmall));
+    }
+
+    #[test]
+    fn test_borrowing_task_destroyed_with_parent() {
+        let _g = test::set_up();
+        let (mut handle_1, _handle_2) = test::create_two_tasks();
+        let data = AtomicUsize::new(0);
+        start_scheduler();
+
+        spawn_borrowed(borrowing_task, &data, 512, Priority::Normal, "borrower", |handle| {
+            assert!(handle.is_valid());
+            // Kill the spawning task from under the scope, freeing its stack when it's switched out
+            assert!(handle_1.destroy());
+            sched_yield();
+            assert_not!(handle.is_valid());
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_new_task_records_current_task_as_parent() {
+        let _g = test::set_up();
+        let parent = new_task(test_task, Args::empty(), 512, Priority::Normal, "parent");
+        assert_eq!(parent.parent(), Ok(None));
+
+        start_scheduler();
+        let child = new_task(test_task, Args::empty(), 512, Priority::Normal, "child");
+        assert_eq!(child.parent(), Ok(Some(parent.tid().unwrap())));
+    }
+
+    #[test]
+    fn test_exit_kills_children() {
+        let _g = test::set_up();
+        let mut parent = new_task(test_task, Args::empty(), 512, Priority::Normal, "parent");
+        parent.set_kill_children_on_exit(true).unwrap();
+
+        start_scheduler();
+        assert_eq!(parent.tid(), Ok(test::current_task().unwrap().tid()));
+        let child_1 = new_task(test_task, Args::empty(), 512, Priority::Normal, "child 1");
+        let child_2 = new_task(test_task, Args::empty(), 512, Priority::Normal, "child 2");
+
+        exit();
+
+        assert_not!(parent.is_valid());
+        assert_not!(child_1.is_valid());
+        assert_not!(child_2.is_valid());
+    }
+
+    #[test]
+    fn test_join_collects_exit_value() {
+        use task::TaskBuilder;
+        let _g = test::set_up();
+        let joiner = test::create_and_schedule_test_task(512, Priority::Normal, "joiner");
+        let worker = TaskBuilder::new(test_task)
+            .name("worker")
+            .joinable(true)
+            .spawn()
+            .unwrap();
+        let id = worker.id().unwrap();
+        start_scheduler();
+
+        // The worker is still running, so the joiner has to wait for it
+        assert_eq!(sys_join(&id), (JOIN_WAITING, 0));
+        assert_eq!(worker.tid(), Ok(test::current_task().unwrap().tid()));
+        set_exit_value(7);
+        exit();
+
+        assert_eq!(joiner.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(sys_join(&id), (JOIN_EXITED, 7));
+        // The exit value can only be collected once
+        assert_eq!(sys_join(&id), (JOIN_NOT_JOINABLE, 0));
+    }
+
+    #[test]
+    fn test_join_errors() {
+        let _g = test::set_up();
+        let joiner = test::create_and_schedule_test_task(512, Priority::Normal, "joiner");
+        let other = test::create_and_schedule_test_task(512, Priority::Normal, "other");
+        start_scheduler();
+
+        assert_eq!(sys_join(&joiner.id().unwrap()), (JOIN_SELF, 0));
+        assert_eq!(sys_join(&other.id().unwrap()), (JOIN_NOT_JOINABLE, 0));
+    }
+
+    #[test]
+    fn test_detached_task_leaves_no_exit_value() {
+        use task::TaskBuilder;
+        let _g = test::set_up();
+        let mut worker = TaskBuilder::new(test_task)
+            .joinable(true)
+            .spawn()
+            .unwrap();
+        let id = worker.id().unwrap();
+        assert_eq!(worker.is_joinable(), Ok(true));
+        worker.detach();
+        assert_eq!(worker.is_joinable(), Ok(false));
+
+        assert!(worker.destroy());
+        assert_eq!(::task::take_exit_value(id), None);
+    }
+
+    #[test]
+    fn test_notification_wakes_waiting_task() {
+        let _g = test::set_up();
+        let mut waiter = test::create_and_schedule_test_task(512, Priority::Critical, "waiter");
+        let notifier = test::create_and_schedule_test_task(512, Priority::Normal, "notifier");
+        start_scheduler();
+
+        assert_eq!(sys_notify_wait(NOTIFY_FOREVER), NOTIFY_WAITING);
+        assert_eq!(notifier.tid(), Ok(test::current_task().unwrap().tid()));
+        // The waiter outranks the notifier, so it runs as soon as it's notified
+        waiter.notify(0b101).unwrap();
+        assert_eq!(waiter.tid(), Ok(test::current_task().unwrap().tid()));
+
+        assert_eq!(sys_notify_wait(NOTIFY_FOREVER), NOTIFY_PENDING);
+        assert_eq!(take_notification(0b001), Some(0b101));
+        assert_eq!(take_notification(0), None);
+        // A notification sent while the task isn't waiting stays pending, uncleared bits carry over
+        waiter.notify(0b010).unwrap();
+        assert_eq!(sys_notify_wait(NOTIFY_FOREVER), NOTIFY_PENDING);
+        assert_eq!(take_notification(!0), Some(0b110));
+    }
+
+    #[test]
+    fn test_notification_wait_times_out() {
+        let _g = test::set_up();
+        let waiter = test::create_and_schedule_test_task(512, Priority::Critical, "waiter");
+        test::create_and_schedule_test_task(512, Priority::Normal, "other");
+        start_scheduler();
+
+        assert_eq!(sys_notify_wait(2), NOTIFY_WAITING);
+        for _ in 0..3 {
+            system_tick();
+        }
+        assert_eq!(waiter.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(wake_reason(), WakeReason::Timeout);
+        assert_eq!(take_notification(!0), None);
+    }
+
+    static EXITED_TID: AtomicUsize = ATOMIC_USIZE_INIT;
+    static EXIT_HANDLER_CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_exit(tid: usize) {
+        EXITED_TID.store(tid, Ordering::SeqCst);
+        EXIT_HANDLER_CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_exit_runs_exit_handlers() {
+        use task::TaskBuilder;
+        let _g = test::set_up();
+        EXIT_HANDLER_CALLS.store(0, Ordering::SeqCst);
+        let handle = TaskBuilder::new(test_task)
+            .on_exit(record_exit)
+            .on_exit(record_exit)
+            .spawn()
+            .unwrap();
+        let tid = handle.tid().unwrap();
+        test::create_and_schedule_test_task(512, Priority::Normal, "other task");
+        start_scheduler();
+
+        exit();
+        assert_eq!(EXIT_HANDLER_CALLS.load(Ordering::SeqCst), 2);
+        assert_eq!(EXITED_TID.load(Ordering::SeqCst), tid);
+    }
+
+    static CLEANUP_ORDER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn first_cleanup() {
+        CLEANUP_ORDER.store(CLEANUP_ORDER.load(Ordering::SeqCst) * 10 + 1, Ordering::SeqCst);
+    }
+
+    fn second_cleanup() {
+        CLEANUP_ORDER.store(CLEANUP_ORDER.load(Ordering::SeqCst) * 10 + 2, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_at_exit_cleanups_run_last_pushed_first() {
+        let _g = test::set_up();
+        CLEANUP_ORDER.store(0, Ordering::SeqCst);
+        let (mut handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        ::task::at_exit(first_cleanup);
+        ::task::at_exit(second_cleanup);
+        sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        // Killing the task runs its cleanups the same as exiting would
+        assert!(handle_1.destroy());
+        assert_eq!(CLEANUP_ORDER.load(Ordering::SeqCst), 21);
+    }
+
+    #[test]
+    fn test_destroy_frees_sleeping_task() {
+        let _g = test::set_up();
+        EXIT_HANDLER_CALLS.store(0, Ordering::SeqCst);
+        let (mut handle_1, handle_2) = test::create_two_tasks();
+        let tid = handle_1.tid().unwrap();
+        handle_1.on_exit(record_exit).unwrap();
+        start_scheduler();
+
+        sys_sleep(0x77);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert!(handle_1.destroy());
+
+        assert_eq!(EXIT_HANDLER_CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(EXITED_TID.load(Ordering::SeqCst), tid);
+        assert!(SLEEP_QUEUE.remove(|task| task.tid() == tid).is_empty());
+        assert_not!(handle_1.destroy());
+    }
+
+    #[test]
+    fn test_destroy_frees_ready_task() {
+        let _g = test::set_up();
+        let (_handle_1, mut handle_2) = test::create_two_tasks();
+        let tid = handle_2.tid().unwrap();
+        start_scheduler();
+
+        assert!(handle_2.destroy());
+        assert!(PRIORITY_QUEUES[Priority::Normal].remove(|task| task.tid() == tid).is_empty());
+    }
+
+    #[test]
+    fn test_suspended_ready_task_not_scheduled() {
+        let _g = test::set_up();
+        let (handle_1, mut handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        handle_2.suspend().unwrap();
+        assert_eq!(handle_2.is_suspended(), Ok(true));
+        sleep(0x77);
+        assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+
+        handle_2.resume().unwrap();
+        assert_eq!(handle_2.is_suspended(), Ok(false));
+        sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+    }
+
+    #[test]
+    fn test_task_suspends_itself() {
+        let _g = test::set_up();
+        let (mut handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        handle_1.suspend().unwrap();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_not!(SUSPENDED_QUEUE.remove(|task| task.tid() == handle_1.tid().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_suspended_blocked_task_keeps_wakeup_until_resumed() {
+        let _g = test::set_up();
+        let (mut handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        sleep(0x77);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        handle_1.suspend().unwrap();
+        assert_eq!(handle_1.state(), Ok(State::Blocked));
+
+        // The wakeup is held on to while the task is suspended
+        wake(0x77);
+        sleep(0x88);
+        assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+
+        handle_1.resume().unwrap();
+        sched_yield();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(wake_reason(), WakeReason::Event);
+    }
+
+    #[test]
+    fn test_status_follows_suspended_blocked_task() {
+        use task::{TaskStatus, WaitReason};
+        let _g = test::set_up();
+        let (mut handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+        assert_eq!(handle_1.status(), Ok(TaskStatus::Running));
+
+        sleep(0x77);
+        assert_eq!(handle_1.status(), Ok(TaskStatus::Blocked(WaitReason::Sleep {
+            wchan: 0x77,
+            deadline: None,
+        })));
+        handle_1.suspend().unwrap();
+        assert_eq!(handle_1.status(), Ok(TaskStatus::Suspended(WaitReason::Sleep {
+            wchan: 0x77,
+            deadline: None,
+        })));
+
+        // Once it's woken the suspended task is no longer waiting on anything
+        wake(0x77);
+        assert_eq!(handle_1.status(), Ok(TaskStatus::Suspended(WaitReason::NotBlocked)));
+
+        handle_1.resume().unwrap();
+        assert_eq!(handle_1.status(), Ok(TaskStatus::Ready));
+        sleep(0x88);
+        assert_eq!(handle_1.status(), Ok(TaskStatus::Running));
+    }
+
+    #[test]
+    fn test_set_priority_requeues_ready_task() {
+        let _g = test::set_up();
+        let (handle_1, mut handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        handle_2.set_priority(Priority::Low).unwrap();
+        assert_eq!(handle_2.priority(), Ok(Priority::Low));
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_not!(PRIORITY_QUEUES[Priority::Low].remove(|task| task.tid() == handle_2.tid().unwrap())
+                    .is_empty());
+    }
+
+    #[test]
+    fn test_set_priority_preempts_for_higher_task() {
+        let _g = test::set_up();
+        let (mut handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        // Dropping the running task below a ready task lets it run
+        handle_1.set_priority(Priority::Low).unwrap();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+
+        handle_1.set_priority(Priority::Critical).unwrap();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+    }
+
+    #[test]
+    fn test_exit_kills_grandchildren() {
+        let _g = test::set_up();
+        let mut parent = new_task(test_task, Args::empty(), 512, Priority::Normal, "parent");
+        parent.set_kill_children_on_exit(true).unwrap();
+
+        start_scheduler();
+        let child = new_task(test_task, Args::empty(), 512, Priority::Normal, "child");
+
+        // Let the child run so it can spawn a task of its own
+        sched_yield();
+        assert_eq!(child.tid(), Ok(test::current_task().unwrap().tid()));
+        let grandchild = new_task(test_task, Args::empty(), 512, Priority::Normal, "grandchild");
+
+        sched_yield();
+        assert_eq!(parent.tid(), Ok(test::current_task().unwrap().tid()));
+        exit();
+
+        assert_not!(child.is_valid());
+        assert_not!(grandchild.is_valid());
+    }
+
+    #[test]
+    fn test_exit_without_kill_reparents_children() {
+        let _g = test::set_up();
+        let grandparent = new_task(test_task, Args::empty(), 512, Priority::Normal, "grandparent");
+
+        start_scheduler();
+        let parent = new_task(test_task, Args::empty(), 512, Priority::Normal, "parent");
+
+        sched_yield();
+        assert_eq!(parent.tid(), Ok(test::current_task().unwrap().tid()));
+        let child = new_task(test_task, Args::empty(), 512, Priority::Normal, "child");
+
+        exit();
+
+        assert!(child.is_valid());
+        assert_eq!(child.parent(), Ok(Some(grandparent.tid().unwrap())));
+    }
+
     // Stub used for new_task calls.
     fn test_task(_args: &mut Args) {}
 }
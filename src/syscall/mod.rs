@@ -51,6 +51,17 @@
 //! returned value is written back to the memory where the register was saved, so that upon return
 //! from the interrupt the correct value is stored in the return register.
 //!
+//! If the dispatcher is given a system call number it doesn't recognize it must not panic, instead
+//! it should return the result of `sys_invalid_syscall` (which is `SYS_ERR_INVALID`) to the
+//! caller, so the bad call can be reported when the `logger` feature is enabled.
+//!
+//! ## User Defined System Calls
+//!
+//! System call numbers below `SYS_USER_BASE` are reserved for the kernel. Applications can
+//! register their own handlers for numbers at or above it with `register_syscall`. When the
+//! supervisor call handler receives one of these numbers it should call `sys_user_syscall` with
+//! the call number and the two argument registers.
+//!
 //! # Important Note
 //!
 //! This calling convention only applies to the way arguments will be passed to the supervisor
@@ -60,14 +71,21 @@
 
 mod imp;
 mod defs;
+mod user;
+#[cfg(feature="blocking_fallback")]
+mod fallback;
 
 use task::Priority;
 use task::args::Args;
-use task::TaskHandle;
+use task::{TaskHandle, TaskId, SpawnError, JoinError};
+use task::StaticTask;
+pub use task::WakeReason;
 use sync::{RawMutex, CondVar};
 use arch;
 pub use self::defs::*;
 pub use self::imp::*;
+pub use self::user::{register_syscall, sys_user_syscall, SyscallHandler, RegisterError, InvalidSyscall};
+pub use self::user::MAX_USER_SYSCALLS;
 
 /// Create a new task and put it into the task queue for running.
 ///
@@ -77,7 +95,8 @@ pub use self::imp::*;
 /// for the task, an `Args` argument for the arguments that will be passed to the task, a `usize`
 /// argument for how much space should be allocated for the task's stack, a `Priority` argument for
 /// the priority that the task should run with, and a `&str` argument to give the task a readable
-/// name.
+/// name. `TaskBuilder` names each of these instead, and returns an error rather than panicking if
+/// the task can't be created.
 ///
 /// # Examples
 ///
@@ -103,6 +122,197 @@ pub fn new_task(code: fn(&mut Args), args: Args, stack_depth: usize, priority: P
     imp::new_task(code, args, stack_depth, priority, name)
 }
 
+/// Creates a new task and puts it into the task queue for running, returning an error if the task
+/// couldn't be created.
+///
+/// This takes the same arguments as `new_task`, but instead of panicking it returns
+/// `Err(SpawnError::StackTooSmall)` if `stack_depth` can't hold the task's initial frame (see
+/// `MIN_STACK_WORDS`). If the stacks come from a stack arena it returns
+/// `Err(SpawnError::TooManyTasks)` once the arena is full, and `Err(SpawnError::StackTooLarge)` if
+/// `stack_depth` is bigger than an arena slot (see `init_stack_arena`).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{Priority, SpawnError};
+/// use altos_core::syscall::try_new_task;
+/// use altos_core::args::Args;
+///
+/// match try_new_task(test_task, Args::empty(), 16, Priority::Normal, "tiny_task") {
+///     Ok(_handle) => { /* The task was created */ },
+///     Err(SpawnError::StackTooSmall) => { /* Try again with a bigger stack */ },
+///     Err(_) => { /* No room for another task */ },
+/// }
+///
+/// fn test_task(_args: &mut Args) {
+///   // Do stuff here...
+///   loop {}
+/// }
+/// ```
+pub fn try_new_task(code: fn(&mut Args), args: Args, stack_depth: usize, priority: Priority, name: &'static str)
+    -> Result<TaskHandle, SpawnError> {
+
+    imp::try_new_task(code, args, stack_depth, priority, name)
+}
+
+/// Creates a new task in static memory and puts it into the task queue for running.
+///
+/// Instead of being allocated, the task's control block and arguments are kept in `tcb` and its
+/// stack is `stack`, so as long as `args` is empty (or was built without allocating) nothing is
+/// allocated at all. This lets a system with no heap create all of its tasks up front. Both `tcb`
+/// and `stack` are handed over to the task for good, they're never reused even after the task is
+/// destroyed.
+///
+/// Returns `Err(SpawnError::StackTooSmall)` if `stack` can't hold the task's initial frame (see
+/// `MIN_STACK_WORDS`). A stack arena isn't used for static tasks.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{Priority, StaticTask};
+/// use altos_core::syscall::new_static_task;
+/// use altos_core::args::Args;
+///
+/// static mut BLINK_TASK: StaticTask = StaticTask::new();
+/// static mut BLINK_STACK: [u8; 512] = [0; 512];
+///
+/// unsafe {
+///     new_static_task(blink, Args::empty(), &mut BLINK_TASK, &mut BLINK_STACK, Priority::Normal,
+///                     "blink").unwrap();
+/// }
+///
+/// fn blink(_args: &mut Args) {
+///   // Toggle an LED...
+///   loop {}
+/// }
+/// ```
+pub fn new_static_task(code: fn(&mut Args), args: Args, tcb: &'static mut StaticTask,
+                       stack: &'static mut [u8], priority: Priority, name: &'static str)
+    -> Result<TaskHandle, SpawnError> {
+
+    imp::new_static_task(code, args, tcb, stack, priority, name)
+}
+
+/// Create a new task that runs a closure, and put it into the task queue for running.
+///
+/// This works like `new_task`, but the task's code is a closure instead of a `fn(&mut Args)`, so
+/// anything it needs can be moved into it rather than packed into `Args` and unpacked again. The
+/// closure is boxed and handed to the task, which calls it once and frees it. A task that's
+/// destroyed before it ever runs leaks the closure, the same as it would its `Args`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::Priority;
+/// use altos_core::syscall::spawn;
+///
+/// let uart = 2;
+/// let baud = 115200;
+///
+/// let handle = spawn(move || {
+///   // Set up the UART with `uart` and `baud`...
+///   loop {}
+/// }, 512, Priority::Normal, "uart_task");
+/// ```
+pub fn spawn<F>(code: F, stack_depth: usize, priority: Priority, name: &'static str) -> TaskHandle
+    where F: FnOnce() + Send + 'static {
+
+    imp::spawn(code, stack_depth, priority, name)
+}
+
+/// Create a new task that's passed `arg`, and put it into the task queue for running.
+///
+/// This works like `new_task`, but the argument keeps its type: `code` takes a `T` rather than
+/// `&mut Args`, so there's nothing to unpack or cast inside the task. `arg` is moved into the task
+/// when it's created. Several arguments can be passed together in a struct or tuple.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::Priority;
+/// use altos_core::syscall::spawn_with;
+///
+/// struct BlinkConfig {
+///   pin: u8,
+///   period: usize,
+/// }
+///
+/// let config = BlinkConfig { pin: 5, period: 500 };
+/// let handle = spawn_with(blink, config, 512, Priority::Normal, "blink");
+///
+/// fn blink(config: BlinkConfig) {
+///   // Toggle `config.pin` every `config.period` ticks...
+///   loop {}
+/// }
+/// ```
+pub fn spawn_with<T>(code: fn(T), arg: T, stack_depth: usize, priority: Priority,
+                     name: &'static str) -> TaskHandle
+    where T: Send + 'static {
+
+    spawn(move || code(arg), stack_depth, priority, name)
+}
+
+/// Creates a task that borrows `data` from the calling task instead of being given `Args`.
+///
+/// `code` is passed a reference to `data` directly, nothing is copied or allocated for it. The
+/// task only lives for as long as `scope` runs: `scope` is called with the new task's handle, and
+/// once it returns the task is destroyed if it hasn't exited already. This is what lets `data` be
+/// a local on the caller's stack, the borrow checker won't let `data` be moved or dropped while
+/// `scope` is running, and the task can't run after it. If the calling task is destroyed in the
+/// middle of `scope`, the borrowing task is destroyed along with it.
+///
+/// `scope` should wait for the task to finish its work before returning, otherwise the task will
+/// be cut off. The value returned from `scope` is passed back to the caller.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::Priority;
+/// use altos_core::syscall::{spawn_borrowed, sched_yield};
+/// use altos_core::atomic::{AtomicBool, Ordering};
+///
+/// let done = AtomicBool::new(false);
+///
+/// spawn_borrowed(worker, &done, 512, Priority::Normal, "worker", |_handle| {
+///     while !done.load(Ordering::SeqCst) {
+///         sched_yield();
+///     }
+/// }).unwrap();
+///
+/// fn worker(done: &AtomicBool) {
+///     // Do stuff here...
+///     done.store(true, Ordering::SeqCst);
+///     loop {}
+/// }
+/// ```
+///
+/// The borrowed data can't be dropped while the task is still alive:
+///
+/// ```rust,compile_fail
+/// use altos_core::Priority;
+/// use altos_core::syscall::spawn_borrowed;
+/// use altos_core::atomic::AtomicBool;
+///
+/// let done = AtomicBool::new(false);
+///
+/// spawn_borrowed(worker, &done, 512, Priority::Normal, "worker", |_handle| {
+///     drop(done);
+/// }).unwrap();
+///
+/// fn worker(_done: &AtomicBool) {}
+/// ```
+///
+/// # Errors
+///
+/// Returns the same errors as `try_new_task` if the task can't be created, `scope` isn't called in
+/// that case.
+pub fn spawn_borrowed<T, F, R>(code: fn(&T), data: &T, stack_depth: usize, priority: Priority,
+                               name: &'static str, scope: F) -> Result<R, SpawnError>
+    where T: Sync, F: FnOnce(TaskHandle) -> R {
+
+    imp::spawn_borrowed(code, data, stack_depth, priority, name, scope)
+}
+
 /// Exit and destroy the currently running task.
 ///
 /// This function must only be called from within task code. Doing so from elsewhere (like an
@@ -135,6 +345,102 @@ pub fn exit() -> ! {
     unreachable!();
 }
 
+/// Exit and destroy the currently running task, handing `value` to a task that joins it.
+///
+/// This works the same as `exit()`, `value` is only kept if the task was spawned joinable. See
+/// `TaskHandle::join`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::syscall;
+/// use altos_core::args::Args;
+///
+/// fn test_task(_args: &mut Args) {
+///   // Count the widgets...
+///   let widgets = 12;
+///
+///   syscall::exit_with(widgets);
+/// }
+/// ```
+pub fn exit_with(value: usize) -> ! {
+    imp::set_exit_value(value);
+    exit()
+}
+
+/// Block until the task with id `id` exits, returning its exit value.
+///
+/// Normally you should use `TaskHandle::join` rather than calling this function directly.
+///
+/// # Errors
+///
+/// Returns the same errors as `TaskHandle::join`.
+pub fn join(id: TaskId) -> Result<usize, JoinError> {
+    loop {
+        let result = match arch::syscall1_wide(SYS_JOIN, &id as *const _ as usize) {
+            (JOIN_EXITED, value) => Ok(value),
+            (JOIN_WAITING, _) => continue,
+            (JOIN_SELF, _) => Err(JoinError::JoinSelf),
+            _ => Err(JoinError::NotJoinable),
+        };
+        #[cfg(feature="signals")]
+        ::task::deliver_signals();
+        return result;
+    }
+}
+
+/// Send the task `handle` refers to a notification, setting `bits` in its notification value.
+///
+/// This is the same as `TaskHandle::notify`, and is safe to call from an interrupt handler.
+///
+/// # Errors
+///
+/// If the task has been destroyed then this returns `Err(())`.
+pub fn notify(mut handle: TaskHandle, bits: u32) -> Result<(), ()> {
+    handle.notify(bits)
+}
+
+/// Wait for the current task to be sent a notification, returning its notification value.
+///
+/// If a notification is already pending this returns right away, otherwise the task blocks until
+/// it's notified (see `TaskHandle::notify`) or `timeout` ticks pass. `None` waits forever. Once
+/// the value has been read, the bits in `clear_mask` are cleared from it, so passing `!0` starts
+/// every wait from 0 while passing 0 leaves the value as it is (to use it as a counter, say).
+///
+/// Returns `None` if the timeout ran out before a notification was sent.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::syscall::wait_notification;
+///
+/// const TX_DONE: u32 = 1 << 1;
+///
+/// match wait_notification(TX_DONE, Some(100)) {
+///   Some(events) if events & TX_DONE != 0 => { /* The transfer finished */ },
+///   Some(_) => { /* Some other event */ },
+///   None => { /* The device never responded */ },
+/// }
+/// ```
+pub fn wait_notification(clear_mask: u32, timeout: Option<usize>) -> Option<u32> {
+    let delay = timeout.unwrap_or(NOTIFY_FOREVER);
+    loop {
+        let value = if arch::syscall1(SYS_NOTIFY_WAIT, delay) == NOTIFY_PENDING {
+            imp::take_notification(clear_mask)
+        }
+        else if timeout.is_some() && arch::syscall0(SYS_WAKE_REASON) != 0 {
+            // A notification may have come in just as the timeout ran out
+            imp::take_notification(clear_mask)
+        }
+        else {
+            continue;
+        };
+        #[cfg(feature="signals")]
+        ::task::deliver_signals();
+        return value;
+    }
+}
+
 /// Yield the current task to the scheduler so another task can run.
 ///
 /// # Examples
@@ -155,6 +461,8 @@ pub fn exit() -> ! {
 /// ```
 pub fn sched_yield() {
     arch::syscall0(SYS_SCHED_YIELD);
+    #[cfg(feature="signals")]
+    ::task::deliver_signals();
 }
 
 /// Put the current task to sleep, waiting on a channel to be woken up.
@@ -162,6 +470,10 @@ pub fn sched_yield() {
 /// `sleep` takes a `usize` argument that acts as an identifier for when to wake up the task. The
 /// task will sleep indefinitely if no wakeup signal is sent.
 ///
+/// Calling this before the scheduler has been started panics, unless the `blocking_fallback`
+/// feature is enabled, in which case the caller polls in place until it's woken. The fallback is
+/// only meant for bring-up code, see `sleep_for`.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -177,12 +489,23 @@ pub fn sched_yield() {
 /// ```
 pub fn sleep(wchan: usize) {
     arch::syscall1(SYS_SLEEP, wchan);
+    #[cfg(feature="signals")]
+    ::task::deliver_signals();
 }
 
 /// Put the current task to sleep with a timeout, waiting on a channel to be woken up.
 ///
 /// `sleep_for` takes a `usize` argument that acts as an identifier to wake up the task. It also
-/// takes a second `usize` argument for the maximum ticks it should sleep before waking.
+/// takes a second `usize` argument for the maximum ticks it should sleep before waking. The
+/// timeout is counted from the next tick edge, so at least `delay` full ticks pass before the
+/// task times out.
+///
+/// Calling this before the scheduler has been started panics, unless the `blocking_fallback`
+/// feature is enabled. With the fallback the caller idles in place, re-checking after every
+/// interrupt, until it's woken or the timeout runs out, so single-threaded bring-up code can
+/// wait on hardware before any tasks are running. The system tick interrupt must already be
+/// running for the timeout to expire. The fallback is for bring-up only, it keeps the processor
+/// busy and only supports one waiter at a time.
 ///
 /// # Examples
 ///
@@ -194,6 +517,38 @@ pub fn sleep(wchan: usize) {
 /// ```
 pub fn sleep_for(wchan: usize, delay: usize) {
     arch::syscall2(SYS_SLEEP_FOR, wchan, delay);
+    #[cfg(feature="signals")]
+    ::task::deliver_signals();
+}
+
+/// Put the current task to sleep until a channel is signaled or a timeout runs out.
+///
+/// This works the same as `sleep_for`, but returns why the task woke up. If the channel is
+/// signaled first the timeout is cancelled and `WakeReason::Event` is returned, if the timeout
+/// runs out first the task stops waiting on the channel and `WakeReason::Timeout` is returned.
+///
+/// # Examples
+///
+/// ```no_run
+/// use altos_core::syscall::{wait_event_timeout, WakeReason};
+///
+/// static RX_DONE: usize = 0;
+///
+/// match wait_event_timeout(&RX_DONE as *const _ as usize, 100) {
+///   WakeReason::Event => { /* The transfer finished */ },
+///   WakeReason::Timeout => { /* The device never responded */ },
+/// }
+/// ```
+pub fn wait_event_timeout(event: usize, ticks: usize) -> WakeReason {
+    arch::syscall2(SYS_SLEEP_FOR, event, ticks);
+    // Read before any signal handlers run, they could sleep again
+    let reason = match arch::syscall0(SYS_WAKE_REASON) {
+        0 => WakeReason::Event,
+        _ => WakeReason::Timeout,
+    };
+    #[cfg(feature="signals")]
+    ::task::deliver_signals();
+    reason
 }
 
 /// Wake up all tasks sleeping on a channel.
@@ -204,6 +559,37 @@ pub fn wake(wchan: usize) {
     arch::syscall1(SYS_WAKE, wchan);
 }
 
+/// Invoke a user defined system call registered with `register_syscall`.
+///
+/// The call goes through the same supervisor call mechanism as the kernel's own system calls, so
+/// the handler runs in privileged mode and the value it returns is passed back to the caller.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::syscall::{register_syscall, user_syscall, SYS_USER_BASE};
+///
+/// const SYS_LED_SET: u32 = SYS_USER_BASE;
+///
+/// fn led_set(led: usize, on: usize) -> usize {
+///   // Poke the GPIO registers...
+///   0
+/// }
+///
+/// register_syscall(SYS_LED_SET, led_set).unwrap();
+/// user_syscall(SYS_LED_SET, 1, 1).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// If no handler has been registered for `call`, this returns `Err(InvalidSyscall)`.
+pub fn user_syscall(call: u32, arg1: usize, arg2: usize) -> Result<usize, InvalidSyscall> {
+    match arch::syscall2(call, arg1, arg2) {
+        SYS_ERR_INVALID => Err(InvalidSyscall),
+        result => Ok(result),
+    }
+}
+
 /// Update the system tick count and wake up any delayed tasks that need to be woken.
 ///
 /// This function will wake any tasks that have a delay.
@@ -294,6 +680,34 @@ pub fn mutex_try_lock(lock: &RawMutex) -> bool {
     arch::syscall1(SYS_MX_TRY_LOCK, lock as *const _ as usize) != 0
 }
 
+/// Attempt to acquire a mutex in a non-blocking fashion, returning who holds it afterwards
+///
+/// This works like `mutex_try_lock`, but along with whether the lock was acquired it returns the
+/// task id of the lock's holder. If the lock was acquired that's the calling task, otherwise it's
+/// the task that was holding it at the time of the call. Both values are returned from a single
+/// system call, so the holder can't change between the attempt and reading the holder.
+///
+/// Normally you should not call this function directly, if you require a mutex lock primitive use
+/// the `Mutex` type provided in the `sync` module.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::sync::RawMutex;
+/// use altos_core::syscall::mutex_try_lock_owner;
+///
+/// let raw_mutex: RawMutex = RawMutex::new();
+///
+/// let (acquired, holder) = mutex_try_lock_owner(&raw_mutex);
+/// if !acquired {
+///   // Task `holder` has the lock
+/// }
+/// ```
+pub fn mutex_try_lock_owner(lock: &RawMutex) -> (bool, usize) {
+    let (acquired, holder) = arch::syscall1_wide(SYS_MX_TRY_LOCK_OWNER, lock as *const _ as usize);
+    (acquired != 0, holder)
+}
+
 /// Unlock a mutex
 ///
 /// This system call will unlock a locked mutex. There is no check to see if the calling thread
@@ -396,3 +810,37 @@ pub fn condvar_wait(condvar: &CondVar, lock: &RawMutex) {
 pub fn condvar_broadcast(condvar: &CondVar) {
     arch::syscall1(SYS_CV_BROADCAST, condvar as *const _ as usize);
 }
+
+/// Signal a condition variable
+///
+/// This system call will wake up a single thread that is waiting on a given condition variable.
+/// The highest priority waiter is woken, if several waiters share the highest priority the one
+/// that has been waiting the longest is chosen, so no waiter can be passed over forever by waiters
+/// of the same priority. Signals are not buffered, if no thread is waiting the signal is lost.
+///
+/// Normally you should not call this function directly, if you require a condition variable
+/// primitive use the `CondVar` type in the `sync` module.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::syscall;
+/// use altos_core::sync::{CondVar, RawMutex};
+///
+/// let raw_mutex: RawMutex = RawMutex::new();
+/// let cond_var: CondVar = CondVar::new();
+///
+/// // Acquire the lock
+/// syscall::mutex_lock(&raw_mutex);
+///
+/// // Wait on the condition variable
+/// syscall::condvar_wait(&cond_var, &raw_mutex);
+///
+/// // From some other thread...
+/// syscall::condvar_signal(&cond_var);
+///
+/// // Original thread can now proceed
+/// ```
+pub fn condvar_signal(condvar: &CondVar) {
+    arch::syscall1(SYS_CV_SIGNAL, condvar as *const _ as usize);
+}
@@ -0,0 +1,144 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! User defined system calls.
+
+use sync::SpinMutex;
+use super::SYS_USER_BASE;
+
+/// The maximum number of user defined system calls that can be registered.
+pub const MAX_USER_SYSCALLS: usize = 16;
+
+/// A handler for a user defined system call.
+///
+/// The handler is passed the two argument registers of the system call, and the value it returns
+/// is handed back to the caller. Handlers must not return `SYS_ERR_INVALID`, as the caller would
+/// see it as a call to an unregistered system call.
+pub type SyscallHandler = fn(usize, usize) -> usize;
+
+static USER_SYSCALLS: SpinMutex<[Option<SyscallHandler>; MAX_USER_SYSCALLS]> =
+    SpinMutex::new([None; MAX_USER_SYSCALLS]);
+
+/// The error returned when invoking a system call that the kernel doesn't know about.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InvalidSyscall;
+
+/// Errors that can occur when registering a system call.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RegisterError {
+    /// The system call number is reserved for the kernel.
+    Reserved,
+
+    /// The system call number is past the end of the user system call table.
+    OutOfRange,
+}
+
+/// Register a handler for a user defined system call.
+///
+/// `call` must be in the range `SYS_USER_BASE..SYS_USER_BASE + MAX_USER_SYSCALLS`. Registering a
+/// handler for a number that already has one replaces the old handler.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::syscall::{register_syscall, SYS_USER_BASE};
+///
+/// fn flash_erase(page: usize, _: usize) -> usize {
+///   // Erase the page...
+///   0
+/// }
+///
+/// register_syscall(SYS_USER_BASE + 1, flash_erase).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Returns `RegisterError::Reserved` if `call` belongs to the kernel, or
+/// `RegisterError::OutOfRange` if it doesn't fit in the table.
+pub fn register_syscall(call: u32, handler: SyscallHandler) -> Result<(), RegisterError> {
+    let index = table_index(call)?;
+    USER_SYSCALLS.lock()[index] = Some(handler);
+    Ok(())
+}
+
+/// Run the handler registered for the user defined system call `call`.
+///
+/// This should be called from the supervisor call handler for any call number at or above
+/// `SYS_USER_BASE`. If no handler has been registered for `call`, `SYS_ERR_INVALID` is returned.
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn sys_user_syscall(call: u32, arg1: usize, arg2: usize) -> usize {
+    let handler = table_index(call).ok().and_then(|index| USER_SYSCALLS.lock()[index]);
+    match handler {
+        Some(handler) => handler(arg1, arg2),
+        None => super::sys_invalid_syscall(call),
+    }
+}
+
+fn table_index(call: u32) -> Result<usize, RegisterError> {
+    if call < SYS_USER_BASE {
+        Err(RegisterError::Reserved)
+    }
+    else if (call - SYS_USER_BASE) as usize >= MAX_USER_SYSCALLS {
+        Err(RegisterError::OutOfRange)
+    }
+    else {
+        Ok((call - SYS_USER_BASE) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syscall::{user_syscall, SYS_SLEEP, SYS_ERR_INVALID};
+    use arch;
+
+    fn add(arg1: usize, arg2: usize) -> usize {
+        arg1 + arg2
+    }
+
+    #[test]
+    fn test_registered_syscall_is_dispatched() {
+        assert_eq!(register_syscall(SYS_USER_BASE, add), Ok(()));
+        assert_eq!(user_syscall(SYS_USER_BASE, 2, 3), Ok(5));
+    }
+
+    #[test]
+    fn test_register_reserved_syscall_fails() {
+        assert_eq!(register_syscall(SYS_SLEEP, add), Err(RegisterError::Reserved));
+    }
+
+    #[test]
+    fn test_register_syscall_past_end_of_table_fails() {
+        let call = SYS_USER_BASE + MAX_USER_SYSCALLS as u32;
+        assert_eq!(register_syscall(call, add), Err(RegisterError::OutOfRange));
+    }
+
+    #[test]
+    fn test_unregistered_syscall_returns_error() {
+        let call = SYS_USER_BASE + MAX_USER_SYSCALLS as u32 - 1;
+        assert_eq!(user_syscall(call, 0, 0), Err(InvalidSyscall));
+        assert_eq!(user_syscall(call + 1, 0, 0), Err(InvalidSyscall));
+    }
+
+    #[test]
+    fn test_unknown_syscall_number_returns_error_sentinel() {
+        assert_eq!(arch::syscall0(SYS_USER_BASE - 1), SYS_ERR_INVALID);
+        assert_eq!(arch::syscall1(SYS_USER_BASE - 1, 0), SYS_ERR_INVALID);
+        assert_eq!(arch::syscall2(SYS_USER_BASE - 1, 0, 0), SYS_ERR_INVALID);
+    }
+}
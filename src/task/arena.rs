@@ -0,0 +1,196 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A fixed region of memory that task stacks are carved out of.
+
+use super::SpawnError;
+use sync::{SpinMutex, CriticalSection};
+
+static ARENA: SpinMutex<Option<StackArena>> = SpinMutex::new(None);
+
+// The arena hands out equally sized slots, free slots are kept in a list threaded through the
+// first word of each slot.
+struct StackArena {
+    start: usize,
+    end: usize,
+    slot_size: usize,
+    free: Option<usize>,
+}
+
+impl StackArena {
+    fn new(memory: &'static mut [u8], slot_size: usize) -> Self {
+        let word = ::core::mem::size_of::<usize>();
+        let begin = memory.as_mut_ptr() as usize;
+        // Keep every slot word aligned
+        let start = (begin + word - 1) & !(word - 1);
+        let slot_size = slot_size & !(word - 1);
+        let len = memory.len().saturating_sub(start - begin);
+        let slots = if slot_size == 0 { 0 } else { len / slot_size };
+
+        let mut arena = StackArena {
+            start: start,
+            end: start + slots * slot_size,
+            slot_size: slot_size,
+            free: None,
+        };
+        // Push the slots in reverse, so they're handed out from the bottom of the arena up
+        for slot in (0..slots).rev() {
+            arena.push(start + slot * slot_size);
+        }
+        arena
+    }
+
+    fn push(&mut self, slot: usize) {
+        // UNSAFE: `slot` is a word aligned slot in the arena that isn't being used by anyone
+        unsafe { *(slot as *mut usize) = self.free.unwrap_or(0) };
+        self.free = Some(slot);
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        let slot = self.free?;
+        // UNSAFE: Free slots always hold the address of the next free slot
+        let next = unsafe { *(slot as *const usize) };
+        self.free = if next == 0 { None } else { Some(next) };
+        Some(slot)
+    }
+
+    fn contains(&self, ptr: usize) -> bool {
+        ptr >= self.start && ptr < self.end
+    }
+}
+
+/// Reserve `memory` for task stacks.
+///
+/// Once an arena is set up, every new task's stack is carved out of it instead of being allocated
+/// from the heap. The arena is split into slots of `slot_size` bytes (rounded down to a whole
+/// number of words), and each task gets one whole slot. Keeping the stacks together stops them
+/// from fragmenting the heap, and makes how many tasks can exist at once fixed and known up front.
+///
+/// When every slot is in use, spawning a task fails with `SpawnError::TooManyTasks`. Asking for a
+/// stack bigger than `slot_size` fails with `SpawnError::StackTooLarge`. The idle task's stack
+/// comes out of the arena too, so the arena should be set up before the scheduler is started.
+///
+/// Stacks that were allocated before the arena was set up are unaffected.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::init_stack_arena;
+///
+/// static mut STACKS: [u8; 4096] = [0; 4096];
+///
+/// // Room for 8 tasks with 512 byte stacks
+/// init_stack_arena(unsafe { &mut STACKS }, 512);
+/// ```
+pub fn init_stack_arena(memory: &'static mut [u8], slot_size: usize) {
+    let _g = CriticalSection::begin();
+    *ARENA.lock() = Some(StackArena::new(memory, slot_size));
+}
+
+#[cfg(test)]
+pub fn clear_stack_arena() {
+    *ARENA.lock() = None;
+}
+
+// Take a slot from the arena for a stack of `depth` bytes, returning the base of the slot and its
+// size. Returns `Ok(None)` if there is no arena.
+pub fn allocate(depth: usize) -> Result<Option<(usize, usize)>, SpawnError> {
+    let _g = CriticalSection::begin();
+    match *ARENA.lock() {
+        Some(ref mut arena) => {
+            if depth > arena.slot_size {
+                return Err(SpawnError::StackTooLarge);
+            }
+            match arena.pop() {
+                Some(slot) => Ok(Some((slot, arena.slot_size))),
+                None => Err(SpawnError::TooManyTasks),
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+// Give a slot back to the arena, returns false if `base` didn't come from the arena.
+pub fn release(base: usize) -> bool {
+    let _g = CriticalSection::begin();
+    match *ARENA.lock() {
+        Some(ref mut arena) if arena.contains(base) => {
+            arena.push(base);
+            true
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use task::Priority;
+
+    const ARENA_SIZE: usize = 3 * 512 + 16;
+    static mut MEMORY: [u8; ARENA_SIZE] = [0; ARENA_SIZE];
+
+    fn arena_bounds() -> (usize, usize) {
+        // UNSAFE: Only the address is used
+        let start = unsafe { MEMORY.as_ptr() as usize };
+        (start, start + ARENA_SIZE)
+    }
+
+    fn test_task(_args: &mut ::task::args::Args) {}
+
+    #[test]
+    fn test_stacks_come_from_arena_until_full() {
+        let _g = test::set_up();
+        // UNSAFE: The test lock is held, so no other test is using the arena memory
+        init_stack_arena(unsafe { &mut MEMORY }, 512);
+        let (start, end) = arena_bounds();
+
+        let mut handles = [None, None, None];
+        for handle in handles.iter_mut() {
+            *handle = Some(::syscall::try_new_task(test_task, ::task::args::Args::empty(), 256,
+                                                   Priority::Normal, "arena task").unwrap());
+        }
+        for handle in handles.iter() {
+            let task = test::convert_handle_to_task_control(handle.unwrap());
+            let (bottom, top) = task.stack_bounds();
+            assert!(bottom >= start && top <= end);
+            assert_eq!(top - bottom, 512);
+        }
+
+        let result = ::syscall::try_new_task(test_task, ::task::args::Args::empty(), 256,
+                                             Priority::Normal, "one too many");
+        assert_eq!(result.err(), Some(SpawnError::TooManyTasks));
+    }
+
+    #[test]
+    fn test_arena_slot_reused_after_task_freed() {
+        let _g = test::set_up();
+        // UNSAFE: The test lock is held, so no other test is using the arena memory
+        init_stack_arena(unsafe { &mut MEMORY }, 512);
+
+        let first = allocate(512).unwrap().unwrap();
+        let second = allocate(512).unwrap().unwrap();
+        assert_ne!(first.0, second.0);
+        assert!(release(first.0));
+        assert_eq!(allocate(512).unwrap(), Some(first));
+        assert_eq!(allocate(513), Err(SpawnError::StackTooLarge));
+
+        clear_stack_arena();
+        assert_eq!(allocate(512), Ok(None));
+    }
+}
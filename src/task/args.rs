@@ -44,9 +44,23 @@
 
 use collections::Vec;
 use alloc::boxed::Box;
+#[cfg(debug_assertions)]
+use core::intrinsics;
 
 type RawPtr = usize;
 
+// A tag identifying the type of an argument, used to check that arguments are popped as the same
+// type they were added as.
+#[cfg(debug_assertions)]
+fn type_tag<T: 'static>() -> u64 {
+    // UNSAFE: `type_id` only looks at the type, it doesn't touch any memory
+    unsafe { intrinsics::type_id::<T>() }
+}
+
+// Stands in for the type of arguments added with `add_num`, so they can't be popped as a box
+#[cfg(debug_assertions)]
+enum Num {}
+
 /// An Args Builder.
 ///
 /// Use this to construct a new list of arguments to pass into a task. The arguments should be
@@ -55,7 +69,10 @@ type RawPtr = usize;
 pub struct ArgsBuilder {
     cap: usize,
     len: usize,
+    boxed: bool,
     vec: Vec<RawPtr>,
+    #[cfg(debug_assertions)]
+    tags: Vec<u64>,
 }
 
 impl ArgsBuilder {
@@ -74,7 +91,10 @@ impl ArgsBuilder {
         ArgsBuilder {
             cap: cap,
             len: 0,
+            boxed: false,
             vec: Vec::with_capacity(cap),
+            #[cfg(debug_assertions)]
+            tags: Vec::with_capacity(cap),
         }
     }
 
@@ -97,15 +117,18 @@ impl ArgsBuilder {
     ///
     /// This method will panic if you attempt to add more arguments than the capacity allocated.
     #[inline(never)]
-    pub fn add_box<T>(&mut self, arg: Box<T>) -> &mut Self {
+    pub fn add_box<T: 'static>(&mut self, arg: Box<T>) -> &mut Self {
         if self.len >= self.cap {
-            panic!("ArgsBuilder::add_arg - added too many arguments!");
+            kernel_panic!("ArgsBuilder::add_arg - added too many arguments!");
         }
         // UNSAFE: We are keeping track of the length ourselves, so we know we won't exceed capacity
         unsafe {
             let cell = self.vec.get_unchecked_mut(self.len);
             *cell = Box::into_raw(arg) as usize;
         }
+        #[cfg(debug_assertions)]
+        self.tags.push(type_tag::<T>());
+        self.boxed = true;
         self.len += 1;
         self
     }
@@ -129,13 +152,15 @@ impl ArgsBuilder {
     /// This method will panic if you attempt to add more arguments than the capacity allocated.
     pub fn add_num(&mut self, arg: usize) -> &mut Self {
         if self.len >= self.cap {
-            panic!("ArgsBuilder::add_copy - added too many arguments!");
+            kernel_panic!("ArgsBuilder::add_copy - added too many arguments!");
         }
         // UNSAFE: We are keeping track of the length ourselves, so we know we won't exceed capacity
         unsafe {
             let cell = self.vec.get_unchecked_mut(self.len);
             *cell = arg;
         }
+        #[cfg(debug_assertions)]
+        self.tags.push(type_tag::<Num>());
         self.len += 1;
         self
     }
@@ -159,7 +184,14 @@ impl ArgsBuilder {
         // UNSAFE: We've kept track of how many args we've added, so this inner length is known
         // to be correct
         unsafe { self.vec.set_len(self.len) };
-        Args::new(self.vec)
+        let mut args = Args::new(self.vec, self.boxed);
+        #[cfg(debug_assertions)]
+        {
+            // Reverse the tags to match the arguments
+            args.tags = self.tags;
+            args.tags.reverse();
+        }
+        args
     }
 }
 
@@ -170,10 +202,21 @@ impl ArgsBuilder {
 /// order and type of arguments passed into it in order to correctly interpret them. Unfortunately
 /// we can not keep type safety across the task initialization barrier in order to keep tasks
 /// uniform.
+///
+/// In debug builds the type of each argument is recorded when it's added, and `pop_box` checks
+/// that it is popped as the same type, panicking if it isn't. The check is compiled out of release
+/// builds.
 #[derive(Debug)]
 pub struct Args {
     // TODO: Turn into boxed slice?
     args: Vec<RawPtr>,
+    // The number of arguments that haven't been popped, popped arguments are kept around so they
+    // can be handed to the task again if it's restarted
+    remaining: usize,
+    // Whether any of the arguments is a box, those belong to the task once they're popped
+    boxed: bool,
+    #[cfg(debug_assertions)]
+    tags: Vec<u64>,
 }
 
 impl Args {
@@ -181,7 +224,13 @@ impl Args {
     ///
     /// Use this when a task doesn't require any arguments.
     pub fn empty() -> Self {
-        Args { args: Vec::with_capacity(0) }
+        Args {
+            args: Vec::with_capacity(0),
+            remaining: 0,
+            boxed: false,
+            #[cfg(debug_assertions)]
+            tags: Vec::with_capacity(0),
+        }
     }
 
     /// Returns the next argument interpreted as a boxed object.
@@ -209,9 +258,16 @@ impl Args {
     ///
     /// # Panics
     ///
-    /// This method will panic if there are no more arguments to retrieve.
-    pub unsafe fn pop_box<T>(&mut self) -> Box<T> {
-        let ptr = self.args.pop().unwrap();
+    /// This method will panic if there are no more arguments to retrieve. In debug builds it will
+    /// also panic if the argument wasn't added as a `Box<T>`.
+    pub unsafe fn pop_box<T: 'static>(&mut self) -> Box<T> {
+        #[cfg(debug_assertions)]
+        {
+            if self.remaining > 0 && self.tags[self.remaining - 1] != type_tag::<T>() {
+                kernel_panic!("Args::pop_box - argument popped as a different type than it was added as!");
+            }
+        }
+        let ptr = self.pop();
         Box::from_raw(ptr as *mut T)
     }
 
@@ -238,13 +294,41 @@ impl Args {
     ///
     /// This method will panic if there are no more arguments to retrieve.
     pub fn pop_num(&mut self) -> usize {
-        self.args.pop().unwrap()
+        self.pop()
+    }
+
+    /// Put back every argument that has been popped, so the task gets them all again when it's
+    /// restarted.
+    ///
+    /// Boxed arguments belong to the task once they've been popped, and may have been freed, so
+    /// this returns false without putting anything back if any of the arguments is a box.
+    #[doc(hidden)]
+    pub fn rewind(&mut self) -> bool {
+        if self.boxed {
+            return false;
+        }
+        self.remaining = self.args.len();
+        true
+    }
+
+    fn pop(&mut self) -> RawPtr {
+        if self.remaining == 0 {
+            kernel_panic!("Args - no more arguments to pop!");
+        }
+        self.remaining -= 1;
+        self.args[self.remaining]
     }
 
-    fn new(mut args: Vec<RawPtr>) -> Self {
+    fn new(mut args: Vec<RawPtr>, boxed: bool) -> Self {
         // Reverse args so they are treated as FIFO.
         args.reverse();
-        Args { args: args }
+        Args {
+            remaining: args.len(),
+            boxed: boxed,
+            args: args,
+            #[cfg(debug_assertions)]
+            tags: Vec::with_capacity(0),
+        }
     }
 }
 
@@ -338,4 +422,63 @@ mod tests {
         unsafe { args.pop_box::<usize>() };
         unsafe { args.pop_box::<usize>() };
     }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "argument popped as a different type")]
+    fn test_args_pop_box_wrong_type_panics() {
+        let mut builder = ArgsBuilder::with_capacity(1);
+        builder.add_box(Box::new(10u32));
+
+        let mut args = builder.finalize();
+
+        unsafe { args.pop_box::<u64>() };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "argument popped as a different type")]
+    fn test_args_pop_num_as_box_panics() {
+        let mut builder = ArgsBuilder::with_capacity(1);
+        builder.add_num(10);
+
+        let mut args = builder.finalize();
+
+        unsafe { args.pop_box::<usize>() };
+    }
+
+    #[test]
+    fn test_args_pop_mixed_types_in_order() {
+        let mut builder = ArgsBuilder::with_capacity(3);
+        builder.add_box(Box::new(1u8)).add_num(2).add_box(Box::new(3u64));
+
+        let mut args = builder.finalize();
+
+        assert_eq!(unsafe { *args.pop_box::<u8>() }, 1);
+        assert_eq!(args.pop_num(), 2);
+        assert_eq!(unsafe { *args.pop_box::<u64>() }, 3);
+    }
+
+    #[test]
+    fn test_args_rewind_puts_back_popped_nums() {
+        let mut builder = ArgsBuilder::with_capacity(2);
+        builder.add_num(1).add_num(2);
+        let mut args = builder.finalize();
+
+        assert_eq!(args.pop_num(), 1);
+        assert!(args.rewind());
+        assert_eq!(args.pop_num(), 1);
+        assert_eq!(args.pop_num(), 2);
+    }
+
+    #[test]
+    fn test_args_with_box_cant_rewind() {
+        let mut builder = ArgsBuilder::with_capacity(2);
+        builder.add_num(1).add_box(Box::new(2u8));
+        let mut args = builder.finalize();
+
+        assert_eq!(args.pop_num(), 1);
+        assert_not!(args.rewind());
+        assert_eq!(unsafe { *args.pop_box::<u8>() }, 2);
+    }
 }
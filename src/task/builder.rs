@@ -0,0 +1,339 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Task builder.
+
+use super::{TaskControl, TaskHandle, Priority, SpawnError};
+use super::args::Args;
+use super::name::TaskName;
+use collections::Vec;
+use sync::CriticalSection;
+
+/// The stack size used by a `TaskBuilder` if none is given.
+pub const DEFAULT_STACK_DEPTH: usize = 512;
+
+/// A builder for configuring and spawning a task.
+///
+/// Every option except the code to run has a default, use the methods on the builder to change
+/// them and call `spawn()` once everything is set up.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{TaskBuilder, Priority};
+/// use altos_core::args::{Args, ArgsBuilder};
+///
+/// let mut args = ArgsBuilder::with_capacity(1);
+/// args.add_num(42);
+///
+/// let handle = TaskBuilder::new(test_task)
+///     .name("sandboxed")
+///     .priority(Priority::Low)
+///     .stack_size(1024)
+///     .args(args.finalize())
+///     .max_runtime(1000)
+///     .spawn();
+///
+/// match handle {
+///     Ok(handle) => { /* Keep the handle to monitor the task... */ },
+///     Err(_) => { /* Not enough memory, try again with a smaller stack... */ },
+/// }
+///
+/// fn test_task(_args: &mut Args) {
+///   // Do stuff here...
+///   loop {}
+/// }
+/// ```
+pub struct TaskBuilder {
+    code: fn(&mut Args),
+    args: Option<Args>,
+    stack_depth: usize,
+    priority: Priority,
+    name: &'static str,
+    max_runtime: Option<usize>,
+    on_runtime_exceeded: Option<fn(usize)>,
+    budget: Option<(usize, usize)>,
+    inline_name: Option<TaskName>,
+    affinity: usize,
+    uses_fpu: bool,
+    time_sliced: bool,
+    joinable: bool,
+    exit_handlers: Vec<fn(usize)>,
+}
+
+impl TaskBuilder {
+    /// Creates a new builder for a task that will run `code`.
+    ///
+    /// The task defaults to no arguments, a stack of `DEFAULT_STACK_DEPTH` bytes, `Normal`
+    /// priority and no runtime limit.
+    pub fn new(code: fn(&mut Args)) -> Self {
+        TaskBuilder {
+            code: code,
+            args: None,
+            stack_depth: DEFAULT_STACK_DEPTH,
+            priority: Priority::Normal,
+            name: "unnamed",
+            max_runtime: None,
+            on_runtime_exceeded: None,
+            budget: None,
+            inline_name: None,
+            affinity: ::sched::ALL_CORES,
+            uses_fpu: true,
+            time_sliced: true,
+            joinable: false,
+            exit_handlers: Vec::new(),
+        }
+    }
+
+    /// Sets the arguments that will be passed to the task.
+    pub fn args(&mut self, args: Args) -> &mut Self {
+        self.args = Some(args);
+        self
+    }
+
+    /// Sets the size of the task's stack in bytes.
+    pub fn stack_size(&mut self, stack_depth: usize) -> &mut Self {
+        self.stack_depth = stack_depth;
+        self
+    }
+
+    /// Sets the priority the task will run with.
+    pub fn priority(&mut self, priority: Priority) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the task's name.
+    pub fn name(&mut self, name: &'static str) -> &mut Self {
+        self.name = name;
+        self
+    }
+
+    /// Sets a name built at runtime, stored in the task's control block.
+    ///
+    /// This takes the place of the static name given to `name()`, see `TaskHandle::task_name()`.
+    pub fn task_name(&mut self, name: TaskName) -> &mut Self {
+        self.inline_name = Some(name);
+        self
+    }
+
+    /// Limits the total number of ticks the task may run for.
+    ///
+    /// Every system tick that lands while the task is running counts against the limit, once it
+    /// runs for more than `ticks` ticks the kernel kills it as if it had called `exit()`. This is
+    /// a cap on the task's whole lifetime, not on any single period.
+    pub fn max_runtime(&mut self, ticks: usize) -> &mut Self {
+        self.max_runtime = Some(ticks);
+        self
+    }
+
+    /// Sets a function to call when the task is killed for going over its `max_runtime`.
+    ///
+    /// The callback is passed the id of the killed task. It is run from the system tick handler,
+    /// so it must be short and must not block.
+    pub fn on_runtime_exceeded(&mut self, callback: fn(usize)) -> &mut Self {
+        self.on_runtime_exceeded = Some(callback);
+        self
+    }
+
+    /// Limits the task to running for `ticks` ticks out of every `period` ticks.
+    ///
+    /// Every system tick that lands while the task is running counts against its budget. Once the
+    /// budget is used up the task is suspended until the start of the next period, when the budget
+    /// is replenished, no matter what it was doing. This keeps a task that occasionally spins from
+    /// starving the tasks below it, without killing it the way `max_runtime` does. The first period
+    /// starts when the task is spawned. A period of 0 is treated as 1.
+    ///
+    /// The task is suspended even while it holds a mutex, so tasks that share locks with it may be
+    /// held up until its budget is replenished.
+    pub fn budget(&mut self, ticks: usize, period: usize) -> &mut Self {
+        self.budget = Some((ticks, period));
+        self
+    }
+
+    /// Keeps the task on the core numbered `core`, tasks may run on any core by default.
+    ///
+    /// Use `TaskHandle::set_affinity` to allow more than one core. Without the `smp` feature the
+    /// kernel only runs on core 0, a task kept on any other core is never run.
+    pub fn affinity(&mut self, core: usize) -> &mut Self {
+        self.affinity = 1 << core;
+        self
+    }
+
+    /// Sets whether the task uses the floating point registers, tasks are assumed to use them by
+    /// default.
+    ///
+    /// Ports for targets with a floating point unit can skip saving and restoring its registers
+    /// when switching integer-only tasks. None of the supported targets have one, so the flag is
+    /// recorded on the task but doesn't change how it's switched.
+    pub fn uses_fpu(&mut self, uses_fpu: bool) -> &mut Self {
+        self.uses_fpu = uses_fpu;
+        self
+    }
+
+    /// Sets whether the task takes turns with tasks of the same priority under the `RoundRobin`
+    /// policy, tasks are time sliced by default.
+    ///
+    /// A task that isn't time sliced runs until it blocks or yields, only a higher priority task
+    /// can preempt it.
+    pub fn time_sliced(&mut self, time_sliced: bool) -> &mut Self {
+        self.time_sliced = time_sliced;
+        self
+    }
+
+    /// Sets whether another task can wait for the task to exit and collect its exit value with
+    /// `TaskHandle::join`, tasks aren't joinable by default.
+    ///
+    /// A joinable task's exit value is kept after it exits until it's joined, so it must be either
+    /// joined or detached (see `TaskHandle::detach`).
+    pub fn joinable(&mut self, joinable: bool) -> &mut Self {
+        self.joinable = joinable;
+        self
+    }
+
+    /// Adds a handler to be called with the task's tid when it exits or is killed.
+    ///
+    /// This can be called more than once, the handlers are run most recently added first. See
+    /// `TaskHandle::on_exit`.
+    pub fn on_exit(&mut self, handler: fn(usize)) -> &mut Self {
+        self.exit_handlers.push(handler);
+        self
+    }
+
+    /// Creates the task and puts it into the task queue for running.
+    ///
+    /// Returns `Err(SpawnError::StackTooSmall)` if the stack can't hold the task's initial frame,
+    /// or one of the stack arena errors described in `init_stack_arena`.
+    /// The arguments are handed to the task, so spawning again from the same builder starts the
+    /// new task with no arguments.
+    pub fn spawn(&mut self) -> Result<TaskHandle, SpawnError> {
+        let args = self.args.take().unwrap_or_else(Args::empty);
+
+        // Make sure the task is allocated in one fell swoop
+        let g = CriticalSection::begin();
+        let task = TaskControl::try_new(self.code, args, self.stack_depth, self.priority, self.name);
+        drop(g);
+        let mut task = task?;
+        task.set_max_runtime(self.max_runtime, self.on_runtime_exceeded);
+        if let Some((ticks, period)) = self.budget {
+            task.set_budget(Some(ticks), period, ::tick::get_tick());
+        }
+        if let Some(name) = self.inline_name {
+            task.set_inline_name(name);
+        }
+        task.set_affinity(self.affinity);
+        task.set_uses_fpu(self.uses_fpu);
+        task.set_time_sliced(self.time_sliced);
+        task.set_joinable(self.joinable);
+        for handler in self.exit_handlers.iter() {
+            task.add_exit_handler(*handler);
+        }
+
+        Ok(::syscall::sys_spawn_task(task))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::State;
+    use sched::PRIORITY_QUEUES;
+    use test;
+
+    fn test_task(_args: &mut Args) {}
+
+    #[test]
+    fn test_builder_defaults() {
+        let _g = test::set_up();
+        let handle = TaskBuilder::new(test_task).spawn().unwrap();
+
+        assert_eq!(handle.name(), Ok("unnamed"));
+        assert_eq!(handle.priority(), Ok(Priority::Normal));
+        assert_eq!(handle.stack_size(), Ok(DEFAULT_STACK_DEPTH));
+        assert_eq!(handle.state(), Ok(State::Ready));
+        assert_not!(PRIORITY_QUEUES[Priority::Normal].remove_all().is_empty());
+    }
+
+    #[test]
+    fn test_builder_sets_options() {
+        let _g = test::set_up();
+        let handle = TaskBuilder::new(test_task)
+            .name("built task")
+            .priority(Priority::Critical)
+            .stack_size(1024)
+            .spawn()
+            .unwrap();
+
+        assert_eq!(handle.name(), Ok("built task"));
+        assert_eq!(handle.priority(), Ok(Priority::Critical));
+        assert_eq!(handle.stack_size(), Ok(1024));
+        assert_not!(PRIORITY_QUEUES[Priority::Critical].remove_all().is_empty());
+    }
+
+    #[test]
+    fn test_builder_inline_name() {
+        use core::fmt::Write;
+        let _g = test::set_up();
+        let mut name = TaskName::empty();
+        write!(name, "worker-{}", 3).unwrap();
+
+        let handle = TaskBuilder::new(test_task).task_name(name).spawn().unwrap();
+        let static_handle = TaskBuilder::new(test_task).name("static").spawn().unwrap();
+
+        assert_eq!(handle.task_name(), Ok(name));
+        assert_eq!(static_handle.task_name(), Ok(TaskName::new("static")));
+    }
+
+    #[test]
+    fn test_builder_affinity() {
+        let _g = test::set_up();
+        let pinned = TaskBuilder::new(test_task).affinity(1).spawn().unwrap();
+        let any = TaskBuilder::new(test_task).spawn().unwrap();
+
+        assert_eq!(pinned.affinity(), Ok(0b10));
+        assert_eq!(any.affinity(), Ok(::sched::ALL_CORES));
+    }
+
+    #[test]
+    fn test_builder_uses_fpu() {
+        let _g = test::set_up();
+        let integer = TaskBuilder::new(test_task).uses_fpu(false).spawn().unwrap();
+        let float = TaskBuilder::new(test_task).spawn().unwrap();
+
+        assert_eq!(integer.uses_fpu(), Ok(false));
+        assert_eq!(float.uses_fpu(), Ok(true));
+    }
+
+    #[test]
+    fn test_builder_time_sliced() {
+        let _g = test::set_up();
+        let exempt = TaskBuilder::new(test_task).time_sliced(false).spawn().unwrap();
+        let sliced = TaskBuilder::new(test_task).spawn().unwrap();
+
+        assert_eq!(exempt.is_time_sliced(), Ok(false));
+        assert_eq!(sliced.is_time_sliced(), Ok(true));
+    }
+
+    #[test]
+    fn test_builder_stack_too_small() {
+        let _g = test::set_up();
+        let result = TaskBuilder::new(test_task).stack_size(8).spawn();
+
+        assert_eq!(result.err(), Some(SpawnError::StackTooSmall));
+        assert!(PRIORITY_QUEUES[Priority::Normal].remove_all().is_empty());
+    }
+}
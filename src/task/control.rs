@@ -17,7 +17,15 @@
 
 use super::stack::Stack;
 use super::args::Args;
+use super::SpawnError;
+use super::name::TaskName;
+use super::id::{self, TaskId};
+use super::exit::{JoinError, ExitSlot};
+use super::local::TASK_LOCAL_SLOTS;
+#[cfg(feature="signals")]
+use super::signal::{SignalHandler, NUM_SIGNALS};
 use alloc::boxed::Box;
+use collections::{Expiring, Vec};
 use sync::CriticalSection;
 
 pub const NUM_PRIORITIES: usize = 4;
@@ -27,17 +35,6 @@ pub const INVALID_TASK: usize = 0x0;
 
 type HandleResult<T> = Result<T, ()>;
 
-mod tid {
-    use atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
-
-    static CURRENT_TID: AtomicUsize = ATOMIC_USIZE_INIT;
-
-    /// Atomically increment the task id and return the old value
-    pub fn fetch_next_tid() -> usize {
-        CURRENT_TID.fetch_add(1, Ordering::Relaxed)
-    }
-}
-
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Delay {
     Timeout,
@@ -46,6 +43,116 @@ pub enum Delay {
     Invalid,
 }
 
+/// The reason a sleeping task was woken up.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WakeReason {
+    /// The channel the task was sleeping on was signaled.
+    Event = 0,
+
+    /// The task's timeout ran out before its channel was signaled.
+    Timeout = 1,
+}
+
+/// What a blocked task is waiting on.
+///
+/// Mutexes and condition variables are identified by their address, which is also the channel
+/// their waiters sleep on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaitReason {
+    /// The task isn't blocked.
+    NotBlocked,
+
+    /// The task is waiting to acquire the mutex at this address.
+    Mutex(usize),
+
+    /// The task is waiting on the condition variable at this address.
+    CondVar(usize),
+
+    /// The task is sleeping on a channel, `deadline` is the tick it times out on, if it has a
+    /// timeout.
+    Sleep {
+        /// The channel the task is sleeping on.
+        wchan: usize,
+        /// The tick the task will be woken on if its channel isn't signaled first.
+        deadline: Option<usize>,
+    },
+
+    /// The task has used up its budget and is held back until it's replenished on this tick.
+    Budget(usize),
+}
+
+/// What a task is doing, along with what it's waiting on if it's blocked.
+///
+/// This combines a task's `State`, its `WaitReason` and whether it's been suspended into one value,
+/// see `TaskHandle::status`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// The task is ready to be run if the scheduler picks it. A task that's still being created
+    /// counts as ready.
+    Ready,
+
+    /// The task is currently running.
+    Running,
+
+    /// The task is blocked, waiting on a mutex, condition variable or channel. A task sleeping for
+    /// a number of ticks is waiting on a channel with a deadline.
+    Blocked(WaitReason),
+
+    /// The task has been suspended, or is held back until its budget is replenished. If it's
+    /// still waiting on something that's recorded here, a task that's `NotBlocked` will be ready
+    /// to run as soon as it's resumed.
+    Suspended(WaitReason),
+}
+
+/// Errors that can occur when restarting a task.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RestartError {
+    /// The task has been destroyed.
+    Destroyed,
+
+    /// A task tried to restart itself.
+    RestartSelf,
+
+    /// The task is running on another core.
+    Running,
+
+    /// The task was given boxed arguments, which belonged to it once it started and may be gone.
+    BoxedArgs,
+
+    /// The task holds a mutex, restarting it would leave the mutex locked forever.
+    HoldsLock,
+}
+
+/// Something to run when a task exits or is killed.
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone)]
+pub enum ExitHandler {
+    /// Added through `TaskBuilder::on_exit` or `TaskHandle::on_exit`, given the task's tid.
+    WithTid(fn(usize)),
+    /// Added by the task itself through `at_exit`.
+    Cleanup(fn()),
+}
+
+impl ExitHandler {
+    /// Run the handler for the task with tid `tid`.
+    pub fn run(self, tid: usize) {
+        match self {
+            ExitHandler::WithTid(handler) => handler(tid),
+            ExitHandler::Cleanup(cleanup) => cleanup(),
+        }
+    }
+}
+
+/// The kind of object a blocked task's channel belongs to.
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WaitObject {
+    Channel,
+    Mutex,
+    CondVar,
+}
+
 /// Priorities that a task can have.
 ///
 /// Priorities declare which tasks should be run before others. In most cases, a higher priority
@@ -229,16 +336,65 @@ pub enum State {
 #[derive(Debug)]
 pub struct TaskControl {
     stack: Stack, /*** stack MUST be the first field of the struct ***/
-    args: Box<Args>,
-    tid: usize,
+    args: Option<Box<Args>>,
+    code: fn(&mut Args),
+    arg: usize,
+    id: TaskId,
     name: &'static str,
     valid: usize,
     wchan: usize,
+    wait_object: WaitObject,
     delay: usize,
     delay_type: Delay,
     destroy: bool,
     priority: Priority,
+    base_priority: Priority,
+    held_locks: usize,
     state: State,
+    parent: Option<usize>,
+    kill_children: bool,
+    wake_reason: WakeReason,
+    run_ticks: usize,
+    max_runtime: Option<usize>,
+    on_runtime_exceeded: Option<fn(usize)>,
+    budget: Option<usize>,
+    budget_period: usize,
+    budget_used: usize,
+    replenish: usize,
+    throttled: bool,
+    inline_name: Option<TaskName>,
+    affinity: usize,
+    borrowed: bool,
+    borrowers: Vec<TaskHandle>,
+    in_static: bool,
+    pinned: bool,
+    time_sliced: bool,
+    suspended: bool,
+    uses_fpu: bool,
+    exit_record: Option<ExitSlot>,
+    exit_value: usize,
+    exit_handlers: Vec<ExitHandler>,
+    locals: [usize; TASK_LOCAL_SLOTS],
+    last_error: usize,
+    notify_value: u32,
+    notify_pending: bool,
+    #[cfg(feature="signals")]
+    signal_handlers: [Option<SignalHandler>; NUM_SIGNALS],
+    #[cfg(feature="signals")]
+    pending_signals: usize,
+    period: usize,
+    relative_deadline: usize,
+    release: usize,
+    deadline: Option<usize>,
+    deadline_missed: bool,
+    #[cfg(feature="stats")]
+    switch_ins: usize,
+    #[cfg(feature="starvation")]
+    starved_ticks: usize,
+    #[cfg(feature="starvation")]
+    aged: bool,
+    #[cfg(feature="stack_sizing")]
+    requested_depth: usize,
 }
 
 unsafe impl Send for TaskControl {}
@@ -248,46 +404,229 @@ impl TaskControl {
     /// Creates a new `TaskControl` initialized and ready to be scheduled.
     ///
     /// All of the arguments to this function are the same as the `new_task` kernel function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack is too small to hold the task's initial frame.
     pub fn new(code: fn(&mut Args), args: Args, depth: usize, priority: Priority, name: &'static str)
         -> Self {
 
-        let stack = Stack::new(depth);
+        match Self::try_new(code, args, depth, priority, name) {
+            Ok(task) => task,
+            Err(err) => kernel_panic!("TaskControl::new - failed to create task: {:?}", err),
+        }
+    }
+
+    /// Creates a new `TaskControl`, returning an error if the task couldn't be set up.
+    pub fn try_new(code: fn(&mut Args), args: Args, depth: usize, priority: Priority, name: &'static str)
+        -> Result<Self, SpawnError> {
 
         // Arguments struct stored right above the stack
         let args_mem: Box<Args> = Box::new(args);
+        let arg = &*args_mem as *const Args as usize;
+        let stack = Self::allocate_stack(depth)?;
+        #[cfg_attr(not(feature="stack_sizing"), allow(unused_mut))]
+        let mut task = Self::build(code, Some(args_mem), arg, stack, priority, name)?;
+        #[cfg(feature="stack_sizing")]
+        {
+            task.requested_depth = depth;
+        }
+        Ok(task)
+    }
+
+    /// Creates a new `TaskControl` that is passed `arg` directly instead of an `Args` list.
+    ///
+    /// Nothing is allocated for the argument, `arg` is handed to `code` as is.
+    ///
+    /// # Safety
+    ///
+    /// Whatever `arg` points to must stay valid for as long as the task can run, and `code` must
+    /// expect to be passed it.
+    pub unsafe fn try_new_borrowed(code: fn(&mut Args), arg: usize, depth: usize, priority: Priority,
+                                   name: &'static str) -> Result<Self, SpawnError> {
+
+        let mut task = Self::build(code, None, arg, Self::allocate_stack(depth)?, priority, name)?;
+        task.borrowed = true;
+        #[cfg(feature="stack_sizing")]
+        {
+            task.requested_depth = depth;
+        }
+        Ok(task)
+    }
+
+    /// Creates a new `TaskControl` that runs on `stack` and is passed `arg` directly, without
+    /// allocating anything.
+    ///
+    /// # Safety
+    ///
+    /// Whatever `arg` points to must stay valid for as long as the task can run, and `code` must
+    /// expect to be passed it. The task must be moved into static memory (see `StaticTask`) before
+    /// it's scheduled, it's never deallocated.
+    pub unsafe fn try_new_static(code: fn(&mut Args), arg: usize, stack: &'static mut [u8],
+                                 priority: Priority, name: &'static str) -> Result<Self, SpawnError> {
+
+        let mut task = Self::build(code, None, arg, Stack::from_static(stack), priority, name)?;
+        task.in_static = true;
+        Ok(task)
+    }
+
+    // Allocate the stack for a task that asked for `depth` bytes, it's made larger when stack sizing
+    // diagnostics are scaling stacks up.
+    fn allocate_stack(depth: usize) -> Result<Stack, SpawnError> {
+        #[cfg(feature="stack_sizing")]
+        let depth = super::stack_sizing::scaled_depth(depth);
+        Stack::new(depth)
+    }
+
+    fn build(code: fn(&mut Args), args: Option<Box<Args>>, arg: usize, stack: Stack,
+             priority: Priority, name: &'static str) -> Result<Self, SpawnError> {
 
-        let tid = tid::fetch_next_tid();
+        // Accounted for once the stack exists, a task that's dropped part way through being built
+        // still frees the arguments through `Drop`
+        #[cfg(feature="stats")]
+        {
+            if args.is_some() {
+                ::stats::record_alloc(::core::mem::size_of::<Args>());
+            }
+        }
+        let id = id::next_id();
+        let tid = id.tid();
+        #[cfg(feature="stack_sizing")]
+        let depth = {
+            let (bottom, top) = stack.bounds();
+            top - bottom
+        };
 
         let mut task = TaskControl {
             stack: stack,
-            args: args_mem,
-            tid: tid,
+            args: args,
+            code: code,
+            arg: arg,
+            id: id,
             name: name,
             valid: VALID_TASK + (tid & 0xFF),
             wchan: 0,
+            wait_object: WaitObject::Channel,
             delay: 0,
             delay_type: Delay::Invalid,
             destroy: false,
             priority: priority,
+            base_priority: priority,
+            held_locks: 0,
             state: State::Embryo,
+            parent: None,
+            kill_children: false,
+            wake_reason: WakeReason::Event,
+            run_ticks: 0,
+            max_runtime: None,
+            on_runtime_exceeded: None,
+            budget: None,
+            budget_period: 0,
+            budget_used: 0,
+            replenish: 0,
+            throttled: false,
+            inline_name: None,
+            affinity: ::sched::ALL_CORES,
+            borrowed: false,
+            borrowers: Vec::new(),
+            in_static: false,
+            pinned: false,
+            time_sliced: true,
+            suspended: false,
+            uses_fpu: true,
+            exit_record: None,
+            exit_value: 0,
+            exit_handlers: Vec::new(),
+            locals: [0; TASK_LOCAL_SLOTS],
+            last_error: 0,
+            notify_value: 0,
+            notify_pending: false,
+            #[cfg(feature="signals")]
+            signal_handlers: [None; NUM_SIGNALS],
+            #[cfg(feature="signals")]
+            pending_signals: 0,
+            period: 0,
+            relative_deadline: 0,
+            release: 0,
+            deadline: None,
+            deadline_missed: false,
+            #[cfg(feature="stats")]
+            switch_ins: 0,
+            #[cfg(feature="starvation")]
+            starved_ticks: 0,
+            #[cfg(feature="starvation")]
+            aged: false,
+            #[cfg(feature="stack_sizing")]
+            requested_depth: depth,
         };
-        task.initialize(code);
-        task
+        task.initialize(code, arg)?;
+        Ok(task)
     }
 
     /// This initializes the task's stack. This method MUST only be called once, calling it more
     /// than once could, at best, waste some stack space and, at worst, corrupt an active stack.
-    fn initialize(&mut self, code: fn(&mut Args)) {
-        self.stack.initialize(code, &self.args);
+    fn initialize(&mut self, code: fn(&mut Args), arg: usize) -> Result<(), SpawnError> {
+        self.stack.initialize(code, arg)?;
         self.state = State::Ready;
+        Ok(())
+    }
+
+    /// Put the task back the way it was when it was created, so it runs its code from the start
+    /// with its original arguments.
+    ///
+    /// Only the task's own state is reset, it must be moved into a ready queue by the caller. The
+    /// task is left untouched if it can't be restarted.
+    pub fn restart(&mut self) -> Result<(), RestartError> {
+        if self.held_locks > 0 {
+            return Err(RestartError::HoldsLock);
+        }
+        let rewound = match self.args {
+            Some(ref mut args) => args.rewind(),
+            // UNSAFE: A static task's argument is the `Args` kept in its `StaticTask`
+            None if self.in_static => unsafe { (*(self.arg as *mut Args)).rewind() },
+            None => true,
+        };
+        if !rewound {
+            return Err(RestartError::BoxedArgs);
+        }
+
+        // The frame fit in the stack when the task was created, so it still does
+        if self.stack.reset(self.code, self.arg).is_err() {
+            kernel_panic!("TaskControl::restart - the initial frame no longer fits in the stack!");
+        }
+        self.state = State::Ready;
+        self.wchan = 0;
+        self.wait_object = WaitObject::Channel;
+        self.delay = 0;
+        self.delay_type = Delay::Invalid;
+        self.wake_reason = WakeReason::Event;
+        self.suspended = false;
+        self.throttled = false;
+        self.exit_value = 0;
+        self.locals = [0; TASK_LOCAL_SLOTS];
+        self.last_error = 0;
+        self.notify_value = 0;
+        self.notify_pending = false;
+        #[cfg(feature="signals")]
+        self.pending_signals = 0;
+        Ok(())
     }
 
     pub fn destroy(&mut self) {
         if let Priority::__Idle = self.priority {
-            panic!("Tried to destroy the Idle task!");
+            kernel_panic!("Tried to destroy the Idle task!");
         }
 
         let _g = CriticalSection::begin();
+        if !self.destroy {
+            super::hooks::task_deleted(self);
+            #[cfg(feature="stack_sizing")]
+            super::stack_sizing::report_stack_usage(self);
+            // Leave the exit value behind the first time a joinable task is destroyed
+            if let Some(record) = self.exit_record.take() {
+                super::exit::record_exit(record, self.exit_value);
+            }
+        }
         self.destroy = true;
         self.valid = INVALID_TASK;
     }
@@ -296,11 +635,45 @@ impl TaskControl {
     ///
     /// Used to check if the stack has exceeded the memory allocated for it. If it has, this means
     /// that we may have corrupted some memory.
+    ///
+    /// The stack pointer is checked, along with a canary at the bottom of the stack that catches
+    /// a task that ran past the end of its stack and came back up before being switched out.
     pub fn is_stack_overflowed(&self) -> bool {
-        // TODO: Add some stack guard bytes to check if we've overflowed during execution? This
-        // would add some extra overhead, maybe have some #[cfg] that determines if we should add
-        // this extra security?
-        self.stack.check_overflow()
+        self.stack.check_overflow() || !self.stack.is_canary_intact()
+    }
+
+    /// Returns true if the task's saved stack pointer lies within its stack.
+    pub fn is_stack_ptr_valid(&self) -> bool {
+        self.stack.is_ptr_valid()
+    }
+
+    /// The lowest and highest addresses of the task's stack.
+    pub fn stack_bounds(&self) -> (usize, usize) {
+        self.stack.bounds()
+    }
+
+    /// The most bytes of its stack the task has used at once.
+    pub fn stack_usage(&self) -> usize {
+        self.stack.peak_usage()
+    }
+
+    /// The stack size in bytes the task was created with, before any stack sizing scale was applied.
+    #[cfg(feature="stack_sizing")]
+    pub fn requested_stack_depth(&self) -> usize {
+        self.requested_depth
+    }
+
+    /// The stack pointer saved when the task was last switched out.
+    pub fn stack_ptr(&self) -> usize {
+        self.stack.ptr()
+    }
+
+    /// Grow the task's stack, returning the relocated stack pointer.
+    ///
+    /// `sp` is the task's stack pointer at the time it ran into the stack guard.
+    #[cfg(feature="growable_stacks")]
+    pub fn grow_stack(&mut self, sp: usize) -> usize {
+        self.stack.grow(sp)
     }
 
     pub fn set_ready(&mut self) {
@@ -310,6 +683,10 @@ impl TaskControl {
 
     pub fn set_running(&mut self) {
         self.state = State::Running;
+        #[cfg(feature="stats")]
+        {
+            self.switch_ins = self.switch_ins.wrapping_add(1);
+        }
     }
 
     pub fn block(&mut self, delay_type: Delay) {
@@ -319,11 +696,16 @@ impl TaskControl {
 
     /// Wake a sleeping task
     ///
-    /// Set a task to the `Ready` state from the `Blocked` state.
-    pub fn wake(&mut self) {
-        debug_assert_eq!(self.state, State::Blocked);
+    /// Set a task to the `Ready` state from the `Blocked` state, recording why it was woken up.
+    /// Waking a task that isn't blocked is a no-op.
+    pub fn wake(&mut self, reason: WakeReason) {
+        if self.state != State::Blocked {
+            return;
+        }
         self.set_ready();
+        self.wake_reason = reason;
         self.wchan = 0;
+        self.wait_object = WaitObject::Channel;
         self.delay = 0;
     }
 
@@ -337,13 +719,66 @@ impl TaskControl {
         self.wchan = wchan;
     }
 
+    /// Record what kind of object the channel a blocked task is sleeping on belongs to.
+    pub fn set_wait_object(&mut self, object: WaitObject) {
+        self.wait_object = object;
+    }
+
+    /// Describe what the task is waiting on.
+    pub fn wait_reason(&self) -> WaitReason {
+        if self.throttled {
+            return WaitReason::Budget(self.replenish);
+        }
+        if self.state != State::Blocked {
+            return WaitReason::NotBlocked;
+        }
+        match self.wait_object {
+            WaitObject::Mutex => WaitReason::Mutex(self.wchan),
+            WaitObject::CondVar => WaitReason::CondVar(self.wchan),
+            WaitObject::Channel => WaitReason::Sleep {
+                wchan: self.wchan,
+                deadline: match self.delay_type {
+                    Delay::Timeout | Delay::Overflowed => Some(self.delay),
+                    Delay::Sleep | Delay::Invalid => None,
+                },
+            },
+        }
+    }
+
+    /// Describe what the task is doing.
+    pub fn status(&self) -> TaskStatus {
+        if self.is_suspended() {
+            return TaskStatus::Suspended(self.wait_reason());
+        }
+        match self.state {
+            State::Embryo | State::Ready => TaskStatus::Ready,
+            State::Running => TaskStatus::Running,
+            State::Blocked => TaskStatus::Blocked(self.wait_reason()),
+        }
+    }
+
+    /// Move a sleeping task to sleep on a different channel
+    ///
+    /// The task will now be woken up by signals to `wchan` instead of the channel it went to sleep
+    /// on.
+    pub fn move_to_wchan(&mut self, wchan: usize) {
+        debug_assert_eq!(self.state, State::Blocked);
+        self.wchan = wchan;
+    }
+
     /// Put a task to sleep
     ///
-    /// The task will sleep on `wchan` until woken up or until a number of ticks > `delay` has
-    /// passed. The task is guaranteed to wake up eventually.
+    /// The task will sleep on `wchan` until woken up or until at least `delay` full tick periods
+    /// have passed. The task is guaranteed to wake up eventually.
+    ///
+    /// Some unknown part of the current tick has already gone by, so it isn't counted. The
+    /// deadline is measured from the next tick edge, which means a sleep of `delay` ticks waits
+    /// for the rest of the current tick plus `delay` whole ticks. A `delay` of 0 wakes the task on
+    /// the next tick.
     pub fn sleep_for(&mut self, wchan: usize, delay: usize) {
         debug_assert_eq!(self.state, State::Running);
         let ticks = ::tick::get_tick();
+        let delay = if delay > 0 { delay.saturating_add(1) } else { 0 };
         self.wchan = wchan;
         self.delay = ticks.wrapping_add(delay);
         if self.delay < ticks {
@@ -354,7 +789,381 @@ impl TaskControl {
         }
     }
 
-    pub fn tid(&self) -> usize { self.tid }
+    /// Remember that `task` borrows its argument from this task's stack, so it's destroyed along
+    /// with this task.
+    pub fn add_borrower(&mut self, task: TaskHandle) {
+        self.borrowers.push(task);
+    }
+
+    /// Forget about a borrowing task once its borrow has ended.
+    pub fn remove_borrower(&mut self, task: TaskHandle) {
+        self.borrowers.retain(|borrower| borrower.1 != task.1);
+    }
+
+    /// Set the tid of the task that owns this one, `None` if it has no parent.
+    pub fn set_parent(&mut self, parent: Option<usize>) {
+        self.parent = parent;
+    }
+
+    /// Limit how many ticks the task may run for, `callback` is called with the task's id if it
+    /// is killed for going over the limit.
+    pub fn set_max_runtime(&mut self, ticks: Option<usize>, callback: Option<fn(usize)>) {
+        self.max_runtime = ticks;
+        self.on_runtime_exceeded = callback;
+    }
+
+    /// Count a system tick against the task's runtime, returns true if the task has now run for
+    /// longer than it's allowed to.
+    pub fn account_tick(&mut self) -> bool {
+        self.run_ticks = self.run_ticks.saturating_add(1);
+        self.max_runtime.map_or(false, |max| self.run_ticks > max)
+    }
+
+    /// Limit the task to running for `ticks` ticks out of every `period`, with the first period
+    /// starting at tick `now`. `None` lifts the limit. A period of 0 is treated as 1.
+    pub fn set_budget(&mut self, ticks: Option<usize>, period: usize, now: usize) {
+        let period = if period == 0 { 1 } else { period };
+        self.budget = ticks;
+        self.budget_period = period;
+        self.budget_used = 0;
+        self.replenish = now.wrapping_add(period);
+    }
+
+    /// Count a system tick at tick `now` against the task's budget.
+    ///
+    /// Returns the tick the budget is next replenished on if the task has now used it up, `None`
+    /// if it can keep running or has no budget.
+    pub fn charge_budget(&mut self, now: usize) -> Option<usize> {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return None,
+        };
+        // Top the budget back up if one or more periods have gone by since it was last charged
+        let elapsed = now.wrapping_sub(self.replenish) as isize;
+        if elapsed >= 0 {
+            let periods = elapsed as usize / self.budget_period + 1;
+            self.replenish = self.replenish.wrapping_add(periods * self.budget_period);
+            self.budget_used = 0;
+        }
+        self.budget_used += 1;
+        if self.budget_used >= budget { Some(self.replenish) } else { None }
+    }
+
+    /// Return how many ticks are left until the task's sleep deadline, `now` is the current tick.
+    ///
+    /// Returns `None` if the task isn't sleeping with a timeout.
+    pub fn remaining_sleep_ticks(&self, now: usize) -> Option<usize> {
+        if self.state != State::Blocked {
+            return None;
+        }
+        match self.delay_type {
+            Delay::Timeout => Some(if now >= self.delay { 0 } else { self.delay - now }),
+            // The deadline is past the point where the tick count wraps around
+            Delay::Overflowed => Some(self.delay.wrapping_sub(now)),
+            Delay::Sleep | Delay::Invalid => None,
+        }
+    }
+
+    pub fn tid(&self) -> usize { self.id.tid() }
+
+    pub fn id(&self) -> TaskId { self.id }
+
+    pub fn name(&self) -> &'static str { self.name }
+
+    /// Give the task a name stored in its control block, this takes the place of its static name.
+    pub fn set_inline_name(&mut self, name: TaskName) {
+        self.inline_name = Some(name);
+    }
+
+    /// Get the task's name, if it was given a static name it's copied into a `TaskName`.
+    pub fn task_name(&self) -> TaskName {
+        self.inline_name.unwrap_or_else(|| TaskName::new(self.name))
+    }
+
+    /// Returns true if the task's name is exactly `name`.
+    ///
+    /// Unlike comparing against `task_name`, a long static name isn't truncated first.
+    pub fn is_named(&self, name: &str) -> bool {
+        match self.inline_name {
+            Some(ref inline) => inline.as_str() == name,
+            None => self.name == name,
+        }
+    }
+
+    /// Set the cores the task may run on, bit `n` of `core_mask` allows core `n`.
+    pub fn set_affinity(&mut self, core_mask: usize) {
+        self.affinity = core_mask;
+    }
+
+    pub fn affinity(&self) -> usize { self.affinity }
+
+    /// Check if the task's affinity allows it to run on `core`.
+    pub fn can_run_on(&self, core: usize) -> bool {
+        core < ::core::mem::size_of::<usize>() * 8 && self.affinity & (1 << core) != 0
+    }
+
+    /// Keep tasks of the same priority from preempting this one while it runs.
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    pub fn is_pinned(&self) -> bool { self.pinned }
+
+    /// Set whether tasks of the same priority can take turns with this one under the `RoundRobin`
+    /// policy.
+    ///
+    /// A task that isn't time sliced runs until it blocks or yields, the same as it would under
+    /// `Fifo`, while the rest of the system keeps taking turns. Unlike pinning this lasts for the
+    /// life of the task.
+    pub fn set_time_sliced(&mut self, time_sliced: bool) {
+        self.time_sliced = time_sliced;
+    }
+
+    pub fn is_time_sliced(&self) -> bool { self.time_sliced }
+
+    /// Set whether the task is kept from being scheduled until it's resumed.
+    pub fn set_suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+    }
+
+    /// Returns whether the task is kept from being scheduled, either because it's been suspended
+    /// or because it's used up its budget.
+    pub fn is_suspended(&self) -> bool { self.suspended || self.throttled }
+
+    /// Set whether the task is held back until its budget is replenished.
+    pub fn set_throttled(&mut self, throttled: bool) {
+        self.throttled = throttled;
+    }
+
+    pub fn is_throttled(&self) -> bool { self.throttled }
+
+    /// Returns the tick the task's budget is next replenished on.
+    pub fn replenish_tick(&self) -> usize { self.replenish }
+
+    /// Mark whether the task uses the floating point registers.
+    ///
+    /// None of the supported targets have a floating point unit, so the flag is only recorded. A
+    /// port for a target with one can check it in its context switch handler and skip saving the
+    /// floating point registers (using the basic exception frame) for tasks that don't use them.
+    pub fn set_uses_fpu(&mut self, uses_fpu: bool) {
+        self.uses_fpu = uses_fpu;
+    }
+
+    pub fn uses_fpu(&self) -> bool { self.uses_fpu }
+
+    /// Set whether the task leaves its exit value behind for a task that joins it.
+    ///
+    /// The record the value is left in is allocated here, so the task doesn't allocate as it exits.
+    pub fn set_joinable(&mut self, joinable: bool) {
+        if !joinable {
+            self.exit_record = None;
+        }
+        else if self.exit_record.is_none() {
+            self.exit_record = Some(super::exit::reserve_exit_record(self.id));
+        }
+    }
+
+    pub fn is_joinable(&self) -> bool { self.exit_record.is_some() }
+
+    /// Set the value a task that joins this one is given once it exits.
+    pub fn set_exit_value(&mut self, value: usize) {
+        self.exit_value = value;
+    }
+
+    /// Add a handler to be called with the task's tid when it exits or is killed.
+    pub fn add_exit_handler(&mut self, handler: fn(usize)) {
+        self.exit_handlers.push(ExitHandler::WithTid(handler));
+    }
+
+    /// Push a cleanup function to be called when the task exits or is killed.
+    pub fn add_cleanup(&mut self, cleanup: fn()) {
+        self.exit_handlers.push(ExitHandler::Cleanup(cleanup));
+    }
+
+    /// The task's value in task-local slot `slot`.
+    pub fn local(&self, slot: usize) -> usize {
+        self.locals[slot]
+    }
+
+    /// Set the task's value in task-local slot `slot`.
+    pub fn set_local(&mut self, slot: usize, value: usize) {
+        self.locals[slot] = value;
+    }
+
+    /// The error code last recorded for the task.
+    pub fn last_error(&self) -> usize { self.last_error }
+
+    /// Record an error code for the task.
+    pub fn set_last_error(&mut self, code: usize) {
+        self.last_error = code;
+    }
+
+    /// Set `bits` in the task's notification value and mark a notification as pending.
+    pub fn notify(&mut self, bits: u32) {
+        self.notify_value |= bits;
+        self.notify_pending = true;
+    }
+
+    pub fn is_notify_pending(&self) -> bool { self.notify_pending }
+
+    /// Take the pending notification, returning the notification value and then clearing the bits
+    /// in `clear_mask` from it. Returns `None` if no notification is pending.
+    pub fn take_notification(&mut self, clear_mask: u32) -> Option<u32> {
+        if !self.notify_pending {
+            return None;
+        }
+        let value = self.notify_value;
+        self.notify_value &= !clear_mask;
+        self.notify_pending = false;
+        Some(value)
+    }
+
+    /// Set the handler run when the task is delivered `signal`, `None` throws the signal away.
+    #[cfg(feature="signals")]
+    pub fn set_signal_handler(&mut self, signal: usize, handler: Option<SignalHandler>) {
+        self.signal_handlers[signal] = handler;
+    }
+
+    /// Mark `signal` as pending, it's delivered the next time the task reaches a safe point.
+    #[cfg(feature="signals")]
+    pub fn post_signal(&mut self, signal: usize) {
+        self.pending_signals |= 1 << signal;
+    }
+
+    /// Take the lowest numbered pending signal, along with the handler it should be delivered to.
+    #[cfg(feature="signals")]
+    pub fn take_signal(&mut self) -> Option<(usize, Option<SignalHandler>)> {
+        if self.pending_signals == 0 {
+            return None;
+        }
+        let signal = self.pending_signals.trailing_zeros() as usize;
+        self.pending_signals &= !(1 << signal);
+        Some((signal, self.signal_handlers[signal]))
+    }
+
+    /// Take the task's exit handlers, leaving it with none so they're only ever run once.
+    ///
+    /// The handlers are in the order they were added, they should be run from the last one back.
+    pub fn take_exit_handlers(&mut self) -> Vec<ExitHandler> {
+        ::core::mem::replace(&mut self.exit_handlers, Vec::new())
+    }
+
+    /// Raise the task's priority to `priority` while it holds a mutex a task with that priority is
+    /// waiting on, does nothing if the task's priority is already at least as high.
+    ///
+    /// The task must not be sitting in a ready queue when its priority changes, the queue is picked
+    /// by priority.
+    pub fn inherit_priority(&mut self, priority: Priority) {
+        if (priority as usize) < (self.priority as usize) {
+            self.priority = priority;
+        }
+    }
+
+    /// The priority the task was created with, before any priority it inherited.
+    pub fn base_priority(&self) -> Priority { self.base_priority }
+
+    /// Change the priority the task runs at when it isn't borrowing a higher one.
+    ///
+    /// A priority inherited through a mutex is kept until the task releases its last mutex, unless
+    /// the new priority is higher. The task must not be sitting in a ready queue when its priority
+    /// changes, the queue is picked by priority.
+    pub fn set_base_priority(&mut self, priority: Priority) {
+        let inherited = self.priority != self.base_priority && self.held_locks > 0;
+        self.base_priority = priority;
+        if !inherited || (priority as usize) < (self.priority as usize) {
+            self.priority = priority;
+        }
+    }
+
+    /// Note that the task acquired a mutex.
+    pub fn lock_acquired(&mut self) {
+        self.held_locks += 1;
+    }
+
+    /// Note that the task released a mutex, dropping any inherited priority once it holds none.
+    pub fn lock_released(&mut self) {
+        self.held_locks = self.held_locks.saturating_sub(1);
+        if self.held_locks == 0 {
+            self.priority = self.base_priority;
+        }
+    }
+
+    /// Count a tick the task spent ready without running, returns the number of ticks it's waited.
+    #[cfg(feature="starvation")]
+    pub fn count_starved_tick(&mut self) -> usize {
+        self.starved_ticks = self.starved_ticks.saturating_add(1);
+        self.starved_ticks
+    }
+
+    /// Start counting the ticks the task has waited to run over from 0.
+    #[cfg(feature="starvation")]
+    pub fn reset_starved_ticks(&mut self) {
+        self.starved_ticks = 0;
+    }
+
+    /// Move the task up a priority level because it's starving, until `drop_aged_priority` is
+    /// called.
+    ///
+    /// The task must not be sitting in a ready queue when its priority changes, the queue is picked
+    /// by priority.
+    #[cfg(feature="starvation")]
+    pub fn age_priority(&mut self) {
+        let aged = match self.priority {
+            Priority::Low => Priority::Normal,
+            Priority::Normal | Priority::Critical => Priority::Critical,
+            Priority::__Idle => return,
+        };
+        if aged != self.priority {
+            self.priority = aged;
+            self.aged = true;
+        }
+    }
+
+    /// Drop the priority the task was aged to, unless it holds a mutex, in which case it keeps it
+    /// until it releases the last one.
+    #[cfg(feature="starvation")]
+    pub fn drop_aged_priority(&mut self) {
+        if self.aged && self.held_locks == 0 {
+            self.priority = self.base_priority;
+        }
+        self.aged = false;
+    }
+
+    /// Start the task's first job at tick `now`, due `relative_deadline` ticks later, with a new job
+    /// released every `period` ticks after that.
+    pub fn set_deadline(&mut self, period: usize, relative_deadline: usize, now: usize) {
+        self.period = period;
+        self.relative_deadline = relative_deadline;
+        self.release = now;
+        self.deadline = Some(now.wrapping_add(relative_deadline));
+        self.deadline_missed = false;
+    }
+
+    /// The tick the task's current job is due by, `None` if it hasn't registered a deadline.
+    pub fn deadline(&self) -> Option<usize> { self.deadline }
+
+    /// Move on to the task's next job, returning the tick it's released on.
+    pub fn advance_period(&mut self) -> usize {
+        self.release = self.release.wrapping_add(self.period);
+        self.deadline = Some(self.release.wrapping_add(self.relative_deadline));
+        self.deadline_missed = false;
+        self.release
+    }
+
+    /// Returns `true` the first time the current job is found past its deadline at tick `now`.
+    pub fn check_deadline_missed(&mut self, now: usize) -> bool {
+        match self.deadline {
+            Some(deadline) if !self.deadline_missed && (now.wrapping_sub(deadline) as isize) > 0 => {
+                self.deadline_missed = true;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn parent(&self) -> Option<usize> { self.parent }
+
+    pub fn kills_children_on_exit(&self) -> bool { self.kill_children }
 
     pub fn wchan(&self) -> usize { self.wchan }
 
@@ -364,11 +1173,51 @@ impl TaskControl {
 
     pub fn priority(&self) -> Priority { self.priority }
 
+    pub fn wake_reason(&self) -> WakeReason { self.wake_reason }
+
+    pub fn run_ticks(&self) -> usize { self.run_ticks }
+
+    /// The number of times the task has been switched to.
+    #[cfg(feature="stats")]
+    pub fn switch_in_count(&self) -> usize { self.switch_ins }
+
+    pub fn runtime_exceeded_callback(&self) -> Option<fn(usize)> { self.on_runtime_exceeded }
+
     pub fn is_destroyed(&self) -> bool { self.destroy }
 
+    /// Returns true if the task borrows its argument from its parent's stack.
+    pub fn is_borrowed(&self) -> bool { self.borrowed }
+
+    /// Returns true if the task lives in static memory rather than on the heap.
+    pub fn is_static(&self) -> bool { self.in_static }
+
     pub fn state(&self) -> State { self.state }
 }
 
+impl Expiring for TaskControl {
+    /// The tick the task's sleep times out on.
+    fn expires_at(&self) -> usize { self.delay }
+}
+
+impl Drop for TaskControl {
+    fn drop(&mut self) {
+        // Tasks borrowing from this one's stack must never run again once the stack is freed
+        for borrower in self.borrowers.iter_mut() {
+            if borrower.is_valid() {
+                borrower.task_ref_mut().destroy();
+            }
+        }
+
+        // The stack accounts for itself, we only need to account for the arguments
+        #[cfg(feature="stats")]
+        {
+            if self.args.is_some() {
+                ::stats::record_free(::core::mem::size_of::<Args>());
+            }
+        }
+    }
+}
+
 /// A `TaskHandle` references a `TaskControl` and provides access to some state about it.
 ///
 /// A `TaskHandle` is created whenever a new task is requested from the operating system. It
@@ -378,8 +1227,11 @@ impl TaskControl {
 /// This struct is thread safe, as all accesses to the internal `TaskControl` are checked for
 /// validity. If a task has been destroyed by one thread, then any other thread trying to access it
 /// will be returned an `Err`.
+///
+/// The handle remembers the `TaskId` of the task it was created for, so once that task has exited
+/// the handle stays invalid even if a new task is given the same memory or the same tid.
 #[derive(Copy, Clone, Debug)]
-pub struct TaskHandle(*const TaskControl);
+pub struct TaskHandle(*const TaskControl, TaskId);
 
 unsafe impl Send for TaskHandle {}
 unsafe impl Sync for TaskHandle {}
@@ -387,17 +1239,21 @@ unsafe impl Sync for TaskHandle {}
 impl TaskHandle {
     /// Creates a new `TaskHandle` referencing a `TaskControl`.
     pub fn new(task: &TaskControl) -> Self {
-        TaskHandle(task)
+        TaskHandle(task, task.id())
     }
 
-    /// Marks a task for destruction by the OS. Returns true if it was in a valid state before the
-    /// call, false otherwise.
+    /// Kills a task. Returns true if it was in a valid state before the call, false otherwise.
+    ///
+    /// The task is treated as if it had exited: its exit handlers are run (see `on_exit`), tasks
+    /// joining it are woken, and its children are killed or reparented as it asked for. If the
+    /// task is ready to run or waiting (asleep, on a mutex or condition variable, etc.) it's taken
+    /// off of whatever queue it was on and its stack and control block are freed right away. A
+    /// task that destroys itself, or one running on another core, is freed once it's switched out.
+    /// Once a task has been destroyed all attempts to access its data through a `TaskHandle` will
+    /// return `Err(())`.
     ///
-    /// This does not immediately clean up the task, it only marks the task for destruction. The
-    /// memory associated with that task will be reclaimed at the operating system's convenience.
-    /// There is no guarantee about when this will happen, and in some circumstances it may in fact
-    /// never happen, but once a task has been marked for destruction all attempts to access its
-    /// data through a `TaskHandle` will return `Err(())`.
+    /// Mutexes the task holds are NOT released, an exit handler should be used to put shared
+    /// resources back into a usable state.
     ///
     /// # Examples
     ///
@@ -428,14 +1284,75 @@ impl TaskHandle {
         //   dynamic allocation within tasks. - Daniel Seitz
         let _g = CriticalSection::begin();
         if self.is_valid() {
-            let task = self.task_ref_mut();
-            task.destroy();
+            ::syscall::kill_task(self.task_ref_mut());
             true
         } else {
             false
         }
     }
 
+    /// Wait for the task to exit, returning the value it passed to `exit_with`.
+    ///
+    /// The task must have been spawned joinable (see `TaskBuilder::joinable`). If it has already
+    /// exited its value is returned right away, otherwise the calling task is blocked until it
+    /// exits. A task that returns from its code, calls `exit()` or is killed exits with 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::TaskBuilder;
+    /// use altos_core::syscall::exit_with;
+    /// use altos_core::args::Args;
+    ///
+    /// let handle = TaskBuilder::new(worker).joinable(true).spawn().unwrap();
+    ///
+    /// let checksum = handle.join().unwrap();
+    ///
+    /// fn worker(_args: &mut Args) {
+    ///     // Work out the checksum...
+    ///     exit_with(0x1234);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(JoinError::NotJoinable)` if the task wasn't spawned joinable, has been detached
+    /// or has already been joined, and `Err(JoinError::JoinSelf)` if a task tries to join itself.
+    pub fn join(self) -> Result<usize, JoinError> {
+        ::syscall::join(self.1)
+    }
+
+    /// Give up on joining the task, its exit value is thrown away when it exits.
+    ///
+    /// A joinable task's exit value is kept until it's joined, so a joinable task that nobody is
+    /// going to join must be detached to free it.
+    pub fn detach(mut self) {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            self.task_ref_mut().set_joinable(false);
+        }
+        else {
+            super::exit::take_exit_value(self.1);
+        }
+    }
+
+    /// Returns whether the task's exit value will be kept for `join`.
+    ///
+    /// Tasks are detached unless they're spawned joinable, and a joinable task stops being joinable
+    /// once it's detached.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn is_joinable(&self) -> HandleResult<bool> {
+        let joinable = self.task_ref().is_joinable();
+        if self.is_valid() {
+            Ok(joinable)
+        } else {
+            Err(())
+        }
+    }
+
     /// Returns a task's priority.
     ///
     /// The `Priority` of a task determines in what order it should be run compared to other tasks.
@@ -472,6 +1389,42 @@ impl TaskHandle {
         }
     }
 
+    /// Change a task's priority.
+    ///
+    /// The task is moved over to the ready queue for its new priority if it's ready to run, and
+    /// if the change means a higher priority task is now ready the running task is preempted. If
+    /// the task has inherited a higher priority from a task waiting on a mutex it holds, it keeps
+    /// it until it releases the mutex, then drops to `priority`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::{TaskHandle, Priority};
+    /// use altos_core::syscall::new_task;
+    /// use altos_core::args::Args;
+    ///
+    /// let mut handle = new_task(test_task, Args::empty(), 512, Priority::Low, "new_task_name");
+    ///
+    /// // There's a backlog building up, let the task catch up
+    /// handle.set_priority(Priority::Normal).unwrap();
+    ///
+    /// # fn test_task(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn set_priority(&mut self, priority: Priority) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            ::syscall::set_task_priority(self.task_ref_mut(), priority);
+            Ok(())
+        }
+        else {
+            Err(())
+        }
+    }
+
     /// Returns a task's current state.
     ///
     /// The `State` of a task determines if it is able to run or not.
@@ -507,6 +1460,45 @@ impl TaskHandle {
         }
     }
 
+    /// Returns what a task is doing, including what it's waiting on and whether it's suspended.
+    ///
+    /// This is meant for watchdogs and debug shells, it tells apart everything `state`,
+    /// `wait_reason` and `is_suspended` do in one call.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority, TaskStatus, WaitReason};
+    /// # use altos_core::syscall::new_task;
+    /// # use altos_core::args::Args;
+    ///
+    /// let handle = new_task(test_task, Args::empty(), 512, Priority::Normal, "new_task_name");
+    ///
+    /// match handle.status() {
+    ///   Ok(TaskStatus::Blocked(WaitReason::Mutex(address))) => { /* Waiting for a lock */ },
+    ///   Ok(TaskStatus::Blocked(WaitReason::Sleep { deadline: Some(tick), .. })) => {
+    ///     /* Sleeping until `tick` */
+    ///   },
+    ///   Ok(TaskStatus::Suspended(_)) => { /* Taken out of scheduling */ },
+    ///   Ok(_) => { /* Running, ready, or waiting on something else */ },
+    ///   Err(()) => { /* Task was destroyed */ },
+    /// }
+    ///
+    /// # fn test_task(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn status(&self) -> HandleResult<TaskStatus> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            Ok(self.task_ref().status())
+        } else {
+            Err(())
+        }
+    }
+
     /// Returns a task's tid (task identifier).
     ///
     /// The tid is a unique identifier that differentiates different tasks even if they have the
@@ -535,9 +1527,18 @@ impl TaskHandle {
     ///
     /// If the task has been destroyed then this method will return an `Err(())`.
     pub fn tid(&self) -> HandleResult<usize> {
-        let tid = self.task_ref().tid;
+        self.id().map(|id| id.tid())
+    }
+
+    /// Returns the task's id, including the generation that tells it apart from other tasks that
+    /// have had the same tid.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn id(&self) -> HandleResult<TaskId> {
         if self.is_valid() {
-            Ok(tid)
+            Ok(self.1)
         } else {
             Err(())
         }
@@ -574,6 +1575,23 @@ impl TaskHandle {
         }
     }
 
+    /// Get the name of the task, including names that were built at runtime.
+    ///
+    /// `name()` only knows about static names, this returns the task's inline `TaskName` if it was
+    /// given one, or a copy of its static name (truncated to `TASK_NAME_CAPACITY`) otherwise.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn task_name(&self) -> HandleResult<TaskName> {
+        let name = self.task_ref().task_name();
+        if self.is_valid() {
+            Ok(name)
+        } else {
+            Err(())
+        }
+    }
+
     /// Returns the task's stack size.
     ///
     /// # Examples
@@ -607,6 +1625,398 @@ impl TaskHandle {
         }
     }
 
+    /// Returns the most bytes of its stack the task has used at once, its stack high-water mark.
+    ///
+    /// New stacks are filled with a pattern, and the usage is measured by finding the deepest word
+    /// of the stack that no longer holds it. This takes a scan of the stack, so it's meant for
+    /// sizing stacks during development rather than being called often. The task's initial frame
+    /// counts towards the usage.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority};
+    /// # use altos_core::syscall::new_task;
+    /// # use altos_core::args::Args;
+    ///
+    /// let handle = new_task(test_task, Args::empty(), 512, Priority::Normal, "new_task_name");
+    ///
+    /// // After letting the task run through its heaviest workload...
+    /// let headroom = handle.stack_size().unwrap() - handle.stack_usage().unwrap();
+    ///
+    /// # fn test_task(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn stack_usage(&self) -> HandleResult<usize> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            Ok(self.task_ref().stack_usage())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the mask of cores the task may run on, bit `n` is set if it may run on core `n`.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn affinity(&self) -> HandleResult<usize> {
+        let affinity = self.task_ref().affinity;
+        if self.is_valid() {
+            Ok(affinity)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns whether the task was marked as using the floating point registers.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn uses_fpu(&self) -> HandleResult<bool> {
+        let uses_fpu = self.task_ref().uses_fpu;
+        if self.is_valid() {
+            Ok(uses_fpu)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns whether tasks of the same priority take turns with the task under round robin.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn is_time_sliced(&self) -> HandleResult<bool> {
+        let time_sliced = self.task_ref().time_sliced;
+        if self.is_valid() {
+            Ok(time_sliced)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns whether the task has been suspended.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn is_suspended(&self) -> HandleResult<bool> {
+        let suspended = self.task_ref().suspended;
+        if self.is_valid() {
+            Ok(suspended)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Take the task out of scheduling until it's resumed, without destroying it.
+    ///
+    /// A task that's ready to run stops being picked right away, and a task that suspends itself
+    /// is switched out. A task that's blocked keeps waiting on whatever it's waiting on (a mutex,
+    /// a condition variable, a sleep...), and if it's woken while it's suspended it holds on to
+    /// the wakeup until it's resumed. A suspended task that's waiting on a mutex doesn't take the
+    /// mutex when it's unlocked, it's left for the other waiters and tried for again once the task
+    /// is resumed. Suspending a task doesn't release any mutexes it holds.
+    ///
+    /// Suspending a task that's already suspended does nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority};
+    /// # use altos_core::syscall::new_task;
+    /// # use altos_core::args::Args;
+    ///
+    /// let mut logger = new_task(logging_task, Args::empty(), 512, Priority::Low, "logger");
+    ///
+    /// // Keep the logger off of the flash while it's being rewritten
+    /// logger.suspend().unwrap();
+    /// // Update the firmware...
+    /// logger.resume().unwrap();
+    ///
+    /// # fn logging_task(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn suspend(&mut self) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            ::syscall::suspend_task(self.task_ref_mut());
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Let a suspended task be scheduled again.
+    ///
+    /// If the task was woken up while it was suspended it's ready to run again right away,
+    /// otherwise it goes back to waiting. Resuming a task that isn't suspended does nothing.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn resume(&mut self) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            ::syscall::resume_task(self.task_ref_mut());
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Send the task a notification, setting `bits` in its notification value.
+    ///
+    /// Every task has a 32 bit notification value of its own. Notifying a task ORs `bits` into the
+    /// value and wakes the task if it's waiting in `wait_notification`, a notification sent while
+    /// the task isn't waiting stays pending until it next waits. Nothing is allocated, so this is
+    /// the cheapest way to signal a task, and it's safe to call from an interrupt handler.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority};
+    /// # use altos_core::syscall::{new_task, wait_notification};
+    /// # use altos_core::args::Args;
+    ///
+    /// const RX_READY: u32 = 1 << 0;
+    ///
+    /// let mut uart = new_task(uart_task, Args::empty(), 512, Priority::Normal, "uart");
+    ///
+    /// // In the UART interrupt handler:
+    /// uart.notify(RX_READY).unwrap();
+    ///
+    /// fn uart_task(_args: &mut Args) {
+    ///     loop {
+    ///         let events = wait_notification(!0, None).unwrap();
+    ///         if events & RX_READY != 0 {
+    ///             // Drain the receive buffer...
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn notify(&mut self, bits: u32) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            ::syscall::notify_task(self.task_ref_mut(), bits);
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Post `signal` to the task.
+    ///
+    /// The signal is delivered to the handler the task registered for it (see
+    /// `set_signal_handler`) the next time the task reaches a safe point, in the task's own
+    /// context. A blocked task isn't woken up by the signal, and waiting on a mutex, condition
+    /// variable or semaphore isn't a safe point, so a task blocked on one of those only sees the
+    /// signal after it gets what it was waiting for and reaches a safe point. This is safe to call
+    /// from an interrupt handler.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority};
+    /// # use altos_core::syscall::new_task;
+    /// # use altos_core::args::Args;
+    ///
+    /// const SIG_CANCEL: usize = 0;
+    ///
+    /// let mut download = new_task(download_task, Args::empty(), 512, Priority::Normal, "download");
+    ///
+    /// // The user pressed cancel
+    /// download.signal(SIG_CANCEL).unwrap();
+    ///
+    /// # fn download_task(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed, or `signal` isn't less than `NUM_SIGNALS`, then this method
+    /// will return an `Err(())`.
+    #[cfg(feature="signals")]
+    pub fn signal(&mut self, signal: usize) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() && signal < NUM_SIGNALS {
+            self.task_ref_mut().post_signal(signal);
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Start the task over from the beginning of its code, with the arguments it was created with.
+    ///
+    /// The task's stack is thrown away and it's made ready to run, whatever it was doing or waiting
+    /// on, even if it was suspended. Its id, name, priority and other settings are kept, along with
+    /// its exit handlers (which aren't run). This is meant for a supervisor task recovering from a
+    /// fault in another task.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority};
+    /// # use altos_core::syscall::new_task;
+    /// # use altos_core::args::Args;
+    ///
+    /// let mut modem = new_task(modem_task, Args::empty(), 512, Priority::Normal, "modem");
+    ///
+    /// // The modem task stopped responding to its watchdog
+    /// modem.restart().unwrap();
+    ///
+    /// # fn modem_task(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(RestartError::Destroyed)` if the task has been destroyed,
+    /// `Err(RestartError::RestartSelf)` if a task tries to restart itself,
+    /// `Err(RestartError::Running)` if the task is running on another core,
+    /// `Err(RestartError::BoxedArgs)` if any of the task's arguments is a box (this includes tasks
+    /// created with `spawn`), and `Err(RestartError::HoldsLock)` if the task holds a mutex.
+    pub fn restart(&mut self) -> Result<(), RestartError> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            ::syscall::restart_task(self.task_ref_mut())
+        } else {
+            Err(RestartError::Destroyed)
+        }
+    }
+
+    /// Returns the tid of the task that created this task.
+    ///
+    /// Tasks created before the scheduler was started have no parent. If a task's parent exits
+    /// without killing its children, the children are handed off to the parent's own parent.
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn parent(&self) -> HandleResult<Option<usize>> {
+        let parent = self.task_ref().parent;
+        if self.is_valid() {
+            Ok(parent)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Add a handler to be called when the task exits or is killed, it's given the task's tid.
+    ///
+    /// Handlers are run most recently added first, along with any the task added for itself with
+    /// `at_exit`. They're run by the kernel in the context of whichever task caused the exit,
+    /// possibly with interrupts disabled, so they must not block and should be kept short.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority};
+    /// # use altos_core::syscall::new_task;
+    /// # use altos_core::args::Args;
+    ///
+    /// let mut handle = new_task(radio_task, Args::empty(), 512, Priority::Normal, "radio");
+    ///
+    /// // Make sure the radio is powered down however the task ends
+    /// handle.on_exit(power_down_radio).unwrap();
+    ///
+    /// # fn radio_task(_args: &mut Args) {}
+    /// # fn power_down_radio(_tid: usize) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn on_exit(&mut self, handler: fn(usize)) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            self.task_ref_mut().add_exit_handler(handler);
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Set whether the task's children should be killed when it exits.
+    ///
+    /// When set, all of the task's descendants (children, grandchildren, etc.) will be destroyed
+    /// when the task exits. Otherwise its children are reparented to the task's own parent.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use altos_core::{TaskHandle, Priority};
+    /// # use altos_core::syscall::new_task;
+    /// # use altos_core::args::Args;
+    ///
+    /// let mut handle = new_task(supervisor, Args::empty(), 512, Priority::Normal, "supervisor");
+    ///
+    /// // Any worker tasks spawned by the supervisor are cleaned up with it
+    /// handle.set_kill_children_on_exit(true).unwrap();
+    ///
+    /// # fn supervisor(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn set_kill_children_on_exit(&mut self, kill: bool) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            self.task_ref_mut().kill_children = kill;
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Set the cores the task may run on, bit `n` of `core_mask` allows core `n`.
+    ///
+    /// The scheduler only picks the task to run on a core its mask allows, so tasks that depend
+    /// on a core's cache or on a peripheral only one core can reach stay where they belong. A
+    /// task whose mask allows none of the cores in the system is never run. Tasks may run on any
+    /// core (`ALL_CORES`) by default. Without the `smp` feature the kernel only runs on core 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::syscall::new_task;
+    /// use altos_core::args::Args;
+    /// use altos_core::Priority;
+    ///
+    /// let mut handle = new_task(dma_worker, Args::empty(), 512, Priority::Normal, "dma");
+    ///
+    /// // Keep the task on the core that owns the DMA controller
+    /// handle.set_affinity(1 << 0).unwrap();
+    ///
+    /// # fn dma_worker(_args: &mut Args) {}
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the task has been destroyed then this method will return an `Err(())`.
+    pub fn set_affinity(&mut self, core_mask: usize) -> HandleResult<()> {
+        let _g = CriticalSection::begin();
+        if self.is_valid() {
+            self.task_ref_mut().affinity = core_mask;
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
     /// Check if the task pointed to by this handle is valid.
     ///
     /// # Examples
@@ -632,9 +2042,14 @@ impl TaskHandle {
         // UNSAFE: Yes, potentially we're reading from a dangling pointer (if the task has been
         // freed, for instance), but there should be specific values at these locations, and if
         // they aren't there then at least we'll know not to do anymore reads.
-        let (tid, valid) = unsafe { ((*self.0).tid, (*self.0).valid) };
-        let tid_mask = tid & 0xFF;
-        valid == VALID_TASK + tid_mask
+        let (id, valid) = unsafe { ((*self.0).id, (*self.0).valid) };
+        let tid_mask = id.tid() & 0xFF;
+        valid == VALID_TASK + tid_mask && id == self.1
+    }
+
+    #[cfg(test)]
+    pub fn as_ptr(&self) -> *const TaskControl {
+        self.0
     }
 
     fn task_ref(&self) -> &TaskControl {
@@ -652,6 +2067,135 @@ impl TaskHandle {
     }
 }
 
+/// Return the number of ticks until a sleeping task's timeout runs out.
+///
+/// Returns `None` if the task isn't sleeping with a timeout, which includes tasks sleeping until
+/// they're signaled and tasks that have been destroyed. Counts that don't fit in a `u32` are
+/// clamped to `u32::MAX`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{TaskHandle, Priority, remaining_sleep_ticks};
+/// use altos_core::syscall::new_task;
+/// use altos_core::args::Args;
+///
+/// let handle = new_task(test_task, Args::empty(), 512, Priority::Normal, "sleepy");
+///
+/// if let Some(ticks) = remaining_sleep_ticks(&handle) {
+///   // The task will wake up on its own within `ticks` ticks
+/// }
+///
+/// fn test_task(_args: &mut Args) {
+///   loop {}
+/// }
+/// ```
+pub fn remaining_sleep_ticks(handle: &TaskHandle) -> Option<u32> {
+    let _g = CriticalSection::begin();
+    if !handle.is_valid() {
+        return None;
+    }
+    handle.task_ref()
+        .remaining_sleep_ticks(::tick::get_tick())
+        .map(|ticks| ::core::cmp::min(ticks, ::core::u32::MAX as usize) as u32)
+}
+
+/// Return what the task referenced by `handle` is waiting on.
+///
+/// This can be used to dump what every task in the system is blocked on when looking for a
+/// deadlock. Tasks that are running, ready to run or have been destroyed are `NotBlocked`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{Priority, WaitReason, wait_reason};
+/// use altos_core::syscall::new_task;
+/// use altos_core::args::Args;
+///
+/// let handle = new_task(test_task, Args::empty(), 512, Priority::Normal, "new_task_name");
+///
+/// if let WaitReason::Mutex(address) = wait_reason(&handle) {
+///   // The task is stuck waiting for the mutex at `address`
+/// }
+///
+/// fn test_task(_args: &mut Args) {
+///   loop {}
+/// }
+/// ```
+pub fn wait_reason(handle: &TaskHandle) -> WaitReason {
+    let _g = CriticalSection::begin();
+    if !handle.is_valid() {
+        return WaitReason::NotBlocked;
+    }
+    handle.task_ref().wait_reason()
+}
+
+/// Return how many system ticks the task has been running for.
+///
+/// The running task is charged for every tick it's running when the system tick fires, so this is
+/// a sample rather than an exact measurement, a task that always blocks between two ticks is never
+/// charged. Over a long enough period it shows which tasks are using the CPU, `TaskInfo::cpu_percent`
+/// turns a snapshot of these counts into percentages. Returns `None` if the task has been
+/// destroyed.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{Priority, cpu_usage};
+/// use altos_core::syscall::new_task;
+/// use altos_core::args::Args;
+///
+/// let handle = new_task(test_task, Args::empty(), 512, Priority::Normal, "new_task_name");
+///
+/// if let Some(ticks) = cpu_usage(&handle) {
+///     // The task has run for `ticks` ticks
+/// }
+///
+/// fn test_task(_args: &mut Args) {
+///   loop {}
+/// }
+/// ```
+pub fn cpu_usage(handle: &TaskHandle) -> Option<usize> {
+    let _g = CriticalSection::begin();
+    if !handle.is_valid() {
+        return None;
+    }
+    Some(handle.task_ref().run_ticks())
+}
+
+/// Return how many times the task has been switched to.
+///
+/// Together with the number of ticks the task has run for this gives the average length of the
+/// task's time slices, which helps when tuning priorities. Returns `None` if the task has been
+/// destroyed.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::Priority;
+/// use altos_core::stats::task_switch_count;
+/// use altos_core::syscall::new_task;
+/// use altos_core::args::Args;
+///
+/// let handle = new_task(test_task, Args::empty(), 512, Priority::Normal, "new_task_name");
+///
+/// if let Some(switches) = task_switch_count(&handle) {
+///     // The task has been scheduled `switches` times
+/// }
+///
+/// fn test_task(_args: &mut Args) {
+///   loop {}
+/// }
+/// ```
+#[cfg(feature="stats")]
+pub fn task_switch_count(handle: &TaskHandle) -> Option<usize> {
+    let _g = CriticalSection::begin();
+    if !handle.is_valid() {
+        return None;
+    }
+    Some(handle.task_ref().switch_in_count())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -673,6 +2217,7 @@ mod tests {
 
     #[test]
     fn test_task_handle_valid() {
+        let _g = test::set_up();
         let mut task = get_task();
         let handle = TaskHandle::new(&task);
 
@@ -681,8 +2226,41 @@ mod tests {
         assert!(!handle.is_valid());
     }
 
+    #[test]
+    fn test_task_handle_stale_after_tid_reused() {
+        let _g = test::set_up();
+        let mut task = get_task();
+        let mut handle = TaskHandle::new(&task);
+        let old_id = task.id();
+
+        // The task exits and a task from a later generation is given its tid and its memory
+        id::set_next_id(id::id_for_test(old_id.tid(), old_id.generation() + 1));
+        task = get_task();
+        assert_eq!(task.tid(), old_id.tid());
+
+        assert_not!(handle.is_valid());
+        assert_eq!(handle.tid(), Err(()));
+        assert_eq!(handle.name(), Err(()));
+        assert_eq!(handle.set_priority(Priority::Critical), Err(()));
+        assert_eq!(handle.suspend(), Err(()));
+        assert_eq!(handle.notify(0x1), Err(()));
+        #[cfg(feature="signals")]
+        assert_eq!(handle.signal(0), Err(()));
+        assert_eq!(handle.restart(), Err(RestartError::Destroyed));
+        assert_not!(handle.destroy());
+
+        // None of it reached the task now using the memory
+        assert_eq!(task.priority(), Priority::Normal);
+        assert_not!(task.is_suspended());
+        assert_not!(task.is_notify_pending());
+        assert_not!(task.is_destroyed());
+        assert!(TaskHandle::new(&task).is_valid());
+    }
+
     #[test]
     fn test_task_handle_destroy() {
+        // Destroying a task now runs its exit handlers and frees waiting tasks in the global queues
+        let _g = test::set_up();
         let task = get_task();
         let mut handle = TaskHandle::new(&task);
 
@@ -693,6 +2271,22 @@ mod tests {
         assert!(!handle.is_valid());
     }
 
+    #[test]
+    fn test_dropping_task_destroys_its_borrowers() {
+        let _g = test::set_up();
+        let borrower = get_task();
+        let finished = get_task();
+        let mut parent = get_task();
+
+        parent.add_borrower(TaskHandle::new(&borrower));
+        parent.add_borrower(TaskHandle::new(&finished));
+        parent.remove_borrower(TaskHandle::new(&finished));
+        drop(parent);
+
+        assert!(borrower.is_destroyed());
+        assert_not!(finished.is_destroyed());
+    }
+
     #[test]
     fn test_invalid_task_handle_destroy() {
         let task = get_invalid_task();
@@ -705,6 +2299,7 @@ mod tests {
 
     #[test]
     fn test_task_handle_stack_size() {
+        let _g = test::set_up();
         let task = get_task();
         let handle = TaskHandle::new(&task);
 
@@ -721,6 +2316,7 @@ mod tests {
 
     #[test]
     fn test_task_handle_priority() {
+        let _g = test::set_up();
         let task = get_task();
         let handle = TaskHandle::new(&task);
 
@@ -737,6 +2333,7 @@ mod tests {
 
     #[test]
     fn test_task_handle_state() {
+        let _g = test::set_up();
         let task = get_task();
         let handle = TaskHandle::new(&task);
 
@@ -753,6 +2350,7 @@ mod tests {
 
     #[test]
     fn test_task_handle_name() {
+        let _g = test::set_up();
         let task = get_task();
         let handle = TaskHandle::new(&task);
 
@@ -769,6 +2367,7 @@ mod tests {
 
     #[test]
     fn test_task_handle_tid() {
+        let _g = test::set_up();
         let task = get_task();
         let handle = TaskHandle::new(&task);
 
@@ -784,6 +2383,22 @@ mod tests {
         assert!(handle.tid().is_err());
     }
 
+    #[test]
+    fn test_set_base_priority_keeps_inherited_priority() {
+        let _g = test::set_up();
+        let mut task = test::create_test_task(512, Priority::Low, "task test");
+        task.lock_acquired();
+        task.inherit_priority(Priority::Critical);
+
+        task.set_base_priority(Priority::Normal);
+        assert_eq!(task.priority(), Priority::Critical);
+        task.lock_released();
+        assert_eq!(task.priority(), Priority::Normal);
+
+        task.set_base_priority(Priority::Low);
+        assert_eq!(task.priority(), Priority::Low);
+    }
+
     #[test]
     fn test_iter_priority() {
         let mut iter_priority = IterPriority::new();
@@ -810,4 +2425,32 @@ mod tests {
         assert_eq!(iter_priority_skip.next().unwrap(), Priority::__Idle);
         assert_eq!(iter_priority_skip.next(), None);
     }
+
+    #[test]
+    fn test_remaining_sleep_ticks_counts_down() {
+        use sched;
+        use syscall;
+        use tick;
+
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        sched::start_scheduler();
+        assert_eq!(remaining_sleep_ticks(&handle_1), None);
+
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        syscall::sleep_for(1, 10);
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        // The partial tick the sleep started in doesn't count
+        assert_eq!(remaining_sleep_ticks(&handle_1), Some(11));
+
+        for _ in 0..3 {
+            tick::tick();
+        }
+        assert_eq!(remaining_sleep_ticks(&handle_1), Some(8));
+        assert_eq!(remaining_sleep_ticks(&handle_2), None);
+
+        // Sleeping without a timeout has no deadline
+        syscall::sleep(1);
+        assert_eq!(remaining_sleep_ticks(&handle_2), None);
+    }
 }
@@ -0,0 +1,118 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Exit records for joinable tasks.
+//!
+//! A task that was spawned joinable leaves a record of its exit value behind when it exits, so a
+//! task that joins it afterwards can still collect the value even though the task itself has been
+//! freed. The record is kept until the task is joined or detached, so every joinable task should
+//! be one or the other.
+
+use collections::{SyncQueue, NodeBox};
+use sync::CriticalSection;
+use sched;
+use super::TaskId;
+
+struct ExitRecord {
+    id: TaskId,
+    value: usize,
+}
+
+// An exit record allocated ahead of time, so a joinable task doesn't touch the heap as it exits.
+pub struct ExitSlot(NodeBox<ExitRecord>);
+
+static EXIT_RECORDS: SyncQueue<ExitRecord> = SyncQueue::new();
+
+/// Errors that can occur while joining a task.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task wasn't spawned joinable, has been detached, or has already been joined.
+    NotJoinable,
+    /// A task tried to join itself, it would wait forever.
+    JoinSelf,
+}
+
+/// Push `cleanup` onto the running task's cleanup stack, to be called when the task exits.
+///
+/// The cleanup functions run whichever way the task ends: returning from its code, calling `exit`
+/// or being killed with `TaskHandle::destroy`. The last one pushed runs first, so a driver can
+/// push a cleanup as it takes each resource and have them given back in the opposite order. They
+/// run by the kernel in the context of whichever task caused the exit, possibly with interrupts
+/// disabled, so they must not block and should be kept short. Use `TaskHandle::on_exit` to add a
+/// handler to another task.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::at_exit;
+/// use altos_core::args::Args;
+///
+/// fn radio_task(_args: &mut Args) {
+///     // Power up the radio...
+///     at_exit(power_down_radio);
+///     loop {
+///         // Send packets...
+///     }
+/// }
+///
+/// fn power_down_radio() {
+///     // Cut the radio's power...
+/// }
+/// ```
+///
+/// # Panics
+///
+/// This panics if it's called before the scheduler has started.
+pub fn at_exit(cleanup: fn()) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so the current task can't change under us
+    match unsafe { sched::current_task() } {
+        Some(current) => current.add_cleanup(cleanup),
+        None => kernel_panic!("at_exit - current task doesn't exist!"),
+    }
+}
+
+// Allocate the exit record for the task with id `id`, done when the task is made joinable.
+pub fn reserve_exit_record(id: TaskId) -> ExitSlot {
+    ExitSlot(NodeBox::new(ExitRecord {
+        id: id,
+        value: 0,
+    }))
+}
+
+// Keep `value` as the exit value of the task `slot` was reserved for until it's joined or detached.
+pub fn record_exit(mut slot: ExitSlot, value: usize) {
+    slot.0.value = value;
+    EXIT_RECORDS.enqueue(slot.0);
+}
+
+// Remove the exit record of the task with id `id`, returning its exit value if it had one.
+#[doc(hidden)]
+pub fn take_exit_value(id: TaskId) -> Option<usize> {
+    EXIT_RECORDS.remove(|record| record.id == id).dequeue().map(|record| record.value)
+}
+
+// The channel tasks waiting to join another task sleep on, it's woken whenever a task exits.
+#[doc(hidden)]
+pub fn join_channel() -> usize {
+    &EXIT_RECORDS as *const _ as usize
+}
+
+#[cfg(test)]
+pub fn clear_exit_records() {
+    EXIT_RECORDS.remove_all();
+}
@@ -0,0 +1,205 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Groups of tasks that are managed together.
+
+use super::{TaskHandle, TaskBuilder, SpawnError, JoinError};
+use collections::Vec;
+use sync::CriticalSection;
+use sched;
+
+/// A set of tasks that can be joined, killed or suspended all at once.
+///
+/// A group is useful for a subsystem made up of several tasks (a parser and its workers, say) that
+/// has to be torn down as a whole. The collective operations each happen inside of a single
+/// critical section, so no task in the group can run in between the others being killed or
+/// suspended.
+///
+/// Tasks spawned into a group are made joinable, so their exit values can be collected with
+/// `join_all`. When the group is dropped every task still in it is detached.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{TaskBuilder, TaskGroup};
+/// use altos_core::args::Args;
+///
+/// let mut decoder = TaskGroup::new();
+/// decoder.spawn(TaskBuilder::new(parser).name("parser")).unwrap();
+/// for _ in 0..2 {
+///     decoder.spawn(TaskBuilder::new(worker).name("worker")).unwrap();
+/// }
+///
+/// // The link went down, tear the whole decoder down
+/// decoder.kill_all();
+///
+/// fn parser(_args: &mut Args) {}
+/// fn worker(_args: &mut Args) {}
+/// ```
+pub struct TaskGroup {
+    tasks: Vec<TaskHandle>,
+}
+
+impl TaskGroup {
+    /// Create an empty group.
+    pub fn new() -> Self {
+        TaskGroup {
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Spawn a task from `builder` into the group, the task is made joinable.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `TaskBuilder::spawn`.
+    pub fn spawn(&mut self, builder: &mut TaskBuilder) -> Result<TaskHandle, SpawnError> {
+        let handle = builder.joinable(true).spawn()?;
+        self.tasks.push(handle);
+        Ok(handle)
+    }
+
+    /// Add a task that's already been spawned to the group.
+    ///
+    /// `join_all` can only collect the exit value of a task that was spawned joinable.
+    pub fn add(&mut self, handle: TaskHandle) {
+        self.tasks.push(handle);
+    }
+
+    /// The handles of the tasks in the group, in the order they were added.
+    pub fn handles(&self) -> &[TaskHandle] {
+        &self.tasks
+    }
+
+    /// Wait for every task in the group to exit, returning their exit values in the order they
+    /// were added.
+    ///
+    /// Each task is joined in turn (see `TaskHandle::join`), tasks that aren't joinable give
+    /// `Err(JoinError::NotJoinable)` without being waited for.
+    pub fn join_all(mut self) -> Vec<Result<usize, JoinError>> {
+        let tasks = ::core::mem::replace(&mut self.tasks, Vec::new());
+        tasks.into_iter().map(|handle| handle.join()).collect()
+    }
+
+    /// Kill every task in the group, as if `TaskHandle::destroy` was called on each of them.
+    ///
+    /// The tasks are all killed inside of one critical section, and their exit values are thrown
+    /// away. If the calling task is in the group it's killed along with the others, and stops
+    /// running as soon as it yields.
+    pub fn kill_all(&mut self) {
+        let _g = CriticalSection::begin();
+        for mut handle in self.tasks.drain(..) {
+            handle.destroy();
+            handle.detach();
+        }
+    }
+
+    /// Suspend every task in the group, see `TaskHandle::suspend`.
+    ///
+    /// The tasks are all suspended inside of one critical section. If the calling task is in the
+    /// group it's suspended last, so it isn't switched out until the rest are suspended too.
+    pub fn suspend_all(&mut self) {
+        let _g = CriticalSection::begin();
+        // UNSAFE: Only the tid is read
+        let current = unsafe { sched::current_task() }.map(|task| task.tid());
+        let mut own = None;
+        for handle in self.tasks.iter_mut() {
+            if handle.tid().ok() == current {
+                own = Some(*handle);
+            }
+            else {
+                let _ = handle.suspend();
+            }
+        }
+        if let Some(mut handle) = own {
+            let _ = handle.suspend();
+        }
+    }
+
+    /// Let every task in the group be scheduled again, see `TaskHandle::resume`.
+    pub fn resume_all(&mut self) {
+        let _g = CriticalSection::begin();
+        for handle in self.tasks.iter_mut() {
+            let _ = handle.resume();
+        }
+    }
+}
+
+impl Drop for TaskGroup {
+    fn drop(&mut self) {
+        for handle in self.tasks.drain(..) {
+            handle.detach();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use task::args::Args;
+
+    fn test_task(_args: &mut Args) {}
+
+    fn spawn_two(group: &mut TaskGroup) -> (TaskHandle, TaskHandle) {
+        (group.spawn(TaskBuilder::new(test_task).name("first")).unwrap(),
+         group.spawn(TaskBuilder::new(test_task).name("second")).unwrap())
+    }
+
+    #[test]
+    fn test_kill_all_kills_every_task() {
+        let _g = test::set_up();
+        let mut group = TaskGroup::new();
+        let (first, second) = spawn_two(&mut group);
+        let first_id = first.id().unwrap();
+        let outsider = test::create_and_schedule_test_task(512, ::task::Priority::Normal, "outsider");
+
+        group.kill_all();
+        assert_not!(first.is_valid());
+        assert_not!(second.is_valid());
+        assert!(outsider.is_valid());
+        // The exit values were thrown away
+        assert_eq!(::task::take_exit_value(first_id), None);
+        assert!(group.handles().is_empty());
+    }
+
+    #[test]
+    fn test_suspend_and_resume_all() {
+        let _g = test::set_up();
+        let mut group = TaskGroup::new();
+        let (first, second) = spawn_two(&mut group);
+
+        group.suspend_all();
+        assert_eq!(first.is_suspended(), Ok(true));
+        assert_eq!(second.is_suspended(), Ok(true));
+        group.resume_all();
+        assert_eq!(first.is_suspended(), Ok(false));
+        assert_eq!(second.is_suspended(), Ok(false));
+    }
+
+    #[test]
+    fn test_join_all_collects_exit_values() {
+        let _g = test::set_up();
+        let mut group = TaskGroup::new();
+        let (mut first, mut second) = spawn_two(&mut group);
+
+        // Killed tasks exit with 0
+        first.destroy();
+        second.destroy();
+        assert_eq!(group.join_all(), vec![Ok(0), Ok(0)]);
+    }
+}
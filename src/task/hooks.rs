@@ -0,0 +1,126 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Kernel-wide task lifecycle hooks.
+//!
+//! Middleware that keeps track of the tasks in the system (a tracer, a leak checker, a watchdog
+//! that expects every task to check in) can install a hook that's called whenever a task is
+//! created, and one that's called whenever a task is deleted, instead of having to wrap every call
+//! that creates or kills a task. Unlike the exit handlers of a single task (see
+//! `TaskHandle::on_exit`), these hooks see every task.
+
+use hook::Hook;
+use super::TaskControl;
+
+static CREATE_HOOK: Hook<fn(&TaskControl)> = Hook::new();
+static DELETE_HOOK: Hook<fn(&TaskControl)> = Hook::new();
+
+/// Set a hook to be called with every task that's created.
+///
+/// The hook is called once the task is fully set up, just before it's handed to the scheduler, so
+/// it hasn't run yet. It's called in the context of the task that created it, and may be called
+/// with interrupts disabled, so it must not block. The idle task isn't reported. Setting a new hook
+/// replaces the old one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{TaskControl, set_task_create_hook};
+/// use altos_core::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+///
+/// static LIVE_TASKS: AtomicUsize = ATOMIC_USIZE_INIT;
+///
+/// fn count_task(_task: &TaskControl) {
+///     LIVE_TASKS.fetch_add(1, Ordering::Relaxed);
+/// }
+///
+/// set_task_create_hook(count_task);
+/// ```
+pub fn set_task_create_hook(hook: fn(&TaskControl)) {
+    CREATE_HOOK.set(hook);
+}
+
+/// Set a hook to be called with every task that's deleted.
+///
+/// The hook is called once for each task, when it exits, returns from its code or is killed
+/// (including when it's killed along with its parent), while all of its state can still be read.
+/// It's called in the context of whichever task caused the deletion, possibly from an interrupt
+/// handler or with interrupts disabled, so it must not block. Setting a new hook replaces the old
+/// one.
+pub fn set_task_delete_hook(hook: fn(&TaskControl)) {
+    DELETE_HOOK.set(hook);
+}
+
+/// Remove both task lifecycle hooks, if they were set.
+pub fn clear_task_hooks() {
+    CREATE_HOOK.clear();
+    DELETE_HOOK.clear();
+}
+
+// Report a newly created task to the create hook.
+#[doc(hidden)]
+pub fn task_created(task: &TaskControl) {
+    call(&CREATE_HOOK, task);
+}
+
+// Report a deleted task to the delete hook.
+#[doc(hidden)]
+pub fn task_deleted(task: &TaskControl) {
+    call(&DELETE_HOOK, task);
+}
+
+fn call(hook: &Hook<fn(&TaskControl)>, task: &TaskControl) {
+    if let Some(hook) = hook.get() {
+        hook(task);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+    use test;
+    use task::Priority;
+
+    static CREATED: AtomicUsize = ATOMIC_USIZE_INIT;
+    static DELETED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_create(task: &TaskControl) {
+        CREATED.store(task.tid(), Ordering::SeqCst);
+    }
+
+    fn record_delete(task: &TaskControl) {
+        DELETED.store(task.tid(), Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_hooks_see_task_created_and_deleted() {
+        let _g = test::set_up();
+        CREATED.store(!0, Ordering::SeqCst);
+        DELETED.store(!0, Ordering::SeqCst);
+        set_task_create_hook(record_create);
+        set_task_delete_hook(record_delete);
+
+        let mut handle = test::create_and_schedule_test_task(512, Priority::Normal, "tracked");
+        let tid = handle.tid().unwrap();
+        assert_eq!(CREATED.load(Ordering::SeqCst), tid);
+        assert_eq!(DELETED.load(Ordering::SeqCst), !0);
+
+        assert!(handle.destroy());
+        assert_eq!(DELETED.load(Ordering::SeqCst), tid);
+    }
+}
@@ -0,0 +1,148 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Task id assignment.
+//!
+//! Every task is given a task id (tid) when it's created. Tids are handed out in creation order,
+//! starting from 0, and the kernel uses them to tell tasks apart in logs, traces, recorded
+//! schedules and lock ownership.
+//!
+//! Tids can't grow forever, once `MAX_TID` has been handed out the count wraps back around to 0
+//! and the generation is bumped. From then on tids that still belong to a live task are skipped,
+//! so no two live tasks ever share a tid, but the tid of a task that has exited can be given to a
+//! new one. The generation tells the two apart, a `TaskId` is only equal to another if both the
+//! tid and the generation match, so a `TaskHandle` to an exited task never mistakes the new task
+//! for the one it was created for.
+
+use atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use core::cell::Cell;
+use sync::CriticalSection;
+
+/// The highest tid that will be handed out before the count wraps around.
+///
+/// The top bit of a mutex's lock word marks it as locked, so tids have to fit in the rest.
+pub const MAX_TID: usize = (!0 >> 1) - 1;
+
+static NEXT_TID: AtomicUsize = ATOMIC_USIZE_INIT;
+static GENERATION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Uniquely identifies a task over the lifetime of the system.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TaskId {
+    tid: usize,
+    generation: usize,
+}
+
+impl TaskId {
+    /// The task's tid, this is the number used throughout the rest of the kernel's API.
+    pub fn tid(&self) -> usize { self.tid }
+
+    /// The number of times the tid count had wrapped around when the task was created.
+    pub fn generation(&self) -> usize { self.generation }
+}
+
+/// Hand out the id for a new task.
+pub fn next_id() -> TaskId {
+    let _g = CriticalSection::begin();
+    loop {
+        let tid = NEXT_TID.load(Ordering::Relaxed);
+        let generation = GENERATION.load(Ordering::Relaxed);
+        if tid == MAX_TID {
+            NEXT_TID.store(0, Ordering::Relaxed);
+            GENERATION.store(generation.wrapping_add(1), Ordering::Relaxed);
+        }
+        else {
+            NEXT_TID.store(tid + 1, Ordering::Relaxed);
+        }
+
+        // Until the count wraps every tid handed out is new
+        if generation == 0 || !is_live(tid) {
+            return TaskId {
+                tid: tid,
+                generation: generation,
+            };
+        }
+    }
+}
+
+// Check if a task that hasn't exited yet is using `tid`.
+fn is_live(tid: usize) -> bool {
+    live_id(tid).is_some()
+}
+
+// Find the id of the task that hasn't exited yet that's using `tid`, must be called from inside of
+// a critical section.
+#[doc(hidden)]
+pub fn live_id(tid: usize) -> Option<TaskId> {
+    let found = Cell::new(None);
+    // UNSAFE: We're in a critical section, so the current task can't change
+    if let Some(current) = unsafe { ::sched::current_task() } {
+        if current.tid() == tid {
+            found.set(Some(current.id()));
+        }
+    }
+    ::sched::for_each_task(|task| {
+        if task.tid() == tid && !task.is_destroyed() {
+            found.set(Some(task.id()));
+        }
+    });
+    found.get()
+}
+
+// Make `id` the next id handed out.
+#[cfg(test)]
+pub fn set_next_id(id: TaskId) {
+    NEXT_TID.store(id.tid, Ordering::Relaxed);
+    GENERATION.store(id.generation, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+pub fn id_for_test(tid: usize, generation: usize) -> TaskId {
+    TaskId {
+        tid: tid,
+        generation: generation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use task::Priority;
+
+    #[test]
+    fn test_ids_handed_out_in_order() {
+        let _g = test::set_up();
+        set_next_id(id_for_test(10, 0));
+
+        assert_eq!(next_id(), id_for_test(10, 0));
+        assert_eq!(next_id(), id_for_test(11, 0));
+    }
+
+    #[test]
+    fn test_wrapped_ids_skip_live_tasks() {
+        let _g = test::set_up();
+        set_next_id(id_for_test(0, 0));
+        let live = test::create_and_schedule_test_task(512, Priority::Normal, "live");
+        assert_eq!(live.tid(), Ok(0));
+
+        set_next_id(id_for_test(MAX_TID, 0));
+        assert_eq!(next_id(), id_for_test(MAX_TID, 0));
+        // Tid 0 is still in use, so it's passed over
+        assert_eq!(next_id(), id_for_test(1, 1));
+    }
+}
@@ -0,0 +1,233 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Task-local storage.
+//!
+//! Every task has `TASK_LOCAL_SLOTS` words of storage of its own. A `LocalKey` (usually declared
+//! with `task_local!`) is given one of the slots the first time it's used, and from then on reads
+//! and writes through the key go to that slot of whichever task is running. This lets a library
+//! keep per-task state, like a random number generator's seed, without a table keyed by tid
+//! behind a mutex.
+//!
+//! The slots are a word each, and every task's slots start out as 0. Larger state can be kept by
+//! storing a pointer to it.
+//!
+//! There are only `TASK_LOCAL_SLOTS` slots for the whole application, and a slot given to a key is
+//! never given back, so keys should be `static`s declared once rather than created on the fly.
+//! Using one more key than there are slots panics, an application that needs more of them should
+//! keep a pointer to a per-task struct in a single key.
+//!
+//! Every task also has a last error slot of its own, outside of the task-local slots, for C-style
+//! APIs that report failures through an `errno`-like code (see `set_last_error`).
+
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use sync::CriticalSection;
+use sched;
+
+/// The number of task-local slots each task has, and so the number of `LocalKey`s that can ever be
+/// used, slots aren't reclaimed.
+pub const TASK_LOCAL_SLOTS: usize = 4;
+
+static NEXT_SLOT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Declare a `LocalKey` for a word of task-local storage.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// #[macro_use]
+/// extern crate altos_core;
+///
+/// task_local!(static RNG_STATE);
+///
+/// fn next_random() -> usize {
+///     let state = RNG_STATE.get().wrapping_mul(1103515245).wrapping_add(12345);
+///     RNG_STATE.set(state);
+///     state
+/// }
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    ($(#[$attr:meta])* static $name:ident) => {
+        $(#[$attr])* static $name: $crate::LocalKey = $crate::LocalKey::new();
+    };
+    ($(#[$attr:meta])* pub static $name:ident) => {
+        $(#[$attr])* pub static $name: $crate::LocalKey = $crate::LocalKey::new();
+    };
+}
+
+/// A key for a word of task-local storage.
+///
+/// Each task sees its own value through the key. See the module documentation for details.
+pub struct LocalKey {
+    // The slot the key was given plus one, or 0 if it hasn't been given one yet
+    slot: AtomicUsize,
+}
+
+impl LocalKey {
+    /// Create a new key, it's given a slot the first time it's used.
+    pub const fn new() -> Self {
+        LocalKey {
+            slot: ATOMIC_USIZE_INIT,
+        }
+    }
+
+    /// Return the running task's value for this key, 0 if the task hasn't set one.
+    ///
+    /// # Panics
+    ///
+    /// This panics if it's called before the scheduler has started, or if more than
+    /// `TASK_LOCAL_SLOTS` keys have been used.
+    pub fn get(&self) -> usize {
+        let _g = CriticalSection::begin();
+        let slot = self.slot();
+        // UNSAFE: We're in a critical section, so the current task can't change under us
+        match unsafe { sched::current_task() } {
+            Some(current) => current.local(slot),
+            None => kernel_panic!("LocalKey::get - current task doesn't exist!"),
+        }
+    }
+
+    /// Set the running task's value for this key.
+    ///
+    /// # Panics
+    ///
+    /// This panics under the same conditions as `get`.
+    pub fn set(&self, value: usize) {
+        let _g = CriticalSection::begin();
+        let slot = self.slot();
+        // UNSAFE: We're in a critical section, so the current task can't change under us
+        match unsafe { sched::current_task() } {
+            Some(current) => current.set_local(slot, value),
+            None => kernel_panic!("LocalKey::set - current task doesn't exist!"),
+        }
+    }
+
+    // Return the slot the key was given, giving it the next free one if it hasn't been given one.
+    // Must be called inside a critical section.
+    fn slot(&self) -> usize {
+        match self.slot.load(Ordering::Relaxed) {
+            0 => {
+                let slot = NEXT_SLOT.load(Ordering::Relaxed);
+                if slot == TASK_LOCAL_SLOTS {
+                    kernel_panic!("LocalKey - all {} task-local slots are in use!", TASK_LOCAL_SLOTS);
+                }
+                NEXT_SLOT.store(slot + 1, Ordering::Relaxed);
+                self.slot.store(slot + 1, Ordering::Relaxed);
+                slot
+            },
+            slot => slot - 1,
+        }
+    }
+}
+
+/// Record `code` as the running task's last error.
+///
+/// The code means whatever the API setting it says it means, the kernel only stores it. Each task
+/// has its own, so a task can't see an error recorded by another task that ran in between.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{set_last_error, last_error};
+///
+/// const EAGAIN: usize = 11;
+///
+/// fn read_sensor() -> Option<u16> {
+///     // The sensor isn't ready yet...
+///     set_last_error(EAGAIN);
+///     None
+/// }
+///
+/// if read_sensor().is_none() && last_error() == EAGAIN {
+///     // Try again later
+/// }
+/// ```
+///
+/// # Panics
+///
+/// This panics if it's called before the scheduler has started.
+pub fn set_last_error(code: usize) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so the current task can't change under us
+    match unsafe { sched::current_task() } {
+        Some(current) => current.set_last_error(code),
+        None => kernel_panic!("set_last_error - current task doesn't exist!"),
+    }
+}
+
+/// Return the last error code recorded for the running task, 0 if none has been.
+///
+/// The code isn't cleared when it's read, so an API should only be checked for an error after it
+/// has reported that it failed.
+///
+/// # Panics
+///
+/// This panics if it's called before the scheduler has started.
+pub fn last_error() -> usize {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so the current task can't change under us
+    match unsafe { sched::current_task() } {
+        Some(current) => current.last_error(),
+        None => kernel_panic!("last_error - current task doesn't exist!"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use syscall::sched_yield;
+    use sched::start_scheduler;
+
+    task_local!(static COUNTER);
+
+    #[test]
+    fn test_each_task_has_its_own_value() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(COUNTER.get(), 0);
+        COUNTER.set(5);
+
+        sched_yield();
+        assert_eq!(handle_2.tid(), Ok(test::current_task().unwrap().tid()));
+        assert_eq!(COUNTER.get(), 0);
+        COUNTER.set(9);
+
+        sched_yield();
+        assert_eq!(COUNTER.get(), 5);
+    }
+
+    #[test]
+    fn test_last_error_is_per_task() {
+        let _g = test::set_up();
+        test::create_two_tasks();
+        start_scheduler();
+
+        assert_eq!(last_error(), 0);
+        set_last_error(11);
+        sched_yield();
+        assert_eq!(last_error(), 0);
+        set_last_error(4);
+        sched_yield();
+        assert_eq!(last_error(), 11);
+    }
+}
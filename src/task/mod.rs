@@ -20,30 +20,132 @@
 //! This module contains the functions used to create tasks and modify them within the kernel.
 
 pub mod args;
+mod arena;
 mod stack;
 mod control;
+mod snapshot;
+mod builder;
+mod name;
+mod id;
+mod exit;
+mod local;
+mod hooks;
+mod static_task;
+#[cfg(feature="signals")]
+mod signal;
+mod group;
+#[cfg(feature="stack_sizing")]
+pub mod stack_sizing;
 
-pub use self::control::{TaskHandle, TaskControl, Delay, State, Priority};
+pub use self::control::{TaskHandle, TaskControl, Delay, State, Priority, WakeReason};
 pub use self::control::NUM_PRIORITIES;
+pub use self::control::remaining_sleep_ticks;
+pub use self::control::{WaitReason, WaitObject, wait_reason};
+pub use self::control::{TaskStatus, RestartError};
+pub use self::control::cpu_usage;
+#[cfg(feature="stats")]
+pub use self::control::task_switch_count;
+pub use self::snapshot::{TaskInfo, with_task_snapshot, find_task};
+pub use self::builder::{TaskBuilder, DEFAULT_STACK_DEPTH};
+pub use self::group::TaskGroup;
+pub use sched::edf::spawn_periodic;
+pub use self::name::{TaskName, TASK_NAME_CAPACITY};
+pub use self::id::{TaskId, MAX_TID};
+pub use self::id::live_id;
+pub use self::exit::{JoinError, take_exit_value, join_channel, at_exit};
+pub use self::local::{LocalKey, TASK_LOCAL_SLOTS};
+pub use self::local::{set_last_error, last_error};
+pub use self::hooks::{set_task_create_hook, set_task_delete_hook, clear_task_hooks};
+pub use self::hooks::task_created;
+pub use self::static_task::StaticTask;
+#[cfg(feature="signals")]
+pub use self::signal::{NUM_SIGNALS, SignalHandler, InvalidSignal};
+#[cfg(feature="signals")]
+pub use self::signal::{set_signal_handler, clear_signal_handler, deliver_signals};
+#[cfg(test)]
+pub use self::exit::clear_exit_records;
+pub use self::arena::init_stack_arena;
+#[cfg(test)]
+pub use self::arena::clear_stack_arena;
 
 use args::Args;
 
+/// Errors that can occur while creating a task.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpawnError {
+    /// The stack isn't large enough to hold the task's initial frame and the canary below it, see
+    /// `MIN_STACK_WORDS`.
+    StackTooSmall,
+    /// The stack arena has no free slots left, see `init_stack_arena`.
+    TooManyTasks,
+    /// The requested stack is bigger than a stack arena slot, see `init_stack_arena`.
+    StackTooLarge,
+}
+
 #[doc(hidden)]
 pub fn init_idle_task() {
-    use sched::PRIORITY_QUEUES;
-    use collections::Node;
-    use alloc::boxed::Box;
+    use sched::{scheduler, NUM_CORES};
     const INIT_TASK_STACK_SIZE: usize = 256;
 
-    let task = TaskControl::new(idle_task_code, Args::empty(), INIT_TASK_STACK_SIZE, Priority::__Idle, "idle");
+    // The idle tasks are static so that starting the scheduler doesn't need the heap
+    #[cfg(not(feature="smp"))]
+    static mut IDLE_TASKS: [StaticTask; NUM_CORES] = [StaticTask::new()];
+    #[cfg(feature="smp")]
+    static mut IDLE_TASKS: [StaticTask; NUM_CORES] = [StaticTask::new(), StaticTask::new()];
+    static mut IDLE_STACKS: [[u8; INIT_TASK_STACK_SIZE]; NUM_CORES] =
+        [[0; INIT_TASK_STACK_SIZE]; NUM_CORES];
+
+    // Every core needs an idle task of its own to fall back on
+    for core in 0..NUM_CORES {
+        // UNSAFE: The idle tasks are only created when the scheduler starts, each with its own
+        // core's memory, and once created they're never destroyed
+        let task = unsafe {
+            let arg = IDLE_TASKS[core].store_args(Args::empty());
+            TaskControl::try_new_static(idle_task_code, arg, &mut IDLE_STACKS[core],
+                                        Priority::__Idle, "idle")
+        };
+        let mut task = match task {
+            Ok(task) => task,
+            Err(_) => kernel_panic!("init_idle_task - the idle task's stack is too small!"),
+        };
+        task.set_affinity(1 << core);
 
-    PRIORITY_QUEUES[task.priority()].enqueue(Box::new(Node::new(task)));
+        // UNSAFE: Same as above
+        scheduler().add_task(unsafe { IDLE_TASKS[core].store(task) });
+    }
 }
 
 fn idle_task_code(_args: &mut Args) {
     use syscall::sched_yield;
+    use power;
 
     loop {
+        ::sched::run_idle_hooks();
+        #[cfg(feature="trace")]
+        ::trace::flush();
+        power::idle();
         sched_yield();
     }
 }
+
+/// Grow the current task's stack after it has run into its stack guard.
+///
+/// Tasks start out with the stack size they were created with, when the `growable_stacks` feature
+/// is enabled the fault handler for the target (usually the MemManage fault raised by an MPU guard
+/// region at the bottom of the stack) should call this function with the faulting task's PSP, and
+/// load the returned value into the PSP before returning from the fault. The stack guard must then
+/// be moved to the bottom of the new stack.
+#[cfg(feature="growable_stacks")]
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn grow_current_stack(psp: usize) -> usize {
+    use sched;
+    use sync::CriticalSection;
+
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing else can be touching the current task.
+    match unsafe { sched::current_task() } {
+        Some(task) => task.grow_stack(psp),
+        None => kernel_panic!("grow_current_stack - stack fault with no task running!"),
+    }
+}
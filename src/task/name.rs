@@ -0,0 +1,184 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Fixed capacity task names.
+
+use core::fmt;
+use core::ops::Deref;
+use core::str;
+
+/// The number of bytes a `TaskName` can hold.
+///
+/// This is a crate wide constant rather than a parameter of `TaskName` because the compiler
+/// versions this kernel supports don't have const generics.
+pub const TASK_NAME_CAPACITY: usize = 16;
+
+/// A task name stored inline, without needing static storage or the heap.
+///
+/// Task names are normally `&'static str`, which is awkward for names built at runtime. A
+/// `TaskName` holds up to `TASK_NAME_CAPACITY` bytes of text directly in the task's control block.
+/// Anything past the capacity is dropped, names are never cut in the middle of a character.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::fmt::Write;
+/// use altos_core::TaskName;
+///
+/// let mut name = TaskName::empty();
+/// write!(name, "worker-{}", 3).unwrap();
+/// assert_eq!(name, "worker-3");
+/// ```
+#[derive(Copy, Clone)]
+pub struct TaskName {
+    bytes: [u8; TASK_NAME_CAPACITY],
+    len: usize,
+    truncated: bool,
+}
+
+impl TaskName {
+    /// Create an empty name.
+    pub const fn empty() -> Self {
+        TaskName {
+            bytes: [0; TASK_NAME_CAPACITY],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// Create a name from `name`, truncating it if it doesn't fit.
+    pub fn new(name: &str) -> Self {
+        let mut task_name = Self::empty();
+        task_name.push_str(name);
+        task_name
+    }
+
+    /// Append `text` to the name, dropping whatever doesn't fit.
+    pub fn push_str(&mut self, text: &str) {
+        let space = TASK_NAME_CAPACITY - self.len;
+        let mut fits = ::core::cmp::min(space, text.len());
+        while !text.is_char_boundary(fits) {
+            fits -= 1;
+        }
+        self.bytes[self.len..self.len + fits].copy_from_slice(&text.as_bytes()[..fits]);
+        self.len += fits;
+        if fits < text.len() {
+            self.truncated = true;
+        }
+    }
+
+    /// Return the name as a string slice.
+    pub fn as_str(&self) -> &str {
+        // UNSAFE: Only whole characters from valid strings are ever copied into the buffer
+        unsafe { str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+
+    /// Returns true if some of the text given to the name had to be dropped.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Deref for TaskName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Write for TaskName {
+    /// Text that doesn't fit is dropped rather than returning an error.
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.push_str(text);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TaskName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for TaskName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for TaskName {
+    fn eq(&self, other: &TaskName) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<'a> PartialEq<&'a str> for TaskName {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_task_name_exact_fit() {
+        let name = TaskName::new("0123456789abcdef");
+
+        assert_eq!(name.len(), TASK_NAME_CAPACITY);
+        assert_eq!(name, "0123456789abcdef");
+        assert_not!(name.is_truncated());
+    }
+
+    #[test]
+    fn test_task_name_truncates() {
+        let name = TaskName::new("0123456789abcdefghij");
+
+        assert_eq!(name, "0123456789abcdef");
+        assert!(name.is_truncated());
+    }
+
+    #[test]
+    fn test_task_name_truncates_on_char_boundary() {
+        // 'é' is two bytes long, and would straddle the end of the buffer
+        let name = TaskName::new("0123456789abcdeé");
+
+        assert_eq!(name, "0123456789abcde");
+        assert!(name.is_truncated());
+    }
+
+    #[test]
+    fn test_task_name_empty() {
+        let name = TaskName::new("");
+
+        assert!(name.is_empty());
+        assert_eq!(name, TaskName::empty());
+        assert_not!(name.is_truncated());
+    }
+
+    #[test]
+    fn test_task_name_formatted_at_runtime() {
+        let worker = 3;
+        let mut name = TaskName::empty();
+        write!(name, "worker-{}", worker).unwrap();
+
+        assert_eq!(name, "worker-3");
+    }
+}
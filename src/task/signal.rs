@@ -0,0 +1,189 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Software signals.
+//!
+//! A task can register a handler for any of `NUM_SIGNALS` numbered signals. Other tasks and
+//! interrupt handlers post signals to it through its `TaskHandle`, and the signals stay pending
+//! until the task reaches a safe point, where the handlers run in the task's own context. The safe
+//! points are returning from `sched_yield`, `sleep`, `sleep_for`, `wait_event_timeout`,
+//! `wait_notification` and `join`, and any call to `deliver_signals`. The kernel gives no meaning
+//! to the signal numbers, so they can be used for whatever the application needs, like asking a
+//! task to cancel what it's doing or to reload its configuration.
+//!
+//! Posting a signal doesn't wake a task that's blocked, the signal is delivered once it wakes up
+//! and reaches a safe point. Waiting on a `Mutex`, `CondVar` or `Semaphore` is NOT a safe point,
+//! so a task blocked on one of them won't see a signal (to cancel, say) until it gets the lock,
+//! is signaled or takes the semaphore, and then reaches a safe point or calls `deliver_signals`.
+//! Tasks that need to be cancellable shouldn't block indefinitely on those, a `wait_event_timeout`
+//! or `wait_notification` loop sees its signals every time it wakes up.
+//! A signal that's posted again before it's delivered is only delivered once, and signals that
+//! the task has no handler for are thrown away when they're delivered.
+//!
+//! Signals are only available with the `signals` feature, without it tasks don't carry the handler
+//! table and pending signal word.
+
+use sync::CriticalSection;
+use sched;
+
+/// The number of signals a task can have handlers for, signals are numbered from 0.
+pub const NUM_SIGNALS: usize = 8;
+
+/// A signal handler, it's passed the number of the signal being delivered.
+pub type SignalHandler = fn(usize);
+
+/// The error returned when using a signal number that isn't less than `NUM_SIGNALS`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InvalidSignal;
+
+/// Register `handler` to be run when the running task is delivered `signal`.
+///
+/// Registering a handler for a signal that already has one replaces the old handler.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::set_signal_handler;
+/// use altos_core::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+///
+/// const SIG_RELOAD: usize = 1;
+///
+/// static RELOAD: AtomicBool = ATOMIC_BOOL_INIT;
+///
+/// fn request_reload(_signal: usize) {
+///     RELOAD.store(true, Ordering::Relaxed);
+/// }
+///
+/// set_signal_handler(SIG_RELOAD, request_reload).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// Returns `Err(InvalidSignal)` if `signal` isn't less than `NUM_SIGNALS`.
+///
+/// # Panics
+///
+/// This panics if it's called before the scheduler has started.
+pub fn set_signal_handler(signal: usize, handler: SignalHandler) -> Result<(), InvalidSignal> {
+    replace_handler("set_signal_handler", signal, Some(handler))
+}
+
+/// Remove the running task's handler for `signal`, the signal is thrown away from now on.
+///
+/// # Errors
+///
+/// Returns `Err(InvalidSignal)` if `signal` isn't less than `NUM_SIGNALS`.
+///
+/// # Panics
+///
+/// This panics if it's called before the scheduler has started.
+pub fn clear_signal_handler(signal: usize) -> Result<(), InvalidSignal> {
+    replace_handler("clear_signal_handler", signal, None)
+}
+
+fn replace_handler(caller: &str, signal: usize, handler: Option<SignalHandler>)
+    -> Result<(), InvalidSignal> {
+
+    if signal >= NUM_SIGNALS {
+        return Err(InvalidSignal);
+    }
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so the current task can't change under us
+    match unsafe { sched::current_task() } {
+        Some(current) => current.set_signal_handler(signal, handler),
+        None => kernel_panic!("{} - current task doesn't exist!", caller),
+    }
+    Ok(())
+}
+
+/// Run the handlers for every signal pending on the running task.
+///
+/// Signals are delivered lowest number first. A task that goes a long time without reaching one of
+/// the kernel's safe points can call this to pick up its signals. Nothing is done if the
+/// scheduler hasn't been started.
+pub fn deliver_signals() {
+    loop {
+        let next = {
+            let _g = CriticalSection::begin();
+            // UNSAFE: We're in a critical section, so the current task can't change under us
+            match unsafe { sched::current_task() } {
+                Some(current) => current.take_signal(),
+                None => None,
+            }
+        };
+        match next {
+            // The handler runs outside of the critical section, it may make system calls
+            Some((signal, Some(handler))) => handler(signal),
+            Some((_, None)) => {},
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use syscall::sched_yield;
+    use sched::start_scheduler;
+    use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    static DELIVERED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn record_signal(signal: usize) {
+        DELIVERED.fetch_or(1 << signal, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_signals_delivered_at_safe_point() {
+        let _g = test::set_up();
+        DELIVERED.store(0, Ordering::SeqCst);
+        let (mut handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        assert_eq!(set_signal_handler(2, record_signal), Ok(()));
+        assert_eq!(set_signal_handler(5, record_signal), Ok(()));
+        assert_eq!(set_signal_handler(NUM_SIGNALS, record_signal), Err(InvalidSignal));
+        sched_yield();
+
+        // The signals stay pending until the first task runs again
+        assert_eq!(handle_1.signal(5), Ok(()));
+        assert_eq!(handle_1.signal(2), Ok(()));
+        assert_eq!(handle_1.signal(3), Ok(()));
+        assert_eq!(handle_1.signal(NUM_SIGNALS), Err(()));
+        assert_eq!(DELIVERED.load(Ordering::SeqCst), 0);
+
+        sched_yield();
+        assert_eq!(handle_1.tid(), Ok(test::current_task().unwrap().tid()));
+        // Signal 3 has no handler, so it's thrown away
+        assert_eq!(DELIVERED.load(Ordering::SeqCst), (1 << 2) | (1 << 5));
+    }
+
+    #[test]
+    fn test_cleared_handler_is_not_run() {
+        let _g = test::set_up();
+        DELIVERED.store(0, Ordering::SeqCst);
+        let (mut handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        set_signal_handler(0, record_signal).unwrap();
+        clear_signal_handler(0).unwrap();
+        handle_1.signal(0).unwrap();
+        deliver_signals();
+        assert_eq!(DELIVERED.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,320 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Consistent snapshots of the task list.
+
+use super::{TaskControl, TaskHandle, Priority, State};
+use sched;
+use sync::CriticalSection;
+use core::cell::{Cell, RefCell};
+
+/// A read-only copy of a task's state at the time a snapshot was taken.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TaskInfo {
+    /// The task's tid.
+    pub tid: usize,
+
+    /// The task's name.
+    pub name: &'static str,
+
+    /// The task's priority.
+    pub priority: Priority,
+
+    /// The task's state.
+    pub state: State,
+
+    /// The number of system ticks the task has been running for.
+    pub run_ticks: usize,
+
+    /// The size of the task's stack in bytes.
+    pub stack_size: usize,
+
+    /// The most bytes of its stack the task has used at once, see `TaskHandle::stack_usage`.
+    pub stack_usage: usize,
+}
+
+impl TaskInfo {
+    /// Create an empty `TaskInfo`, used to fill a buffer before taking a snapshot.
+    pub const fn empty() -> Self {
+        TaskInfo {
+            tid: 0,
+            name: "",
+            priority: Priority::__Idle,
+            state: State::Embryo,
+            run_ticks: 0,
+            stack_size: 0,
+            stack_usage: 0,
+        }
+    }
+
+    /// Return the share of the CPU the task has used, from 0 to 100, out of the ticks used by every
+    /// task in `snapshot`.
+    ///
+    /// The idle task is part of a full snapshot, so the percentages of all the tasks add up to
+    /// (about) 100, with the idle task's share being the time the CPU had nothing to do.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use altos_core::{with_task_snapshot, TaskInfo};
+    ///
+    /// let mut buffer = [TaskInfo::empty(); 16];
+    /// with_task_snapshot(&mut buffer, |tasks| {
+    ///   for task in tasks {
+    ///     let percent = task.cpu_percent(tasks);
+    ///     // Report the task's load...
+    ///   }
+    /// });
+    /// ```
+    pub fn cpu_percent(&self, snapshot: &[TaskInfo]) -> u8 {
+        let total = snapshot.iter().fold(0usize, |total, task| total.saturating_add(task.run_ticks));
+        if total == 0 {
+            return 0;
+        }
+        // Widened so large tick counts don't overflow, rounded to the nearest percent
+        ((self.run_ticks as u64 * 100 + total as u64 / 2) / total as u64) as u8
+    }
+
+    fn from(task: &TaskControl) -> Self {
+        TaskInfo {
+            tid: task.tid(),
+            name: task.name(),
+            priority: task.priority(),
+            state: task.state(),
+            run_ticks: task.run_ticks(),
+            stack_size: task.stack_bounds().1 - task.stack_bounds().0,
+            stack_usage: task.stack_usage(),
+        }
+    }
+}
+
+/// Take a snapshot of every task in the system and pass it to `block`.
+///
+/// The snapshot is taken inside of a critical section, so no task can change state while it's
+/// being copied, but the critical section ends before `block` is run so the time spent with
+/// interrupts disabled doesn't depend on what `block` does with it. The snapshot is written into
+/// `buffer`, so no memory is allocated. If there are more tasks than fit in the buffer the extra
+/// tasks are left out of the snapshot. Tasks that have been destroyed are not included.
+///
+/// Finding each task's stack usage takes a scan of its stack, so the critical section lasts longer
+/// the more stack the tasks have. This is meant for debug consoles and health monitors that list
+/// the tasks every so often, not for anything that needs to run often.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::{with_task_snapshot, TaskInfo};
+///
+/// let mut buffer = [TaskInfo::empty(); 16];
+/// with_task_snapshot(&mut buffer, |tasks| {
+///   for task in tasks {
+///     // Print a line like `ps` would...
+///     let line = (task.tid, task.name, task.state, task.priority, task.stack_usage, task.stack_size);
+///   }
+/// });
+/// ```
+pub fn with_task_snapshot<F, R>(buffer: &mut [TaskInfo], block: F) -> R
+    where F: FnOnce(&[TaskInfo]) -> R {
+
+    let len = {
+        let buffer = RefCell::new(&mut *buffer);
+        let len = Cell::new(0);
+        let record = |task: &mut TaskControl| {
+            let mut buffer = buffer.borrow_mut();
+            if !task.is_destroyed() && len.get() < buffer.len() {
+                buffer[len.get()] = TaskInfo::from(task);
+                len.set(len.get() + 1);
+            }
+        };
+
+        let _g = CriticalSection::begin();
+        // UNSAFE: Accessing the current task, we're in a critical section so it can't change
+        if let Some(current) = unsafe { sched::current_task() } {
+            record(current);
+        }
+        sched::for_each_task(&record);
+        len.get()
+    };
+
+    block(&buffer[..len])
+}
+
+/// Return a handle to the task named `name`, or `None` if there isn't one.
+///
+/// Both static names and names stored in the task's control block (see `TaskBuilder::task_name`)
+/// are matched, and only exact matches count. Names aren't required to be unique, if more than one
+/// task has the name then which of them is returned isn't specified. Tasks that have been destroyed
+/// aren't found. Every task is compared inside of a critical section, so this is meant for debug
+/// consoles and supervisors rather than anything that needs to run often.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::find_task;
+///
+/// if let Some(mut uart_rx) = find_task("uart_rx") {
+///     uart_rx.restart().ok();
+/// }
+/// ```
+pub fn find_task(name: &str) -> Option<TaskHandle> {
+    let found = Cell::new(None);
+    let check = |task: &mut TaskControl| {
+        if found.get().is_none() && !task.is_destroyed() && task.is_named(name) {
+            found.set(Some(TaskHandle::new(task)));
+        }
+    };
+
+    let _g = CriticalSection::begin();
+    // UNSAFE: Accessing the current task, we're in a critical section so it can't change
+    if let Some(current) = unsafe { sched::current_task() } {
+        check(current);
+    }
+    sched::for_each_task(&check);
+    found.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::Delay;
+    use sched::{start_scheduler, switch_context};
+    use test;
+
+    fn count(tasks: &[TaskInfo], tid: usize) -> usize {
+        tasks.iter().filter(|task| task.tid == tid).count()
+    }
+
+    #[test]
+    fn test_snapshot_between_state_transitions() {
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+        let mut buffer = [TaskInfo::empty(); 8];
+
+        // Task 1 has blocked, but hasn't been switched out yet
+        test::block_current_task(Delay::Sleep);
+        with_task_snapshot(&mut buffer, |tasks| {
+            // Both test tasks and the idle task, each exactly once
+            assert_eq!(tasks.len(), 3);
+            assert_eq!(count(tasks, handle_1.tid().unwrap()), 1);
+            assert_eq!(count(tasks, handle_2.tid().unwrap()), 1);
+            assert_eq!(tasks[0].tid, handle_1.tid().unwrap());
+            assert_eq!(tasks[0].state, State::Blocked);
+        });
+
+        switch_context();
+        with_task_snapshot(&mut buffer, |tasks| {
+            assert_eq!(tasks.len(), 3);
+            assert_eq!(count(tasks, handle_1.tid().unwrap()), 1);
+            assert_eq!(count(tasks, handle_2.tid().unwrap()), 1);
+            assert_eq!(tasks.iter().filter(|task| task.state == State::Running).count(), 1);
+            assert_eq!(tasks[0].tid, handle_2.tid().unwrap());
+            assert_eq!(tasks[0].name, "test task 2");
+        });
+    }
+
+    #[test]
+    fn test_cpu_usage_charged_to_running_task() {
+        use syscall;
+        use task::cpu_usage;
+
+        let _g = test::set_up();
+        let (handle_1, handle_2) = test::create_two_tasks();
+        start_scheduler();
+
+        for _ in 0..3 {
+            syscall::system_tick();
+        }
+        test::block_current_task(Delay::Sleep);
+        switch_context();
+        syscall::system_tick();
+
+        assert_eq!(cpu_usage(&handle_1), Some(3));
+        assert_eq!(cpu_usage(&handle_2), Some(1));
+        let mut buffer = [TaskInfo::empty(); 8];
+        with_task_snapshot(&mut buffer, |tasks| {
+            let task_1 = tasks.iter().find(|task| task.tid == handle_1.tid().unwrap()).unwrap();
+            assert_eq!(task_1.run_ticks, 3);
+            assert_eq!(task_1.cpu_percent(tasks), 75);
+        });
+    }
+
+    #[test]
+    fn test_snapshot_includes_stack_usage() {
+        let _g = test::set_up();
+        let handle = test::create_and_schedule_test_task(512, Priority::Normal, "stack user");
+        let mut buffer = [TaskInfo::empty(); 8];
+
+        with_task_snapshot(&mut buffer, |tasks| {
+            let task = tasks.iter().find(|task| task.tid == handle.tid().unwrap()).unwrap();
+            assert_eq!(task.stack_size, 512);
+            assert_eq!(Ok(task.stack_usage), handle.stack_usage());
+            assert!(task.stack_usage > 0);
+        });
+    }
+
+    #[test]
+    fn test_snapshot_is_limited_to_buffer_size() {
+        let _g = test::set_up();
+        test::create_two_tasks();
+        start_scheduler();
+        let mut buffer = [TaskInfo::empty(); 2];
+
+        let len = with_task_snapshot(&mut buffer, |tasks| tasks.len());
+        assert_eq!(len, 2);
+    }
+
+    #[test]
+    fn test_snapshot_skips_destroyed_tasks() {
+        let _g = test::set_up();
+        let (mut handle_1, _handle_2) = test::create_two_tasks();
+        start_scheduler();
+        let tid = handle_1.tid().unwrap();
+        handle_1.destroy();
+        let mut buffer = [TaskInfo::empty(); 8];
+
+        with_task_snapshot(&mut buffer, |tasks| {
+            assert_eq!(tasks.len(), 2);
+            assert_eq!(count(tasks, tid), 0);
+        });
+    }
+
+    #[test]
+    fn test_find_task_by_name() {
+        use task::{Priority, TaskName, TaskBuilder};
+        use task::args::Args;
+        fn uart_rx(_args: &mut Args) {}
+
+        let _g = test::set_up();
+        let (handle_1, mut handle_2) = test::create_two_tasks();
+        let long = test::create_and_schedule_test_task(512, Priority::Normal,
+                                                       "a name longer than a TaskName holds");
+        let inline = TaskBuilder::new(uart_rx).task_name(TaskName::new("uart_rx")).spawn().unwrap();
+        start_scheduler();
+
+        // The running task is found as well as the ones waiting to run
+        assert_eq!(find_task("test task 1").map(|task| task.tid()), Some(handle_1.tid()));
+        assert_eq!(find_task("test task 2").map(|task| task.tid()), Some(handle_2.tid()));
+        assert_eq!(find_task("a name longer than a TaskName holds").map(|task| task.tid()),
+                   Some(long.tid()));
+        assert_eq!(find_task("uart_rx").map(|task| task.tid()), Some(inline.tid()));
+
+        assert!(find_task("test task").is_none());
+        handle_2.destroy();
+        assert!(find_task("test task 2").is_none());
+    }
+}
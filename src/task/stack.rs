@@ -15,11 +15,28 @@
 * along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
-use volatile::Volatile;
+use core::slice;
 use super::args::Args;
+use super::SpawnError;
+use super::arena;
 use alloc::{self, heap};
-use alloc::boxed::Box;
 use arch;
+use core::ptr;
+
+// Written to the bottom of every stack, a task that runs off the end of its stack overwrites it.
+const CANARY: usize = 0xC0FFEE5A;
+const CANARY_WORDS: usize = 2;
+// The rest of a new stack is filled with this, so the deepest the task has reached can be found
+const FILL: usize = 0xA5A5A5A5;
+
+// Where the memory backing a stack came from, and so where it goes back to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Backing {
+    Heap,
+    Arena,
+    // Handed over by the caller for good, it's never given back
+    Static,
+}
 
 #[repr(C)]
 #[derive(Debug)]
@@ -27,10 +44,28 @@ pub struct Stack {
     ptr: *const usize,
     base: *const usize,
     depth: usize,
+    backing: Backing,
 }
 
 impl Stack {
-    pub fn new(depth: usize) -> Self {
+    /// Allocate a stack of `depth` bytes.
+    ///
+    /// If a stack arena has been set up the stack takes a whole slot from it, otherwise it's
+    /// allocated from the heap. Returns `Err(SpawnError::TooManyTasks)` if the arena is full, or
+    /// `Err(SpawnError::StackTooLarge)` if `depth` won't fit in one of its slots.
+    pub fn new(depth: usize) -> Result<Self, SpawnError> {
+        if let Some((base, size)) = arena::allocate(depth)? {
+            let mut stack = Stack {
+                ptr: (base + size) as *const usize,
+                base: base as *const usize,
+                depth: size,
+                backing: Backing::Arena,
+            };
+            stack.write_canary();
+            stack.fill(base + size);
+            return Ok(stack);
+        }
+
         let align = ::core::mem::align_of::<u8>();
         // UNSAFE: We're touching the allocation interface, but the stack keeps track of any memory
         // that gets allocated, when the stack is dropped it will free the memory.
@@ -38,71 +73,418 @@ impl Stack {
         if ptr.is_null() {
             alloc::oom();
         }
+        #[cfg(feature="stats")]
+        ::stats::record_alloc(depth);
 
-        Stack {
+        let mut stack = Stack {
             // UNSAFE: We've allocated 'depth' size already successfuly, so this offset must
             // be within bounds.
             ptr: unsafe { ptr.offset(depth as isize) } as *const usize,
             base: ptr as *const usize,
             depth: depth,
-        }
+            backing: Backing::Heap,
+        };
+        stack.write_canary();
+        stack.fill(ptr as usize + depth);
+        Ok(stack)
     }
 
-    pub fn initialize(&mut self, code: fn(&mut Args), args: &Box<Args>) {
-        // UNSAFE: We're creating a volatile pointer to our stack, but we know that it must be
-        // valid if the object was successfully created.
-        unsafe {
-            let stack_ptr = self.ptr();
-            self.ptr = arch::initialize_stack(stack_ptr, code, args) as *const usize;
+    /// Use `memory` as the stack, nothing is allocated.
+    ///
+    /// The memory is never given back, even once the task using it is gone.
+    pub fn from_static(memory: &'static mut [u8]) -> Self {
+        let base = memory.as_mut_ptr() as usize;
+        let depth = memory.len();
+        let mut stack = Stack {
+            ptr: (base + depth) as *const usize,
+            base: base as *const usize,
+            depth: depth,
+            backing: Backing::Static,
+        };
+        stack.write_canary();
+        stack.fill(base + depth);
+        stack
+    }
+
+    /// Write the task's initial frame to the top of the stack, `arg` is passed to `code` when the
+    /// task starts.
+    ///
+    /// Returns `Err(SpawnError::StackTooSmall)` without touching the stack if the frame wouldn't
+    /// fit.
+    pub fn initialize(&mut self, code: fn(&mut Args), arg: usize) -> Result<(), SpawnError> {
+        let word = ::core::mem::size_of::<usize>();
+        let top = frame_top(self.base as usize, self.ptr as usize)?;
+        // The frame mustn't be written over the canary
+        let words = ((top - self.base as usize) / word).saturating_sub(CANARY_WORDS);
+        // UNSAFE: The `words` whole words below `top` all lie between the base of the stack and its
+        // top, and `top` is word aligned, so they make up a valid slice of our own memory.
+        let stack = unsafe { slice::from_raw_parts_mut((top - words * word) as *mut usize, words) };
+        match arch::initialize_stack(stack, code, arg) {
+            Some(ptr) => {
+                self.ptr = ptr as *const usize;
+                Ok(())
+            },
+            None => Err(SpawnError::StackTooSmall),
         }
     }
 
+    /// Throw away everything on the stack and write a new initial frame, as if the stack had just
+    /// been created.
+    pub fn reset(&mut self, code: fn(&mut Args), arg: usize) -> Result<(), SpawnError> {
+        let top = self.base as usize + self.depth;
+        self.ptr = top as *const usize;
+        self.write_canary();
+        self.fill(top);
+        self.initialize(code, arg)
+    }
+
     pub fn check_overflow(&self) -> bool {
         self.ptr <= self.base
     }
 
-    pub fn depth(&self) -> usize { self.depth }
+    /// Returns true if the canary at the bottom of the stack hasn't been written over.
+    ///
+    /// A task whose stack pointer has come back up after running off the end of its stack isn't
+    /// caught by `check_overflow`, but it will have left the canary overwritten.
+    pub fn is_canary_intact(&self) -> bool {
+        let word = ::core::mem::size_of::<usize>();
+        if self.depth < CANARY_WORDS * word {
+            return true;
+        }
+        // UNSAFE: The canary words lie within the memory backing the stack. The base is only byte
+        // aligned, so they're read unaligned.
+        (0..CANARY_WORDS).all(|i| unsafe {
+            ptr::read_unaligned((self.base as usize + i * word) as *const usize) == CANARY
+        })
+    }
 
-    unsafe fn ptr(&self) -> Volatile<usize> {
-        Volatile::new(self.ptr)
+    /// The most bytes of the stack that have been in use at once, as far as can be told.
+    ///
+    /// The stack is scanned up from the bottom for the first word that isn't the fill pattern, so
+    /// this is only wrong if the task happened to write the pattern itself at the deepest point it
+    /// reached. It's counted from the top of the stack, including the task's initial frame.
+    pub fn peak_usage(&self) -> usize {
+        let word = ::core::mem::size_of::<usize>();
+        let (bottom, top) = self.bounds();
+        let mut addr = bottom + CANARY_WORDS * word;
+        // UNSAFE: Every word read lies between the canary and the top of the stack
+        while addr + word <= top && unsafe { ptr::read_unaligned(addr as *const usize) } == FILL {
+            addr += word;
+        }
+        top.saturating_sub(addr)
     }
-}
 
-impl Drop for Stack {
-    fn drop(&mut self) {
+    // Fill the stack from just above the canary up to `end` with the fill pattern.
+    fn fill(&mut self, end: usize) {
+        let word = ::core::mem::size_of::<usize>();
+        let mut addr = self.base as usize + CANARY_WORDS * word;
+        while addr + word <= end {
+            // UNSAFE: Every word written lies between the canary and `end`, which is at most the
+            // top of the stack
+            unsafe { ptr::write_unaligned(addr as *mut usize, FILL) };
+            addr += word;
+        }
+    }
+
+    // Write the canary to the bottom of the stack.
+    fn write_canary(&mut self) {
+        let word = ::core::mem::size_of::<usize>();
+        if self.depth < CANARY_WORDS * word {
+            return;
+        }
+        for i in 0..CANARY_WORDS {
+            // UNSAFE: Same as in `is_canary_intact`
+            unsafe { ptr::write_unaligned((self.base as usize + i * word) as *mut usize, CANARY) };
+        }
+    }
+
+    /// Grow the stack after it has run into its guard region.
+    ///
+    /// A new buffer twice the size of the current one is allocated, the used portion of the stack
+    /// (everything above `sp`) is copied to the top of it and the old buffer is freed. Returns the
+    /// relocated stack pointer, which must be loaded into the PSP before the task resumes.
+    ///
+    /// Any pointers into the old stack (references to locals, saved frame pointers) are NOT fixed
+    /// up, so this is only safe for tasks that don't hold onto addresses of their own stack
+    /// across the point where the stack grows.
+    ///
+    /// Arena slots all have the same size, so a stack that came from the arena moves to the heap
+    /// and gives its slot back. A stack in static memory moves to the heap too.
+    #[cfg(feature="growable_stacks")]
+    pub fn grow(&mut self, sp: usize) -> usize {
+        let align = ::core::mem::align_of::<u8>();
+        let new_depth = self.depth * 2;
+        // UNSAFE: Same as in `new`, the stack keeps track of the new memory and will free it when
+        // it gets dropped.
+        let new_base = unsafe { heap::allocate(new_depth, align) };
+        if new_base.is_null() {
+            alloc::oom();
+        }
+        #[cfg(feature="stats")]
+        ::stats::record_alloc(new_depth);
+
+        // UNSAFE: `sp` is within the old stack, and the new buffer is larger than the old one, so
+        // the used portion must fit. The old buffer is freed only after everything's been copied.
+        let new_sp = unsafe {
+            relocate(self.base as *const u8, self.depth, sp, new_base, new_depth)
+        };
+        self.free();
+
+        self.base = new_base as *const usize;
+        self.depth = new_depth;
+        self.backing = Backing::Heap;
+        self.ptr = new_sp as *const usize;
+        self.write_canary();
+        self.fill(new_sp);
+        new_sp
+    }
+
+    /// Check that the saved stack pointer points at a whole frame inside of the stack.
+    ///
+    /// The pointer must be word aligned and leave room for the `MIN_STACK_WORDS` words that get
+    /// restored when the task is switched back in.
+    pub fn is_ptr_valid(&self) -> bool {
+        let word = ::core::mem::size_of::<usize>();
+        let (bottom, top) = self.bounds();
+        let ptr = self.ptr as usize;
+        ptr % word == 0 && ptr >= bottom && ptr <= top &&
+            (top - ptr) / word >= arch::MIN_STACK_WORDS
+    }
+
+    /// The lowest and highest addresses of the memory backing the stack.
+    pub fn bounds(&self) -> (usize, usize) {
+        (self.base as usize, self.base as usize + self.depth)
+    }
+
+    pub fn ptr(&self) -> usize { self.ptr as usize }
+
+    pub fn depth(&self) -> usize { self.depth }
+
+    // Give the memory backing the stack back to wherever it came from.
+    fn free(&mut self) {
+        match self.backing {
+            Backing::Arena => {
+                arena::release(self.base as usize);
+                return;
+            },
+            Backing::Static => return,
+            Backing::Heap => {},
+        }
+
         let align = ::core::mem::align_of::<u8>();
         // UNSAFE: We're touching the allocation interface again, but we know this is the exact
         // size and location of the block of memory that we allocated.
         unsafe {
             heap::deallocate(self.base as *mut _, self.depth, align);
         }
+        #[cfg(feature="stats")]
+        ::stats::record_free(self.depth);
+    }
+}
+
+// Find where the initial frame should start for a stack spanning `base` to `top`.
+//
+// The top is rounded down to a word boundary, then there must be room for `MIN_STACK_WORDS` words
+// below it, otherwise the frame would be written past the bottom of the stack.
+fn frame_top(base: usize, top: usize) -> Result<usize, SpawnError> {
+    let word = ::core::mem::size_of::<usize>();
+    let top = top & !(word - 1);
+    if top < base || (top - base) / word < arch::MIN_STACK_WORDS {
+        return Err(SpawnError::StackTooSmall);
+    }
+    Ok(top)
+}
+
+// Copy the used portion of a stack into a new buffer, returning the relocated stack pointer.
+//
+// Stacks grow downwards, so the used portion is the `top - sp` bytes at the top of the old buffer,
+// these get copied to the top of the new buffer.
+#[cfg(feature="growable_stacks")]
+unsafe fn relocate(old_base: *const u8, old_depth: usize, sp: usize, new_base: *mut u8, new_depth: usize)
+    -> usize {
+
+    let used = old_base as usize + old_depth - sp;
+    debug_assert!(used <= new_depth, "relocate - new stack is too small to hold the old one!");
+    let new_sp = new_base as usize + new_depth - used;
+    ptr::copy_nonoverlapping(sp as *const u8, new_sp as *mut u8, used);
+    new_sp
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        self.free();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test;
+    use alloc::boxed::Box;
 
     #[test]
     fn test_stack_allocates_correct_size() {
-        let stack = Stack::new(1024);
+        let _g = test::set_up();
+        let stack = Stack::new(1024).unwrap();
         let size = stack.ptr as usize - stack.base as usize;
 
         assert_eq!(size, stack.depth);
     }
 
+    #[test]
+    fn test_frame_top_rejects_one_word_too_small() {
+        let word = ::core::mem::size_of::<usize>();
+        let buffer = [0usize; arch::MIN_STACK_WORDS];
+        let base = buffer.as_ptr() as usize;
+
+        assert_eq!(frame_top(base, base + (arch::MIN_STACK_WORDS - 1) * word),
+                   Err(SpawnError::StackTooSmall));
+        assert_eq!(frame_top(base, base + arch::MIN_STACK_WORDS * word),
+                   Ok(base + arch::MIN_STACK_WORDS * word));
+    }
+
+    #[test]
+    fn test_frame_top_aligns_top_down() {
+        let word = ::core::mem::size_of::<usize>();
+        let buffer = [0usize; arch::MIN_STACK_WORDS + 1];
+        let base = buffer.as_ptr() as usize;
+        let top = base + arch::MIN_STACK_WORDS * word + 1;
+
+        assert_eq!(frame_top(base, top), Ok(base + arch::MIN_STACK_WORDS * word));
+        assert_eq!(frame_top(base, top - 2), Err(SpawnError::StackTooSmall));
+    }
+
+    #[test]
+    fn test_initialized_stack_ptr_is_valid() {
+        let _g = test::set_up();
+        fn test_task(_args: &mut Args) {}
+        let mut stack = Stack::new(512).unwrap();
+        let args = Box::new(Args::empty());
+        stack.initialize(test_task, &*args as *const _ as usize).unwrap();
+
+        assert!(stack.is_ptr_valid());
+    }
+
+    #[test]
+    fn test_stack_ptr_out_of_bounds_is_invalid() {
+        let _g = test::set_up();
+        let word = ::core::mem::size_of::<usize>();
+        let mut stack = Stack::new(512).unwrap();
+        let (bottom, top) = stack.bounds();
+
+        stack.ptr = (bottom - word) as *const usize;
+        assert_not!(stack.is_ptr_valid());
+        stack.ptr = (top + word) as *const usize;
+        assert_not!(stack.is_ptr_valid());
+        // Not enough room above the pointer for a saved frame
+        stack.ptr = (top - word) as *const usize;
+        assert_not!(stack.is_ptr_valid());
+        stack.ptr = (bottom + 1) as *const usize;
+        assert_not!(stack.is_ptr_valid());
+        stack.ptr = bottom as *const usize;
+        assert!(stack.is_ptr_valid());
+    }
+
+    #[test]
+    fn test_initialize_too_small_stack_fails() {
+        let _g = test::set_up();
+        fn test_task(_args: &mut Args) {}
+        let word = ::core::mem::size_of::<usize>();
+        let mut stack = Stack::new((arch::MIN_STACK_WORDS - 1) * word).unwrap();
+        let top = stack.ptr;
+
+        let args = Box::new(Args::empty());
+        assert_eq!(stack.initialize(test_task, &*args as *const _ as usize),
+                   Err(SpawnError::StackTooSmall));
+        assert_eq!(stack.ptr, top);
+    }
+
     #[test]
     fn test_check_stack_overflow_no_overflow() {
-        let stack = Stack::new(1024);
+        let _g = test::set_up();
+        let stack = Stack::new(1024).unwrap();
 
         assert_not!(stack.check_overflow());
     }
 
     #[test]
     fn test_check_stack_overflow_yes_overflow() {
-        let mut stack = Stack::new(1024);
+        let _g = test::set_up();
+        let mut stack = Stack::new(1024).unwrap();
         stack.ptr = unsafe { stack.ptr.offset(-1025) };
 
         assert!(stack.check_overflow());
     }
+
+    #[test]
+    fn test_canary_catches_overflow_that_came_back() {
+        let _g = test::set_up();
+        let mut stack = Stack::new(1024).unwrap();
+        assert!(stack.is_canary_intact());
+
+        // The task ran off the bottom of its stack, then returned back up into it
+        unsafe { *(stack.base as *mut usize) = 0 };
+        stack.ptr = unsafe { stack.ptr.offset(-2) };
+        assert_not!(stack.check_overflow());
+        assert_not!(stack.is_canary_intact());
+    }
+
+    #[test]
+    fn test_peak_usage_finds_deepest_write() {
+        let _g = test::set_up();
+        fn test_task(_args: &mut Args) {}
+        let word = ::core::mem::size_of::<usize>();
+        let mut stack = Stack::new(512).unwrap();
+        assert_eq!(stack.peak_usage(), 0);
+
+        let args = Box::new(Args::empty());
+        stack.initialize(test_task, &*args as *const _ as usize).unwrap();
+        assert_eq!(stack.peak_usage(), arch::MIN_STACK_WORDS * word);
+
+        // The task used more of its stack, then returned back up
+        let (_, top) = stack.bounds();
+        unsafe { *((top - 40 * word) as *mut usize) = 0 };
+        assert_eq!(stack.peak_usage(), 40 * word);
+    }
+
+    #[test]
+    #[cfg(feature="growable_stacks")]
+    fn test_relocate_copies_used_portion_to_top_of_new_buffer() {
+        let old: [usize; 4] = [0, 0, 0xAA, 0xBB];
+        let mut new: [usize; 8] = [0; 8];
+        let word = ::core::mem::size_of::<usize>();
+
+        let old_base = old.as_ptr() as *const u8;
+        let new_base = new.as_mut_ptr() as *mut u8;
+        let sp = old_base as usize + 2 * word;
+
+        let new_sp = unsafe { relocate(old_base, 4 * word, sp, new_base, 8 * word) };
+
+        assert_eq!(new_sp, new_base as usize + 6 * word);
+        assert_eq!(new, [0, 0, 0, 0, 0, 0, 0xAA, 0xBB]);
+    }
+
+    #[test]
+    #[cfg(feature="growable_stacks")]
+    fn test_grow_stack_keeps_contents() {
+        let _g = test::set_up();
+        let mut stack = Stack::new(64).unwrap();
+        let word = ::core::mem::size_of::<usize>();
+        let sp = unsafe {
+            let sp = stack.ptr.offset(-2) as *mut usize;
+            *sp = 0x1234;
+            *sp.offset(1) = 0x5678;
+            sp as usize
+        };
+
+        let new_sp = stack.grow(sp);
+
+        assert_eq!(stack.depth(), 128);
+        assert_eq!(stack.base as usize + 128 - 2 * word, new_sp);
+        unsafe {
+            assert_eq!(*(new_sp as *const usize), 0x1234);
+            assert_eq!(*(new_sp as *const usize).offset(1), 0x5678);
+        }
+        assert_not!(stack.check_overflow());
+    }
 }
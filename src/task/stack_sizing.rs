@@ -0,0 +1,169 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Stack sizing diagnostics.
+//!
+//! With the `stack_sizing` feature enabled every task's stack can be made generously larger than
+//! the size it was spawned with (see `set_stack_scale`), and when the task ends the deepest its
+//! stack went is reported to a hook, along with the size it asked for. Running the application
+//! like this for a while turns guessed stack sizes into measured ones. If a task used more than a
+//! set share of the size it asked for a `KernelEvent::StackUsageHigh` warning is also logged, when
+//! the `logger` feature is enabled.
+//!
+//! Only stacks allocated by the kernel are scaled, a static task keeps the stack it was given. The
+//! peak is found by scanning for the deepest word the task wrote to, so it's as accurate as
+//! `TaskHandle::stack_usage`.
+
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use hook::Hook;
+use super::TaskControl;
+
+// The factor every allocated stack is scaled by, 0 is the same as 1
+static STACK_SCALE: AtomicUsize = ATOMIC_USIZE_INIT;
+// The share of its requested stack a task may use before a warning is logged, 0 if never
+static WARN_PERCENT: AtomicUsize = ATOMIC_USIZE_INIT;
+static REPORT_HOOK: Hook<fn(&StackReport)> = Hook::new();
+
+/// How much of its stack a task used over its lifetime.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StackReport {
+    /// The tid of the task.
+    pub tid: usize,
+    /// The task's name.
+    pub name: &'static str,
+    /// The stack size in bytes the task was spawned with.
+    pub requested: usize,
+    /// The stack size in bytes the task was actually given.
+    pub allocated: usize,
+    /// The most bytes of the stack the task had in use at once, including its initial frame.
+    pub peak: usize,
+}
+
+/// Give every task spawned from now on a stack `scale` times the size it asks for.
+///
+/// The default is 1, so stacks are the size they're spawned with. A scale of 0 is taken as 1.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::stack_sizing::{self, StackReport};
+///
+/// fn print_usage(report: &StackReport) {
+///     // Write out "`report.name` used `report.peak` of `report.requested` bytes"...
+/// }
+///
+/// stack_sizing::set_stack_scale(4);
+/// stack_sizing::set_warning_threshold(75);
+/// stack_sizing::set_report_hook(print_usage);
+/// ```
+pub fn set_stack_scale(scale: usize) {
+    STACK_SCALE.store(scale, Ordering::Relaxed);
+}
+
+/// Log a warning when a task ends having used more than `percent` percent of the stack it asked
+/// for, 0 turns the warning off.
+///
+/// The warning is only logged when the `logger` feature is enabled.
+pub fn set_warning_threshold(percent: u8) {
+    WARN_PERCENT.store(percent as usize, Ordering::Relaxed);
+}
+
+/// Set a function to be called with the stack usage of every task when it ends.
+///
+/// The hook is called from wherever the task is deleted, possibly from an interrupt handler or
+/// with interrupts disabled, so it must not block. Setting a new hook replaces the old one.
+pub fn set_report_hook(hook: fn(&StackReport)) {
+    REPORT_HOOK.set(hook);
+}
+
+/// Stop reporting stack usage.
+pub fn clear_report_hook() {
+    REPORT_HOOK.clear();
+}
+
+// The number of bytes to allocate for a stack that's asked to be `depth` bytes.
+#[doc(hidden)]
+pub fn scaled_depth(depth: usize) -> usize {
+    match STACK_SCALE.load(Ordering::Relaxed) {
+        0 => depth,
+        scale => depth.saturating_mul(scale),
+    }
+}
+
+// Report how much of its stack `task` used, called when the task is deleted.
+#[doc(hidden)]
+pub fn report_stack_usage(task: &TaskControl) {
+    let (bottom, top) = task.stack_bounds();
+    let report = StackReport {
+        tid: task.tid(),
+        name: task.name(),
+        requested: task.requested_stack_depth(),
+        allocated: top - bottom,
+        peak: task.stack_usage(),
+    };
+
+    let percent = WARN_PERCENT.load(Ordering::Relaxed);
+    if percent != 0 && report.peak.saturating_mul(100) > report.requested.saturating_mul(percent) {
+        #[cfg(feature="logger")]
+        ::logger::warn(::logger::KernelEvent::StackUsageHigh {
+            tid: report.tid,
+            peak: report.peak,
+            requested: report.requested,
+        });
+    }
+
+    if let Some(hook) = REPORT_HOOK.get() {
+        hook(&report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use task::Priority;
+    use sync::SpinMutex;
+
+    static REPORTED: SpinMutex<Option<StackReport>> = SpinMutex::new(None);
+
+    fn record_report(report: &StackReport) {
+        *REPORTED.lock() = Some(*report);
+    }
+
+    #[test]
+    fn test_task_gets_scaled_stack_and_reports_usage_on_exit() {
+        let _g = test::set_up();
+        *REPORTED.lock() = None;
+        set_stack_scale(4);
+        set_report_hook(record_report);
+
+        let mut handle = test::create_and_schedule_test_task(512, Priority::Normal, "generous");
+        let tid = handle.tid().unwrap();
+        let (bottom, top) = test::convert_handle_to_task_control(handle).stack_bounds();
+        assert_eq!(top - bottom, 2048);
+        let peak = handle.stack_usage().unwrap();
+        assert!(handle.destroy());
+
+        assert_eq!(*REPORTED.lock(), Some(StackReport {
+            tid: tid,
+            name: "generous",
+            requested: 512,
+            allocated: 2048,
+            peak: peak,
+        }));
+    }
+}
@@ -0,0 +1,77 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Tasks that live in static memory.
+//!
+//! Normally a task's control block and stack are allocated from the heap (or its stack from a
+//! stack arena). A static task is instead given a `StaticTask` for its control block and a buffer
+//! for its stack by whoever creates it, so a system that only creates static tasks never touches
+//! the allocator. Both are handed over for good, and are never reused once the task is gone.
+
+use collections::{Node, NodeBox};
+use core::ptr;
+use super::TaskControl;
+use super::args::Args;
+
+/// Static memory for a task's control block, see `syscall::new_static_task`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::StaticTask;
+///
+/// static mut BLINK_TASK: StaticTask = StaticTask::new();
+/// ```
+pub struct StaticTask {
+    node: Option<Node<TaskControl>>,
+    args: Option<Args>,
+}
+
+impl StaticTask {
+    /// Create the memory for a task, this is meant to be used to initialize a `static mut`.
+    pub const fn new() -> Self {
+        StaticTask {
+            node: None,
+            args: None,
+        }
+    }
+
+    // Store the arguments for the task, returning the address the task is handed.
+    #[doc(hidden)]
+    pub fn store_args(&mut self, args: Args) -> usize {
+        self.args = Some(args);
+        match self.args {
+            Some(ref args) => args as *const Args as usize,
+            None => unreachable!(),
+        }
+    }
+
+    // Move `task` into the static memory, returning a node the scheduler can keep it in.
+    #[doc(hidden)]
+    pub fn store(&'static mut self, task: TaskControl) -> NodeBox<TaskControl> {
+        debug_assert!(task.is_static(), "StaticTask::store - task wasn't created as a static task!");
+        // UNSAFE: Whatever was stored here before belonged to a task that's already been dropped in
+        // place, so it's written over without being dropped again
+        unsafe { ptr::write(&mut self.node, Some(Node::new(task))) };
+        match self.node {
+            // UNSAFE: The node is in static memory that nothing else reaches once it's handed out,
+            // the `NodeBox` drops it in place and never gives it to the heap
+            Some(ref mut node) => unsafe { NodeBox::from_static(node) },
+            None => unreachable!(),
+        }
+    }
+}
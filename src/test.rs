@@ -23,8 +23,10 @@ macro_rules! assert_not {
     ($cond:expr, $($arg:tt)+) => { assert!(!$cond $(, $arg)+); }
 }
 
-use sched::{CURRENT_TASK, SLEEP_QUEUE, DELAY_QUEUE,
-            OVERFLOW_DELAY_QUEUE, PRIORITY_QUEUES, NORMAL_TASK_COUNTER};
+use sched::{CURRENT_TASK, SLEEP_QUEUE, DELAY_WHEEL, SUSPENDED_QUEUE, PRIORITY_QUEUES};
+use sched::NORMAL_TASK_COUNTER;
+use sched::{self, Policy};
+use power::{self, LowPowerMode};
 
 use sync::{SpinMutex, SpinGuard};
 use task::{Priority, TaskControl, TaskHandle, Delay};
@@ -36,13 +38,66 @@ static TEST_LOCK: SpinMutex<()> = SpinMutex::new(());
 pub fn set_up() -> SpinGuard<'static, ()> {
     let guard = TEST_LOCK.lock();
     SLEEP_QUEUE.remove_all();
-    DELAY_QUEUE.remove_all();
-    OVERFLOW_DELAY_QUEUE.remove_all();
+    DELAY_WHEEL.remove_all();
+    SUSPENDED_QUEUE.remove_all();
     NORMAL_TASK_COUNTER.store(0, Ordering::Relaxed);
+    sched::set_intra_priority_policy(Policy::Fifo);
+    sched::set_time_slice(1);
+    sched::set_cooperative(false);
+    sched::reset_scheduler_suspension();
+    sched::edf::clear_deadline_miss_hook();
+    sched::clear_idle_hooks();
+    sched::clear_stack_overflow_handler();
+    ::task::clear_task_hooks();
+    sched::install_scheduler(&sched::DEFAULT_SCHEDULER);
+    power::set_low_power_mode(LowPowerMode::Disabled);
+    ::arch::PENDSV_PENDING.store(false, Ordering::Relaxed);
+    ::arch::set_in_interrupt(false);
+    ::arch::BASEPRI.store(0, Ordering::Relaxed);
+    ::arch::PRIMASK.store(0, Ordering::Relaxed);
+    ::arch::DEMCR.store(0, Ordering::Relaxed);
+    ::tick::clear_tick_hook();
+    ::tick::set_timer_coalescing(0);
+    ::tick::clear_tickless_timer();
+    ::panic::clear_panic_hook();
+    ::panic::reset_task_panics();
+    ::task::clear_stack_arena();
+    ::task::clear_exit_records();
+    ::syscall::clear_replenish();
+    #[cfg(feature="replay")]
+    sched::replay::stop();
+    #[cfg(feature="logger")]
+    ::logger::clear_logger();
+    #[cfg(feature="starvation")]
+    {
+        sched::starvation::set_starvation_limit(0);
+        sched::starvation::clear_starvation_hook();
+        sched::starvation::set_priority_aging(false);
+    }
+    #[cfg(feature="stack_sizing")]
+    {
+        ::task::stack_sizing::set_stack_scale(1);
+        ::task::stack_sizing::set_warning_threshold(0);
+        ::task::stack_sizing::clear_report_hook();
+    }
+    #[cfg(feature="trace")]
+    {
+        ::trace::clear();
+        ::trace::clear_flush_hook();
+        ::trace::clear_switch_hooks();
+    }
     for queue in PRIORITY_QUEUES.iter() {
         queue.remove_all();
     }
+    #[cfg(not(feature="smp"))]
     unsafe { CURRENT_TASK = None };
+    #[cfg(feature="smp")]
+    {
+        unsafe { CURRENT_TASK = [None, None] };
+        ::arch::CORE.store(0, Ordering::Relaxed);
+        ::arch::STARTED_CORES.store(0, Ordering::Relaxed);
+        ::arch::INTERRUPTED_CORES.store(0, Ordering::Relaxed);
+    }
     guard
 }
 
@@ -58,11 +113,11 @@ pub fn create_and_schedule_test_task(stack_size: usize, priority: Priority, name
 
 #[allow(dead_code)]
 pub fn convert_handle_to_task_control(handle: TaskHandle) -> &'static TaskControl {
-    unsafe { ::std::mem::transmute::<TaskHandle, &TaskControl>(handle) }
+    unsafe { &*handle.as_ptr() }
 }
 
 pub fn current_task() -> Option<&'static mut TaskControl> {
-    unsafe { CURRENT_TASK.as_mut().map(|task| &mut ***task) }
+    unsafe { sched::current_task() }
 }
 
 pub fn block_current_task(delay_type: Delay) {
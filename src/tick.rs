@@ -19,15 +19,237 @@
 //!
 //! This module helps keep track of the system time and how much time has passed.
 
-use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use power::LowPowerMode;
+use sync::CriticalSection;
+use hook::Hook;
+use arch;
+
+/// The trace enable bit in the Debug Exception and Monitor Control Register.
+pub const DEMCR_TRCENA: usize = 0b1 << 24;
+
+/// The cycle counter enable bit in the DWT Control Register.
+pub const DWT_CTRL_CYCCNTENA: usize = 0b1;
+
+/// The bit in the DWT Control Register that is set if the cycle counter is NOT implemented.
+pub const DWT_CTRL_NOCYCCNT: usize = 0b1 << 25;
 
 static SYSTEM_TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+static CYCLE_COUNTER_ENABLED: AtomicBool = ATOMIC_BOOL_INIT;
+static CYCLES_PER_TICK: AtomicUsize = ATOMIC_USIZE_INIT;
+static TICK_HOOK: Hook<fn(u64)> = Hook::new();
+static TICK_SUSPENDED: AtomicBool = ATOMIC_BOOL_INIT;
+static TIMER_COALESCING: AtomicUsize = ATOMIC_USIZE_INIT;
+static mut TICKLESS_TIMER: Option<&'static TicklessTimer> = None;
 
 /// Tick the system tick counter.
 ///
-/// This method should only be called by the system tick interrupt handler.
+/// This method should only be called by the system tick interrupt handler. The tick hook, if one
+/// is set, is called with the new tick count.
 pub fn tick() {
-    SYSTEM_TICKS.fetch_add(1, Ordering::Relaxed);
+    let ticks = SYSTEM_TICKS.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    if let Some(hook) = TICK_HOOK.get() {
+        hook(ticks as u64);
+    }
+}
+
+/// Set a function to be called on every system tick.
+///
+/// The hook is passed the current tick count (the same value `get_tick` returns) and runs before
+/// the scheduler decides which task to run next. This is meant for tiny periodic jobs like a
+/// heartbeat LED or software PWM, which don't deserve a whole task to themselves.
+///
+/// The hook runs in interrupt context, so it must be short and must NOT block, sleep or use any of
+/// the blocking synchronization primitives. Setting a new hook replaces the old one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::tick::set_tick_hook;
+///
+/// fn heartbeat(ticks: u64) {
+///     if ticks % 1000 == 0 {
+///         // Toggle an LED...
+///     }
+/// }
+///
+/// set_tick_hook(heartbeat);
+/// ```
+pub fn set_tick_hook(hook: fn(u64)) {
+    TICK_HOOK.set(hook);
+}
+
+/// Remove the tick hook, if one was set.
+pub fn clear_tick_hook() {
+    TICK_HOOK.clear();
+}
+
+/// Pause timekeeping and scheduling.
+///
+/// Meant for operations like flash writes or clock reconfiguration that stop or disturb the
+/// SysTick timer. While the tick is suspended the system tick handler does nothing, so the tick
+/// count stays put, sleeping tasks don't time out and the running task isn't preempted. Stopping
+/// the timer hardware itself is up to the caller.
+///
+/// Call `resume_tick` once the operation is done.
+pub fn suspend_tick() {
+    TICK_SUSPENDED.store(true, Ordering::Relaxed);
+}
+
+/// Resume timekeeping after `suspend_tick`.
+///
+/// `elapsed_ticks` is how long the tick was stopped, measured by some other timer, it's added to
+/// the tick count so sleep deadlines stay accurate. Every task whose deadline passed while the
+/// tick was suspended is woken up, they'll get a chance to run on the next tick.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::tick::{suspend_tick, resume_tick};
+///
+/// suspend_tick();
+/// // Reprogram the clocks, measuring how long it took with the RTC...
+/// let elapsed = 25;
+/// resume_tick(elapsed);
+/// ```
+pub fn resume_tick(elapsed_ticks: usize) {
+    // The tick handler mustn't see the count advanced before the expired tasks are woken
+    let _g = CriticalSection::begin();
+    ::syscall::sys_resume_tick(elapsed_ticks);
+    TICK_SUSPENDED.store(false, Ordering::Relaxed);
+}
+
+/// Returns true if the tick has been stopped by `suspend_tick`.
+pub fn is_tick_suspended() -> bool {
+    TICK_SUSPENDED.load(Ordering::Relaxed)
+}
+
+// Move the tick count forward by `ticks` at once, returning the new count.
+pub(crate) fn advance(ticks: usize) -> usize {
+    SYSTEM_TICKS.fetch_add(ticks, Ordering::Relaxed).wrapping_add(ticks)
+}
+
+/// Set how many ticks apart sleep timeouts may be and still be serviced together.
+///
+/// Normally every task sleeping with a timeout is woken on the first tick after its deadline. With
+/// a coalescing window of `window_ticks`, a task that comes due is held back if another task is
+/// due within `window_ticks` ticks of it, and they are all woken together in one pass. This trades
+/// a little timing precision for fewer wakeups, which can let the processor stay in low power
+/// modes for longer. Timeouts are never cut short, a task is woken at most `window_ticks` ticks
+/// late.
+///
+/// A window of 0 turns coalescing off, this is the default.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::tick::set_timer_coalescing;
+///
+/// // Timeouts due within 5 ticks of each other are handled together
+/// set_timer_coalescing(5);
+/// ```
+pub fn set_timer_coalescing(window_ticks: usize) {
+    TIMER_COALESCING.store(window_ticks, Ordering::Relaxed);
+}
+
+/// Return the timer coalescing window set with `set_timer_coalescing`.
+pub fn timer_coalescing() -> usize {
+    TIMER_COALESCING.load(Ordering::Relaxed)
+}
+
+/// A timer that can stand in for the periodic system tick while the system is idle.
+///
+/// Ports implement this on top of whatever timer can wake the processor from the low power mode in
+/// use, the SysTick itself for `Sleep`, or something like an RTC alarm or low power timer for
+/// `Stop`. Both methods are called with interrupts disabled.
+pub trait TicklessTimer: Sync {
+    /// Stop the periodic tick and set the timer to wake the processor `ticks` ticks from now.
+    ///
+    /// If the timer can't count that far it should wake the processor as late as it can, the
+    /// kernel just goes back to sleep if there's still nothing to do.
+    fn stop_tick(&self, ticks: usize);
+
+    /// Restart the periodic tick after the processor has woken up, returning how many whole ticks
+    /// passed while it was stopped.
+    ///
+    /// The processor may have been woken early by some other interrupt, so this has to measure the
+    /// time that actually passed rather than assume the whole sleep ran its course.
+    fn restart_tick(&self) -> usize;
+}
+
+/// Install a timer that lets the idle task stop the periodic tick.
+///
+/// With a tickless timer installed, and a low power mode selected, the idle task stops the system
+/// tick when no task is ready to run and sleeps straight through to the tick the next sleeping
+/// task times out on, instead of being woken on every tick only to go back to sleep. The tick count
+/// is fixed up when the processor wakes up.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::tick::{set_tickless_timer, TicklessTimer};
+///
+/// struct RtcTimer;
+///
+/// impl TicklessTimer for RtcTimer {
+///     fn stop_tick(&self, ticks: usize) {
+///         // Disable the SysTick and set an RTC alarm `ticks` ticks out...
+///     }
+///
+///     fn restart_tick(&self) -> usize {
+///         // Read how far the RTC got, then restart the SysTick...
+///         0
+///     }
+/// }
+///
+/// static TIMER: RtcTimer = RtcTimer;
+///
+/// set_tickless_timer(&TIMER);
+/// ```
+pub fn set_tickless_timer(timer: &'static TicklessTimer) {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so nothing can be using the timer while we swap it
+    unsafe { TICKLESS_TIMER = Some(timer) };
+}
+
+/// Go back to waking up on every tick while idle.
+pub fn clear_tickless_timer() {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section
+    unsafe { TICKLESS_TIMER = None };
+}
+
+// Sleep in `mode` with the periodic tick stopped until the next sleeping task is due, called from
+// the idle task. Returns `false` without sleeping if the tick can't be stopped right now.
+#[doc(hidden)]
+pub fn idle_tickless(mode: LowPowerMode) -> bool {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section, so neither the timer nor the current task can change
+    let timer = match unsafe { TICKLESS_TIMER } {
+        Some(timer) => timer,
+        None => return false,
+    };
+    if is_tick_suspended() {
+        return false;
+    }
+    // Don't sleep through a task that was woken since the idle task started running
+    match unsafe { ::sched::current_task() } {
+        Some(current) if !::sched::scheduler().should_yield(current) => {},
+        _ => return false,
+    }
+    // The next tick is coming anyway, there's nothing to gain from stopping it
+    let ticks = ::syscall::ticks_until_wake().unwrap_or(!0);
+    if ticks <= 1 {
+        return false;
+    }
+
+    timer.stop_tick(ticks);
+    // Interrupts are masked, but a pending interrupt still wakes the processor, it's serviced once
+    // the critical section ends
+    arch::enter_low_power(mode);
+    let elapsed = timer.restart_tick();
+    ::syscall::sys_resume_tick(elapsed);
+    true
 }
 
 /// Return the number of ticks that have passed since the system started.
@@ -37,3 +259,154 @@ pub fn tick() {
 pub fn get_tick() -> usize {
     SYSTEM_TICKS.load(Ordering::Relaxed)
 }
+
+/// Turn on the hardware cycle counter.
+///
+/// On Cortex-M parts with a DWT cycle counter (most Cortex-M3 and up) this enables tracing in the
+/// `DEMCR` register, resets `DWT_CYCCNT` and starts it counting. Returns `true` if the part has a
+/// cycle counter, `false` otherwise.
+pub fn enable_cycle_counter() -> bool {
+    let enabled = arch::enable_cycle_counter();
+    CYCLE_COUNTER_ENABLED.store(enabled, Ordering::Relaxed);
+    enabled
+}
+
+/// Set the number of CPU cycles in a system tick.
+///
+/// This is only used to approximate the cycle count on parts without a cycle counter.
+pub fn set_cycles_per_tick(cycles: usize) {
+    CYCLES_PER_TICK.store(cycles, Ordering::Relaxed);
+}
+
+/// Return the number of CPU cycles that have passed.
+///
+/// This is meant for timing short spans of code, the count wraps around every 2^32 cycles so only
+/// the difference between two readings is meaningful.
+///
+/// If the cycle counter is not enabled, or the part doesn't have one (like the Cortex-M0), the tick
+/// count scaled by the value passed to `set_cycles_per_tick` is returned instead. This is only as
+/// accurate as the system tick, so it is of little use for micro-benchmarks.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::tick::{enable_cycle_counter, now_cycles};
+///
+/// enable_cycle_counter();
+///
+/// let start = now_cycles();
+/// // Do some work...
+/// let elapsed = now_cycles().wrapping_sub(start);
+/// ```
+pub fn now_cycles() -> u32 {
+    if CYCLE_COUNTER_ENABLED.load(Ordering::Relaxed) {
+        arch::read_cycle_counter() as u32
+    }
+    else {
+        get_tick().wrapping_mul(CYCLES_PER_TICK.load(Ordering::Relaxed)) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arch;
+    use test;
+
+    static HOOK_CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+    static HOOK_LAST_TICK: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn test_hook(ticks: u64) {
+        let last = HOOK_LAST_TICK.swap(ticks as usize, Ordering::SeqCst);
+        // Every call must see a later tick than the one before it
+        assert!(ticks as usize > last);
+        HOOK_CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_tick_hook_called_every_tick() {
+        let _g = test::set_up();
+        HOOK_CALLS.store(0, Ordering::SeqCst);
+        HOOK_LAST_TICK.store(get_tick(), Ordering::SeqCst);
+
+        set_tick_hook(test_hook);
+        for _ in 0..5 {
+            tick();
+        }
+        assert_eq!(HOOK_CALLS.load(Ordering::SeqCst), 5);
+        assert_eq!(HOOK_LAST_TICK.load(Ordering::SeqCst), get_tick());
+
+        clear_tick_hook();
+        tick();
+        assert_eq!(HOOK_CALLS.load(Ordering::SeqCst), 5);
+    }
+
+    struct TestTimer {
+        stopped_for: AtomicUsize,
+    }
+
+    impl TicklessTimer for TestTimer {
+        fn stop_tick(&self, ticks: usize) {
+            self.stopped_for.store(ticks, Ordering::SeqCst);
+        }
+
+        fn restart_tick(&self) -> usize {
+            // Sleep the whole way through
+            self.stopped_for.load(Ordering::SeqCst)
+        }
+    }
+
+    static TEST_TIMER: TestTimer = TestTimer { stopped_for: ATOMIC_USIZE_INIT };
+
+    #[test]
+    fn test_tickless_idle_sleeps_until_next_wakeup() {
+        use power::{self, LowPowerMode};
+        use syscall::{self, FOREVER_CHAN};
+        use sched::start_scheduler;
+        use task::{Priority, State};
+
+        let _g = test::set_up();
+        TEST_TIMER.stopped_for.store(0, Ordering::SeqCst);
+        set_tickless_timer(&TEST_TIMER);
+        power::set_low_power_mode(LowPowerMode::Sleep);
+        let handle = test::create_and_schedule_test_task(512, Priority::Normal, "sleeper");
+        start_scheduler();
+
+        let start = get_tick();
+        syscall::sleep_for(FOREVER_CHAN, 10);
+        assert_eq!(test::current_task().unwrap().priority(), Priority::__Idle);
+
+        power::idle();
+        // The timeout is counted from the next tick edge
+        assert_eq!(TEST_TIMER.stopped_for.load(Ordering::SeqCst), 11);
+        assert_eq!(get_tick(), start + 11);
+        assert_eq!(handle.state(), Ok(State::Ready));
+    }
+
+    #[test]
+    fn test_enable_cycle_counter_sets_up_dwt() {
+        let _g = test::set_up();
+        arch::DWT_CTRL.store(0, Ordering::Relaxed);
+        arch::DWT_CYCCNT.store(1234, Ordering::Relaxed);
+
+        assert!(enable_cycle_counter());
+        assert_eq!(arch::DEMCR.load(Ordering::Relaxed) & DEMCR_TRCENA, DEMCR_TRCENA);
+        assert_eq!(arch::DWT_CTRL.load(Ordering::Relaxed) & DWT_CTRL_CYCCNTENA, DWT_CTRL_CYCCNTENA);
+        assert_eq!(now_cycles(), 0);
+
+        arch::DWT_CYCCNT.store(5000, Ordering::Relaxed);
+        assert_eq!(now_cycles(), 5000);
+    }
+
+    #[test]
+    fn test_now_cycles_falls_back_to_ticks() {
+        let _g = test::set_up();
+        arch::DWT_CTRL.store(DWT_CTRL_NOCYCCNT, Ordering::Relaxed);
+
+        assert_not!(enable_cycle_counter());
+        assert_eq!(arch::DWT_CTRL.load(Ordering::Relaxed) & DWT_CTRL_CYCCNTENA, 0);
+
+        set_cycles_per_tick(48_000);
+        assert_eq!(now_cycles(), get_tick().wrapping_mul(48_000) as u32);
+    }
+}
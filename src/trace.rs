@@ -0,0 +1,472 @@
+/*
+* Copyright (C) 2017 AltOS-Rust Team
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Kernel event tracing.
+//!
+//! When the `trace` feature is enabled the kernel writes a small record into a fixed-size ring
+//! every time it switches tasks, a task blocks or the kernel panics. Once the ring is full the
+//! oldest records are overwritten.
+//!
+//! The ring is placed in the `.noinit` section, if the target's linker script keeps that section
+//! out of the memory that gets zeroed at startup (`NOLOAD`), the ring survives a soft reset and
+//! the events leading up to a crash can be read back with `events` after the system reboots. The
+//! ring is checked for a valid header the first time it's used, so it starts out empty after a
+//! power cycle.
+//!
+//! The idle task hands any records that haven't been seen yet to the flush hook, if one is set, so
+//! they can be sent somewhere more permanent while the system has nothing better to do.
+//!
+//! For external trace tools that want to see every context switch as it happens, hooks can also be
+//! set that the scheduler calls with the task being switched out and the task being switched in.
+
+use hook::Hook;
+use sync::CriticalSection;
+use task::TaskControl;
+use tick;
+
+/// The number of records the ring can hold, older records are overwritten once it's full.
+///
+/// This must stay a power of two so record positions keep lining up when the write count wraps.
+pub const TRACE_CAPACITY: usize = 64;
+
+// Marks the ring as holding records from before the last reset rather than garbage
+const TRACE_MAGIC: usize = 0x7EAC_E001;
+
+// The tid recorded for events that happen when no task is running
+const NO_TASK: usize = !0;
+
+#[cfg_attr(not(any(test, feature="test")), link_section = ".noinit")]
+static mut RING: Ring = Ring::new();
+
+static FLUSH_HOOK: Hook<fn(&TraceEvent)> = Hook::new();
+static SWITCH_OUT_HOOK: Hook<fn(&TaskControl)> = Hook::new();
+static SWITCH_IN_HOOK: Hook<fn(&TaskControl)> = Hook::new();
+
+/// The kind of event a trace record describes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// The scheduler switched to the task.
+    Switch,
+    /// The task blocked waiting for an event or a timeout.
+    Block,
+    /// The kernel panicked while the task was running.
+    Fault,
+}
+
+impl EventKind {
+    fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(EventKind::Switch),
+            1 => Some(EventKind::Block),
+            2 => Some(EventKind::Fault),
+            _ => None,
+        }
+    }
+
+    fn to_raw(self) -> usize {
+        match self {
+            EventKind::Switch => 0,
+            EventKind::Block => 1,
+            EventKind::Fault => 2,
+        }
+    }
+}
+
+/// A single traced kernel event.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// The system tick the event happened on.
+    pub tick: usize,
+    /// What happened.
+    pub kind: EventKind,
+    /// The id of the task the event happened to, `None` if no task was running.
+    pub tid: Option<usize>,
+}
+
+// The kind is kept as a plain word, the ring may hold whatever was in RAM at power on
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawRecord {
+    tick: usize,
+    kind: usize,
+    tid: usize,
+}
+
+impl RawRecord {
+    fn event(&self) -> Option<TraceEvent> {
+        EventKind::from_raw(self.kind).map(|kind| TraceEvent {
+            tick: self.tick,
+            kind: kind,
+            tid: if self.tid == NO_TASK { None } else { Some(self.tid) },
+        })
+    }
+}
+
+#[repr(C)]
+struct Ring {
+    magic: usize,
+    // The total number of records ever written, wrapping, the next record goes at
+    // `written % TRACE_CAPACITY`
+    written: usize,
+    // The number of valid records in the ring
+    len: usize,
+    // The value of `written` the last time the ring was flushed
+    flushed: usize,
+    records: [RawRecord; TRACE_CAPACITY],
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Ring {
+            magic: 0,
+            written: 0,
+            len: 0,
+            flushed: 0,
+            records: [RawRecord { tick: 0, kind: 0, tid: 0 }; TRACE_CAPACITY],
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == TRACE_MAGIC && self.len <= TRACE_CAPACITY &&
+            self.written.wrapping_sub(self.flushed) <= self.len
+    }
+
+    fn reset(&mut self) {
+        self.magic = TRACE_MAGIC;
+        self.written = 0;
+        self.len = 0;
+        self.flushed = 0;
+    }
+
+    fn oldest(&self) -> usize {
+        self.written.wrapping_sub(self.len)
+    }
+
+    fn push(&mut self, record: RawRecord) {
+        self.records[self.written % TRACE_CAPACITY] = record;
+        self.written = self.written.wrapping_add(1);
+        if self.len < TRACE_CAPACITY {
+            self.len += 1;
+        }
+        else if self.written.wrapping_sub(self.flushed) > self.len {
+            // Unflushed records were just overwritten
+            self.flushed = self.oldest();
+        }
+    }
+}
+
+// Get the ring, starting it over if it doesn't hold valid records. Must be called from inside of a
+// critical section.
+unsafe fn ring() -> &'static mut Ring {
+    if !RING.is_valid() {
+        RING.reset();
+    }
+    &mut RING
+}
+
+// Write a record for an event that happened to the task with id `tid`.
+#[doc(hidden)]
+pub fn record(kind: EventKind, tid: Option<usize>) {
+    let record = RawRecord {
+        tick: tick::get_tick(),
+        kind: kind.to_raw(),
+        tid: tid.unwrap_or(NO_TASK),
+    };
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section
+    unsafe { ring().push(record) };
+}
+
+/// Iterate over the records in the ring, oldest first.
+///
+/// The iterator only covers the records that were in the ring when it was created. Each record is
+/// read inside of its own short critical section, so tracing carries on while the records are
+/// being read, if a record gets overwritten before it's reached it's skipped.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::trace;
+///
+/// // After rebooting from a crash, dump what the kernel was doing
+/// for event in trace::events() {
+///     // Send `event` over the serial port...
+/// }
+/// ```
+pub fn events() -> TraceEvents {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section
+    let ring = unsafe { ring() };
+    TraceEvents {
+        next: ring.oldest(),
+        end: ring.written,
+    }
+}
+
+/// Throw away every record in the ring.
+pub fn clear() {
+    let _g = CriticalSection::begin();
+    // UNSAFE: We're in a critical section
+    unsafe { RING.reset() };
+}
+
+/// Set a function that the idle task passes new records to.
+///
+/// Every record is passed to the hook once, oldest first. Records that are overwritten before the
+/// idle task gets to run are never passed to it.
+pub fn set_flush_hook(hook: fn(&TraceEvent)) {
+    FLUSH_HOOK.set(hook);
+}
+
+/// Remove the flush hook, if one was set.
+pub fn clear_flush_hook() {
+    FLUSH_HOOK.clear();
+}
+
+// Pass any records that haven't been flushed yet to the flush hook, called from the idle task.
+#[doc(hidden)]
+pub fn flush() {
+    let (hook, pending) = {
+        let _g = CriticalSection::begin();
+        let hook = match FLUSH_HOOK.get() {
+            Some(hook) => hook,
+            None => return,
+        };
+        // UNSAFE: We're in a critical section
+        let ring = unsafe { ring() };
+        let pending = TraceEvents {
+            next: ring.flushed,
+            end: ring.written,
+        };
+        ring.flushed = ring.written;
+        (hook, pending)
+    };
+
+    for event in pending {
+        hook(&event);
+    }
+}
+
+/// Set a function that the scheduler calls with the running task every time it's switched out.
+///
+/// The hook is called from the context switch handler with interrupts disabled, before the task
+/// is put back on a ready or sleep queue, so it must be short and must NOT block or make system
+/// calls. It isn't called for tasks that have exited.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use altos_core::trace;
+/// use altos_core::TaskControl;
+///
+/// fn task_out(task: &TaskControl) {
+///     // Tell the trace probe that `task.tid()` stopped running...
+/// }
+///
+/// trace::set_switch_out_hook(task_out);
+/// ```
+pub fn set_switch_out_hook(hook: fn(&TaskControl)) {
+    SWITCH_OUT_HOOK.set(hook);
+}
+
+/// Set a function that the scheduler calls with each task it switches to, just before it runs.
+///
+/// The same restrictions as the switch out hook apply. The first task run by `start_scheduler` is
+/// passed to this hook too.
+pub fn set_switch_in_hook(hook: fn(&TaskControl)) {
+    SWITCH_IN_HOOK.set(hook);
+}
+
+/// Remove the switch out and switch in hooks, if they were set.
+pub fn clear_switch_hooks() {
+    SWITCH_OUT_HOOK.clear();
+    SWITCH_IN_HOOK.clear();
+}
+
+// Pass the task being switched out to the switch out hook, called from the context switch handler.
+#[doc(hidden)]
+pub fn switch_out(task: &TaskControl) {
+    if let Some(hook) = SWITCH_OUT_HOOK.get() {
+        hook(task);
+    }
+}
+
+// Pass the task being switched in to the switch in hook, called from the context switch handler.
+#[doc(hidden)]
+pub fn switch_in(task: &TaskControl) {
+    if let Some(hook) = SWITCH_IN_HOOK.get() {
+        hook(task);
+    }
+}
+
+/// An iterator over the records in the trace ring, created by `events`.
+pub struct TraceEvents {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for TraceEvents {
+    type Item = TraceEvent;
+
+    fn next(&mut self) -> Option<TraceEvent> {
+        let _g = CriticalSection::begin();
+        // UNSAFE: We're in a critical section
+        let ring = unsafe { ring() };
+        loop {
+            let oldest = ring.oldest();
+            // Everything we were going to read has been overwritten
+            if self.end.wrapping_sub(oldest) > ring.len {
+                return None;
+            }
+            // Skip ahead past anything that was overwritten while we weren't looking
+            if self.next.wrapping_sub(oldest) > self.end.wrapping_sub(oldest) {
+                self.next = oldest;
+            }
+            if self.next == self.end {
+                return None;
+            }
+
+            let record = ring.records[self.next % TRACE_CAPACITY];
+            self.next = self.next.wrapping_add(1);
+            // Records with a kind we don't know about are left over from before a power cycle
+            if let Some(event) = record.event() {
+                return Some(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test;
+    use tick;
+    use atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    #[test]
+    fn test_events_recorded_in_order() {
+        let _g = test::set_up();
+        let now = tick::get_tick();
+        record(EventKind::Switch, Some(1));
+        record(EventKind::Block, Some(1));
+        record(EventKind::Fault, None);
+
+        let mut events = events();
+        assert_eq!(events.next(), Some(TraceEvent { tick: now, kind: EventKind::Switch, tid: Some(1) }));
+        assert_eq!(events.next(), Some(TraceEvent { tick: now, kind: EventKind::Block, tid: Some(1) }));
+        assert_eq!(events.next(), Some(TraceEvent { tick: now, kind: EventKind::Fault, tid: None }));
+        assert_eq!(events.next(), None);
+    }
+
+    #[test]
+    fn test_ring_wraps_around_keeping_newest_records() {
+        let _g = test::set_up();
+        for tid in 0..(TRACE_CAPACITY + 5) {
+            record(EventKind::Switch, Some(tid));
+        }
+
+        let mut expected = 5;
+        for event in events() {
+            assert_eq!(event.tid, Some(expected));
+            expected += 1;
+        }
+        assert_eq!(expected, TRACE_CAPACITY + 5);
+    }
+
+    #[test]
+    fn test_overwritten_records_skipped_while_iterating() {
+        let _g = test::set_up();
+        for tid in 0..TRACE_CAPACITY {
+            record(EventKind::Switch, Some(tid));
+        }
+
+        let mut events = events();
+        assert_eq!(events.next().unwrap().tid, Some(0));
+        // Overwrite the record that was just read and the one after it
+        record(EventKind::Block, Some(100));
+        record(EventKind::Block, Some(101));
+        assert_eq!(events.next().unwrap().tid, Some(2));
+        // Only the records that were there when iteration started are returned
+        assert_eq!(events.last().unwrap().tid, Some(TRACE_CAPACITY - 1));
+    }
+
+    #[test]
+    fn test_corrupt_ring_starts_empty() {
+        let _g = test::set_up();
+        record(EventKind::Switch, Some(1));
+        // Simulate the garbage the ring holds after a power cycle
+        unsafe { RING.magic = 0xDEAD_BEEF };
+
+        assert_eq!(events().next(), None);
+    }
+
+    static SWITCHED_OUT: AtomicUsize = ATOMIC_USIZE_INIT;
+    static SWITCHED_IN: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn note_switch_out(task: &TaskControl) {
+        SWITCHED_OUT.store(task.tid() + 1, Ordering::Relaxed);
+    }
+
+    fn note_switch_in(task: &TaskControl) {
+        SWITCHED_IN.store(task.tid() + 1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_switch_hooks_see_both_tasks() {
+        use sched;
+
+        let _g = test::set_up();
+        SWITCHED_OUT.store(0, Ordering::Relaxed);
+        SWITCHED_IN.store(0, Ordering::Relaxed);
+        set_switch_out_hook(note_switch_out);
+        set_switch_in_hook(note_switch_in);
+        let (handle_1, handle_2) = test::create_two_tasks();
+
+        sched::start_scheduler();
+        assert_eq!(SWITCHED_IN.load(Ordering::Relaxed), handle_1.tid().unwrap() + 1);
+        assert_eq!(SWITCHED_OUT.load(Ordering::Relaxed), 0);
+
+        sched::switch_context();
+        assert_eq!(SWITCHED_OUT.load(Ordering::Relaxed), handle_1.tid().unwrap() + 1);
+        assert_eq!(SWITCHED_IN.load(Ordering::Relaxed), handle_2.tid().unwrap() + 1);
+    }
+
+    static FLUSHED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn count_flushed(_event: &TraceEvent) {
+        FLUSHED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_flush_passes_each_record_once() {
+        let _g = test::set_up();
+        FLUSHED.store(0, Ordering::Relaxed);
+        set_flush_hook(count_flushed);
+
+        record(EventKind::Switch, Some(1));
+        record(EventKind::Switch, Some(2));
+        flush();
+        assert_eq!(FLUSHED.load(Ordering::Relaxed), 2);
+
+        record(EventKind::Block, Some(2));
+        flush();
+        flush();
+        assert_eq!(FLUSHED.load(Ordering::Relaxed), 3);
+        // Flushing doesn't remove anything from the ring
+        assert_eq!(events().count(), 3);
+    }
+}